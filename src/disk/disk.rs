@@ -1 +1,208 @@
-pub struct Disk {}
+use crate::{
+    disk::{
+        layout::{FileSlice, Layout},
+        partfile::PartFile,
+    },
+    error::Result,
+    torrent::FilePriority,
+};
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+/// Torrent storage. Slices of skipped files are kept in the part file,
+/// so skipped files are never created.
+#[derive(Debug)]
+pub struct Disk {
+    save_path: PathBuf,
+    layout: Layout,
+    file_priorities: Vec<FilePriority>,
+    partfile: PartFile,
+}
+
+impl Disk {
+    /// `name` is used for the part file, usually the info hash in hex.
+    pub fn new(save_path: PathBuf, layout: Layout, name: &str) -> Self {
+        let partfile = PartFile::new(
+            save_path.join(format!(".{name}.parts")),
+            layout.num_pieces,
+            layout.piece_length,
+        );
+        Self {
+            file_priorities: vec![FilePriority::default(); layout.files.len()],
+            save_path,
+            layout,
+            partfile,
+        }
+    }
+
+    #[inline]
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    #[inline]
+    pub fn file_priorities(&self) -> &[FilePriority] {
+        &self.file_priorities
+    }
+
+    pub fn file_path(&self, file_index: usize) -> Option<PathBuf> {
+        self.layout
+            .files
+            .get(file_index)
+            .map(|f| self.save_path.join(&f.path))
+    }
+
+    fn is_wanted(&self, file_index: usize) -> bool {
+        self.file_priorities
+            .get(file_index)
+            .is_none_or(|p| p.is_wanted())
+    }
+
+    /// Padding files are never written.
+    async fn write_file(&self, slice: &FileSlice, data: &[u8]) -> Result<()> {
+        let entry = &self.layout.files[slice.file_index];
+        if entry.is_padding() {
+            return Ok(());
+        }
+        let path = self.save_path.join(&entry.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .await?;
+        #[cfg(unix)]
+        if entry.attr.executable {
+            use std::os::unix::fs::PermissionsExt;
+            let mut permissions = file.metadata().await?.permissions();
+            if permissions.mode() & 0o111 == 0 {
+                permissions.set_mode(permissions.mode() | 0o111);
+                file.set_permissions(permissions).await?;
+            }
+        }
+        file.seek(SeekFrom::Start(slice.file_offset)).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Padding files read as zeros.
+    async fn read_file(&self, slice: &FileSlice, buf: &mut [u8]) -> Result<()> {
+        let entry = &self.layout.files[slice.file_index];
+        if entry.is_padding() {
+            buf.fill(0);
+            return Ok(());
+        }
+        let path = self.save_path.join(&entry.path);
+        let mut file = File::open(&path).await?;
+        file.seek(SeekFrom::Start(slice.file_offset)).await?;
+        file.read_exact(buf).await?;
+        Ok(())
+    }
+
+    pub async fn write(&mut self, piece: usize, begin: u64, data: &[u8]) -> Result<()> {
+        for slice in self.layout.slices(piece, begin, data.len() as u64) {
+            let start = (slice.piece_offset - begin) as usize;
+            let chunk = &data[start..start + slice.length as usize];
+            if self.is_wanted(slice.file_index) {
+                self.write_file(&slice, chunk).await?;
+            } else {
+                self.partfile
+                    .write(piece, slice.piece_offset, chunk)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn read(&mut self, piece: usize, begin: u64, length: u64) -> Result<Vec<u8>> {
+        let slices = self.layout.slices(piece, begin, length);
+        let mut buf = vec![0u8; slices.iter().map(|s| s.length as usize).sum()];
+        for slice in slices {
+            let start = (slice.piece_offset - begin) as usize;
+            let chunk = &mut buf[start..start + slice.length as usize];
+            if !self.is_wanted(slice.file_index) {
+                if let Some(data) = self
+                    .partfile
+                    .read(piece, slice.piece_offset, slice.length)
+                    .await?
+                {
+                    chunk.copy_from_slice(&data);
+                    continue;
+                }
+            }
+            self.read_file(&slice, chunk).await?;
+        }
+        Ok(buf)
+    }
+
+    /// Creates the symlinks of the torrent, with relative targets so the links keep
+    /// working when the save directory moves. Existing files are left alone.
+    pub async fn create_symlinks(&self) -> Result<()> {
+        for entry in self.layout.files.iter().filter(|f| f.attr.symlink) {
+            let Some(target) = entry.symlink_path.as_ref() else {
+                continue;
+            };
+            let link = self.save_path.join(&entry.path);
+            if fs::symlink_metadata(&link).await.is_ok() {
+                continue;
+            }
+            if let Some(parent) = link.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            let depth = entry.path.components().count().saturating_sub(1);
+            let relative = std::iter::repeat_n(Path::new(".."), depth).collect::<PathBuf>();
+            #[cfg(unix)]
+            fs::symlink(relative.join(target), &link).await?;
+            #[cfg(windows)]
+            fs::symlink_file(relative.join(target), &link).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the data of files that are no longer skipped out of the part file.
+    pub async fn set_file_priorities(&mut self, priorities: &[FilePriority]) -> Result<()> {
+        let mut new_priorities = vec![FilePriority::default(); self.layout.files.len()];
+        for (p, new) in new_priorities.iter_mut().zip(priorities) {
+            *p = *new;
+        }
+        let old_priorities = std::mem::replace(&mut self.file_priorities, new_priorities);
+
+        for piece in self.partfile.pieces().await? {
+            let mut skipped = false;
+            for slice in self.layout.piece_slices(piece) {
+                if !self.is_wanted(slice.file_index) {
+                    skipped = true;
+                    continue;
+                }
+                let was_wanted = old_priorities
+                    .get(slice.file_index)
+                    .is_none_or(|p| p.is_wanted());
+                if was_wanted {
+                    continue;
+                }
+                if let Some(data) = self
+                    .partfile
+                    .read(piece, slice.piece_offset, slice.length)
+                    .await?
+                {
+                    self.write_file(&slice, &data).await?;
+                }
+            }
+            if !skipped {
+                self.partfile.remove(piece).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
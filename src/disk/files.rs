@@ -0,0 +1,48 @@
+use crate::{
+    error::{Error, Result},
+    proto::metainfo::file::FileEntry,
+};
+use std::{
+    io::ErrorKind,
+    path::{Component, Path},
+};
+use tokio::fs;
+
+/// Removes the torrent files and the directories left empty by them. Nothing
+/// is removed if a path could point outside of `save_path`.
+pub async fn remove_torrent_files(save_path: &Path, files: &[FileEntry]) -> Result<()> {
+    let unsafe_path = files.iter().map(|file| &file.path).find(|path| {
+        let relative = path.components().all(|c| matches!(c, Component::Normal(_)));
+        !relative || !save_path.join(path).starts_with(save_path)
+    });
+    if let Some(path) = unsafe_path {
+        return Err(Error::UnsafePath(path.clone()));
+    }
+
+    for file in files.iter() {
+        match fs::remove_file(save_path.join(&file.path)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    let mut dirs = files
+        .iter()
+        .flat_map(|file| file.path.ancestors().skip(1))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect::<Vec<_>>();
+    dirs.sort_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then(a.cmp(b))
+    });
+    dirs.dedup();
+
+    for dir in dirs {
+        // Fails for directories that still contain foreign files.
+        let _ = fs::remove_dir(save_path.join(dir)).await;
+    }
+
+    Ok(())
+}
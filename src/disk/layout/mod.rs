@@ -0,0 +1,104 @@
+use crate::proto::metainfo::{file::FileEntry, Info};
+use std::ops::Range;
+
+/// Part of a piece that belongs to a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSlice {
+    pub file_index: usize,
+    /// Offset inside the file.
+    pub file_offset: u64,
+    /// Offset inside the piece.
+    pub piece_offset: u64,
+    pub length: u64,
+}
+
+/// Maps pieces of the torrent onto its files.
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    pub piece_length: u64,
+    pub total_length: u64,
+    pub num_pieces: usize,
+    pub files: Vec<FileEntry>,
+}
+
+impl Layout {
+    pub fn new(piece_length: u64, files: Vec<FileEntry>) -> Self {
+        let total_length = files.iter().map(|f| f.offset + f.length).max().unwrap_or(0);
+        let num_pieces = total_length.div_ceil(piece_length.max(1)) as usize;
+        Self {
+            piece_length,
+            total_length,
+            num_pieces,
+            files,
+        }
+    }
+
+    pub fn from_torrent_info(info: &Info) -> Self {
+        Self::new(info.piece_length, info.files())
+    }
+
+    #[inline]
+    pub fn piece_offset(&self, index: usize) -> u64 {
+        index as u64 * self.piece_length
+    }
+
+    /// A piece ends early where a v2 file ends before the next piece boundary.
+    pub fn piece_size(&self, index: usize) -> u64 {
+        if index >= self.num_pieces {
+            return 0;
+        }
+        let start = self.piece_offset(index);
+        let end = (start + self.piece_length).min(self.total_length);
+        let last = self.files.partition_point(|f| f.offset < end);
+        self.files[..last]
+            .iter()
+            .rev()
+            .find(|f| f.length > 0)
+            .map_or(0, |f| (f.offset + f.length).min(end).saturating_sub(start))
+    }
+
+    /// Splits `length` bytes starting at `begin` inside the piece into file slices.
+    pub fn slices(&self, index: usize, begin: u64, length: u64) -> Vec<FileSlice> {
+        let start = self.piece_offset(index) + begin;
+        let end = (start + length).min(self.total_length);
+        if start >= end {
+            return Vec::new();
+        }
+
+        let first = self.files.partition_point(|f| f.offset + f.length <= start);
+        self.files[first..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, f)| f.offset < end)
+            .filter(|(_, f)| f.length > 0)
+            .map(|(i, f)| {
+                let slice_start = start.max(f.offset);
+                let slice_end = end.min(f.offset + f.length);
+                FileSlice {
+                    file_index: first + i,
+                    file_offset: slice_start - f.offset,
+                    piece_offset: slice_start - self.piece_offset(index),
+                    length: slice_end - slice_start,
+                }
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn piece_slices(&self, index: usize) -> Vec<FileSlice> {
+        self.slices(index, 0, self.piece_size(index))
+    }
+
+    /// Pieces that contain at least one byte of the file.
+    pub fn file_pieces(&self, file_index: usize) -> Range<usize> {
+        let Some(file) = self.files.get(file_index) else {
+            return 0..0;
+        };
+        if file.length == 0 || self.piece_length == 0 {
+            return 0..0;
+        }
+        let first = file.offset / self.piece_length;
+        let last = (file.offset + file.length - 1) / self.piece_length;
+        first as usize..last as usize + 1
+    }
+}
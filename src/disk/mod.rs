@@ -1,15 +1,12 @@
-use std::io::SeekFrom;
+#[allow(clippy::module_inception)]
 mod disk;
-// pub mod layout;
+mod files;
+pub mod layout;
+mod partfile;
 
 pub use disk::Disk;
-
-use tokio::{
-    fs::OpenOptions,
-    io::{AsyncSeekExt, AsyncWriteExt},
-};
-
-use crate::error::Result;
+pub use files::*;
+pub use partfile::PartFile;
 
 // disk layout
 
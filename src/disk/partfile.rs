@@ -0,0 +1,192 @@
+use crate::error::Result;
+use std::{collections::BTreeMap, io::ErrorKind, io::SeekFrom, path::PathBuf};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+const UNUSED_SLOT: u32 = u32::MAX;
+
+/// Keeps the parts of pieces that belong to skipped files, so those files
+/// are never created.
+///
+/// File format: `num_pieces: u32`, `piece_length: u32`, a slot table of
+/// `num_pieces` big-endian `u32` entries and then the slots, each one piece long.
+#[derive(Debug)]
+pub struct PartFile {
+    path: PathBuf,
+    num_pieces: usize,
+    piece_length: u64,
+    slots: BTreeMap<usize, u32>,
+    free_slots: Vec<u32>,
+    num_slots: u32,
+    loaded: bool,
+}
+
+impl PartFile {
+    pub fn new(path: PathBuf, num_pieces: usize, piece_length: u64) -> Self {
+        Self {
+            path,
+            num_pieces,
+            piece_length,
+            slots: BTreeMap::new(),
+            free_slots: Vec::new(),
+            num_slots: 0,
+            loaded: false,
+        }
+    }
+
+    #[inline]
+    fn header_len(&self) -> u64 {
+        8 + self.num_pieces as u64 * 4
+    }
+
+    #[inline]
+    fn slot_offset(&self, slot: u32) -> u64 {
+        self.header_len() + slot as u64 * self.piece_length
+    }
+
+    async fn load(&mut self) -> Result<()> {
+        if self.loaded {
+            return Ok(());
+        }
+        self.loaded = true;
+
+        let bytes = match fs::read(&self.path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if (bytes.len() as u64) < self.header_len()
+            || u32::from_be_bytes(bytes[0..4].try_into()?) as usize != self.num_pieces
+            || u32::from_be_bytes(bytes[4..8].try_into()?) as u64 != self.piece_length
+        {
+            return Ok(());
+        }
+
+        let mut used = Vec::new();
+        for (piece, entry) in bytes[8..self.header_len() as usize].chunks(4).enumerate() {
+            let slot = u32::from_be_bytes(entry.try_into()?);
+            if slot != UNUSED_SLOT {
+                self.slots.insert(piece, slot);
+                used.push(slot);
+            }
+        }
+        used.sort_unstable();
+        self.num_slots = used.last().map_or(0, |last| last + 1);
+        self.free_slots = (0..self.num_slots)
+            .filter(|slot| used.binary_search(slot).is_err())
+            .collect();
+
+        Ok(())
+    }
+
+    async fn write_header(&self, file: &mut File) -> Result<()> {
+        let mut header = Vec::with_capacity(self.header_len() as usize);
+        header.extend_from_slice(&(self.num_pieces as u32).to_be_bytes());
+        header.extend_from_slice(&(self.piece_length as u32).to_be_bytes());
+        for piece in 0..self.num_pieces {
+            let slot = self.slots.get(&piece).copied().unwrap_or(UNUSED_SLOT);
+            header.extend_from_slice(&slot.to_be_bytes());
+        }
+        file.seek(SeekFrom::Start(0)).await?;
+        file.write_all(&header).await?;
+        Ok(())
+    }
+
+    pub async fn has_piece(&mut self, piece: usize) -> Result<bool> {
+        self.load().await?;
+        Ok(self.slots.contains_key(&piece))
+    }
+
+    pub async fn pieces(&mut self) -> Result<Vec<usize>> {
+        self.load().await?;
+        Ok(self.slots.keys().copied().collect())
+    }
+
+    pub async fn is_empty(&mut self) -> Result<bool> {
+        self.load().await?;
+        Ok(self.slots.is_empty())
+    }
+
+    pub async fn write(&mut self, piece: usize, offset: u64, data: &[u8]) -> Result<()> {
+        self.load().await?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)
+            .await?;
+
+        let slot = match self.slots.get(&piece) {
+            Some(slot) => *slot,
+            None => {
+                let slot = self.free_slots.pop().unwrap_or_else(|| {
+                    self.num_slots += 1;
+                    self.num_slots - 1
+                });
+                self.slots.insert(piece, slot);
+                self.write_header(&mut file).await?;
+                slot
+            }
+        };
+
+        file.seek(SeekFrom::Start(self.slot_offset(slot) + offset))
+            .await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Returns `None` if the piece has never been written to the part file.
+    pub async fn read(
+        &mut self,
+        piece: usize,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        self.load().await?;
+
+        let Some(slot) = self.slots.get(&piece).copied() else {
+            return Ok(None);
+        };
+        let mut file = File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(self.slot_offset(slot) + offset))
+            .await?;
+
+        let mut buf = vec![0u8; length as usize];
+        let mut filled = 0;
+        while filled < buf.len() {
+            match file.read(&mut buf[filled..]).await? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(Some(buf))
+    }
+
+    /// Frees the slot of the piece, the file is deleted once it holds nothing.
+    pub async fn remove(&mut self, piece: usize) -> Result<()> {
+        self.load().await?;
+
+        let Some(slot) = self.slots.remove(&piece) else {
+            return Ok(());
+        };
+        if self.slots.is_empty() {
+            self.free_slots.clear();
+            self.num_slots = 0;
+            return match fs::remove_file(&self.path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        self.free_slots.push(slot);
+
+        let mut file = OpenOptions::new().write(true).open(&self.path).await?;
+        self.write_header(&mut file).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
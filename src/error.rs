@@ -35,6 +35,9 @@ pub enum Error {
     #[error("AnnounceBuilderError: {0:?}")]
     AnnounceBuilder(String),
 
+    #[error("TorrentBuilderError: {0:?}")]
+    TorrentBuilder(String),
+
     #[error("TrackerFailureReason: {0:?}")]
     TrackerFailureReason(String),
 
@@ -44,9 +47,45 @@ pub enum Error {
     #[error("ParseTorrentSourceError: {0:?}")]
     ParseTorrentSource(String),
 
+    #[error("UnsafePathError: {0:?}")]
+    UnsafePath(std::path::PathBuf),
+
+    #[error("ParseLsdAnnounceError: {0:?}")]
+    ParseLsdAnnounce(String),
+
     #[error("InvalidBep15Response: {0:?}")]
     InvalidBep15Response(String),
 
+    #[error("WebSeedError: HTTP status {status}")]
+    WebSeedStatus {
+        status: u16,
+        retry_after: Option<std::time::Duration>,
+    },
+
+    #[error("WebSeedError: {0:?}")]
+    WebSeed(String),
+
+    #[error("WebSeedError: the server ignores range requests")]
+    WebSeedRangesIgnored,
+
+    #[error("MseHandshakeError: {0:?}")]
+    MseHandshake(String),
+
+    #[error("ProxyError: {0:?}")]
+    Proxy(String),
+
+    #[error("PeerBlocked: {0}")]
+    PeerBlocked(std::net::SocketAddr),
+
+    #[error("PeerWireError: {0:?}")]
+    PeerWire(String),
+
+    #[error("PortMappingError: {0:?}")]
+    PortMapping(String),
+
+    #[error("InvalidUtpPacket: {0:?}")]
+    InvalidUtpPacket(String),
+
     #[error("InvalidKrpcDhtTransactionID: type incompatibility")]
     InvalidKrpcDhtTransactionID,
 
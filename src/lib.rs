@@ -2,6 +2,7 @@ pub mod disk;
 pub mod error;
 pub mod peers;
 pub mod proto;
+pub mod proxy;
 pub mod session;
 pub mod torrent;
 pub mod util;
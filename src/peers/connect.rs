@@ -0,0 +1,119 @@
+use crate::{
+    error::{Error, Result},
+    peers::{mse_connect, EncryptionPolicy, MseStream},
+    proto::{bep10::ExtendedHandshake, BitField, Handshake},
+    proxy::Proxy,
+    util::RateMeter,
+};
+use std::{fmt, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(7);
+const MSE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Transport of a peer connection, TCP or uTP.
+pub trait PeerIo: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> PeerIo for T {}
+
+/// Peer connection past the BitTorrent handshake.
+pub struct PeerConnection {
+    pub addr: SocketAddr,
+    /// Handshake the peer sent.
+    pub handshake: Handshake,
+    /// Extension handshake the peer sent (BEP 10).
+    pub extended: Option<ExtendedHandshake>,
+    pub stream: Box<dyn PeerIo>,
+}
+
+impl PeerConnection {
+    /// Splits the stream off the description of the peer.
+    pub fn into_parts(self) -> (ConnectedPeer, Box<dyn PeerIo>) {
+        let peer = ConnectedPeer {
+            addr: self.addr,
+            handshake: self.handshake,
+            extended: self.extended,
+            bitfield: BitField::new(0),
+            choked: true,
+            download_rate: RateMeter::new(),
+        };
+        (peer, self.stream)
+    }
+}
+
+/// A peer connection whose stream is owned by the task serving it.
+#[derive(Debug, Clone)]
+pub struct ConnectedPeer {
+    pub addr: SocketAddr,
+    pub handshake: Handshake,
+    pub extended: Option<ExtendedHandshake>,
+    /// Pieces the peer has announced so far.
+    pub bitfield: BitField,
+    /// Whether the peer chokes us.
+    pub choked: bool,
+    /// Blocks received from the peer.
+    pub download_rate: RateMeter,
+}
+
+impl fmt::Debug for PeerConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerConnection")
+            .field("addr", &self.addr)
+            .field("handshake", &self.handshake)
+            .field("extended", &self.extended)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Opens a TCP connection to a peer of the torrent `info_hash` following the
+/// encryption policy. With `Preferred` a failed MSE handshake is retried on a
+/// new plaintext connection.
+pub async fn connect_peer(
+    addr: SocketAddr,
+    info_hash: &[u8; 20],
+    policy: EncryptionPolicy,
+) -> Result<MseStream<TcpStream>> {
+    connect_peer_via(None, addr, info_hash, policy).await
+}
+
+/// `connect_peer` tunnelled through `proxy` when set.
+pub async fn connect_peer_via(
+    proxy: Option<&Proxy>,
+    addr: SocketAddr,
+    info_hash: &[u8; 20],
+    policy: EncryptionPolicy,
+) -> Result<MseStream<TcpStream>> {
+    let stream = open_stream(proxy, addr).await?;
+    if policy == EncryptionPolicy::Disabled {
+        return Ok(MseStream::plaintext(stream, Vec::new()));
+    }
+    let encrypted = timeout(
+        MSE_HANDSHAKE_TIMEOUT,
+        mse_connect(stream, info_hash, policy),
+    )
+    .await
+    .map_err(Error::from)
+    .and_then(|result| result);
+    match (encrypted, policy) {
+        (Ok(stream), _) => Ok(stream),
+        (Err(_), EncryptionPolicy::Preferred) => {
+            let stream = open_stream(proxy, addr).await?;
+            Ok(MseStream::plaintext(stream, Vec::new()))
+        }
+        (Err(err), _) => Err(err),
+    }
+}
+
+async fn open_stream(proxy: Option<&Proxy>, addr: SocketAddr) -> Result<TcpStream> {
+    let connect = async {
+        match proxy {
+            Some(proxy) => proxy.connect(&addr.ip().to_string(), addr.port()).await,
+            None => Ok(TcpStream::connect(addr).await?),
+        }
+    };
+    timeout(CONNECT_TIMEOUT, connect).await?
+}
@@ -0,0 +1,140 @@
+use crate::error::Result;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+};
+
+/// eMule access levels below this block the range.
+const EMULE_BLOCK_LEVEL: u32 = 128;
+
+/// Blocked address ranges, kept sorted and merged so a lookup is a binary
+/// search.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    v4: Vec<(u32, u32)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads an eMule `ipfilter.dat` or a PeerGuardian P2P text list, the
+    /// format is detected per line and unparsable lines are skipped.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Ok(Self::parse(&String::from_utf8_lossy(&bytes)))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut filter = Self::new();
+        for line in text.lines() {
+            if let Some((first, last)) = parse_line(line) {
+                filter.push(first, last);
+            }
+        }
+        filter.normalize();
+        filter
+    }
+
+    /// Ranges across address families are ignored.
+    pub fn add_range(&mut self, first: IpAddr, last: IpAddr) {
+        self.push(first, last);
+        self.normalize();
+    }
+
+    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => contains(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => contains(&self.v6, u128::from(ip)),
+        }
+    }
+
+    #[inline]
+    pub fn num_ranges(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.num_ranges() == 0
+    }
+
+    fn push(&mut self, first: IpAddr, last: IpAddr) {
+        match (first.to_canonical(), last.to_canonical()) {
+            (IpAddr::V4(first), IpAddr::V4(last)) => {
+                let (first, last) = (u32::from(first), u32::from(last));
+                self.v4.push((first.min(last), first.max(last)));
+            }
+            (IpAddr::V6(first), IpAddr::V6(last)) => {
+                let (first, last) = (u128::from(first), u128::from(last));
+                self.v6.push((first.min(last), first.max(last)));
+            }
+            _ => {}
+        }
+    }
+
+    fn normalize(&mut self) {
+        merge(&mut self.v4, |ip| ip.checked_add(1));
+        merge(&mut self.v6, |ip| ip.checked_add(1));
+    }
+}
+
+/// Sorts the ranges and merges overlapping or adjacent ones.
+fn merge<T: Ord + Copy>(ranges: &mut Vec<(T, T)>, next: fn(T) -> Option<T>) {
+    ranges.sort_unstable();
+    let mut merged: Vec<(T, T)> = Vec::with_capacity(ranges.len());
+    for &(first, last) in ranges.iter() {
+        match merged.last_mut() {
+            Some(prev) if next(prev.1).is_none_or(|after| first <= after) => {
+                prev.1 = prev.1.max(last);
+            }
+            _ => merged.push((first, last)),
+        }
+    }
+    *ranges = merged;
+}
+
+fn contains<T: Ord + Copy>(ranges: &[(T, T)], ip: T) -> bool {
+    let i = ranges.partition_point(|&(first, _)| first <= ip);
+    i > 0 && ranges[i - 1].1 >= ip
+}
+
+/// eMule: `001.002.003.000 - 001.002.003.255 , 000 , description`
+/// P2P: `description:1.2.3.0-1.2.3.255`, the description may contain commas.
+fn parse_line(line: &str) -> Option<(IpAddr, IpAddr)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+        return None;
+    }
+    if let Some((range, rest)) = line.split_once(',') {
+        if let Some(range) = parse_range(range) {
+            let level = rest.split(',').next()?.trim().parse::<u32>().ok()?;
+            return (level < EMULE_BLOCK_LEVEL).then_some(range);
+        }
+    }
+    parse_range(line).or_else(|| parse_range(line.rsplit_once(':')?.1))
+}
+
+fn parse_range(range: &str) -> Option<(IpAddr, IpAddr)> {
+    let (first, last) = range.split_once('-')?;
+    Some((parse_ip(first)?, parse_ip(last)?))
+}
+
+/// eMule lists pad IPv4 octets with zeros, which `Ipv4Addr` rejects.
+fn parse_ip(ip: &str) -> Option<IpAddr> {
+    let ip = ip.trim();
+    if ip.contains(':') {
+        return ip.parse().ok();
+    }
+    let mut octets = [0u8; 4];
+    let mut parts = ip.split('.');
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Ipv4Addr::from(octets).into()),
+    }
+}
@@ -1,5 +1,13 @@
 mod client;
 mod conn;
+mod connect;
+mod ip_filter;
+mod mse;
 mod peer;
 mod util;
 mod wire;
+
+pub use connect::*;
+pub use ip_filter::*;
+pub use mse::*;
+pub use util::*;
@@ -0,0 +1,108 @@
+use crate::{
+    error::{Error, Result},
+    proto::constants::{
+        MSE_GENERATOR, MSE_KEY_SIZE, MSE_PRIME_HEX, MSE_PRIVATE_KEY_SIZE, MSE_RC4_DISCARD,
+    },
+};
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+use std::sync::LazyLock;
+
+static PRIME: LazyLock<BigUint> =
+    LazyLock::new(|| BigUint::parse_bytes(MSE_PRIME_HEX, 16).unwrap());
+
+/// RC4 with the first kilobyte of keystream dropped, as MSE requires.
+#[derive(Clone)]
+pub struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    pub fn new(key: &[u8]) -> Self {
+        let mut state = [0u8; 256];
+        for (i, byte) in state.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+        let mut rc4 = Self { state, i: 0, j: 0 };
+        rc4.apply(&mut [0u8; MSE_RC4_DISCARD]);
+        rc4
+    }
+
+    /// Encrypts or decrypts `data` in place.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let k = self.state[self.i as usize].wrapping_add(self.state[self.j as usize]);
+            *byte ^= self.state[k as usize];
+        }
+    }
+}
+
+/// SHA-1 of the concatenated parts, the `HASH()` of the MSE spec.
+pub fn mse_hash(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Ciphers for sending and receiving, keyed with the shared secret and the
+/// info hash (`SKEY`).
+pub fn mse_ciphers(secret: &[u8; MSE_KEY_SIZE], skey: &[u8; 20], initiator: bool) -> (Rc4, Rc4) {
+    let key_a = Rc4::new(&mse_hash(&[b"keyA", secret, skey]));
+    let key_b = Rc4::new(&mse_hash(&[b"keyB", secret, skey]));
+    match initiator {
+        true => (key_a, key_b),
+        false => (key_b, key_a),
+    }
+}
+
+/// Diffie-Hellman keys over the 768 bit MSE prime.
+pub struct DhKeyPair {
+    private: BigUint,
+    public: [u8; MSE_KEY_SIZE],
+}
+
+impl DhKeyPair {
+    pub fn generate() -> Self {
+        let private = BigUint::from_bytes_be(&rand::random::<[u8; MSE_PRIVATE_KEY_SIZE]>());
+        let public = BigUint::from(MSE_GENERATOR).modpow(&private, &PRIME);
+        Self {
+            private,
+            public: to_key_bytes(&public),
+        }
+    }
+
+    #[inline]
+    pub fn public_key(&self) -> &[u8; MSE_KEY_SIZE] {
+        &self.public
+    }
+
+    /// `S`, rejects the trivial keys 0, 1 and `P - 1`.
+    pub fn shared_secret(&self, remote: &[u8]) -> Result<[u8; MSE_KEY_SIZE]> {
+        let remote = BigUint::from_bytes_be(remote);
+        let one = BigUint::from(1u8);
+        if remote <= one || remote >= &*PRIME - &one {
+            return Err(Error::MseHandshake("invalid public key".into()));
+        }
+        Ok(to_key_bytes(&remote.modpow(&self.private, &PRIME)))
+    }
+}
+
+/// Big-endian, left-padded with zeros to the key size.
+fn to_key_bytes(n: &BigUint) -> [u8; MSE_KEY_SIZE] {
+    let bytes = n.to_bytes_be();
+    let mut key = [0u8; MSE_KEY_SIZE];
+    key[MSE_KEY_SIZE - bytes.len()..].copy_from_slice(&bytes);
+    key
+}
@@ -0,0 +1,245 @@
+/// <https://wiki.vuze.com/w/Message_Stream_Encryption>
+use crate::{
+    error::{Error, Result},
+    peers::{mse_ciphers, mse_hash, DhKeyPair, MseStream, Rc4},
+    proto::constants::{
+        MSE_CRYPTO_PLAINTEXT, MSE_CRYPTO_RC4, MSE_KEY_SIZE, MSE_MAX_PAD_LEN, MSE_VC,
+    },
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionPolicy {
+    /// Only RC4 encrypted connections, both ways.
+    Forced,
+    /// Outgoing connections try MSE first and reconnect in plaintext if it
+    /// fails, incoming ones may use either.
+    #[default]
+    Preferred,
+    /// Plaintext only, MSE handshakes are refused.
+    Disabled,
+}
+
+impl EncryptionPolicy {
+    /// `crypto_provide` bits, `None` when MSE isn't used at all.
+    pub fn crypto_provide(self) -> Option<u32> {
+        match self {
+            Self::Forced => Some(MSE_CRYPTO_RC4),
+            Self::Preferred => Some(MSE_CRYPTO_RC4 | MSE_CRYPTO_PLAINTEXT),
+            Self::Disabled => None,
+        }
+    }
+
+    /// Picks RC4 over plaintext among the offered methods.
+    pub fn crypto_select(self, crypto_provide: u32) -> Option<u32> {
+        let allowed = self.crypto_provide()? & crypto_provide;
+        [MSE_CRYPTO_RC4, MSE_CRYPTO_PLAINTEXT]
+            .into_iter()
+            .find(|method| allowed & method != 0)
+    }
+}
+
+/// Handshake bytes read ahead, the peer's padding has no length so the next
+/// field is found by scanning for it.
+#[derive(Default)]
+struct HandshakeReader {
+    buf: Vec<u8>,
+}
+
+impl HandshakeReader {
+    async fn fill<S: AsyncRead + Unpin>(&mut self, stream: &mut S) -> Result<()> {
+        let mut chunk = [0u8; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(Error::MseHandshake("connection closed".into()));
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(())
+    }
+
+    async fn take<S: AsyncRead + Unpin>(&mut self, stream: &mut S, len: usize) -> Result<Vec<u8>> {
+        while self.buf.len() < len {
+            self.fill(stream).await?;
+        }
+        Ok(self.buf.drain(..len).collect())
+    }
+
+    /// Skips up to `max_skip` bytes of padding followed by `pattern`.
+    async fn sync<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        pattern: &[u8],
+        max_skip: usize,
+    ) -> Result<()> {
+        loop {
+            if let Some(pos) = self
+                .buf
+                .windows(pattern.len())
+                .position(|window| window == pattern)
+            {
+                self.buf.drain(..pos + pattern.len());
+                return Ok(());
+            }
+            if self.buf.len() >= max_skip + pattern.len() {
+                return Err(Error::MseHandshake("synchronization failed".into()));
+            }
+            self.fill(stream).await?;
+        }
+    }
+}
+
+fn random_pad() -> Vec<u8> {
+    let len = rand::random_range(0..=MSE_MAX_PAD_LEN);
+    (0..len).map(|_| rand::random()).collect()
+}
+
+fn xor(a: [u8; 20], b: [u8; 20]) -> [u8; 20] {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+fn read_u16(bytes: &[u8]) -> usize {
+    u16::from_be_bytes([bytes[0], bytes[1]]) as usize
+}
+
+/// Initiates MSE for the torrent `skey`, the info hash the peer knows it by.
+pub async fn mse_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    skey: &[u8; 20],
+    policy: EncryptionPolicy,
+) -> Result<MseStream<S>> {
+    let crypto_provide = policy
+        .crypto_provide()
+        .ok_or_else(|| Error::MseHandshake("encryption disabled".into()))?;
+    let keys = DhKeyPair::generate();
+    let mut msg = keys.public_key().to_vec();
+    msg.extend(random_pad());
+    stream.write_all(&msg).await?;
+
+    let mut reader = HandshakeReader::default();
+    let secret = keys.shared_secret(&reader.take(&mut stream, MSE_KEY_SIZE).await?)?;
+    let (mut encrypt, mut decrypt) = mse_ciphers(&secret, skey, true);
+
+    let mut msg = mse_hash(&[b"req1", &secret]).to_vec();
+    msg.extend(xor(
+        mse_hash(&[b"req2", skey]),
+        mse_hash(&[b"req3", &secret]),
+    ));
+    let mut encrypted = MSE_VC.to_vec();
+    encrypted.extend(crypto_provide.to_be_bytes());
+    // no padding and no initial payload
+    encrypted.extend(0u16.to_be_bytes());
+    encrypted.extend(0u16.to_be_bytes());
+    encrypt.apply(&mut encrypted);
+    msg.extend(encrypted);
+    stream.write_all(&msg).await?;
+
+    // the peer's VC is the next 8 bytes of its keystream, after its padding
+    let mut vc = MSE_VC;
+    decrypt.apply(&mut vc);
+    reader.sync(&mut stream, &vc, MSE_MAX_PAD_LEN).await?;
+    let mut head = reader.take(&mut stream, 6).await?;
+    decrypt.apply(&mut head);
+    let crypto_select = u32::from_be_bytes(head[..4].try_into().unwrap());
+    let pad_len = read_u16(&head[4..]);
+    if pad_len > MSE_MAX_PAD_LEN {
+        return Err(Error::MseHandshake(format!("padding of {pad_len} bytes")));
+    }
+    decrypt.apply(&mut reader.take(&mut stream, pad_len).await?);
+    if crypto_select.count_ones() != 1 || crypto_select & crypto_provide == 0 {
+        return Err(Error::MseHandshake(format!(
+            "peer selected unoffered method {crypto_select:#x}"
+        )));
+    }
+
+    Ok(finish(
+        stream,
+        encrypt,
+        decrypt,
+        crypto_select,
+        Vec::new(),
+        reader.buf,
+    ))
+}
+
+/// Answers an MSE handshake whose first bytes were already read. The peer's
+/// `SKEY` has to be one of `skeys`, it is returned with the stream.
+pub async fn mse_accept<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    read_ahead: Vec<u8>,
+    skeys: &[[u8; 20]],
+    policy: EncryptionPolicy,
+) -> Result<(MseStream<S>, [u8; 20])> {
+    let mut reader = HandshakeReader { buf: read_ahead };
+    let remote = reader.take(&mut stream, MSE_KEY_SIZE).await?;
+    let keys = DhKeyPair::generate();
+    let secret = keys.shared_secret(&remote)?;
+    let mut msg = keys.public_key().to_vec();
+    msg.extend(random_pad());
+    stream.write_all(&msg).await?;
+
+    let req1 = mse_hash(&[b"req1", &secret]);
+    reader.sync(&mut stream, &req1, MSE_MAX_PAD_LEN).await?;
+    let obfuscated: [u8; 20] = reader.take(&mut stream, 20).await?.try_into().unwrap();
+    let req3 = mse_hash(&[b"req3", &secret]);
+    let skey = *skeys
+        .iter()
+        .find(|skey| xor(mse_hash(&[b"req2", *skey]), req3) == obfuscated)
+        .ok_or_else(|| Error::MseHandshake("unknown info hash".into()))?;
+    let (mut encrypt, mut decrypt) = mse_ciphers(&secret, &skey, false);
+
+    let mut head = reader.take(&mut stream, 14).await?;
+    decrypt.apply(&mut head);
+    if head[..8] != MSE_VC {
+        return Err(Error::MseHandshake("invalid verification constant".into()));
+    }
+    let crypto_provide = u32::from_be_bytes(head[8..12].try_into().unwrap());
+    let pad_len = read_u16(&head[12..]);
+    if pad_len > MSE_MAX_PAD_LEN {
+        return Err(Error::MseHandshake(format!("padding of {pad_len} bytes")));
+    }
+    let mut rest = reader.take(&mut stream, pad_len + 2).await?;
+    decrypt.apply(&mut rest);
+    let mut initial_payload = reader.take(&mut stream, read_u16(&rest[pad_len..])).await?;
+    decrypt.apply(&mut initial_payload);
+
+    let crypto_select = policy.crypto_select(crypto_provide).ok_or_else(|| {
+        Error::MseHandshake(format!("no acceptable method in {crypto_provide:#x}"))
+    })?;
+    let mut msg = MSE_VC.to_vec();
+    msg.extend(crypto_select.to_be_bytes());
+    msg.extend(0u16.to_be_bytes());
+    encrypt.apply(&mut msg);
+    stream.write_all(&msg).await?;
+
+    let stream = finish(
+        stream,
+        encrypt,
+        decrypt,
+        crypto_select,
+        initial_payload,
+        reader.buf,
+    );
+    Ok((stream, skey))
+}
+
+/// Bytes read past the handshake belong to the payload stream.
+fn finish<S>(
+    stream: S,
+    encrypt: Rc4,
+    mut decrypt: Rc4,
+    crypto_select: u32,
+    mut read_ahead: Vec<u8>,
+    mut rest: Vec<u8>,
+) -> MseStream<S> {
+    match crypto_select {
+        MSE_CRYPTO_RC4 => {
+            decrypt.apply(&mut rest);
+            read_ahead.extend(rest);
+            MseStream::new(stream, Some((encrypt, decrypt)), read_ahead)
+        }
+        _ => {
+            read_ahead.extend(rest);
+            MseStream::new(stream, None, read_ahead)
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod crypto;
+mod handshake;
+mod stream;
+
+pub use crypto::*;
+pub use handshake::*;
+pub use stream::*;
@@ -0,0 +1,124 @@
+use crate::peers::Rc4;
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Peer connection after the MSE handshake, RC4 encrypted or plaintext.
+pub struct MseStream<S> {
+    inner: S,
+    /// Encrypt and decrypt ciphers, `None` for plaintext.
+    ciphers: Option<(Rc4, Rc4)>,
+    /// Plaintext read ahead during the handshake, handed out first.
+    read_ahead: Vec<u8>,
+    read_pos: usize,
+    /// Encrypted bytes accepted by `poll_write` but not yet written.
+    write_buf: Vec<u8>,
+    write_pos: usize,
+}
+
+impl<S> MseStream<S> {
+    pub(crate) fn new(inner: S, ciphers: Option<(Rc4, Rc4)>, read_ahead: Vec<u8>) -> Self {
+        Self {
+            inner,
+            ciphers,
+            read_ahead,
+            read_pos: 0,
+            write_buf: Vec::new(),
+            write_pos: 0,
+        }
+    }
+
+    /// A plaintext connection whose first bytes were already read.
+    pub fn plaintext(inner: S, read_ahead: Vec<u8>) -> Self {
+        Self::new(inner, None, read_ahead)
+    }
+
+    #[inline]
+    pub fn is_encrypted(&self) -> bool {
+        self.ciphers.is_some()
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncWrite + Unpin> MseStream<S> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.write_buf[self.write_pos..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += n;
+        }
+        self.write_buf.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.read_pos < this.read_ahead.len() {
+            let n = buf.remaining().min(this.read_ahead.len() - this.read_pos);
+            buf.put_slice(&this.read_ahead[this.read_pos..this.read_pos + n]);
+            this.read_pos += n;
+            if this.read_pos == this.read_ahead.len() {
+                this.read_ahead = Vec::new();
+                this.read_pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some((_, decrypt)) = this.ciphers.as_mut() {
+            decrypt.apply(&mut buf.filled_mut()[filled..]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        let Some((encrypt, _)) = this.ciphers.as_mut() else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        // once encrypted the bytes must go out, whatever the inner stream takes now
+        this.write_buf.extend_from_slice(buf);
+        encrypt.apply(&mut this.write_buf);
+        if let Poll::Ready(Err(err)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
@@ -1,7 +1,47 @@
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+pub const COMPACT_PEER_V4_SIZE: usize = 6;
+pub const COMPACT_PEER_V6_SIZE: usize = 18;
 
 pub fn socket_addr_from_bytes(bytes: &[u8; 6]) -> SocketAddrV4 {
     let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
     let port = ((bytes[4] as u16) << 8) | (bytes[5] as u16);
     SocketAddrV4::new(ip, port)
 }
+
+pub fn socket_addr_v6_from_bytes(bytes: &[u8; 18]) -> SocketAddrV6 {
+    let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[..16]).unwrap());
+    let port = u16::from_be_bytes([bytes[16], bytes[17]]);
+    SocketAddrV6::new(ip, port, 0, 0)
+}
+
+/// 6 bytes for IPv4, 18 bytes for IPv6 peers.
+pub fn socket_addr_from_compact(bytes: &[u8]) -> Option<SocketAddr> {
+    match bytes.len() {
+        COMPACT_PEER_V4_SIZE => Some(socket_addr_from_bytes(bytes.try_into().ok()?).into()),
+        COMPACT_PEER_V6_SIZE => Some(socket_addr_v6_from_bytes(bytes.try_into().ok()?).into()),
+        _ => None,
+    }
+}
+
+/// Concatenated compact entries of one family (BEP 23, BEP 7), a trailing
+/// partial entry is ignored.
+pub fn parse_compact_peers(bytes: &[u8], ipv6: bool) -> Vec<SocketAddr> {
+    let size = match ipv6 {
+        true => COMPACT_PEER_V6_SIZE,
+        false => COMPACT_PEER_V4_SIZE,
+    };
+    bytes
+        .chunks_exact(size)
+        .filter_map(socket_addr_from_compact)
+        .collect()
+}
+
+pub fn compact_peer_bytes(addr: &SocketAddr) -> Vec<u8> {
+    let mut bytes = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.extend(addr.port().to_be_bytes());
+    bytes
+}
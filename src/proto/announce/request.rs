@@ -61,7 +61,7 @@ impl<'a> AnnounceRequestParams<'a> {
             .ip
             .as_ref()
             .and_then(|s| s.parse::<std::net::Ipv4Addr>().ok())
-            .map(|ip| u32::from(ip))
+            .map(u32::from)
             .unwrap_or(0);
         buf[offset..offset + 4].copy_from_slice(&ip.to_be_bytes());
         offset += 4;
@@ -1,7 +1,11 @@
 /// https://wiki.theory.org/BitTorrentSpecification#Tracker_Response
 /// https://bittorrent.org/beps/bep_0015.html#announce
-use crate::error::{Error, Result};
+use crate::{
+    error::{Error, Result},
+    peers::parse_compact_peers,
+};
 use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
 
 type BencodeValue = serde_bencode::value::Value;
 
@@ -22,12 +26,27 @@ pub enum Peers {
 }
 
 impl Peers {
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         match self {
             Self::BinaryModel(v) => v.len(),
             Self::DictModel(d) => d.len(),
         }
     }
+
+    /// Dictionary peers may be IPv4 or IPv6, hostnames are skipped.
+    pub fn to_socket_addrs(&self) -> Vec<SocketAddr> {
+        match self {
+            Self::BinaryModel(bytes) => parse_compact_peers(bytes, false),
+            Self::DictModel(dicts) => dicts
+                .iter()
+                .filter_map(|peer| {
+                    let ip = peer.ip.parse::<IpAddr>().ok()?;
+                    Some(SocketAddr::new(ip, peer.port))
+                })
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -59,6 +78,10 @@ pub struct HttpAnnounceResponse {
 
     #[serde(skip)]
     peers: Option<Peers>,
+
+    /// BEP 7 compact IPv6 peers, 18 bytes each.
+    #[serde(default, with = "serde_bytes")]
+    peers6: Option<Vec<u8>>,
 }
 
 impl HttpAnnounceResponse {
@@ -94,4 +117,22 @@ impl HttpAnnounceResponse {
     pub fn take_peers(&mut self) -> Option<Peers> {
         self.peers.take()
     }
+
+    pub fn peers6(&self) -> Vec<SocketAddr> {
+        self.peers6
+            .as_deref()
+            .map(|bytes| parse_compact_peers(bytes, true))
+            .unwrap_or_default()
+    }
+
+    /// `peers` and `peers6` together.
+    pub fn peer_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs = self
+            .peers
+            .as_ref()
+            .map(Peers::to_socket_addrs)
+            .unwrap_or_default();
+        addrs.extend(self.peers6());
+        addrs
+    }
 }
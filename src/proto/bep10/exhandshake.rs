@@ -1,36 +1,92 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
 
 /// Структура extended handshake-сообщения (обогащённая)
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ExtendedHandshake {
     /// "m": карта поддерживаемых расширений
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub m: Option<HashMap<String, u8>>,
 
     /// "v": строка версии клиента
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub v: Option<String>,
 
     /// "metadata_size": если используется ut_metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub metadata_size: Option<u32>,
 
     /// "reqq": максимальное количество параллельных metadata-запросов
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub reqq: Option<u32>,
 
     /// "yourip": IP-адрес отправителя (массив байтов)
-    #[serde(default, with = "serde_bytes")]
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
     pub yourip: Option<Vec<u8>>,
 
     /// "ipv4": публичный IPv4 (опционально, по BEP 7)
-    #[serde(default, with = "serde_bytes")]
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
     pub ipv4: Option<Vec<u8>>,
 
     /// "ipv6": публичный IPv6 (опционально, по BEP 7)
-    #[serde(default, with = "serde_bytes")]
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
     pub ipv6: Option<Vec<u8>>,
 
     /// "p": порт, на котором слушает пир (по BEP 5/7)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub p: Option<u16>,
     // Дополнительные произвольные поля (всё, что не указано явно)
     // #[serde(flatten)]
     // pub extra: HashMap<String, Value>,
 }
+
+impl ExtendedHandshake {
+    /// Our handshake to a peer at `peer_ip`, `alternate_ip` is our address on the
+    /// other address family (BEP 7).
+    pub fn local(port: u16, peer_ip: IpAddr, alternate_ip: Option<IpAddr>) -> Self {
+        let mut handshake = Self {
+            m: Some(HashMap::new()),
+            yourip: Some(ip_octets(peer_ip)),
+            p: Some(port),
+            ..Default::default()
+        };
+        match alternate_ip {
+            Some(ip @ IpAddr::V4(_)) => handshake.ipv4 = Some(ip_octets(ip)),
+            Some(ip @ IpAddr::V6(_)) => handshake.ipv6 = Some(ip_octets(ip)),
+            None => {}
+        }
+        handshake
+    }
+
+    pub fn ipv4_addr(&self) -> Option<Ipv4Addr> {
+        let octets: [u8; 4] = self.ipv4.as_deref()?.try_into().ok()?;
+        Some(Ipv4Addr::from(octets))
+    }
+
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        let octets: [u8; 16] = self.ipv6.as_deref()?.try_into().ok()?;
+        Some(Ipv6Addr::from(octets))
+    }
+
+    /// Address the peer advertised on the other address family than the one it
+    /// is connected from (BEP 7), on its listen port `p`.
+    pub fn alternate_addr(&self, peer_addr: SocketAddr) -> Option<SocketAddr> {
+        let port = self.p.unwrap_or(peer_addr.port());
+        let ip = match peer_addr.ip() {
+            IpAddr::V4(_) => self.ipv6_addr().map(IpAddr::V6),
+            IpAddr::V6(_) => self.ipv4_addr().map(IpAddr::V4),
+        };
+        ip.filter(|ip| !ip.is_unspecified() && port != 0)
+            .map(|ip| SocketAddr::new(ip, port))
+    }
+}
+
+fn ip_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
@@ -1,4 +1,8 @@
 use super::ExtendedHandshake;
+use crate::proto::constants::EXTENSION_MSG_ID;
+
+/// Extended message id of the handshake (BEP 10).
+const HANDSHAKE_ID: u8 = 0;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ExtensionMessage {
@@ -7,18 +11,29 @@ pub enum ExtensionMessage {
 }
 
 impl ExtensionMessage {
-    pub fn from_bytes(_bytes: &[u8]) -> Self {
-        Self::Empty
+    /// `bytes` is the whole message with its length prefix.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match bytes.get(5) {
+            Some(&HANDSHAKE_ID) => serde_bencode::from_bytes(&bytes[6..])
+                .map(Self::Handshake)
+                .unwrap_or(Self::Empty),
+            _ => Self::Empty,
+        }
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        vec![]
+        let Self::Handshake(handshake) = self else {
+            return vec![];
+        };
+        let payload = serde_bencode::to_bytes(handshake).unwrap_or_default();
+        let mut bytes = (2 + payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend([EXTENSION_MSG_ID, HANDSHAKE_ID]);
+        bytes.extend(payload);
+        bytes
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
-        match self {
-            Self::Empty => 0,
-            Self::Handshake(_) => 0,
-        }
+        self.to_bytes().len()
     }
 }
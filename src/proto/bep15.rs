@@ -2,10 +2,11 @@
 use super::constants::{BEP15_CONNECT_LEN, BEP15_MAGIC_CONSTANT};
 use crate::{
     error::{Error, Result},
-    proto::constants::BEP15_MIN_MSG_LEN,
+    peers::parse_compact_peers,
+    proto::constants::{BEP15_ANNOUNCE_RESPONSE_MIN_LEN, BEP15_MIN_MSG_LEN},
 };
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::SocketAddr,
     sync::atomic::{AtomicI32, Ordering},
 };
 
@@ -39,14 +40,15 @@ impl Bep15Response {
 }
 
 impl Bep15Response {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    /// Announce responses from a tracker reached over IPv6 carry 18 byte peers.
+    pub fn from_bytes(bytes: &[u8], ipv6: bool) -> Result<Self> {
         if bytes.len() < BEP15_MIN_MSG_LEN {
             return Result::Err(Error::InvalidBep15Response("Response too short".into()));
         }
         let action = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
         Ok(match action {
             0 => Self::Connect(Bep15ConnectResponse::from_bytes(bytes)?),
-            1 => Self::Announce(Bep15AnnounceResponse::from_bytes(bytes)?),
+            1 => Self::Announce(Bep15AnnounceResponse::from_bytes(bytes, ipv6)?),
             // 2 => Scrape,
             3 => {
                 let transaction_id =
@@ -72,6 +74,12 @@ pub struct Bep15ConnectRequest {
     pub transaction_id: Bep15TransactionID,
 }
 
+impl Default for Bep15ConnectRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Bep15ConnectRequest {
     pub fn new() -> Self {
         let transaction_id = rand::random();
@@ -131,8 +139,8 @@ pub struct Bep15AnnounceResponse {
 }
 
 impl Bep15AnnounceResponse {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < 20 {
+    pub fn from_bytes(bytes: &[u8], ipv6: bool) -> Result<Self> {
+        if bytes.len() < BEP15_ANNOUNCE_RESPONSE_MIN_LEN {
             return Result::Err(Error::InvalidBep15Response(
                 "Announce response too short".into(),
             ));
@@ -150,20 +158,7 @@ impl Bep15AnnounceResponse {
         let leechers = u32::from_be_bytes(bytes[12..16].try_into().unwrap());
         let seeders = u32::from_be_bytes(bytes[16..20].try_into().unwrap());
 
-        let mut peers = Vec::new();
-        let mut offset = 20;
-
-        while offset + 6 <= bytes.len() {
-            let ip = IpAddr::V4(Ipv4Addr::new(
-                bytes[offset],
-                bytes[offset + 1],
-                bytes[offset + 2],
-                bytes[offset + 3],
-            ));
-            let port = u16::from_be_bytes(bytes[offset + 4..offset + 6].try_into().unwrap());
-            peers.push(SocketAddr::new(ip, port));
-            offset += 6;
-        }
+        let peers = parse_compact_peers(&bytes[BEP15_ANNOUNCE_RESPONSE_MIN_LEN..], ipv6);
 
         Ok(Self {
             transaction_id,
@@ -19,10 +19,17 @@ impl DerefMut for BitField {
 
 impl BitField {
     pub fn new(num_pieces: usize) -> Self {
-        let num_bytes = (num_pieces + 7) / 8;
+        let num_bytes = num_pieces.div_ceil(8);
         Self(vec![0; num_bytes])
     }
 
+    /// Every piece is set, e.g. for seeds.
+    pub fn full(num_pieces: usize) -> Self {
+        let mut bitfield = Self::new(num_pieces);
+        (0..num_pieces).for_each(|index| bitfield.set(index));
+        bitfield
+    }
+
     pub fn set(&mut self, index: usize) {
         let byte_index = index / 8;
         let bit_offset = 7 - (index % 8);
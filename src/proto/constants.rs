@@ -1,3 +1,5 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
 pub const DEFAULT_PEER_FINGERPRINT: &[u8; 8] = b"-qB5050-";
 
 pub const INFO_HASH_V1_SIZE: usize = 20;
@@ -21,6 +23,9 @@ pub const PIECE_MSG_ID: u8 = 7;
 pub const CANCEL_MSG_ID: u8 = 8;
 pub const PORT_MSG_ID: u8 = 9;
 pub const EXTENSION_MSG_ID: u8 = 20;
+pub const HASH_REQUEST_MSG_ID: u8 = 21;
+pub const HASHES_MSG_ID: u8 = 22;
+pub const HASH_REJECT_MSG_ID: u8 = 23;
 
 pub const KEEP_ALIVE_MSG: [u8; 4] = [0, 0, 0, 0];
 pub const CHOKE_MSG: [u8; 5] = [0, 0, 0, 1, CHOKE_MSG_ID];
@@ -31,6 +36,7 @@ pub const NOT_INTERESTED_MSG: [u8; 5] = [0, 0, 0, 1, NOT_INTERESTED_MSG_ID];
 pub const HAVE_PAYLOAD_LEN: usize = 4;
 pub const REQUEST_PAYLOAD_LEN: usize = 12;
 pub const PORT_PAYLOAD_LEN: usize = 2;
+pub const HASH_REQUEST_PAYLOAD_LEN: usize = 48;
 
 pub const HAVE_MSG_HEADER: [u8; 5] = [0, 0, 0, 1 + HAVE_PAYLOAD_LEN as u8, HAVE_MSG_ID];
 pub const REQUEST_MSG_HEADER: [u8; 5] = [0, 0, 0, 1 + REQUEST_PAYLOAD_LEN as u8, REQUEST_MSG_ID];
@@ -39,10 +45,28 @@ pub const PORT_MSG_HEADER: [u8; 5] = [0, 0, 0, 1 + PORT_PAYLOAD_LEN as u8, PORT_
 pub const HANDSHAKE_SIZE: usize = 68;
 pub const HANDSHAKE_PSTR: [u8; 19] = *b"BitTorrent protocol";
 pub const HANDSHAKE_PREFIX: [u8; 5] = [19, 66, 105, 116, 84];
+/// Reserved byte and bit of the extension protocol (BEP 10).
+pub const EXTENSION_RESERVED_BYTE: usize = 25;
+pub const EXTENSION_RESERVED_BIT: u8 = 0x10;
+/// Reserved byte and bit of peers that speak the v2 protocol (BEP 52).
+pub const V2_RESERVED_BYTE: usize = 27;
+pub const V2_RESERVED_BIT: u8 = 0x10;
+
+/// <https://wiki.vuze.com/w/Message_Stream_Encryption>
+pub const MSE_PRIME_HEX: &[u8] = b"FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+pub const MSE_GENERATOR: u32 = 2;
+pub const MSE_KEY_SIZE: usize = 96;
+pub const MSE_PRIVATE_KEY_SIZE: usize = 20;
+pub const MSE_MAX_PAD_LEN: usize = 512;
+pub const MSE_RC4_DISCARD: usize = 1024;
+pub const MSE_VC: [u8; 8] = [0; 8];
+pub const MSE_CRYPTO_PLAINTEXT: u32 = 0x01;
+pub const MSE_CRYPTO_RC4: u32 = 0x02;
 
 pub const BEP15_MAGIC_CONSTANT: [u8; 8] = [0, 0, 4, 23, 39, 16, 25, 128];
 pub const BEP15_MIN_MSG_LEN: usize = 8;
 pub const BEP15_CONNECT_LEN: usize = 16;
+pub const BEP15_ANNOUNCE_RESPONSE_MIN_LEN: usize = 20;
 pub const BEP15_ANNOUNCE_REQUEST_LEN: usize = 98;
 
 pub const DHT_CLIENT_VERSION: &[u8; 4] = b"rT01";
@@ -60,3 +84,16 @@ pub const BOOTSTRAP_NODES: [&str; 7] = [
     "dht.libtorrent.org:6881",
     "dht.libtorrent.org:25401",
 ];
+
+pub const LSD_PORT: u16 = 6771;
+pub const LSD_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 192, 152, 143);
+pub const LSD_MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff15, 0, 0, 0, 0, 0, 0xefc0, 0x988f);
+/// Announces larger than this are split, BEP 14 recommends staying below the MTU.
+pub const LSD_MAX_ANNOUNCE_LEN: usize = 1400;
+
+/// PCP and NAT-PMP servers listen on the gateway at this port.
+pub const NATPMP_PORT: u16 = 5351;
+pub const NATPMP_VERSION: u8 = 0;
+pub const PCP_VERSION: u8 = 2;
+pub const SSDP_PORT: u16 = 1900;
+pub const SSDP_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
@@ -1,4 +1,5 @@
-use std::collections::BTreeMap;
+use crate::peers::{socket_addr_from_bytes, socket_addr_from_compact};
+use std::{collections::BTreeMap, net::SocketAddr};
 
 type BencodeValue = serde_bencode::value::Value;
 
@@ -14,7 +15,7 @@ pub enum ResponseArgs {
     GetPeersWithValues {
         id: Vec<u8>,
         token: Option<Vec<u8>>,
-        values: Vec<String>,
+        values: Vec<SocketAddr>,
     },
     GetPeersWithNodes {
         id: Vec<u8>,
@@ -74,12 +75,10 @@ impl ResponseArgs {
                             match map.remove("values") {
                                 Some(BencodeValue::List(l)) => l
                                     .into_iter()
-                                    .map(|v| match v {
-                                        BencodeValue::Bytes(b) => String::from_utf8(b).ok(),
+                                    .filter_map(|v| match v {
+                                        BencodeValue::Bytes(b) => socket_addr_from_compact(&b),
                                         _ => None,
                                     })
-                                    .filter(|v| v.is_some())
-                                    .map(|v| v.unwrap())
                                     .collect(),
                                 _ => Vec::default(),
                             }
@@ -97,6 +96,19 @@ impl ResponseArgs {
     }
 }
 
+/// Compact node info of `nodes` (BEP 5): 20 byte ids each followed by an
+/// IPv4 address, a trailing partial entry is ignored.
+pub fn parse_compact_nodes(bytes: &[u8]) -> Vec<([u8; 20], SocketAddr)> {
+    bytes
+        .chunks_exact(26)
+        .map(|node| {
+            let id = node[..20].try_into().unwrap();
+            let addr = socket_addr_from_bytes(node[20..].try_into().unwrap());
+            (id, addr.into())
+        })
+        .collect()
+}
+
 #[inline]
 fn get_bytes(map: &mut BTreeMap<String, BencodeValue>, key: &str) -> Vec<u8> {
     match map.remove(key) {
@@ -1,4 +1,7 @@
-use super::constants::{HANDSHAKE_PSTR, HANDSHAKE_SIZE};
+use super::constants::{
+    EXTENSION_RESERVED_BIT, EXTENSION_RESERVED_BYTE, HANDSHAKE_PSTR, HANDSHAKE_SIZE,
+    V2_RESERVED_BIT, V2_RESERVED_BYTE,
+};
 use super::PeerId;
 use crate::proto::constants::{INFO_HASH_V1_SIZE, PEER_ID_SIZE};
 use crate::proto::infohash::{InfoHash, InfoHashV1};
@@ -38,9 +41,13 @@ impl Handshake {
         curr += pstr_len;
 
         buf[curr..curr + 8].copy_from_slice(&[0; 8]);
+        buf[EXTENSION_RESERVED_BYTE] |= EXTENSION_RESERVED_BIT;
+        if info_hash.v2().is_some() {
+            buf[V2_RESERVED_BYTE] |= V2_RESERVED_BIT;
+        }
         curr += 8;
 
-        buf[curr..curr + 20].copy_from_slice(info_hash.inner().as_bytes());
+        buf[curr..curr + 20].copy_from_slice(info_hash.inner().truncate());
         curr += 20;
 
         buf[curr..curr + 20].copy_from_slice(peer_id.as_slice());
@@ -48,6 +55,17 @@ impl Handshake {
         Self(buf)
     }
 
+    #[inline]
+    pub fn supports_extensions(&self) -> bool {
+        self[EXTENSION_RESERVED_BYTE] & EXTENSION_RESERVED_BIT != 0
+    }
+
+    /// The peer can exchange Merkle tree hashes (BEP 52).
+    #[inline]
+    pub fn supports_v2(&self) -> bool {
+        self[V2_RESERVED_BYTE] & V2_RESERVED_BIT != 0
+    }
+
     pub fn extract_info_hash(&self) -> InfoHash {
         InfoHash::V1(InfoHashV1::new(*self.select_info_hash()))
     }
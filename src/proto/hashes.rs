@@ -0,0 +1,110 @@
+/// https://www.bittorrent.org/beps/bep_0052.html
+use super::constants::HASH_REQUEST_PAYLOAD_LEN;
+use super::metainfo::merkle::{self, Sha256Hash};
+
+/// Payload of the hash request and hash reject messages.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HashRequest {
+    pub pieces_root: Sha256Hash,
+    /// Layer of the requested hashes, counted from the 16 KiB leaves.
+    pub base_layer: u32,
+    pub index: u32,
+    pub length: u32,
+    /// Number of uncle hashes to add above the requested hashes.
+    pub proof_layers: u32,
+}
+
+impl HashRequest {
+    pub fn new(
+        pieces_root: Sha256Hash,
+        base_layer: u32,
+        index: u32,
+        length: u32,
+        proof_layers: u32,
+    ) -> Self {
+        Self {
+            pieces_root,
+            base_layer,
+            index,
+            length,
+            proof_layers,
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; HASH_REQUEST_PAYLOAD_LEN]) -> Self {
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+        Self {
+            pieces_root: bytes[..32].try_into().unwrap(),
+            base_layer: u32_at(32),
+            index: u32_at(36),
+            length: u32_at(40),
+            proof_layers: u32_at(44),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HASH_REQUEST_PAYLOAD_LEN] {
+        let mut buf = [0u8; HASH_REQUEST_PAYLOAD_LEN];
+
+        buf[..32].copy_from_slice(&self.pieces_root);
+        buf[32..36].copy_from_slice(&self.base_layer.to_be_bytes());
+        buf[36..40].copy_from_slice(&self.index.to_be_bytes());
+        buf[40..44].copy_from_slice(&self.length.to_be_bytes());
+        buf[44..48].copy_from_slice(&self.proof_layers.to_be_bytes());
+
+        buf
+    }
+}
+
+/// Answer to a hash request: `length` base layer hashes followed by the uncle hashes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Hashes {
+    pub request: HashRequest,
+    pub hashes: Vec<Sha256Hash>,
+}
+
+impl Hashes {
+    pub fn new(request: HashRequest, hashes: Vec<Sha256Hash>) -> Self {
+        Self { request, hashes }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let request = bytes.get(..HASH_REQUEST_PAYLOAD_LEN)?.try_into().ok()?;
+        Some(Self {
+            request: HashRequest::from_bytes(request),
+            hashes: merkle::split_hashes(&bytes[HASH_REQUEST_PAYLOAD_LEN..])?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.len());
+
+        buf.extend_from_slice(&self.request.to_bytes());
+        for hash in self.hashes.iter() {
+            buf.extend_from_slice(hash);
+        }
+
+        buf
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        HASH_REQUEST_PAYLOAD_LEN + 32 * self.hashes.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Requested hashes without the proof.
+    #[inline]
+    pub fn base_hashes(&self) -> &[Sha256Hash] {
+        let len = (self.request.length as usize).min(self.hashes.len());
+        &self.hashes[..len]
+    }
+
+    #[inline]
+    pub fn proof(&self) -> &[Sha256Hash] {
+        &self.hashes[self.base_hashes().len()..]
+    }
+}
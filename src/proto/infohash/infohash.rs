@@ -1,6 +1,7 @@
 use super::{InfoHashV1, InfoHashV2};
 use crate::proto::constants::INFO_HASH_V1_SIZE;
 
+#[allow(clippy::len_without_is_empty)]
 pub trait InfoHashT {
     fn hex(&self) -> String;
 
@@ -19,20 +20,75 @@ pub trait InfoHashT {
 pub enum InfoHash {
     V1(InfoHashV1),
     V2(InfoHashV2),
+    /// Hybrid torrents are in the v1 and in the v2 swarm.
+    Hybrid(InfoHashV1, InfoHashV2),
 }
 
 impl InfoHash {
+    /// `None` if neither hash is known.
+    pub fn from_parts(v1: Option<InfoHashV1>, v2: Option<InfoHashV2>) -> Option<Self> {
+        match (v1, v2) {
+            (Some(v1), Some(v2)) => Some(Self::Hybrid(v1, v2)),
+            (Some(v1), None) => Some(Self::V1(v1)),
+            (None, Some(v2)) => Some(Self::V2(v2)),
+            (None, None) => None,
+        }
+    }
+
+    /// The v1 hash of hybrid torrents.
     pub fn inner(&self) -> &dyn InfoHashT {
         match self {
-            Self::V1(info_hash) => info_hash,
+            Self::V1(info_hash) | Self::Hybrid(info_hash, _) => info_hash,
             Self::V2(info_hash) => info_hash,
         }
     }
 
     pub fn inner_mut(&mut self) -> &mut dyn InfoHashT {
         match self {
-            Self::V1(info_hash) => info_hash,
+            Self::V1(info_hash) | Self::Hybrid(info_hash, _) => info_hash,
             Self::V2(info_hash) => info_hash,
         }
     }
+
+    pub fn v1(&self) -> Option<&InfoHashV1> {
+        match self {
+            Self::V1(v1) | Self::Hybrid(v1, _) => Some(v1),
+            Self::V2(_) => None,
+        }
+    }
+
+    pub fn v2(&self) -> Option<&InfoHashV2> {
+        match self {
+            Self::V2(v2) | Self::Hybrid(_, v2) => Some(v2),
+            Self::V1(_) => None,
+        }
+    }
+
+    #[inline]
+    pub fn is_hybrid(&self) -> bool {
+        matches!(self, Self::Hybrid(..))
+    }
+
+    /// One hash per swarm, each is announced to trackers and the DHT.
+    pub fn swarm_hashes(&self) -> Vec<InfoHash> {
+        match self {
+            Self::Hybrid(v1, v2) => vec![Self::V1(v1.clone()), Self::V2(v2.clone())],
+            info_hash => vec![info_hash.clone()],
+        }
+    }
+
+    /// Handshakes carry the v1 hash or the truncated v2 hash.
+    pub fn matches(&self, info_hash: &[u8; INFO_HASH_V1_SIZE]) -> bool {
+        self.v1().is_some_and(|v1| v1.truncate() == info_hash)
+            || self.v2().is_some_and(|v2| v2.truncate() == info_hash)
+    }
+
+    /// Matches the hex of either hash.
+    pub fn matches_hex(&self, hex: &str) -> bool {
+        self.v1()
+            .is_some_and(|v1| v1.hex().eq_ignore_ascii_case(hex))
+            || self
+                .v2()
+                .is_some_and(|v2| v2.hex().eq_ignore_ascii_case(hex))
+    }
 }
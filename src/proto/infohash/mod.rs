@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 mod infohash;
 mod v1;
 mod v2;
@@ -40,7 +40,7 @@ impl InfoHashT for InfoHashV1 {
     }
 
     fn truncate(&self) -> &[u8; INFO_HASH_V1_SIZE] {
-        &self
+        self
     }
 
     fn len(&self) -> usize {
@@ -78,7 +78,7 @@ impl InfoHashV1 {
     }
 
     pub fn hex(&self) -> String {
-        hex::encode(&self.0)
+        hex::encode(self.0)
     }
 
     pub fn urlencode(&self) -> String {
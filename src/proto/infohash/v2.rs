@@ -68,7 +68,7 @@ impl InfoHashV2 {
     }
 
     pub fn hex(&self) -> String {
-        hex::encode(&self.0)
+        hex::encode(self.0)
     }
 
     pub fn urlencode(&self) -> String {
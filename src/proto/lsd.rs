@@ -0,0 +1,109 @@
+/// <https://www.bittorrent.org/beps/bep_0014.html>
+use crate::{
+    error::{Error, Result},
+    proto::constants::{INFO_HASH_V1_HEX_SIZE, INFO_HASH_V1_SIZE, LSD_MAX_ANNOUNCE_LEN},
+};
+
+const LSD_REQUEST_LINE: &str = "BT-SEARCH * HTTP/1.1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsdAnnounce {
+    /// `239.192.152.143:6771` or `[ff15::efc0:988f]:6771`.
+    pub host: String,
+    /// Port of the peer listener.
+    pub port: u16,
+    pub info_hashes: Vec<[u8; INFO_HASH_V1_SIZE]>,
+    /// Lets a client recognize its own announces.
+    pub cookie: Option<String>,
+}
+
+impl LsdAnnounce {
+    pub fn new(
+        host: String,
+        port: u16,
+        info_hashes: Vec<[u8; INFO_HASH_V1_SIZE]>,
+        cookie: Option<String>,
+    ) -> Self {
+        Self {
+            host,
+            port,
+            info_hashes,
+            cookie,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut message = format!(
+            "{LSD_REQUEST_LINE}\r\nHost: {}\r\nPort: {}\r\n",
+            self.host, self.port
+        );
+        for info_hash in self.info_hashes.iter() {
+            message.push_str(&format!("Infohash: {}\r\n", hex::encode(info_hash)));
+        }
+        if let Some(cookie) = self.cookie.as_ref() {
+            message.push_str(&format!("cookie: {cookie}\r\n"));
+        }
+        message.push_str("\r\n\r\n");
+        message.into_bytes()
+    }
+
+    /// Header names are case-insensitive, unknown headers are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let message =
+            std::str::from_utf8(bytes).map_err(|_| Error::ParseLsdAnnounce("not UTF-8".into()))?;
+        let mut lines = message.split("\r\n");
+        if lines.next() != Some(LSD_REQUEST_LINE) {
+            return Err(Error::ParseLsdAnnounce("not a BT-SEARCH request".into()));
+        }
+        let (mut host, mut port, mut cookie) = (None, None, None);
+        let mut info_hashes = Vec::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "host" => host = Some(value.to_string()),
+                "port" => port = value.parse().ok(),
+                "cookie" => cookie = Some(value.to_string()),
+                "infohash" if value.len() == INFO_HASH_V1_HEX_SIZE => {
+                    let mut info_hash = [0; INFO_HASH_V1_SIZE];
+                    if hex::decode_to_slice(value, &mut info_hash).is_ok() {
+                        info_hashes.push(info_hash);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let port = port.ok_or_else(|| Error::ParseLsdAnnounce("missing port".into()))?;
+        if info_hashes.is_empty() {
+            return Err(Error::ParseLsdAnnounce("missing infohash".into()));
+        }
+        Ok(Self {
+            host: host.unwrap_or_default(),
+            port,
+            info_hashes,
+            cookie,
+        })
+    }
+
+    /// Splits the info hashes so that every announce fits in a datagram.
+    pub fn split(
+        host: &str,
+        port: u16,
+        info_hashes: &[[u8; INFO_HASH_V1_SIZE]],
+        cookie: Option<&str>,
+    ) -> Vec<Self> {
+        let empty = Self::new(host.into(), port, Vec::new(), cookie.map(String::from));
+        let header_len = empty.to_bytes().len();
+        let per_hash = "Infohash: \r\n".len() + INFO_HASH_V1_HEX_SIZE;
+        let chunk_len = (LSD_MAX_ANNOUNCE_LEN.saturating_sub(header_len) / per_hash).max(1);
+        info_hashes
+            .chunks(chunk_len)
+            .map(|chunk| Self {
+                info_hashes: chunk.to_vec(),
+                ..empty.clone()
+            })
+            .collect()
+    }
+}
@@ -2,16 +2,29 @@
 use crate::error::Error;
 use crate::proto::constants::{INFO_HASH_V1_HEX_SIZE, INFO_HASH_V2_HEX_SIZE};
 use crate::proto::infohash::{InfoHash, InfoHashV1, InfoHashV2};
+use crate::proto::metainfo::MetaInfo;
+use crate::util::urlencode;
 use reqwest::Url;
+use std::fmt;
 use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+/// Multihash prefix of a SHA-256 digest in `urn:btmh:`.
+const SHA256_MULTIHASH_PREFIX: &str = "1220";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MagnetLink {
     pub info_hash: InfoHash,
     pub display_name: Option<String>,
     pub trackers: Vec<String>,
     pub peers: Vec<SocketAddr>,
+    /// `ws`, BEP 19 web seeds.
+    pub web_seeds: Vec<String>,
+    /// `xl`, total length in bytes.
+    pub exact_length: Option<u64>,
+    /// `so`, BEP 53 file indices to download.
+    pub select_only: Vec<RangeInclusive<usize>>,
 }
 
 impl FromStr for MagnetLink {
@@ -23,30 +36,32 @@ impl FromStr for MagnetLink {
             return Err(Error::ParseMagnetLink("URI scheme is not 'magnet'".into()));
         }
 
-        let mut info_hash = None;
+        let mut info_hash_v1 = None;
+        let mut info_hash_v2 = None;
         let mut display_name = None;
         let mut trackers = Vec::new();
         let mut peers = Vec::<SocketAddr>::new();
+        let mut web_seeds = Vec::new();
+        let mut exact_length = None;
+        let mut select_only = Vec::new();
 
         for (key, value) in url.query_pairs() {
             match key.as_ref() {
                 "xt" if value.starts_with("urn:btih:") => {
                     let hash = &value["urn:btih:".len()..];
-                    info_hash = Some(match hash.len() {
+                    info_hash_v1 = Some(match hash.len() {
                         32 => {
                             let base32 = hash.to_uppercase();
-                            InfoHashV1::from_base32(&base32)
-                                .map(InfoHash::V1)
-                                .map_err(|e| {
-                                    Error::ParseMagnetLink(format!("Invalid base32 btih: {e}"))
-                                })
+                            InfoHashV1::from_base32(&base32).map_err(|e| {
+                                Error::ParseMagnetLink(format!("Invalid base32 btih: {e}"))
+                            })
                         }
                         INFO_HASH_V1_HEX_SIZE => {
                             let bytes: [u8; INFO_HASH_V1_HEX_SIZE] =
                                 hash.as_bytes().try_into().map_err(|_| {
                                     Error::ParseMagnetLink("btih hex length mismatch".into())
                                 })?;
-                            InfoHashV1::from_hex(&bytes).map(InfoHash::V1).map_err(|e| {
+                            InfoHashV1::from_hex(&bytes).map_err(|e| {
                                 Error::ParseMagnetLink(format!("Invalid hex btih: {e}"))
                             })
                         }
@@ -55,16 +70,22 @@ impl FromStr for MagnetLink {
                 }
                 "xt" if value.starts_with("urn:btmh:") => {
                     let hash = &value["urn:btmh:".len()..];
-                    if hash.len() == INFO_HASH_V2_HEX_SIZE {
-                        let v2 = InfoHashV2::from_hex(hash.as_bytes().try_into().unwrap())
-                            .map_err(|e| Error::ParseMagnetLink(format!("Invalid btmh: {e}")))?;
-                        info_hash = Some(InfoHash::V2(v2));
-                    }
+                    let hash = match hash.len() {
+                        INFO_HASH_V2_HEX_SIZE => hash,
+                        _ => hash.strip_prefix(SHA256_MULTIHASH_PREFIX).ok_or_else(|| {
+                            Error::ParseMagnetLink(format!("Unknown btmh format: {hash}"))
+                        })?,
+                    };
+                    let hash: &[u8; INFO_HASH_V2_HEX_SIZE] = hash
+                        .as_bytes()
+                        .try_into()
+                        .map_err(|_| Error::ParseMagnetLink("btmh hex length mismatch".into()))?;
+                    let v2 = InfoHashV2::from_hex(hash)
+                        .map_err(|e| Error::ParseMagnetLink(format!("Invalid btmh: {e}")))?;
+                    info_hash_v2 = Some(v2);
                 }
-                "dn" => {
-                    if display_name.is_none() {
-                        display_name = Some(value.to_string());
-                    }
+                "dn" if display_name.is_none() => {
+                    display_name = Some(value.to_string());
                 }
                 "tr" => {
                     trackers.push(value.to_string());
@@ -74,11 +95,20 @@ impl FromStr for MagnetLink {
                         peers.push(addr);
                     }
                 }
+                "ws" => {
+                    web_seeds.push(value.to_string());
+                }
+                "xl" => {
+                    exact_length = value.parse().ok();
+                }
+                "so" => {
+                    select_only.extend(parse_select_only(&value));
+                }
                 _ => {}
             }
         }
 
-        let info_hash = info_hash
+        let info_hash = InfoHash::from_parts(info_hash_v1, info_hash_v2)
             .ok_or_else(|| Self::Err::ParseMagnetLink("No infohash (xt) parameter found".into()))?;
 
         Ok(Self {
@@ -86,8 +116,85 @@ impl FromStr for MagnetLink {
             display_name,
             trackers,
             peers,
+            web_seeds,
+            exact_length,
+            select_only,
         })
     }
 }
 
-impl MagnetLink {}
+/// `0,2,4-6`, malformed entries are skipped.
+fn parse_select_only(value: &str) -> Vec<RangeInclusive<usize>> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let entry = entry.trim();
+            match entry.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                    (start <= end).then_some(start..=end)
+                }
+                None => entry.parse().ok().map(|index| index..=index),
+            }
+        })
+        .collect()
+}
+
+/// Canonical order: `xt`, `dn`, `xl`, `tr`, `ws`, `x.pe`, `so`.
+impl fmt::Display for MagnetLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut params = Vec::new();
+        if let Some(v1) = self.info_hash.v1() {
+            params.push(format!("xt=urn:btih:{}", v1.hex()));
+        }
+        if let Some(v2) = self.info_hash.v2() {
+            params.push(format!("xt=urn:btmh:{SHA256_MULTIHASH_PREFIX}{}", v2.hex()));
+        }
+        if let Some(display_name) = self.display_name.as_ref() {
+            params.push(format!("dn={}", urlencode(display_name.as_bytes())));
+        }
+        if let Some(exact_length) = self.exact_length {
+            params.push(format!("xl={exact_length}"));
+        }
+        for tracker in self.trackers.iter() {
+            params.push(format!("tr={}", urlencode(tracker.as_bytes())));
+        }
+        for web_seed in self.web_seeds.iter() {
+            params.push(format!("ws={}", urlencode(web_seed.as_bytes())));
+        }
+        for peer in self.peers.iter() {
+            params.push(format!("x.pe={}", urlencode(peer.to_string().as_bytes())));
+        }
+        if !self.select_only.is_empty() {
+            let ranges = self
+                .select_only
+                .iter()
+                .map(|range| match range.start() == range.end() {
+                    true => range.start().to_string(),
+                    false => format!("{}-{}", range.start(), range.end()),
+                })
+                .collect::<Vec<_>>();
+            params.push(format!("so={}", ranges.join(",")));
+        }
+        write!(f, "magnet:?{}", params.join("&"))
+    }
+}
+
+impl MagnetLink {
+    pub fn from_metainfo(metainfo: &MetaInfo) -> Self {
+        Self {
+            info_hash: metainfo.info_hash().clone(),
+            display_name: Some(metainfo.info.name.clone()).filter(|name| !name.is_empty()),
+            trackers: metainfo.announce_list().into_iter().flatten().collect(),
+            peers: Vec::new(),
+            web_seeds: metainfo.url_list.clone().unwrap_or_default(),
+            exact_length: Some(metainfo.info.total_length()),
+            select_only: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn to_uri(&self) -> String {
+        self.to_string()
+    }
+}
@@ -1,6 +1,7 @@
 use super::bitfield::BitField;
 use super::constants::*;
 use super::handshake::Handshake;
+use super::hashes::{HashRequest, Hashes};
 use super::piece::Piece;
 use super::request::Request;
 use crate::proto::bep10;
@@ -22,6 +23,9 @@ pub enum Message {
     Handshake(Handshake),
     Port(u16),
     Extension(bep10::ExtensionMessage),
+    HashRequest(HashRequest),
+    Hashes(Hashes),
+    HashReject(HashRequest),
 }
 
 impl Message {
@@ -79,6 +83,18 @@ impl Message {
             EXTENSION_MSG_ID if len > 6 => {
                 Self::Extension(bep10::ExtensionMessage::from_bytes(&bytes[..end]))
             }
+            HASH_REQUEST_MSG_ID if len == 1 + HASH_REQUEST_PAYLOAD_LEN => {
+                let buf: [u8; HASH_REQUEST_PAYLOAD_LEN] = payload.try_into().unwrap();
+                Self::HashRequest(HashRequest::from_bytes(buf))
+            }
+            HASHES_MSG_ID if len > HASH_REQUEST_PAYLOAD_LEN => match Hashes::from_bytes(payload) {
+                Some(hashes) => Self::Hashes(hashes),
+                None => Self::Invalid(n),
+            },
+            HASH_REJECT_MSG_ID if len == 1 + HASH_REQUEST_PAYLOAD_LEN => {
+                let buf: [u8; HASH_REQUEST_PAYLOAD_LEN] = payload.try_into().unwrap();
+                Self::HashReject(HashRequest::from_bytes(buf))
+            }
             _ => Message::Invalid(n),
         }
     }
@@ -150,6 +166,24 @@ impl Message {
                 buf[..len].copy_from_slice(&bytes);
                 len
             }
+            Self::HashRequest(r) | Self::HashReject(r) => {
+                let id = if matches!(self, Self::HashRequest(_)) {
+                    HASH_REQUEST_MSG_ID
+                } else {
+                    HASH_REJECT_MSG_ID
+                };
+                buf[..4].copy_from_slice(&(1 + HASH_REQUEST_PAYLOAD_LEN as u32).to_be_bytes());
+                buf[4] = id;
+                buf[5..5 + HASH_REQUEST_PAYLOAD_LEN].copy_from_slice(&r.to_bytes());
+                5 + HASH_REQUEST_PAYLOAD_LEN
+            }
+            Self::Hashes(h) => {
+                let len = h.len();
+                buf[..4].copy_from_slice(&(1 + len as u32).to_be_bytes());
+                buf[4] = HASHES_MSG_ID;
+                buf[5..5 + len].copy_from_slice(&h.to_bytes());
+                5 + len
+            }
             _ => {
                 buf[..4].copy_from_slice(&KEEP_ALIVE_MSG);
                 4
@@ -163,6 +197,7 @@ impl Message {
         buf
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         match self {
             Self::Empty => 0,
@@ -176,6 +211,8 @@ impl Message {
             Self::Piece(p) => 4 + 1 + p.len(),
             Self::Port(_) => 4 + 1 + PORT_PAYLOAD_LEN,
             Self::Extension(e) => e.len(),
+            Self::HashRequest(_) | Self::HashReject(_) => 4 + 1 + HASH_REQUEST_PAYLOAD_LEN,
+            Self::Hashes(h) => 4 + 1 + h.len(),
         }
     }
 }
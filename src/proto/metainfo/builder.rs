@@ -0,0 +1,494 @@
+use super::{
+    merkle::{self, Sha256Hash, BLOCK_SIZE},
+    AnnounceList,
+};
+use crate::error::{Error, Result};
+use serde_bencode::value::Value as BencodeValue;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MIN_PIECE_LENGTH: u64 = BLOCK_SIZE as u64;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Automatic piece length aims for about this many pieces.
+const TARGET_NUM_PIECES: u64 = 1500;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentVersion {
+    #[default]
+    V1,
+    V2,
+    /// v1 and v2 metadata in one torrent, files are aligned to pieces with pad files.
+    Hybrid,
+}
+
+#[derive(Debug, Clone)]
+struct SourceFile {
+    /// Absolute path on disk.
+    full_path: PathBuf,
+    /// Path components inside the torrent, empty for single-file torrents.
+    components: Vec<String>,
+    length: u64,
+    /// Offset in the v1 piece stream, including pad files for hybrid torrents.
+    offset: u64,
+}
+
+/// Creates `.torrent` files from a file or a directory.
+#[derive(Debug, Clone)]
+pub struct TorrentBuilder {
+    path: PathBuf,
+    version: TorrentVersion,
+    piece_length: Option<u64>,
+    announce_list: AnnounceList,
+    url_list: Vec<String>,
+    comment: Option<String>,
+    created_by: Option<String>,
+    creation_date: Option<i64>,
+    private: bool,
+    source: Option<String>,
+    num_threads: Option<usize>,
+}
+
+impl TorrentBuilder {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let creation_date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .ok();
+        Self {
+            path: path.into(),
+            version: TorrentVersion::default(),
+            piece_length: None,
+            announce_list: AnnounceList::new(),
+            url_list: Vec::new(),
+            comment: None,
+            created_by: Some(concat!("rutor/", env!("CARGO_PKG_VERSION")).to_owned()),
+            creation_date,
+            private: false,
+            source: None,
+            num_threads: None,
+        }
+    }
+
+    pub fn version(mut self, value: TorrentVersion) -> Self {
+        self.version = value;
+        self
+    }
+
+    /// Power of two, at least 16 KiB. Chosen from the total size by default.
+    pub fn piece_length(mut self, value: u64) -> Self {
+        self.piece_length = Some(value);
+        self
+    }
+
+    /// Every call adds a tier.
+    pub fn tracker_tier<S: Into<String>>(mut self, urls: Vec<S>) -> Self {
+        self.announce_list
+            .push(urls.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn tracker<S: Into<String>>(self, url: S) -> Self {
+        self.tracker_tier(vec![url])
+    }
+
+    pub fn web_seed<S: Into<String>>(mut self, url: S) -> Self {
+        self.url_list.push(url.into());
+        self
+    }
+
+    pub fn comment<S: Into<String>>(mut self, value: S) -> Self {
+        self.comment = Some(value.into());
+        self
+    }
+
+    pub fn created_by<S: Into<String>>(mut self, value: Option<S>) -> Self {
+        self.created_by = value.map(Into::into);
+        self
+    }
+
+    /// Unix timestamp, the current time by default.
+    pub fn creation_date(mut self, value: Option<i64>) -> Self {
+        self.creation_date = value;
+        self
+    }
+
+    pub fn private(mut self, value: bool) -> Self {
+        self.private = value;
+        self
+    }
+
+    /// `source` tag in the info dictionary, makes the info hash unique per tracker.
+    pub fn source<S: Into<String>>(mut self, value: S) -> Self {
+        self.source = Some(value.into());
+        self
+    }
+
+    /// Hashing threads, the number of CPUs by default.
+    pub fn num_threads(mut self, value: usize) -> Self {
+        self.num_threads = Some(value.max(1));
+        self
+    }
+
+    fn collect_files(&self) -> Result<(String, Vec<SourceFile>)> {
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::TorrentBuilder("Path has no file name".into()))?;
+
+        let metadata = std::fs::metadata(&self.path)?;
+        if metadata.is_file() {
+            let file = SourceFile {
+                full_path: self.path.clone(),
+                components: Vec::new(),
+                length: metadata.len(),
+                offset: 0,
+            };
+            return Ok((name, vec![file]));
+        }
+
+        let mut files = Vec::new();
+        walk_dir(&self.path, &mut Vec::new(), &mut files)?;
+        if files.is_empty() {
+            return Err(Error::TorrentBuilder("No files to add".into()));
+        }
+        files.sort_by(|a, b| a.components.cmp(&b.components));
+        Ok((name, files))
+    }
+
+    fn choose_piece_length(&self, total_length: u64) -> Result<u64> {
+        match self.piece_length {
+            Some(piece_length)
+                if piece_length >= MIN_PIECE_LENGTH && piece_length.is_power_of_two() =>
+            {
+                Ok(piece_length)
+            }
+            Some(piece_length) => Err(Error::TorrentBuilder(format!(
+                "Invalid piece length {piece_length}: expected a power of two of at least 16 KiB"
+            ))),
+            None => Ok((total_length / TARGET_NUM_PIECES)
+                .next_power_of_two()
+                .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)),
+        }
+    }
+
+    /// Hashes the files and returns the bencoded torrent.
+    pub fn build(self) -> Result<Vec<u8>> {
+        let (name, mut files) = self.collect_files()?;
+        let total_length = files.iter().map(|f| f.length).sum();
+        let piece_length = self.choose_piece_length(total_length)?;
+
+        let aligned = self.version == TorrentVersion::Hybrid;
+        // no padding after the last file with data
+        let last_file = files.iter().rposition(|f| f.length > 0).unwrap_or(0);
+        let mut offset = 0;
+        for (i, file) in files.iter_mut().enumerate() {
+            file.offset = offset;
+            offset += file.length;
+            if aligned && i < last_file {
+                offset = offset.next_multiple_of(piece_length);
+            }
+        }
+        let stream_length = files
+            .last()
+            .map(|f| f.offset + f.length)
+            .unwrap_or_default();
+
+        let mut info = HashMap::new();
+        insert_str(&mut info, "name", &name);
+        insert_int(&mut info, "piece length", piece_length as i64);
+        if self.private {
+            insert_int(&mut info, "private", 1);
+        }
+        if let Some(source) = self.source.as_ref() {
+            insert_str(&mut info, "source", source);
+        }
+
+        let mut piece_layers = HashMap::new();
+        let threads = self.num_threads.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        });
+
+        match self.version {
+            TorrentVersion::V1 => {
+                let num_pieces = stream_length.div_ceil(piece_length) as usize;
+                let pieces = parallel_map(num_pieces, threads, |index| {
+                    let start = index as u64 * piece_length;
+                    let length = piece_length.min(stream_length - start);
+                    let data = read_stream(&files, start, length)?;
+                    Ok(Sha1::digest(&data).to_vec())
+                })?;
+                insert_bytes(&mut info, "pieces", pieces.concat());
+            }
+            TorrentVersion::V2 | TorrentVersion::Hybrid => {
+                // (file index, piece index inside the file)
+                let jobs: Vec<(usize, u64)> = files
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, f)| (0..f.length.div_ceil(piece_length)).map(move |p| (i, p)))
+                    .collect();
+                let hashes = parallel_map(jobs.len(), threads, |job| {
+                    let (file_index, piece) = jobs[job];
+                    let file = &files[file_index];
+                    let start = piece * piece_length;
+                    let length = piece_length.min(file.length - start);
+                    let data = read_stream(&files, file.offset + start, length)?;
+
+                    let v2_hash = if file.length <= piece_length {
+                        merkle::root_from_data(&data)
+                    } else {
+                        merkle::piece_hash(&data, piece_length)
+                    };
+                    let v1_hash = aligned.then(|| {
+                        let mut hasher = Sha1::new();
+                        hasher.update(&data);
+                        if file_index < last_file {
+                            hasher.update(vec![0; (piece_length - length) as usize]);
+                        }
+                        hasher.finalize().to_vec()
+                    });
+                    Ok((v2_hash, v1_hash))
+                })?;
+
+                if aligned {
+                    let pieces = hashes
+                        .iter()
+                        .filter_map(|(_, h)| h.clone())
+                        .collect::<Vec<_>>();
+                    insert_bytes(&mut info, "pieces", pieces.concat());
+                }
+
+                let mut file_tree = HashMap::new();
+                let mut hashes = hashes.into_iter();
+                for file in files.iter() {
+                    let num_pieces = file.length.div_ceil(piece_length) as usize;
+                    let layer: Vec<Sha256Hash> =
+                        hashes.by_ref().take(num_pieces).map(|(h, _)| h).collect();
+                    let pieces_root = match layer.len() {
+                        0 => None,
+                        1 if file.length <= piece_length => Some(layer[0]),
+                        _ => {
+                            let root = merkle::root_from_piece_layer(&layer, piece_length);
+                            piece_layers.insert(root.to_vec(), BencodeValue::Bytes(layer.concat()));
+                            Some(root)
+                        }
+                    };
+                    let components = match file.components.is_empty() {
+                        true => vec![name.clone()],
+                        false => file.components.clone(),
+                    };
+                    insert_file_tree(&mut file_tree, &components, file.length, pieces_root);
+                }
+                insert_int(&mut info, "meta version", 2);
+                info.insert(b"file tree".to_vec(), BencodeValue::Dict(file_tree));
+            }
+        }
+
+        if self.version != TorrentVersion::V2 {
+            if let [file] = files.as_slice() {
+                if file.components.is_empty() {
+                    insert_int(&mut info, "length", file.length as i64);
+                }
+            }
+            if !info.contains_key(b"length".as_slice()) {
+                let mut list = Vec::new();
+                for (i, file) in files.iter().enumerate() {
+                    list.push(file_entry(file.length, &file.components, false));
+                    let pad = match files.get(i + 1) {
+                        Some(next) => next.offset - file.offset - file.length,
+                        None => 0,
+                    };
+                    if pad > 0 {
+                        let components = vec![".pad".to_owned(), pad.to_string()];
+                        list.push(file_entry(pad, &components, true));
+                    }
+                }
+                info.insert(b"files".to_vec(), BencodeValue::List(list));
+            }
+        }
+
+        let mut torrent = HashMap::new();
+        torrent.insert(b"info".to_vec(), BencodeValue::Dict(info));
+        if !piece_layers.is_empty() {
+            torrent.insert(b"piece layers".to_vec(), BencodeValue::Dict(piece_layers));
+        }
+        if let Some(announce) = self.announce_list.first().and_then(|tier| tier.first()) {
+            insert_str(&mut torrent, "announce", announce);
+        }
+        if self.announce_list.iter().map(Vec::len).sum::<usize>() > 1 {
+            let tiers = self
+                .announce_list
+                .iter()
+                .map(|tier| BencodeValue::List(tier.iter().map(|url| str_value(url)).collect()))
+                .collect();
+            torrent.insert(b"announce-list".to_vec(), BencodeValue::List(tiers));
+        }
+        if !self.url_list.is_empty() {
+            let urls = self.url_list.iter().map(|url| str_value(url)).collect();
+            torrent.insert(b"url-list".to_vec(), BencodeValue::List(urls));
+        }
+        if let Some(comment) = self.comment.as_ref() {
+            insert_str(&mut torrent, "comment", comment);
+        }
+        if let Some(created_by) = self.created_by.as_ref() {
+            insert_str(&mut torrent, "created by", created_by);
+        }
+        if let Some(creation_date) = self.creation_date {
+            insert_int(&mut torrent, "creation date", creation_date);
+        }
+
+        Ok(serde_bencode::to_bytes(&BencodeValue::Dict(torrent))?)
+    }
+}
+
+type BencodeDict = HashMap<Vec<u8>, BencodeValue>;
+
+#[inline]
+fn str_value(value: &str) -> BencodeValue {
+    BencodeValue::Bytes(value.as_bytes().to_vec())
+}
+
+#[inline]
+fn insert_str(dict: &mut BencodeDict, key: &str, value: &str) {
+    dict.insert(key.as_bytes().to_vec(), str_value(value));
+}
+
+#[inline]
+fn insert_int(dict: &mut BencodeDict, key: &str, value: i64) {
+    dict.insert(key.as_bytes().to_vec(), BencodeValue::Int(value));
+}
+
+#[inline]
+fn insert_bytes(dict: &mut BencodeDict, key: &str, value: Vec<u8>) {
+    dict.insert(key.as_bytes().to_vec(), BencodeValue::Bytes(value));
+}
+
+fn file_entry(length: u64, components: &[String], pad: bool) -> BencodeValue {
+    let mut entry = HashMap::new();
+    insert_int(&mut entry, "length", length as i64);
+    let path = components.iter().map(|c| str_value(c)).collect();
+    entry.insert(b"path".to_vec(), BencodeValue::List(path));
+    if pad {
+        insert_str(&mut entry, "attr", "p");
+    }
+    BencodeValue::Dict(entry)
+}
+
+fn insert_file_tree(
+    tree: &mut BencodeDict,
+    components: &[String],
+    length: u64,
+    pieces_root: Option<Sha256Hash>,
+) {
+    let [first, rest @ ..] = components else {
+        return;
+    };
+    let node = tree
+        .entry(first.as_bytes().to_vec())
+        .or_insert_with(|| BencodeValue::Dict(HashMap::new()));
+    let BencodeValue::Dict(node) = node else {
+        return;
+    };
+    if rest.is_empty() {
+        let mut file = HashMap::new();
+        insert_int(&mut file, "length", length as i64);
+        if let Some(pieces_root) = pieces_root {
+            insert_bytes(&mut file, "pieces root", pieces_root.to_vec());
+        }
+        node.insert(Vec::new(), BencodeValue::Dict(file));
+    } else {
+        insert_file_tree(node, rest, length, pieces_root);
+    }
+}
+
+fn walk_dir(dir: &Path, prefix: &mut Vec<String>, files: &mut Vec<SourceFile>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        prefix.push(entry.file_name().to_string_lossy().into_owned());
+        if file_type.is_dir() {
+            walk_dir(&entry.path(), prefix, files)?;
+        } else if file_type.is_file() {
+            files.push(SourceFile {
+                full_path: entry.path(),
+                components: prefix.clone(),
+                length: entry.metadata()?.len(),
+                offset: 0,
+            });
+        }
+        prefix.pop();
+    }
+    Ok(())
+}
+
+/// Reads `length` bytes of the piece stream, gaps between files are zeros.
+fn read_stream(files: &[SourceFile], start: u64, length: u64) -> Result<Vec<u8>> {
+    let end = start + length;
+    let mut data = vec![0u8; length as usize];
+    let first = files.partition_point(|f| f.offset + f.length <= start);
+    for file in files[first..].iter().take_while(|f| f.offset < end) {
+        let slice_start = start.max(file.offset);
+        let slice_end = end.min(file.offset + file.length);
+        if slice_start >= slice_end {
+            continue;
+        }
+        let mut handle = File::open(&file.full_path)?;
+        handle.seek(SeekFrom::Start(slice_start - file.offset))?;
+        handle
+            .read_exact(&mut data[(slice_start - start) as usize..(slice_end - start) as usize])?;
+    }
+    Ok(data)
+}
+
+/// Runs `f` for `0..len` on `threads` threads, results are in index order.
+fn parallel_map<T, F>(len: usize, threads: usize, f: F) -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(usize) -> Result<T> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..len).map(|_| None).collect::<Vec<Option<T>>>());
+    let error = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(len).max(1) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= len {
+                    break;
+                }
+                match f(index) {
+                    Ok(value) => results.lock().unwrap()[index] = Some(value),
+                    Err(e) => {
+                        next.store(len, Ordering::Relaxed);
+                        error.lock().unwrap().get_or_insert(e);
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect())
+}
@@ -1,10 +1,7 @@
 /// https://wiki.theory.org/BitTorrentSpecification#Metainfo_File_Structure
 use crate::error::Result;
 use serde::Deserialize;
-use std::{
-    collections::BTreeMap,
-    path::{Path, PathBuf},
-};
+use std::{collections::BTreeMap, path::PathBuf};
 
 type BencodeValue = serde_bencode::value::Value;
 type BencodeError = serde_bencode::Error;
@@ -13,17 +10,82 @@ type BencodeError = serde_bencode::Error;
 pub struct FileInfo {
     pub length: u64,
     pub path: Vec<String>,
+
+    #[serde(default)]
+    pub attr: Option<String>,
+
+    #[serde(default, rename = "symlink path")]
+    pub symlink_path: Option<Vec<String>>,
+
+    #[serde(default, with = "serde_bytes")]
+    pub sha1: Option<Vec<u8>>,
+}
+
+/// https://www.bittorrent.org/beps/bep_0047.html
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttributes {
+    /// `p`, alignment padding, all zeros and never written to disk.
+    pub padding: bool,
+    /// `x`
+    pub executable: bool,
+    /// `h`
+    pub hidden: bool,
+    /// `l`, the target is in `symlink path`.
+    pub symlink: bool,
+}
+
+impl FileAttributes {
+    /// Unknown flags are ignored.
+    pub fn parse(attr: &str) -> Self {
+        let mut attributes = Self::default();
+        for flag in attr.chars() {
+            match flag {
+                'p' => attributes.padding = true,
+                'x' => attributes.executable = true,
+                'h' => attributes.hidden = true,
+                'l' => attributes.symlink = true,
+                _ => {}
+            }
+        }
+        attributes
+    }
+}
+
+/// A file of the torrent with its byte offset in the concatenated torrent data.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    /// Relative to the save directory.
+    pub path: PathBuf,
+    pub length: u64,
+    pub offset: u64,
+    pub attr: FileAttributes,
+    /// Symlink target, relative to the save directory like `path`.
+    pub symlink_path: Option<PathBuf>,
+    /// Path components as named by the torrent, web seeds serve the file under them.
+    pub torrent_path: Vec<String>,
+}
+
+impl FileEntry {
+    #[inline]
+    pub fn is_padding(&self) -> bool {
+        self.attr.padding
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum FileTree {
     Dir(BTreeMap<String, FileTree>),
-    File { length: u64, pieces_root: Vec<u8> },
+    File {
+        length: u64,
+        pieces_root: Vec<u8>,
+        attr: FileAttributes,
+        symlink_path: Option<Vec<String>>,
+    },
 }
 
 impl FileTree {
     pub fn from_bencode_value(value: BencodeValue) -> Result<FileTree> {
-        Ok(de_file_tree::bencode_to_file_tree(value).map_err(|e| BencodeError::Custom(e))?)
+        Ok(de_file_tree::bencode_to_file_tree(value).map_err(BencodeError::Custom)?)
     }
 
     #[inline]
@@ -34,22 +96,29 @@ impl FileTree {
         }
     }
 
-    pub fn iter_files(&self) -> Vec<(PathBuf, &FileTree)> {
+    /// Files with their path components as named in the tree, `Info::files`
+    /// sanitizes them.
+    pub fn iter_files(&self) -> Vec<(Vec<String>, &FileTree)> {
         let mut files = Vec::new();
-        self.collect_files(Path::new(""), &mut files);
+        self.collect_files(&mut Vec::new(), &mut files);
         files
     }
 
     #[inline]
-    fn collect_files<'a>(&'a self, base: &Path, acc: &mut Vec<(PathBuf, &'a FileTree)>) {
+    fn collect_files<'a>(
+        &'a self,
+        base: &mut Vec<String>,
+        acc: &mut Vec<(Vec<String>, &'a FileTree)>,
+    ) {
         match self {
             FileTree::File { .. } => {
-                acc.push((base.to_path_buf(), self));
+                acc.push((base.clone(), self));
             }
             FileTree::Dir(map) => {
                 for (name, subtree) in map {
-                    let path = base.join(name);
-                    subtree.collect_files(&path, acc);
+                    base.push(name.clone());
+                    subtree.collect_files(base, acc);
+                    base.pop();
                 }
             }
         }
@@ -85,7 +154,7 @@ mod de_file_tree {
                 return Err("Root directory cannot be a file".into());
             }
 
-            let path = sanitize_path(key);
+            let path = String::from_utf8_lossy(&key).into_owned();
             let subtree = de_entry(value)?;
             dir_map.insert(path, subtree);
         }
@@ -128,17 +197,29 @@ mod de_file_tree {
             })
             .unwrap_or_default();
 
+        let attr = match dict.get(b"attr".as_slice()) {
+            Some(BencodeValue::Bytes(attr)) => {
+                FileAttributes::parse(&String::from_utf8_lossy(attr))
+            }
+            _ => FileAttributes::default(),
+        };
+
+        let symlink_path = match dict.get(b"symlink path".as_slice()) {
+            Some(BencodeValue::List(components)) => components
+                .iter()
+                .map(|c| match c {
+                    BencodeValue::Bytes(c) => String::from_utf8(c.clone()).ok(),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        };
+
         Ok(FileTree::File {
             length,
             pieces_root,
+            attr,
+            symlink_path,
         })
     }
-
-    fn sanitize_path(raw: Vec<u8>) -> String {
-        if raw == b"." || raw == b".." {
-            String::from("_")
-        } else {
-            String::from_utf8(raw).unwrap_or("_".into())
-        }
-    }
 }
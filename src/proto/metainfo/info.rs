@@ -1,7 +1,13 @@
 /// https://wiki.theory.org/BitTorrentSpecification#Metainfo_File_Structure
-use super::file::{FileInfo, FileTree};
+use super::{
+    file::{FileAttributes, FileEntry, FileInfo, FileTree},
+    merkle::Sha256Hash,
+    sanitize,
+};
 use crate::error::Result;
 use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::path::PathBuf;
 
 type BencodeValue = serde_bencode::value::Value;
 
@@ -22,6 +28,19 @@ pub struct Info {
     #[serde(default)]
     files: Option<Vec<FileInfo>>,
 
+    #[serde(default)]
+    pub private: Option<i64>,
+
+    #[serde(default)]
+    pub source: Option<String>,
+
+    /// BEP 47 attributes of a single-file torrent.
+    #[serde(default)]
+    attr: Option<String>,
+
+    #[serde(default, rename = "symlink path")]
+    symlink_path: Option<Vec<String>>,
+
     #[serde(default, rename = "meta version")]
     meta_version: Option<i32>,
 
@@ -54,6 +73,18 @@ impl Info {
         self.meta_version.unwrap_or(1)
     }
 
+    /// BEP 27, peers may only come from the torrent's own trackers.
+    #[inline]
+    pub fn is_private(&self) -> bool {
+        self.private == Some(1)
+    }
+
+    /// v2 metadata with v1 `pieces` for the v1 swarm.
+    #[inline]
+    pub fn is_hybrid(&self) -> bool {
+        self.meta_version() == 2 && self.pieces.is_some()
+    }
+
     #[inline]
     pub fn total_length(&self) -> u64 {
         if let Some(length) = self.length {
@@ -68,15 +99,191 @@ impl Info {
         0
     }
 
+    /// v2 files start at a piece boundary, so their pieces are counted per file.
     #[inline]
     pub fn num_pieces(&self) -> usize {
-        if self.meta_version() == 2 {
-            let total = self.total_length();
-            return ((total + self.piece_length - 1) / self.piece_length) as usize;
-        }
         if let Some(pieces) = &self.pieces {
             return pieces.len() / 20;
         }
+        if self.meta_version() == 2 && self.piece_length > 0 {
+            return self
+                .file_tree_entries()
+                .iter()
+                .map(|(_, f)| f.total_length().div_ceil(self.piece_length) as usize)
+                .sum();
+        }
         0
     }
+
+    /// SHA-1 of a v1 piece.
+    pub fn piece_hash(&self, index: usize) -> Option<&[u8]> {
+        self.pieces.as_ref()?.chunks_exact(20).nth(index)
+    }
+
+    pub fn verify_piece(&self, index: usize, data: &[u8]) -> bool {
+        self.piece_hash(index)
+            .is_some_and(|hash| Sha1::digest(data).as_slice() == hash)
+    }
+
+    /// Files in torrent order, multi-file torrents are placed in the `name` directory.
+    /// v2 files start at a piece boundary. Paths are sanitized and unique, so they
+    /// can be joined to the save directory.
+    pub fn files(&self) -> Vec<FileEntry> {
+        let mut files = self.sanitized_files();
+        sanitize::dedup_paths(
+            files
+                .iter_mut()
+                .filter(|f| !f.is_padding())
+                .map(|f| &mut f.path),
+        );
+        files
+    }
+
+    /// Torrent name as a single path component.
+    #[inline]
+    fn dir_name(&self) -> String {
+        sanitize::sanitize_component(&self.name)
+    }
+
+    fn sanitized_files(&self) -> Vec<FileEntry> {
+        let name = self.dir_name();
+        let piece_length = self.piece_length.max(1);
+        let mut offset = 0;
+        let mut entry = |path: PathBuf,
+                         torrent_path: Vec<String>,
+                         length: u64,
+                         attr: FileAttributes,
+                         symlink_path,
+                         aligned| {
+            if aligned && length > 0 {
+                offset = u64::next_multiple_of(offset, piece_length);
+            }
+            let file = FileEntry {
+                path,
+                length,
+                offset,
+                attr,
+                symlink_path,
+                torrent_path,
+            };
+            offset += length;
+            file
+        };
+
+        if let Some(length) = self.length {
+            let attr = self.attr.as_deref().map(FileAttributes::parse);
+            let symlink_path = self
+                .symlink_path
+                .as_deref()
+                .and_then(|p| symlink_target(None, p));
+            return vec![entry(
+                PathBuf::from(&name),
+                vec![self.name.clone()],
+                length,
+                attr.unwrap_or_default(),
+                symlink_path,
+                false,
+            )];
+        }
+        if let Some(ref files) = self.files {
+            return files
+                .iter()
+                .map(|f| {
+                    let attr = f.attr.as_deref().map(FileAttributes::parse);
+                    let symlink_path = f
+                        .symlink_path
+                        .as_deref()
+                        .and_then(|p| symlink_target(Some(&name), p));
+                    entry(
+                        PathBuf::from(&name).join(sanitize::sanitize_path(&f.path)),
+                        std::iter::once(&self.name)
+                            .chain(&f.path)
+                            .cloned()
+                            .collect(),
+                        f.length,
+                        attr.unwrap_or_default(),
+                        symlink_path,
+                        false,
+                    )
+                })
+                .collect();
+        }
+        let entries = self.file_tree_entries();
+        let single_file = entries.len() == 1 && entries[0].0.len() == 1;
+        entries
+            .into_iter()
+            .map(|(torrent_path, f)| {
+                let (attr, symlink_path) = match f {
+                    FileTree::File {
+                        attr, symlink_path, ..
+                    } => {
+                        let base = (!single_file).then_some(name.as_str());
+                        let target = symlink_path
+                            .as_deref()
+                            .and_then(|p| symlink_target(base, p));
+                        (*attr, target)
+                    }
+                    FileTree::Dir(_) => (FileAttributes::default(), None),
+                };
+                let path = torrent_path
+                    .iter()
+                    .map(|c| sanitize::sanitize_component(c))
+                    .collect();
+                entry(
+                    path,
+                    torrent_path,
+                    f.total_length(),
+                    attr,
+                    symlink_path,
+                    true,
+                )
+            })
+            .collect()
+    }
+
+    /// v2 files with their path components as named by the torrent, the torrent
+    /// name is the top directory unless the tree holds a single file.
+    fn file_tree_entries(&self) -> Vec<(Vec<String>, &FileTree)> {
+        let Some(ref file_tree) = self.file_tree else {
+            return Vec::new();
+        };
+        let files = file_tree.iter_files();
+        let single_file = files.len() == 1 && files[0].0.len() == 1;
+        files
+            .into_iter()
+            .map(|(path, f)| match single_file {
+                true => (path, f),
+                false => (std::iter::once(self.name.clone()).chain(path).collect(), f),
+            })
+            .collect()
+    }
+
+    /// `pieces root` of every file in `files()` order. `None` for v1 torrents,
+    /// empty files and the pad files of hybrid torrents.
+    pub fn pieces_roots(&self) -> Vec<Option<Sha256Hash>> {
+        let mut roots = self.file_tree_entries().into_iter().map(|(_, f)| match f {
+            FileTree::File { pieces_root, .. } => pieces_root.as_slice().try_into().ok(),
+            FileTree::Dir(_) => None,
+        });
+        self.sanitized_files()
+            .iter()
+            .map(|f| match f.is_padding() {
+                true => None,
+                false => roots.next().flatten(),
+            })
+            .collect()
+    }
+}
+
+/// Symlink targets are relative to the torrent root and may not leave it.
+fn symlink_target(base: Option<&str>, components: &[String]) -> Option<PathBuf> {
+    let valid = !components.is_empty() && components.iter().all(|c| sanitize::is_safe_component(c));
+    if !valid {
+        return None;
+    }
+    let target = components.iter().collect::<PathBuf>();
+    Some(match base {
+        Some(base) => PathBuf::from(base).join(target),
+        None => target,
+    })
 }
@@ -0,0 +1,126 @@
+/// https://www.bittorrent.org/beps/bep_0052.html
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+pub const BLOCK_SIZE: usize = 16 * 1024;
+
+pub type Sha256Hash = [u8; 32];
+
+pub const ZERO_HASH: Sha256Hash = [0; 32];
+
+/// `piece layers` of the metainfo, keyed by the `pieces root` of each file.
+pub type PieceLayers = BTreeMap<Sha256Hash, Vec<Sha256Hash>>;
+
+#[inline]
+pub fn hash_pair(left: &Sha256Hash, right: &Sha256Hash) -> Sha256Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// SHA-256 of every 16 KiB block, the last one may be shorter.
+pub fn block_hashes(data: &[u8]) -> Vec<Sha256Hash> {
+    data.chunks(BLOCK_SIZE)
+        .map(|block| Sha256::digest(block).into())
+        .collect()
+}
+
+/// Root of a tree with `num_leaves` leaves, `hashes` followed by `pad`.
+/// `num_leaves` is rounded up to a power of two.
+pub fn merkle_root(hashes: &[Sha256Hash], num_leaves: usize, pad: Sha256Hash) -> Sha256Hash {
+    let num_leaves = num_leaves.max(hashes.len()).max(1).next_power_of_two();
+    let mut layer = hashes.to_vec();
+    let mut pad = pad;
+    let mut width = num_leaves;
+    while width > 1 {
+        if layer.len() % 2 == 1 {
+            layer.push(pad);
+        }
+        layer = layer
+            .chunks_exact(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+        pad = hash_pair(&pad, &pad);
+        width /= 2;
+    }
+    layer.first().copied().unwrap_or(pad)
+}
+
+/// Root of a subtree of `num_leaves` zero leaves.
+pub fn zero_root(num_leaves: usize) -> Sha256Hash {
+    merkle_root(&[], num_leaves, ZERO_HASH)
+}
+
+/// Hash of a piece in the piece layer, short pieces are padded with zero leaves.
+pub fn piece_hash(data: &[u8], piece_length: u64) -> Sha256Hash {
+    merkle_root(
+        &block_hashes(data),
+        piece_length as usize / BLOCK_SIZE,
+        ZERO_HASH,
+    )
+}
+
+/// `pieces root` of a file larger than one piece, from its piece layer.
+pub fn root_from_piece_layer(piece_layer: &[Sha256Hash], piece_length: u64) -> Sha256Hash {
+    merkle_root(
+        piece_layer,
+        piece_layer.len(),
+        zero_root(piece_length as usize / BLOCK_SIZE),
+    )
+}
+
+/// `pieces root` of a file that fits into a single piece.
+pub fn root_from_data(data: &[u8]) -> Sha256Hash {
+    let hashes = block_hashes(data);
+    merkle_root(&hashes, hashes.len(), ZERO_HASH)
+}
+
+/// Splits concatenated hashes, `None` if the length is not a multiple of 32.
+pub fn split_hashes(bytes: &[u8]) -> Option<Vec<Sha256Hash>> {
+    if !bytes.len().is_multiple_of(32) {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(32)
+            .map(|hash| hash.try_into().unwrap())
+            .collect(),
+    )
+}
+
+/// Every layer of the tree, leaves first and the root last.
+pub fn tree_layers(
+    hashes: &[Sha256Hash],
+    num_leaves: usize,
+    pad: Sha256Hash,
+) -> Vec<Vec<Sha256Hash>> {
+    let width = num_leaves.max(hashes.len()).max(1).next_power_of_two();
+    let mut layer = hashes.to_vec();
+    layer.resize(width, pad);
+    let mut layers = vec![layer];
+    while layers.last().unwrap().len() > 1 {
+        let next = layers
+            .last()
+            .unwrap()
+            .chunks_exact(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+        layers.push(next);
+    }
+    layers
+}
+
+/// Walks up from the node at `index` with its uncle hashes.
+pub fn root_from_proof(hash: Sha256Hash, index: usize, proof: &[Sha256Hash]) -> Sha256Hash {
+    let mut node = hash;
+    let mut index = index;
+    for uncle in proof {
+        node = match index % 2 {
+            0 => hash_pair(&node, uncle),
+            _ => hash_pair(uncle, &node),
+        };
+        index /= 2;
+    }
+    node
+}
@@ -1,9 +1,16 @@
 /// https://wiki.theory.org/BitTorrentSpecification#Metainfo_File_Structure
-use super::info::Info;
-use crate::error::Result;
+use super::{
+    info::Info,
+    merkle::{self, PieceLayers},
+    raw,
+};
+use crate::error::{Error, Result};
 use crate::proto::infohash::{InfoHash, InfoHashV1, InfoHashV2};
-use serde::Deserialize;
-use std::net::{IpAddr, SocketAddr};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+};
 
 type BencodeValue = serde_bencode::value::Value;
 
@@ -17,6 +24,10 @@ pub struct MetaInfo {
     #[serde(default, rename = "info")]
     info_value: Option<BencodeValue>,
 
+    /// The raw `info` dictionary as it was received, hashed and saved as is.
+    #[serde(skip)]
+    info_bytes: Option<Vec<u8>>,
+
     // info_bytes -> info_hash
     #[serde(skip)]
     info_hash: Option<InfoHash>,
@@ -28,7 +39,7 @@ pub struct MetaInfo {
     #[serde(default)]
     announce: Option<String>,
 
-    #[serde(default)]
+    #[serde(default, rename = "announce-list")]
     announce_list: Option<AnnounceList>,
 
     // nodes_value -> nodes
@@ -38,9 +49,26 @@ pub struct MetaInfo {
     #[serde(skip)]
     pub nodes: Option<Nodes>,
 
-    #[serde(default, rename = "url-list")]
+    // piece_layers_value -> piece_layers
+    #[serde(default, rename = "piece layers")]
+    piece_layers_value: Option<BencodeValue>,
+
+    /// BEP 52 piece layers, entries that are not 32-byte keys with whole hashes are dropped.
+    #[serde(skip)]
+    pub piece_layers: Option<PieceLayers>,
+
+    /// BEP 19 web seeds, a single url is stored as a list of one.
+    #[serde(
+        default,
+        rename = "url-list",
+        deserialize_with = "deserialize_url_list"
+    )]
     pub url_list: Option<Vec<String>>,
 
+    /// BEP 17 seed scripts.
+    #[serde(default)]
+    pub httpseeds: Option<Vec<String>>,
+
     #[serde(default, rename = "creation date")]
     pub creation_date: Option<i64>,
 
@@ -52,34 +80,80 @@ pub struct MetaInfo {
 
     #[serde(default)]
     pub encoding: Option<String>,
+
+    /// Unknown top level keys (e.g. `publisher`), written back by `to_bytes`.
+    #[serde(skip)]
+    extra: BTreeMap<Vec<u8>, BencodeValue>,
+}
+
+/// Top level keys that are written from the struct fields.
+const KNOWN_KEYS: [&str; 10] = [
+    "info",
+    "piece layers",
+    "announce",
+    "announce-list",
+    "url-list",
+    "httpseeds",
+    "creation date",
+    "comment",
+    "created by",
+    "encoding",
+];
+
+fn deserialize_url_list<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum UrlList {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<UrlList>::deserialize(deserializer)? {
+        Some(UrlList::One(url)) => Some(vec![url]),
+        Some(UrlList::Many(urls)) => Some(urls),
+        None => None,
+    })
 }
 
 impl MetaInfo {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         let mut metainfo = serde_bencode::from_bytes::<Self>(bytes)?;
 
-        if let Some(info_value) = metainfo.info_value.take() {
-            let info_bytes = serde_bencode::ser::to_bytes(&info_value)?;
-            metainfo.info = Info::from_bytes(&info_bytes)?;
-            metainfo.info_hash = Some(match metainfo.info.meta_version() {
-                2 => InfoHash::V2(InfoHashV2::from_bytes(&info_bytes)),
-                _ => InfoHash::V1(InfoHashV1::from_bytes(&info_bytes)),
-            })
+        if metainfo.info_value.take().is_some() {
+            let span = raw::dict_value_span(bytes, b"info")
+                .ok_or_else(|| Error::Custom("Invalid info dictionary".into()))?;
+            metainfo.set_info_bytes(bytes[span].to_vec())?;
+        }
+        if let BencodeValue::Dict(dict) = serde_bencode::from_bytes::<BencodeValue>(bytes)? {
+            metainfo.extra = dict
+                .into_iter()
+                .filter(|(key, _)| !KNOWN_KEYS.iter().any(|k| k.as_bytes() == key.as_slice()))
+                .collect();
+        }
+        if let Some(BencodeValue::Dict(dict)) = metainfo.piece_layers_value.take() {
+            let piece_layers = dict
+                .into_iter()
+                .filter_map(|(key, value)| match value {
+                    BencodeValue::Bytes(bytes) => {
+                        Some((key.try_into().ok()?, merkle::split_hashes(&bytes)?))
+                    }
+                    _ => None,
+                })
+                .collect();
+            metainfo.piece_layers = Some(piece_layers);
         }
         if let Some(nodes_value) = metainfo.nodes_value.take() {
             let nodes = nodes_value
                 .into_iter()
-                .map(|v| {
-                    let ip = v[0].parse::<IpAddr>();
-                    let port = v[1].parse::<u16>();
-                    if ip.is_ok() && port.is_ok() {
-                        Some(SocketAddr::new(ip.unwrap(), port.unwrap()))
-                    } else {
-                        None
-                    }
+                .filter_map(|v| {
+                    let ip = v[0].parse::<IpAddr>().ok()?;
+                    let port = v[1].parse::<u16>().ok()?;
+                    Some(SocketAddr::new(ip, port))
                 })
-                .filter(|v| v.is_some())
-                .map(Option::unwrap)
                 .collect::<Vec<_>>();
             metainfo.nodes.replace(nodes);
         }
@@ -87,6 +161,100 @@ impl MetaInfo {
         Ok(metainfo)
     }
 
+    /// Metainfo for an info dictionary fetched from peers (BEP 9).
+    pub fn from_info_bytes(info_bytes: Vec<u8>) -> Result<Self> {
+        let mut metainfo = Self::default();
+        metainfo.set_info_bytes(info_bytes)?;
+        Ok(metainfo)
+    }
+
+    fn set_info_bytes(&mut self, info_bytes: Vec<u8>) -> Result<()> {
+        self.info = Info::from_bytes(&info_bytes)?;
+        self.info_hash = Some(match self.info.meta_version() {
+            2 if self.info.is_hybrid() => InfoHash::Hybrid(
+                InfoHashV1::from_bytes(&info_bytes),
+                InfoHashV2::from_bytes(&info_bytes),
+            ),
+            2 => InfoHash::V2(InfoHashV2::from_bytes(&info_bytes)),
+            _ => InfoHash::V1(InfoHashV1::from_bytes(&info_bytes)),
+        });
+        self.info_bytes = Some(info_bytes);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn info_bytes(&self) -> Option<&[u8]> {
+        self.info_bytes.as_deref()
+    }
+
+    /// Bencoded torrent. The `info` dictionary is written exactly as it was
+    /// parsed, so the info hash does not change.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        fn encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+            Ok(serde_bencode::to_bytes(value)?)
+        }
+
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut insert = |key: &str, value: Vec<u8>| {
+            entries.insert(key.as_bytes().to_vec(), value);
+        };
+        if let Some(info_bytes) = self.info_bytes.as_ref() {
+            insert("info", info_bytes.clone());
+        }
+        if let Some(piece_layers) = self.piece_layers.as_ref() {
+            let dict = piece_layers
+                .iter()
+                .map(|(root, layer)| (root.to_vec(), BencodeValue::Bytes(layer.concat())))
+                .collect();
+            insert("piece layers", encode(&BencodeValue::Dict(dict))?);
+        }
+        if let Some(announce) = self.announce.as_ref() {
+            insert("announce", encode(announce)?);
+        }
+        if let Some(announce_list) = self.announce_list.as_ref() {
+            insert("announce-list", encode(announce_list)?);
+        }
+        if let Some(url_list) = self.url_list.as_ref() {
+            insert("url-list", encode(url_list)?);
+        }
+        if let Some(httpseeds) = self.httpseeds.as_ref() {
+            insert("httpseeds", encode(httpseeds)?);
+        }
+        if let Some(creation_date) = self.creation_date {
+            insert("creation date", encode(&creation_date)?);
+        }
+        if let Some(comment) = self.comment.as_ref() {
+            insert("comment", encode(comment)?);
+        }
+        if let Some(created_by) = self.created_by.as_ref() {
+            insert("created by", encode(created_by)?);
+        }
+        if let Some(encoding) = self.encoding.as_ref() {
+            insert("encoding", encode(encoding)?);
+        }
+        for (key, value) in self.extra.iter() {
+            entries.insert(key.clone(), encode(value)?);
+        }
+
+        let mut bytes = vec![b'd'];
+        for (key, value) in entries {
+            bytes.extend(format!("{}:", key.len()).as_bytes());
+            bytes.extend(key);
+            bytes.extend(value);
+        }
+        bytes.push(b'e');
+        Ok(bytes)
+    }
+
+    /// Replaces the trackers, `announce` is set to the first one.
+    pub fn set_announce_list(&mut self, announce_list: AnnounceList) {
+        self.announce = announce_list.iter().flatten().next().cloned();
+        self.announce_list = match announce_list.is_empty() {
+            true => None,
+            false => Some(announce_list),
+        };
+    }
+
     pub fn take_info_hash(&mut self) -> Option<InfoHash> {
         self.info_hash.take()
     }
@@ -96,6 +264,16 @@ impl MetaInfo {
         self.info_hash.as_ref().expect("")
     }
 
+    pub fn announce_list(&self) -> AnnounceList {
+        if let Some(announce_list) = self.announce_list.as_ref() {
+            return announce_list.clone();
+        }
+        match self.announce.as_ref() {
+            Some(announce) => vec![vec![announce.clone()]],
+            None => AnnounceList::new(),
+        }
+    }
+
     pub fn take_announce_list(&mut self) -> Option<AnnounceList> {
         if let Some(announce_list) = self.announce_list.take() {
             return Some(announce_list);
@@ -1,7 +1,13 @@
 /// https://wiki.theory.org/BitTorrentSpecification#Metainfo_File_Structure
+mod builder;
 pub mod file;
 mod info;
+pub mod merkle;
+#[allow(clippy::module_inception)]
 mod metainfo;
+mod raw;
+pub mod sanitize;
 
+pub use builder::{TorrentBuilder, TorrentVersion};
 pub use info::Info;
 pub use metainfo::{AnnounceList, MetaInfo};
@@ -0,0 +1,52 @@
+use std::ops::Range;
+
+const MAX_DEPTH: usize = 256;
+
+/// Length of the bencoded value at the start of `bytes`.
+fn value_len(bytes: &[u8]) -> Option<usize> {
+    nested_value_len(bytes, 0)
+}
+
+fn nested_value_len(bytes: &[u8], depth: usize) -> Option<usize> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    match bytes.first()? {
+        b'i' => Some(bytes.iter().position(|&b| b == b'e')? + 1),
+        b'l' | b'd' => {
+            let mut pos = 1;
+            while *bytes.get(pos)? != b'e' {
+                pos += nested_value_len(&bytes[pos..], depth + 1)?;
+            }
+            Some(pos + 1)
+        }
+        b'0'..=b'9' => {
+            let colon = bytes.iter().position(|&b| b == b':')?;
+            let len: usize = std::str::from_utf8(&bytes[..colon]).ok()?.parse().ok()?;
+            let end = (colon + 1).checked_add(len)?;
+            (end <= bytes.len()).then_some(end)
+        }
+        _ => None,
+    }
+}
+
+/// Byte range of the value stored under `key` in the top level dictionary.
+pub(crate) fn dict_value_span(bytes: &[u8], key: &[u8]) -> Option<Range<usize>> {
+    if bytes.first() != Some(&b'd') {
+        return None;
+    }
+    let mut pos = 1;
+    while *bytes.get(pos)? != b'e' {
+        let key_len = value_len(&bytes[pos..])?;
+        let colon = bytes[pos..].iter().position(|&b| b == b':')?;
+        let current_key = &bytes[pos + colon + 1..pos + key_len];
+        pos += key_len;
+
+        let len = value_len(&bytes[pos..])?;
+        if current_key == key {
+            return Some(pos..pos + len);
+        }
+        pos += len;
+    }
+    None
+}
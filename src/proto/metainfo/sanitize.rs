@@ -0,0 +1,112 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Longest file name most file systems accept, in bytes.
+pub const MAX_COMPONENT_LEN: usize = 255;
+/// Extensions up to this length survive truncation.
+const MAX_EXTENSION_LEN: usize = 16;
+
+/// Makes a single path component safe: separators, NUL and control characters
+/// are replaced, `.` and `..` become `_` and long names are truncated.
+pub fn sanitize_component(component: &str) -> String {
+    let mut name: String = component
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            #[cfg(windows)]
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+    #[cfg(windows)]
+    {
+        name.truncate(name.trim_end_matches([' ', '.']).len());
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        name = String::from("_");
+    }
+    truncate_component(name)
+}
+
+/// Cuts the name at a char boundary, keeping a short extension.
+fn truncate_component(name: String) -> String {
+    if name.len() <= MAX_COMPONENT_LEN {
+        return name;
+    }
+    let extension = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_LEN => &name[dot..],
+        _ => "",
+    };
+    let mut end = MAX_COMPONENT_LEN - extension.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &name[..end], extension)
+}
+
+/// Joins sanitized components. Empty components are dropped, a path without
+/// any component becomes `_`.
+pub fn sanitize_path<S: AsRef<str>>(components: &[S]) -> PathBuf {
+    let path: PathBuf = components
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|c| !c.is_empty())
+        .map(sanitize_component)
+        .collect();
+    match path.as_os_str().is_empty() {
+        true => PathBuf::from("_"),
+        false => path,
+    }
+}
+
+/// Component that can be joined as is, used for symlink targets.
+pub fn is_safe_component(component: &str) -> bool {
+    !component.is_empty() && sanitize_component(component) == component
+}
+
+/// Renames files whose path is already taken by another file or by a directory
+/// holding other files: `name.ext` becomes `name.1.ext`.
+pub fn dedup_paths<'a, I>(paths: I)
+where
+    I: IntoIterator<Item = &'a mut PathBuf>,
+{
+    let mut paths: Vec<_> = paths.into_iter().collect();
+    let dirs: HashSet<PathBuf> = paths
+        .iter()
+        .flat_map(|p| p.ancestors().skip(1))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .collect();
+    let mut used = HashSet::new();
+    for path in paths.iter_mut() {
+        let mut candidate = path.to_path_buf();
+        let mut n = 1;
+        while dirs.contains(&candidate) || used.contains(&candidate) {
+            candidate = numbered(path, n);
+            n += 1;
+        }
+        used.insert(candidate.clone());
+        **path = candidate;
+    }
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let suffix = match path.extension() {
+        Some(ext) => format!(".{n}.{}", ext.to_string_lossy()),
+        None => format!(".{n}"),
+    };
+    let mut end = stem
+        .len()
+        .min(MAX_COMPONENT_LEN.saturating_sub(suffix.len()));
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    path.with_file_name(format!("{}{suffix}", &stem[..end]))
+}
@@ -5,16 +5,22 @@ mod bitfield;
 pub mod constants;
 pub mod dht;
 mod handshake;
+mod hashes;
 pub mod infohash;
+pub mod lsd;
 mod magnet;
 mod message;
 pub mod metainfo;
+pub mod natpmp;
+pub mod pcp;
 mod peerid;
 mod piece;
 mod request;
+pub mod upnp;
 
 pub use bitfield::*;
 pub use handshake::*;
+pub use hashes::*;
 pub use magnet::*;
 pub use message::*;
 pub use peerid::*;
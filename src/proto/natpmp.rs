@@ -0,0 +1,203 @@
+/// <https://www.rfc-editor.org/rfc/rfc6886>
+use crate::{
+    error::{Error, Result},
+    proto::constants::NATPMP_VERSION,
+};
+use std::net::Ipv4Addr;
+
+const NATPMP_OP_EXTERNAL_ADDRESS: u8 = 0;
+const NATPMP_OP_MAP_UDP: u8 = 1;
+const NATPMP_OP_MAP_TCP: u8 = 2;
+/// Added to the opcode of the request in its response.
+const NATPMP_OP_RESPONSE: u8 = 128;
+const NATPMP_SUCCESS: u16 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MappingProtocol {
+    Tcp,
+    Udp,
+}
+
+impl MappingProtocol {
+    /// IANA protocol number, used by PCP.
+    pub fn number(&self) -> u8 {
+        match self {
+            Self::Tcp => 6,
+            Self::Udp => 17,
+        }
+    }
+
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            6 => Some(Self::Tcp),
+            17 => Some(Self::Udp),
+            _ => None,
+        }
+    }
+
+    /// Name used by UPnP.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tcp => "TCP",
+            Self::Udp => "UDP",
+        }
+    }
+
+    fn natpmp_opcode(&self) -> u8 {
+        match self {
+            Self::Tcp => NATPMP_OP_MAP_TCP,
+            Self::Udp => NATPMP_OP_MAP_UDP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NatPmpRequest {
+    ExternalAddress,
+    /// A lifetime of `0` deletes the mapping.
+    Map {
+        protocol: MappingProtocol,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    },
+}
+
+impl NatPmpRequest {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Self::ExternalAddress => NATPMP_OP_EXTERNAL_ADDRESS,
+            Self::Map { protocol, .. } => protocol.natpmp_opcode(),
+        }
+    }
+
+    pub fn response_opcode(&self) -> u8 {
+        NATPMP_OP_RESPONSE + self.opcode()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![NATPMP_VERSION, self.opcode()];
+        if let Self::Map {
+            internal_port,
+            external_port,
+            lifetime,
+            ..
+        } = self
+        {
+            bytes.extend([0, 0]);
+            bytes.extend(internal_port.to_be_bytes());
+            bytes.extend(external_port.to_be_bytes());
+            bytes.extend(lifetime.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 2 || bytes[0] != NATPMP_VERSION {
+            return Err(Error::PortMapping("not a NAT-PMP request".into()));
+        }
+        if bytes[1] == NATPMP_OP_EXTERNAL_ADDRESS {
+            return Ok(Self::ExternalAddress);
+        }
+        let protocol = match bytes[1] {
+            NATPMP_OP_MAP_TCP => MappingProtocol::Tcp,
+            NATPMP_OP_MAP_UDP => MappingProtocol::Udp,
+            opcode => return Err(Error::PortMapping(format!("unknown opcode {opcode}"))),
+        };
+        if bytes.len() < 12 {
+            return Err(Error::PortMapping("NAT-PMP request too short".into()));
+        }
+        Ok(Self::Map {
+            protocol,
+            internal_port: u16::from_be_bytes([bytes[4], bytes[5]]),
+            external_port: u16::from_be_bytes([bytes[6], bytes[7]]),
+            lifetime: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NatPmpResponse {
+    ExternalAddress {
+        /// Seconds since the gateway started, a drop means it lost its mappings.
+        epoch: u32,
+        addr: Ipv4Addr,
+    },
+    Map {
+        protocol: MappingProtocol,
+        epoch: u32,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    },
+}
+
+impl NatPmpResponse {
+    pub fn opcode(&self) -> u8 {
+        NATPMP_OP_RESPONSE
+            + match self {
+                Self::ExternalAddress { .. } => NATPMP_OP_EXTERNAL_ADDRESS,
+                Self::Map { protocol, .. } => protocol.natpmp_opcode(),
+            }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![NATPMP_VERSION, self.opcode()];
+        bytes.extend(NATPMP_SUCCESS.to_be_bytes());
+        match self {
+            Self::ExternalAddress { epoch, addr } => {
+                bytes.extend(epoch.to_be_bytes());
+                bytes.extend(addr.octets());
+            }
+            Self::Map {
+                epoch,
+                internal_port,
+                external_port,
+                lifetime,
+                ..
+            } => {
+                bytes.extend(epoch.to_be_bytes());
+                bytes.extend(internal_port.to_be_bytes());
+                bytes.extend(external_port.to_be_bytes());
+                bytes.extend(lifetime.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Responses with a result code other than success are errors.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 8 || bytes[0] != NATPMP_VERSION || bytes[1] < NATPMP_OP_RESPONSE {
+            return Err(Error::PortMapping("not a NAT-PMP response".into()));
+        }
+        let result_code = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if result_code != NATPMP_SUCCESS {
+            return Err(Error::PortMapping(format!(
+                "NAT-PMP result code {result_code}"
+            )));
+        }
+        let epoch = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        let protocol = match bytes[1] - NATPMP_OP_RESPONSE {
+            NATPMP_OP_EXTERNAL_ADDRESS if bytes.len() >= 12 => {
+                let octets: [u8; 4] = bytes[8..12].try_into().unwrap();
+                return Ok(Self::ExternalAddress {
+                    epoch,
+                    addr: Ipv4Addr::from(octets),
+                });
+            }
+            NATPMP_OP_MAP_TCP => MappingProtocol::Tcp,
+            NATPMP_OP_MAP_UDP => MappingProtocol::Udp,
+            _ => return Err(Error::PortMapping("invalid NAT-PMP response".into())),
+        };
+        if bytes.len() < 16 {
+            return Err(Error::PortMapping("NAT-PMP response too short".into()));
+        }
+        Ok(Self::Map {
+            protocol,
+            epoch,
+            internal_port: u16::from_be_bytes([bytes[8], bytes[9]]),
+            external_port: u16::from_be_bytes([bytes[10], bytes[11]]),
+            lifetime: u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+        })
+    }
+}
@@ -0,0 +1,122 @@
+/// <https://www.rfc-editor.org/rfc/rfc6887>
+use crate::{
+    error::{Error, Result},
+    proto::{constants::PCP_VERSION, natpmp::MappingProtocol},
+};
+use std::net::{IpAddr, Ipv6Addr};
+
+const PCP_OP_MAP: u8 = 1;
+/// Set in the opcode byte of responses.
+const PCP_RESPONSE_BIT: u8 = 0x80;
+const PCP_SUCCESS: u8 = 0;
+pub const PCP_MAP_LEN: usize = 60;
+
+fn ip_bytes(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> IpAddr {
+    let octets: [u8; 16] = bytes.try_into().unwrap();
+    IpAddr::V6(Ipv6Addr::from(octets)).to_canonical()
+}
+
+/// A lifetime of `0` deletes the mapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcpMapRequest {
+    pub lifetime: u32,
+    /// Address the gateway sees the request coming from.
+    pub client_ip: IpAddr,
+    /// Identifies the mapping in later requests and in responses.
+    pub nonce: [u8; 12],
+    pub protocol: MappingProtocol,
+    pub internal_port: u16,
+    pub external_port: u16,
+    /// Unspecified to let the gateway pick.
+    pub external_ip: IpAddr,
+}
+
+impl PcpMapRequest {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![PCP_VERSION, PCP_OP_MAP, 0, 0];
+        bytes.extend(self.lifetime.to_be_bytes());
+        bytes.extend(ip_bytes(self.client_ip));
+        bytes.extend(self.nonce);
+        bytes.extend([self.protocol.number(), 0, 0, 0]);
+        bytes.extend(self.internal_port.to_be_bytes());
+        bytes.extend(self.external_port.to_be_bytes());
+        bytes.extend(ip_bytes(self.external_ip));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < PCP_MAP_LEN || bytes[0] != PCP_VERSION || bytes[1] != PCP_OP_MAP {
+            return Err(Error::PortMapping("not a PCP MAP request".into()));
+        }
+        Ok(Self {
+            lifetime: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            client_ip: ip_from_bytes(&bytes[8..24]),
+            nonce: bytes[24..36].try_into().unwrap(),
+            protocol: MappingProtocol::from_number(bytes[36])
+                .ok_or_else(|| Error::PortMapping(format!("unknown protocol {}", bytes[36])))?,
+            internal_port: u16::from_be_bytes([bytes[40], bytes[41]]),
+            external_port: u16::from_be_bytes([bytes[42], bytes[43]]),
+            external_ip: ip_from_bytes(&bytes[44..60]),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcpMapResponse {
+    pub lifetime: u32,
+    /// Seconds since the gateway started, a drop means it lost its mappings.
+    pub epoch: u32,
+    pub nonce: [u8; 12],
+    pub protocol: MappingProtocol,
+    pub internal_port: u16,
+    pub external_port: u16,
+    pub external_ip: IpAddr,
+}
+
+impl PcpMapResponse {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![PCP_VERSION, PCP_OP_MAP | PCP_RESPONSE_BIT, 0, PCP_SUCCESS];
+        bytes.extend(self.lifetime.to_be_bytes());
+        bytes.extend(self.epoch.to_be_bytes());
+        bytes.extend([0; 12]);
+        bytes.extend(self.nonce);
+        bytes.extend([self.protocol.number(), 0, 0, 0]);
+        bytes.extend(self.internal_port.to_be_bytes());
+        bytes.extend(self.external_port.to_be_bytes());
+        bytes.extend(ip_bytes(self.external_ip));
+        bytes
+    }
+
+    /// Responses with a result code other than success are errors.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 4 || bytes[0] != PCP_VERSION {
+            return Err(Error::PortMapping("not a PCP response".into()));
+        }
+        if bytes[1] != PCP_OP_MAP | PCP_RESPONSE_BIT {
+            return Err(Error::PortMapping("not a PCP MAP response".into()));
+        }
+        if bytes[3] != PCP_SUCCESS {
+            return Err(Error::PortMapping(format!("PCP result code {}", bytes[3])));
+        }
+        if bytes.len() < PCP_MAP_LEN {
+            return Err(Error::PortMapping("PCP response too short".into()));
+        }
+        Ok(Self {
+            lifetime: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            epoch: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+            nonce: bytes[24..36].try_into().unwrap(),
+            protocol: MappingProtocol::from_number(bytes[36])
+                .ok_or_else(|| Error::PortMapping(format!("unknown protocol {}", bytes[36])))?,
+            internal_port: u16::from_be_bytes([bytes[40], bytes[41]]),
+            external_port: u16::from_be_bytes([bytes[42], bytes[43]]),
+            external_ip: ip_from_bytes(&bytes[44..60]),
+        })
+    }
+}
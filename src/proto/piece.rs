@@ -36,6 +36,7 @@ impl Piece {
         buf
     }
 
+    #[allow(clippy::len_without_is_empty)]
     #[inline]
     pub fn len(&self) -> usize {
         8 + self.block.len()
@@ -10,9 +10,9 @@ pub struct Request {
 impl Request {
     pub fn new(index: u32, begin: u32, length: u32) -> Self {
         Self {
-            index: index,
-            begin: begin,
-            length: length,
+            index,
+            begin,
+            length,
         }
     }
 
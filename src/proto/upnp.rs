@@ -0,0 +1,115 @@
+/// <https://upnp.org/specs/gw/UPnP-gw-WANIPConnection-v2-Service.pdf>
+use crate::error::{Error, Result};
+use reqwest::Url;
+
+pub const UPNP_IGD_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+/// Services that map ports, in order of preference.
+const UPNP_WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// SSDP `M-SEARCH` for Internet Gateway Devices, sent to `host`.
+pub fn ssdp_search(host: &str) -> Vec<u8> {
+    format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {host}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\n\
+         ST: {UPNP_IGD_SEARCH_TARGET}\r\n\r\n"
+    )
+    .into_bytes()
+}
+
+/// URL of the device description of an SSDP search response.
+pub fn ssdp_location(bytes: &[u8]) -> Option<String> {
+    let response = std::str::from_utf8(bytes).ok()?;
+    let mut lines = response.split("\r\n");
+    if !lines.next()?.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+    lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// Text of the first `tag` element, namespace prefixes and attributes are
+/// ignored.
+pub fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find('>')?;
+        let name = rest[..end].split_whitespace().next().unwrap_or_default();
+        let local = name.rsplit(':').next().unwrap_or_default();
+        if local == tag && !name.starts_with('/') {
+            let content = &rest[end + 1..];
+            let close = content.find("</")?;
+            return Some(content[..close].trim());
+        }
+    }
+    None
+}
+
+/// `errorCode` of a SOAP fault.
+pub fn soap_error_code(xml: &str) -> Option<u16> {
+    xml_value(xml, "errorCode")?.parse().ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgdService {
+    pub service_type: String,
+    pub control_url: Url,
+}
+
+impl IgdService {
+    /// The preferred WAN connection service of a device description fetched
+    /// from `location`.
+    pub fn from_description(xml: &str, location: &Url) -> Result<Self> {
+        let base = match xml_value(xml, "URLBase") {
+            Some(base) => Url::parse(base).unwrap_or_else(|_| location.clone()),
+            None => location.clone(),
+        };
+        let services = xml
+            .split("<service>")
+            .skip(1)
+            .filter_map(|service| {
+                let service_type = xml_value(service, "serviceType")?;
+                let control_url = xml_value(service, "controlURL")?;
+                Some((service_type, control_url))
+            })
+            .collect::<Vec<_>>();
+        for wanted in UPNP_WAN_SERVICES {
+            if let Some((service_type, control_url)) = services.iter().find(|s| s.0 == wanted) {
+                let control_url = base
+                    .join(control_url)
+                    .map_err(|e| Error::PortMapping(format!("invalid control URL: {e}")))?;
+                return Ok(Self {
+                    service_type: service_type.to_string(),
+                    control_url,
+                });
+            }
+        }
+        Err(Error::PortMapping("no WAN connection service".into()))
+    }
+
+    /// Value of the `SOAPAction` header.
+    pub fn soap_action(&self, action: &str) -> String {
+        format!("\"{}#{action}\"", self.service_type)
+    }
+
+    pub fn soap_body(&self, action: &str, args: &[(&str, String)]) -> String {
+        let mut body = format!(
+            "<?xml version=\"1.0\"?>\r\n<s:Envelope \
+             xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:{action} xmlns:u=\"{}\">",
+            self.service_type
+        );
+        for (name, value) in args {
+            body.push_str(&format!("<{name}>{value}</{name}>"));
+        }
+        body.push_str(&format!("</u:{action}></s:Body></s:Envelope>"));
+        body
+    }
+}
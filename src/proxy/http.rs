@@ -0,0 +1,52 @@
+/// <https://www.rfc-editor.org/rfc/rfc9110#name-connect>
+use crate::{
+    error::{Error, Result},
+    proxy::Proxy,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const MAX_RESPONSE_HEADER_LEN: usize = 8 * 1024;
+
+pub(crate) async fn http_connect(
+    stream: &mut TcpStream,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> Result<()> {
+    let authority = match host.contains(':') && !host.starts_with('[') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    };
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let (Some(username), Some(password)) = (proxy.username.as_ref(), proxy.password.as_ref()) {
+        let credentials = STANDARD.encode(format!("{username}:{password}"));
+        request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // byte by byte so nothing of the tunnel is read
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_HEADER_LEN {
+            return Err(Error::Proxy("response header too long".into()));
+        }
+        header.push(stream.read_u8().await?);
+    }
+    let status_line = String::from_utf8_lossy(&header);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        _ => Err(Error::Proxy(format!(
+            "CONNECT refused: {}",
+            status_line.lines().next().unwrap_or_default()
+        ))),
+    }
+}
@@ -0,0 +1,8 @@
+mod http;
+#[allow(clippy::module_inception)]
+mod proxy;
+mod socks5;
+
+pub(crate) use http::*;
+pub use proxy::*;
+pub use socks5::*;
@@ -0,0 +1,113 @@
+use crate::{
+    error::{Error, Result},
+    proxy::{http_connect, socks5_connect, socks5_udp_associate, Socks5Datagram},
+};
+use reqwest::ClientBuilder;
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    Socks5,
+    /// HTTP CONNECT tunnels, TCP only.
+    Http,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    /// `host:port` of the proxy server.
+    pub addr: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Proxy {
+    pub fn socks5<S: Into<String>>(addr: S) -> Self {
+        Self::new(ProxyKind::Socks5, addr)
+    }
+
+    pub fn http<S: Into<String>>(addr: S) -> Self {
+        Self::new(ProxyKind::Http, addr)
+    }
+
+    fn new<S: Into<String>>(kind: ProxyKind, addr: S) -> Self {
+        Self {
+            kind,
+            addr: addr.into(),
+            username: None,
+            password: None,
+        }
+    }
+
+    pub fn auth<S: Into<String>>(mut self, username: S, password: S) -> Self {
+        self.username = Some(username.into());
+        self.password = Some(password.into());
+        self
+    }
+
+    #[inline]
+    pub fn supports_udp(&self) -> bool {
+        self.kind == ProxyKind::Socks5
+    }
+
+    /// TCP connection to `host:port` tunnelled through the proxy, host names
+    /// are resolved by the proxy.
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect(self.addr.as_str()).await?;
+        match self.kind {
+            ProxyKind::Socks5 => socks5_connect(&mut stream, self, host, port).await?,
+            ProxyKind::Http => http_connect(&mut stream, self, host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    /// SOCKS5 UDP ASSOCIATE, for UDP trackers and the DHT.
+    pub async fn udp_associate(&self) -> Result<Socks5Datagram> {
+        match self.kind {
+            ProxyKind::Socks5 => socks5_udp_associate(self).await,
+            ProxyKind::Http => Err(Error::Proxy("HTTP proxies can't relay UDP".into())),
+        }
+    }
+
+    /// Routes every request of the client through the proxy, SOCKS5 proxies
+    /// resolve host names themselves.
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder> {
+        let scheme = match self.kind {
+            ProxyKind::Socks5 => "socks5h",
+            ProxyKind::Http => "http",
+        };
+        let mut proxy = reqwest::Proxy::all(format!("{scheme}://{}", self.addr))?;
+        if let (Some(username), Some(password)) = (self.username.as_ref(), self.password.as_ref()) {
+            proxy = proxy.basic_auth(username, password);
+        }
+        Ok(builder.proxy(proxy))
+    }
+}
+
+/// `builder` with `proxy` applied when set.
+pub fn proxied_client(builder: ClientBuilder, proxy: Option<&Proxy>) -> Result<reqwest::Client> {
+    let builder = match proxy {
+        Some(proxy) => proxy.apply(builder)?,
+        None => builder,
+    };
+    Ok(builder.build()?)
+}
+
+/// Proxies per traffic class, `None` connects directly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxySettings {
+    /// Peer connections and, with SOCKS5, the DHT.
+    pub peers: Option<Proxy>,
+    pub trackers: Option<Proxy>,
+    pub web_seeds: Option<Proxy>,
+}
+
+impl ProxySettings {
+    pub fn all(proxy: Proxy) -> Self {
+        Self {
+            peers: Some(proxy.clone()),
+            trackers: Some(proxy.clone()),
+            web_seeds: Some(proxy),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+/// <https://www.rfc-editor.org/rfc/rfc1928>
+use crate::{
+    error::{Error, Result},
+    proxy::Proxy,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTH: u8 = 0;
+const SOCKS5_USER_PASS_AUTH: u8 = 2;
+const SOCKS5_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+/// <https://www.rfc-editor.org/rfc/rfc1929>
+const SOCKS5_USER_PASS_VERSION: u8 = 1;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_CMD_UDP_ASSOCIATE: u8 = 3;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+const SOCKS5_SUCCEEDED: u8 = 0;
+
+fn write_addr(buf: &mut Vec<u8>, host: &str, port: u16) -> Result<()> {
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(IpAddr::V4(ip)) => {
+            buf.push(SOCKS5_ATYP_IPV4);
+            buf.extend(ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            buf.push(SOCKS5_ATYP_IPV6);
+            buf.extend(ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| Error::Proxy(format!("host name too long: {host}")))?;
+            buf.push(SOCKS5_ATYP_DOMAIN);
+            buf.push(len);
+            buf.extend(host.as_bytes());
+        }
+    }
+    buf.extend(port.to_be_bytes());
+    Ok(())
+}
+
+/// Domain names are not resolved, the relay only reports addresses to us.
+async fn read_addr<R: AsyncRead + Unpin>(reader: &mut R) -> Result<SocketAddr> {
+    let ip = match reader.read_u8().await? {
+        SOCKS5_ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            reader.read_exact(&mut octets).await?;
+            IpAddr::from(Ipv4Addr::from(octets))
+        }
+        SOCKS5_ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            reader.read_exact(&mut octets).await?;
+            IpAddr::from(Ipv6Addr::from(octets))
+        }
+        SOCKS5_ATYP_DOMAIN => {
+            let len = reader.read_u8().await? as usize;
+            let mut domain = vec![0u8; len];
+            reader.read_exact(&mut domain).await?;
+            IpAddr::from(Ipv4Addr::UNSPECIFIED)
+        }
+        atyp => return Err(Error::Proxy(format!("unknown address type {atyp}"))),
+    };
+    Ok(SocketAddr::new(ip, reader.read_u16().await?))
+}
+
+async fn authenticate(stream: &mut TcpStream, proxy: &Proxy) -> Result<()> {
+    let credentials = proxy.username.as_deref().zip(proxy.password.as_deref());
+    let method = match credentials {
+        Some(_) => SOCKS5_USER_PASS_AUTH,
+        None => SOCKS5_NO_AUTH,
+    };
+    stream.write_all(&[SOCKS5_VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS5_VERSION || reply[1] == SOCKS5_NO_ACCEPTABLE_METHOD {
+        return Err(Error::Proxy("no acceptable authentication method".into()));
+    }
+    let Some((username, password)) = credentials.filter(|_| reply[1] == SOCKS5_USER_PASS_AUTH)
+    else {
+        return Ok(());
+    };
+
+    let mut request = vec![SOCKS5_USER_PASS_VERSION];
+    for field in [username, password] {
+        let len =
+            u8::try_from(field.len()).map_err(|_| Error::Proxy("credentials too long".into()))?;
+        request.push(len);
+        request.extend(field.as_bytes());
+    }
+    stream.write_all(&request).await?;
+    stream.read_exact(&mut reply).await?;
+    match reply[1] {
+        SOCKS5_SUCCEEDED => Ok(()),
+        _ => Err(Error::Proxy("authentication failed".into())),
+    }
+}
+
+/// Returns the bound address of the reply.
+async fn request(
+    stream: &mut TcpStream,
+    proxy: &Proxy,
+    command: u8,
+    host: &str,
+    port: u16,
+) -> Result<SocketAddr> {
+    authenticate(stream, proxy).await?;
+    let mut buf = vec![SOCKS5_VERSION, command, 0];
+    write_addr(&mut buf, host, port)?;
+    stream.write_all(&buf).await?;
+
+    let mut reply = [0u8; 3];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS5_VERSION || reply[1] != SOCKS5_SUCCEEDED {
+        return Err(Error::Proxy(format!(
+            "request failed with reply {}",
+            reply[1]
+        )));
+    }
+    read_addr(stream).await
+}
+
+pub(crate) async fn socks5_connect(
+    stream: &mut TcpStream,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> Result<()> {
+    request(stream, proxy, SOCKS5_CMD_CONNECT, host, port).await?;
+    Ok(())
+}
+
+pub(crate) async fn socks5_udp_associate(proxy: &Proxy) -> Result<Socks5Datagram> {
+    let mut control = TcpStream::connect(proxy.addr.as_str()).await?;
+    let proxy_addr = control.peer_addr()?;
+    let unspecified = match proxy_addr {
+        SocketAddr::V4(_) => "0.0.0.0",
+        SocketAddr::V6(_) => "::",
+    };
+    let mut relay = request(
+        &mut control,
+        proxy,
+        SOCKS5_CMD_UDP_ASSOCIATE,
+        unspecified,
+        0,
+    )
+    .await?;
+    // relays bound to all interfaces report an unspecified address
+    if relay.ip().is_unspecified() {
+        relay.set_ip(proxy_addr.ip());
+    }
+    let socket = UdpSocket::bind((control.local_addr()?.ip(), 0)).await?;
+    Ok(Socks5Datagram {
+        _control: control,
+        socket,
+        relay,
+    })
+}
+
+/// UDP relayed by a SOCKS5 proxy, the association lasts as long as this
+/// value.
+#[derive(Debug)]
+pub struct Socks5Datagram {
+    _control: TcpStream,
+    socket: UdpSocket,
+    relay: SocketAddr,
+}
+
+impl Socks5Datagram {
+    #[inline]
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay
+    }
+
+    pub async fn send_to(&self, buf: &[u8], host: &str, port: u16) -> Result<usize> {
+        let mut packet = vec![0, 0, 0];
+        write_addr(&mut packet, host, port)?;
+        packet.extend(buf);
+        self.socket.send_to(&packet, self.relay).await?;
+        Ok(buf.len())
+    }
+
+    /// Returns the payload length and the address it came from, fragmented
+    /// datagrams are dropped.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let mut packet = vec![0u8; buf.len() + 262];
+        loop {
+            let (n, from) = self.socket.recv_from(&mut packet).await?;
+            if from != self.relay || n < 4 || packet[2] != 0 {
+                continue;
+            }
+            let mut reader = &packet[3..n];
+            let Ok(addr) = read_addr(&mut reader).await else {
+                continue;
+            };
+            let len = reader.len().min(buf.len());
+            buf[..len].copy_from_slice(&reader[..len]);
+            return Ok((len, addr));
+        }
+    }
+}
@@ -1,2 +1,51 @@
+use crate::{
+    session::PortMapping,
+    torrent::{SeedingGoal, SeedingGoalAction, TorrentID, TorrentStatus},
+};
+use std::net::{IpAddr, SocketAddr};
+
 #[derive(Debug, Clone)]
-pub enum SessionAlert {}
+pub enum SessionAlert {
+    TorrentAdded(TorrentID),
+    TorrentRemoved(TorrentID),
+    TorrentStatusChanged {
+        torrent_id: TorrentID,
+        status: TorrentStatus,
+    },
+    TorrentError {
+        torrent_id: TorrentID,
+        error: String,
+    },
+    SeedingGoalReached {
+        torrent_id: TorrentID,
+        goal: SeedingGoal,
+        action: SeedingGoalAction,
+    },
+    /// A piece with a deadline has been downloaded, `late` if it missed the deadline.
+    PieceDeadlineReady {
+        torrent_id: TorrentID,
+        piece: usize,
+        late: bool,
+    },
+    /// A peer connection was handed to the torrent.
+    PeerConnected {
+        torrent_id: TorrentID,
+        addr: SocketAddr,
+    },
+    /// The IP filter turned more peer addresses or connections down,
+    /// `blocked` counts all such attempts of the session.
+    PeersBlocked {
+        blocked: u64,
+    },
+    /// The peer sent data that failed a hash check, it stays blocked for the
+    /// session.
+    PeerBanned(IpAddr),
+    IpFilterReloaded {
+        num_ranges: usize,
+    },
+    IpFilterError(String),
+    /// A listen port was mapped on the gateway, or its mapping changed on
+    /// renewal.
+    PortMapped(PortMapping),
+    PortMappingError(String),
+}
@@ -1,13 +1,37 @@
-use crate::session::state::SessionState;
+use crate::{
+    disk,
+    peers::IpFilter,
+    session::{background::update_queue, state::SessionState, QueueSettings, SessionAlert},
+    torrent::{
+        SeedingLimits, TorrentCommand, TorrentHandle, TorrentID, TorrentSource, TorrentSpawnParams,
+        TorrentStatus,
+    },
+};
 use std::sync::Arc;
 use tokio::{
     sync::mpsc::{self, Sender},
     task::JoinHandle,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum SessionCommand {
-    // AddTorrent(int),
+    AddTorrent(Box<TorrentSource>),
+    RemoveTorrent(TorrentID),
+    RemoveTorrentWithFiles(TorrentID),
+    PauseTorrent(TorrentID),
+    ResumeTorrent(TorrentID),
+    QueueMoveUp(TorrentID),
+    QueueMoveDown(TorrentID),
+    QueueMoveTop(TorrentID),
+    QueueMoveBottom(TorrentID),
+    SetQueueSettings(QueueSettings),
+    SetDefaultSeedingLimits(SeedingLimits),
+    /// Reads the `ip_filter` file of the settings again.
+    ReloadIpFilter,
+    SendToTorrent {
+        torrent_id: TorrentID,
+        command: TorrentCommand,
+    },
 }
 
 pub async fn spawn_command_handler(
@@ -17,9 +41,138 @@ pub async fn spawn_command_handler(
 
     let jh = tokio::spawn(async move {
         while let Some(command) = rx.recv().await {
-            match command {}
+            match command {
+                SessionCommand::AddTorrent(source) => add_torrent(&state, *source).await,
+                SessionCommand::RemoveTorrent(torrent_id) => {
+                    remove_torrent(&state, &torrent_id, false).await
+                }
+                SessionCommand::RemoveTorrentWithFiles(torrent_id) => {
+                    remove_torrent(&state, &torrent_id, true).await
+                }
+                SessionCommand::PauseTorrent(torrent_id) => {
+                    set_torrent_status(&state, &torrent_id, TorrentStatus::Stopped).await
+                }
+                SessionCommand::ResumeTorrent(torrent_id) => {
+                    set_torrent_status(&state, &torrent_id, TorrentStatus::Waiting).await
+                }
+                SessionCommand::QueueMoveUp(torrent_id) => {
+                    state.queue.lock().await.move_up(&torrent_id);
+                }
+                SessionCommand::QueueMoveDown(torrent_id) => {
+                    state.queue.lock().await.move_down(&torrent_id);
+                }
+                SessionCommand::QueueMoveTop(torrent_id) => {
+                    state.queue.lock().await.move_top(&torrent_id);
+                }
+                SessionCommand::QueueMoveBottom(torrent_id) => {
+                    state.queue.lock().await.move_bottom(&torrent_id);
+                }
+                SessionCommand::SetQueueSettings(settings) => {
+                    state.queue.lock().await.set_settings(settings);
+                }
+                SessionCommand::SetDefaultSeedingLimits(limits) => {
+                    *state.seeding_limits.lock().await = limits;
+                }
+                SessionCommand::ReloadIpFilter => reload_ip_filter(&state).await,
+                SessionCommand::SendToTorrent {
+                    torrent_id,
+                    command,
+                } => {
+                    let _ = state.send_to_torrent_cmd(&torrent_id, command).await;
+                }
+            }
+            update_queue(&state).await;
         }
     });
 
     (tx, jh)
 }
+
+async fn add_torrent(state: &SessionState, source: TorrentSource) {
+    let torrent_id = source.torrent_id();
+    if state.torrents.lock().await.contains_key(&torrent_id) {
+        return;
+    }
+
+    let params = TorrentSpawnParams {
+        status: TorrentStatus::Waiting,
+        port: state.listen_port(),
+        peer_id: state.peer_id.clone(),
+        save_path: state.settings.save_path.clone(),
+        alert_tx: state.alert_sender(),
+        web_seed_proxy: state.settings.proxy.web_seeds.clone(),
+        ip_filter: Some(state.ip_filter.clone()),
+    };
+    let torrent = TorrentHandle::spawn(source, params).await;
+    let is_finished = torrent.state.lock().await.is_finished();
+
+    state.torrents.lock().await.insert(torrent_id, torrent);
+    state
+        .queue
+        .lock()
+        .await
+        .push(torrent_id, TorrentStatus::Waiting, is_finished);
+
+    let _ = state.send_alert(SessionAlert::TorrentAdded(torrent_id));
+}
+
+async fn reload_ip_filter(state: &SessionState) {
+    let filter = match state.settings.ip_filter.as_ref() {
+        Some(path) => IpFilter::load(path),
+        None => Ok(IpFilter::new()),
+    };
+    let alert = match filter {
+        Ok(filter) => {
+            let num_ranges = filter.num_ranges();
+            state.ip_filter.set(filter);
+            SessionAlert::IpFilterReloaded { num_ranges }
+        }
+        Err(err) => SessionAlert::IpFilterError(err.to_string()),
+    };
+    let _ = state.send_alert(alert);
+}
+
+pub(super) async fn remove_torrent(
+    state: &SessionState,
+    torrent_id: &TorrentID,
+    delete_files: bool,
+) {
+    let Some(torrent) = state.torrents.lock().await.remove(torrent_id) else {
+        return;
+    };
+    state.queue.lock().await.remove(torrent_id);
+
+    if delete_files {
+        // the files are deleted after the state lock is released
+        let files = {
+            let torrent_state = torrent.state.lock().await;
+            let files = torrent_state.info.as_ref().map(|info| info.files());
+            files.map(|files| (torrent_state.save_path.clone(), files))
+        };
+        if let Some((save_path, files)) = files {
+            if let Err(e) = disk::remove_torrent_files(&save_path, &files).await {
+                let _ = state.send_alert(SessionAlert::TorrentError {
+                    torrent_id: *torrent_id,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    let _ = state.send_alert(SessionAlert::TorrentRemoved(*torrent_id));
+}
+
+pub(super) async fn set_torrent_status(
+    state: &SessionState,
+    torrent_id: &TorrentID,
+    status: TorrentStatus,
+) {
+    let torrent = state.torrents.lock().await.get(torrent_id).cloned();
+    if let Some(torrent) = torrent {
+        torrent.state.lock().await.set_status(status.clone());
+        let _ = state.send_alert(SessionAlert::TorrentStatusChanged {
+            torrent_id: *torrent_id,
+            status,
+        });
+    }
+}
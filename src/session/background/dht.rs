@@ -0,0 +1,355 @@
+use crate::{
+    proto::{
+        constants::DHT_GET_PEERS_QUERY_STR,
+        dht::{
+            fetch_add_dht_transaction_id, parse_compact_nodes, DhtTransactionID, KrpcArgs,
+            KrpcMessage, QueryArgs, ResponseArgs,
+        },
+        infohash::InfoHash,
+    },
+    proxy::Socks5Datagram,
+    session::{state::SessionState, RedirectChan},
+    torrent::{PeerSource, TorrentHandle, TorrentID, TorrentStatus},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::lookup_host,
+    sync::oneshot,
+    task::{JoinHandle, JoinSet},
+    time,
+};
+
+const DHT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const DHT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const DHT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of closest nodes a lookup ends on and announces to.
+const DHT_LOOKUP_WIDTH: usize = 8;
+const DHT_LOOKUP_PARALLEL: usize = 3;
+const DHT_LOOKUP_MAX_QUERIES: usize = 64;
+
+/// Asks the bootstrap nodes for peers of every active torrent that may use the DHT.
+pub async fn spawn_dht_announcer(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if !state.settings.dht {
+            return;
+        }
+        let mut announced = BTreeMap::<TorrentID, Instant>::new();
+        let mut ticker = time::interval(DHT_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let torrents = state
+                .torrents
+                .lock()
+                .await
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+            announced.retain(|id, _| torrents.iter().any(|t| t.id == *id));
+
+            for torrent in torrents {
+                if announced
+                    .get(&torrent.id)
+                    .is_some_and(|time| time.elapsed() < DHT_ANNOUNCE_INTERVAL)
+                {
+                    continue;
+                }
+                let info_hashes = {
+                    let torrent_state = torrent.state.lock().await;
+                    let active = matches!(
+                        torrent_state.status(),
+                        TorrentStatus::Downloading | TorrentStatus::Seeding
+                    );
+                    if !active || !torrent_state.allows_peer_source(PeerSource::Dht) {
+                        continue;
+                    }
+                    torrent_state.info_hash.swarm_hashes()
+                };
+                announced.insert(torrent.id, Instant::now());
+                tokio::spawn(get_peers(state.clone(), torrent, info_hashes));
+            }
+        }
+    })
+}
+
+/// A node to query, bootstrap nodes go through a SOCKS5 proxy by name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum DhtNode {
+    Addr(SocketAddr),
+    Host(String, u16),
+}
+
+/// What a node answered to `get_peers`.
+struct GetPeersReply {
+    id: Vec<u8>,
+    token: Option<Vec<u8>>,
+    values: Vec<SocketAddr>,
+    nodes: Vec<([u8; 20], SocketAddr)>,
+}
+
+async fn get_peers(state: Arc<SessionState>, torrent: TorrentHandle, info_hashes: Vec<InfoHash>) {
+    // HTTP proxies can't relay UDP, the DHT is left out rather than leaking
+    let datagram = match state.settings.proxy.peers.as_ref() {
+        Some(proxy) => match proxy.udp_associate().await {
+            Ok(datagram) => Some(Arc::new(datagram)),
+            Err(_) => return,
+        },
+        None => None,
+    };
+    let mut bootstrap = Vec::new();
+    for node in state.settings.dht_bootstrap_nodes.iter() {
+        if datagram.is_some() {
+            // the proxy resolves the node names, nothing goes to the local resolver
+            let Some((host, port)) = split_host_port(node) else {
+                continue;
+            };
+            let allowed = host.parse().map_or(true, |ip| {
+                state.ip_filter.allows(&SocketAddr::new(ip, port))
+            });
+            if allowed {
+                bootstrap.push(DhtNode::Host(host, port));
+            }
+        } else if let Ok(addrs) = lookup_host(node.as_str()).await {
+            bootstrap.extend(
+                addrs
+                    .filter(|addr| is_reachable(&state, addr))
+                    .map(DhtNode::Addr),
+            );
+        }
+    }
+    for info_hash in info_hashes.iter() {
+        lookup(&state, datagram.as_ref(), &torrent, &bootstrap, info_hash).await;
+    }
+}
+
+fn is_reachable(state: &SessionState, addr: &SocketAddr) -> bool {
+    state.udp_socket_for(addr).is_some() && state.ip_filter.allows(addr)
+}
+
+/// XOR distance of a node id to the info hash, unknown ids are the farthest.
+fn distance(id: &[u8], target: &[u8; 20]) -> [u8; 20] {
+    let mut distance = [0xff; 20];
+    if id.len() == 20 {
+        distance
+            .iter_mut()
+            .zip(id.iter().zip(target))
+            .for_each(|(d, (a, b))| *d = a ^ b);
+    }
+    distance
+}
+
+/// Walks the DHT toward the info hash (BEP 5): the closest nodes heard of are
+/// asked for peers until the `DHT_LOOKUP_WIDTH` closest ones have answered.
+/// Those that handed out a token get our announce.
+async fn lookup(
+    state: &Arc<SessionState>,
+    datagram: Option<&Arc<Socks5Datagram>>,
+    torrent: &TorrentHandle,
+    bootstrap: &[DhtNode],
+    info_hash: &InfoHash,
+) {
+    let target = *info_hash.inner().truncate();
+    // bootstrap nodes have no id yet, they are asked first
+    let mut pending = bootstrap
+        .iter()
+        .map(|node| ([0; 20], node.clone()))
+        .collect::<BTreeSet<_>>();
+    let mut seen = bootstrap.iter().cloned().collect::<BTreeSet<_>>();
+    let mut answered = BTreeMap::<[u8; 20], (DhtNode, Option<Vec<u8>>)>::new();
+    // a shared proxy association reads one reply at a time
+    let parallel = match datagram {
+        Some(_) => 1,
+        None => DHT_LOOKUP_PARALLEL,
+    };
+    let mut queries = 0;
+    while queries < DHT_LOOKUP_MAX_QUERIES {
+        let Some((closest, _)) = pending.first() else {
+            break;
+        };
+        let done = answered
+            .keys()
+            .nth(DHT_LOOKUP_WIDTH - 1)
+            .is_some_and(|farthest| farthest < closest);
+        if done {
+            break;
+        }
+        let mut replies = JoinSet::new();
+        while let Some((_, node)) = pending.pop_first() {
+            let query = query_node(
+                state.clone(),
+                datagram.cloned(),
+                node.clone(),
+                get_peers_query(state, info_hash),
+            );
+            replies.spawn(async move { (node, query.await) });
+            queries += 1;
+            if replies.len() == parallel {
+                break;
+            }
+        }
+        while let Some(Ok((node, reply))) = replies.join_next().await {
+            let Some(reply) = reply.and_then(get_peers_reply) else {
+                continue;
+            };
+            if !reply.values.is_empty() {
+                torrent
+                    .state
+                    .lock()
+                    .await
+                    .add_peers(PeerSource::Dht, reply.values);
+            }
+            for (id, addr) in reply.nodes {
+                let node = DhtNode::Addr(addr);
+                if is_reachable(state, &addr) && seen.insert(node.clone()) {
+                    pending.insert((distance(&id, &target), node));
+                }
+            }
+            answered.insert(distance(&reply.id, &target), (node, reply.token));
+        }
+    }
+
+    let port = state.listen_port();
+    for (node, token) in answered.into_values().take(DHT_LOOKUP_WIDTH) {
+        let Some(token) = token else {
+            continue;
+        };
+        let query = QueryArgs::AnnouncePeer {
+            id: state.peer_id.to_vec(),
+            info_hash: target.to_vec(),
+            port: port as i64,
+            token,
+            implied_port: 0,
+        };
+        query_node(state.clone(), datagram.cloned(), node, query).await;
+    }
+}
+
+/// Splits a `host:port` node, IPv6 hosts may be bracketed.
+fn split_host_port(node: &str) -> Option<(String, u16)> {
+    let (host, port) = node.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+fn get_peers_query(state: &SessionState, info_hash: &InfoHash) -> QueryArgs {
+    QueryArgs::GetPeers {
+        id: state.peer_id.to_vec(),
+        info_hash: info_hash.inner().truncate().to_vec(),
+    }
+}
+
+fn get_peers_reply(response: KrpcMessage) -> Option<GetPeersReply> {
+    let reply = match response.into_args(Some(DHT_GET_PEERS_QUERY_STR)) {
+        KrpcArgs::Response(ResponseArgs::GetPeersWithValues { id, token, values }) => {
+            GetPeersReply {
+                id,
+                token,
+                values,
+                nodes: Vec::new(),
+            }
+        }
+        KrpcArgs::Response(ResponseArgs::GetPeersWithNodes { id, token, nodes }) => GetPeersReply {
+            id,
+            token,
+            values: Vec::new(),
+            nodes: parse_compact_nodes(&nodes),
+        },
+        _ => return None,
+    };
+    Some(reply)
+}
+
+/// Sends a query and waits for the node's reply.
+async fn query_node(
+    state: Arc<SessionState>,
+    datagram: Option<Arc<Socks5Datagram>>,
+    node: DhtNode,
+    query: QueryArgs,
+) -> Option<KrpcMessage> {
+    let transaction_id = fetch_add_dht_transaction_id();
+    let bytes = KrpcMessage::from_query_args(&transaction_id, query)
+        .to_bytes()
+        .ok()?;
+    match (datagram, node) {
+        (Some(datagram), DhtNode::Addr(addr)) => {
+            let host = addr.ip().to_string();
+            query_via_proxy(
+                &state,
+                &datagram,
+                &host,
+                addr.port(),
+                transaction_id,
+                &bytes,
+            )
+            .await
+        }
+        (Some(datagram), DhtNode::Host(host, port)) => {
+            query_via_proxy(&state, &datagram, &host, port, transaction_id, &bytes).await
+        }
+        (None, DhtNode::Addr(addr)) => query_direct(&state, addr, transaction_id, &bytes).await,
+        (None, DhtNode::Host(..)) => None,
+    }
+}
+
+async fn query_direct(
+    state: &SessionState,
+    node: SocketAddr,
+    transaction_id: DhtTransactionID,
+    bytes: &[u8],
+) -> Option<KrpcMessage> {
+    let socket = state.udp_socket_for(&node)?;
+    let (tx, rx) = oneshot::channel();
+    state
+        .dht_router
+        .lock()
+        .await
+        .insert_redirect(node, transaction_id, RedirectChan::Oneshot(tx));
+    if socket.send_to(bytes, node).await.is_ok() {
+        if let Ok(Ok(response)) = time::timeout(DHT_RESPONSE_TIMEOUT, rx).await {
+            return Some(response);
+        }
+    }
+    state
+        .dht_router
+        .lock()
+        .await
+        .remove_redirect(&node, &transaction_id);
+    None
+}
+
+async fn query_via_proxy(
+    state: &SessionState,
+    datagram: &Socks5Datagram,
+    host: &str,
+    port: u16,
+    transaction_id: DhtTransactionID,
+    bytes: &[u8],
+) -> Option<KrpcMessage> {
+    datagram.send_to(bytes, host, port).await.ok()?;
+    // a named node answers from whatever address the proxy resolved
+    let node_ip = host.parse::<IpAddr>().ok();
+    let response = time::timeout(DHT_RESPONSE_TIMEOUT, async {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (n, from) = datagram.recv_from(&mut buf).await.ok()?;
+            let Ok(message) = KrpcMessage::from_bytes(&buf[..n]) else {
+                continue;
+            };
+            let from_node = from.port() == port && node_ip.is_none_or(|ip| ip == from.ip());
+            if from_node
+                && state.ip_filter.allows(&from)
+                && message
+                    .transaction_id()
+                    .is_ok_and(|id| id == transaction_id)
+            {
+                return Some(message);
+            }
+        }
+    })
+    .await;
+    response.ok().flatten()
+}
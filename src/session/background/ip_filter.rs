@@ -0,0 +1,24 @@
+use crate::session::{state::SessionState, SessionAlert};
+use std::{sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time};
+
+const BLOCKED_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reports the blocked attempts of the IP filter when there are new ones.
+pub async fn spawn_blocked_reporter(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = time::interval(BLOCKED_REPORT_INTERVAL);
+        let mut reported = 0;
+        loop {
+            ticker.tick().await;
+            let blocked = state.ip_filter.blocked();
+            if blocked > reported
+                && state
+                    .send_alert(SessionAlert::PeersBlocked { blocked })
+                    .is_ok()
+            {
+                reported = blocked;
+            }
+        }
+    })
+}
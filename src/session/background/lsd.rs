@@ -0,0 +1,102 @@
+use crate::{
+    proto::constants::INFO_HASH_V1_SIZE,
+    session::{state::SessionState, Lsd},
+    torrent::{PeerSource, TorrentID, TorrentStatus},
+};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{task::JoinHandle, time};
+
+const LSD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const LSD_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Announces active public torrents on the LAN and collects peers announced by others.
+pub async fn spawn_lsd_service(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if !state.settings.lsd {
+            return;
+        }
+        let Ok(lsd) = Lsd::bind(state.settings.lsd_port, state.listen_port()) else {
+            return;
+        };
+        let mut announced = BTreeMap::<TorrentID, Instant>::new();
+        let mut ticker = time::interval(LSD_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let info_hashes = due_info_hashes(&state, &mut announced).await;
+                    if !info_hashes.is_empty() {
+                        let _ = lsd.announce(&info_hashes).await;
+                    }
+                }
+                res = lsd.recv() => {
+                    if let Ok((info_hashes, peer)) = res {
+                        add_lsd_peer(&state, &info_hashes, peer).await;
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Hashes of the torrents that weren't announced during the last interval.
+async fn due_info_hashes(
+    state: &SessionState,
+    announced: &mut BTreeMap<TorrentID, Instant>,
+) -> Vec<[u8; INFO_HASH_V1_SIZE]> {
+    let torrents = state
+        .torrents
+        .lock()
+        .await
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    announced.retain(|id, _| torrents.iter().any(|t| t.id == *id));
+
+    let mut info_hashes = Vec::new();
+    for torrent in torrents {
+        if announced
+            .get(&torrent.id)
+            .is_some_and(|time| time.elapsed() < LSD_ANNOUNCE_INTERVAL)
+        {
+            continue;
+        }
+        let torrent_state = torrent.state.lock().await;
+        let active = matches!(
+            torrent_state.status(),
+            TorrentStatus::Downloading | TorrentStatus::Seeding
+        );
+        if !active || !torrent_state.allows_peer_source(PeerSource::Lsd) {
+            continue;
+        }
+        info_hashes.extend(
+            torrent_state
+                .info_hash
+                .swarm_hashes()
+                .iter()
+                .map(|info_hash| *info_hash.inner().truncate()),
+        );
+        announced.insert(torrent.id, Instant::now());
+    }
+    info_hashes
+}
+
+async fn add_lsd_peer(
+    state: &SessionState,
+    info_hashes: &[[u8; INFO_HASH_V1_SIZE]],
+    peer: SocketAddr,
+) {
+    for info_hash in info_hashes {
+        if let Some(torrent) = state.find_torrent(info_hash).await {
+            torrent
+                .state
+                .lock()
+                .await
+                .add_peers(PeerSource::Lsd, [peer]);
+        }
+    }
+}
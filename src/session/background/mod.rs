@@ -1,7 +1,25 @@
 mod command;
+mod dht;
+mod ip_filter;
+mod lsd;
+mod port_mapping;
+mod queue;
+mod seeding;
+mod stream;
 mod tcp;
+mod tracker;
 mod udp;
+mod utp;
 
 pub use command::*;
+pub use dht::*;
+pub use ip_filter::*;
+pub use lsd::*;
+pub use port_mapping::*;
+pub use queue::*;
+pub use seeding::*;
+pub use stream::*;
 pub use tcp::*;
+pub use tracker::*;
 pub use udp::*;
+pub use utp::*;
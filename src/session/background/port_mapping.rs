@@ -0,0 +1,146 @@
+use crate::{
+    error::{Error, Result},
+    proto::{constants::NATPMP_PORT, natpmp::MappingProtocol},
+    session::{default_gateway, state::SessionState, PortMapper, PortMapping, SessionAlert},
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    sync::oneshot::{self, Sender},
+    task::JoinHandle,
+    time,
+};
+
+/// Requested lease, the gateway may grant less.
+const PORT_MAPPING_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+const PORT_MAPPING_MIN_RENEW_INTERVAL: Duration = Duration::from_secs(1);
+const PORT_MAPPING_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const PORT_MAPPING_PROTOCOLS: [MappingProtocol; 2] = [MappingProtocol::Tcp, MappingProtocol::Udp];
+
+/// Maps the TCP and UDP listen ports on the gateway and renews the leases
+/// halfway through. The mappings are removed once the returned sender is
+/// used or dropped.
+pub async fn spawn_port_mapper(state: Arc<SessionState>) -> (Sender<()>, JoinHandle<()>) {
+    let (tx, rx) = oneshot::channel();
+    let jh = tokio::spawn(async move {
+        if !state.settings.upnp && !state.settings.natpmp {
+            return;
+        }
+        let mut mapper = None::<PortMapper>;
+        let mut mappings = Vec::<PortMapping>::new();
+        tokio::select! {
+            _ = keep_mapped(&state, &mut mapper, &mut mappings) => {}
+            _ = rx => {}
+        }
+        if let Some(mapper) = mapper.as_mut() {
+            for mapping in mappings.iter() {
+                let _ = mapper.unmap(mapping).await;
+            }
+        }
+    });
+    (tx, jh)
+}
+
+async fn keep_mapped(
+    state: &SessionState,
+    mapper: &mut Option<PortMapper>,
+    mappings: &mut Vec<PortMapping>,
+) {
+    let port = state.listen_port();
+    loop {
+        let renew_in = match map_listen_port(state, mapper, mappings, port).await {
+            Ok(()) => mappings
+                .iter()
+                .map(|mapping| match mapping.lifetime.is_zero() {
+                    true => PORT_MAPPING_LIFETIME / 2,
+                    false => mapping.lifetime / 2,
+                })
+                .min()
+                .unwrap_or(PORT_MAPPING_RETRY_INTERVAL)
+                .max(PORT_MAPPING_MIN_RENEW_INTERVAL),
+            Err(err) => {
+                // the gateway may have changed or lost the mappings, look
+                // for one again
+                *mapper = None;
+                mappings.clear();
+                let _ = state.send_alert(SessionAlert::PortMappingError(err.to_string()));
+                PORT_MAPPING_RETRY_INTERVAL
+            }
+        };
+        time::sleep(renew_in).await;
+    }
+}
+
+/// Maps or renews both protocols, new or changed mappings are alerted.
+async fn map_listen_port(
+    state: &SessionState,
+    mapper: &mut Option<PortMapper>,
+    mappings: &mut Vec<PortMapping>,
+    port: u16,
+) -> Result<()> {
+    let (mapper, mut probed) = match mapper {
+        Some(mapper) => (mapper, None),
+        None => {
+            let (found, mapping) = discover(state, port).await?;
+            (mapper.insert(found), Some(mapping))
+        }
+    };
+    for protocol in PORT_MAPPING_PROTOCOLS {
+        let mapping = match probed.take().filter(|m| m.protocol == protocol) {
+            Some(mapping) => mapping,
+            None => mapper.map(protocol, port, PORT_MAPPING_LIFETIME).await?,
+        };
+        if let Some(ip) = mapping.external_ip {
+            *state
+                .external_ip
+                .write()
+                .unwrap_or_else(PoisonError::into_inner) = Some(ip);
+        }
+        let known = mappings.iter().any(|m| {
+            m.protocol == mapping.protocol
+                && m.external_port == mapping.external_port
+                && m.external_ip == mapping.external_ip
+        });
+        mappings.retain(|m| m.protocol != protocol);
+        mappings.push(mapping.clone());
+        if !known {
+            let _ = state.send_alert(SessionAlert::PortMapped(mapping));
+        }
+    }
+    Ok(())
+}
+
+/// PCP/NAT-PMP is tried first as it needs no discovery, then UPnP. A method
+/// is kept once the gateway accepted a mapping of the TCP port.
+async fn discover(state: &SessionState, port: u16) -> Result<(PortMapper, PortMapping)> {
+    let mut error = Error::PortMapping("no gateway found".into());
+    let natpmp_gateway = state
+        .settings
+        .natpmp_gateway
+        .or_else(|| default_gateway().map(|ip| SocketAddr::from((ip, NATPMP_PORT))))
+        .filter(|_| state.settings.natpmp);
+    if let Some(gateway) = natpmp_gateway {
+        match probe(PortMapper::natpmp(gateway).await, port).await {
+            Ok(found) => return Ok(found),
+            Err(err) => error = err,
+        }
+    }
+    if state.settings.upnp {
+        match probe(PortMapper::upnp(state.settings.ssdp_addr).await, port).await {
+            Ok(found) => return Ok(found),
+            Err(err) => error = err,
+        }
+    }
+    Err(error)
+}
+
+async fn probe(mapper: Result<PortMapper>, port: u16) -> Result<(PortMapper, PortMapping)> {
+    let mut mapper = mapper?;
+    let mapping = mapper
+        .map(MappingProtocol::Tcp, port, PORT_MAPPING_LIFETIME)
+        .await?;
+    Ok((mapper, mapping))
+}
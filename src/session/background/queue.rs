@@ -0,0 +1,49 @@
+use crate::session::{state::SessionState, SessionAlert};
+use std::{sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time};
+
+const QUEUE_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub async fn spawn_queue_manager(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = time::interval(QUEUE_UPDATE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            update_queue(&state).await;
+        }
+    })
+}
+
+/// Syncs the queue with the torrents and applies the resulting status transitions.
+pub async fn update_queue(state: &SessionState) {
+    let torrents = state
+        .torrents
+        .lock()
+        .await
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut queue = state.queue.lock().await;
+    for torrent in torrents.iter() {
+        let torrent_state = torrent.state.lock().await;
+        queue.update(
+            &torrent.id,
+            torrent_state.status(),
+            torrent_state.is_finished(),
+            torrent_state.download_rate(),
+            torrent_state.upload_rate(),
+        );
+    }
+    let transitions = queue.recalculate();
+    for (torrent_id, status) in transitions.iter() {
+        if let Some(torrent) = torrents.iter().find(|t| &t.id == torrent_id) {
+            torrent.state.lock().await.set_status(status.clone());
+        }
+    }
+    drop(queue);
+
+    for (torrent_id, status) in transitions {
+        let _ = state.send_alert(SessionAlert::TorrentStatusChanged { torrent_id, status });
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{
+    session::{
+        background::command::{remove_torrent, set_torrent_status},
+        state::SessionState,
+        SessionAlert,
+    },
+    torrent::{SeedingGoalAction, TorrentStatus},
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time};
+
+const SEEDING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub async fn spawn_seeding_monitor(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = time::interval(SEEDING_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            check_seeding_goals(&state).await;
+        }
+    })
+}
+
+/// Applies the goal action to every seeding torrent that reached one of its limits.
+pub async fn check_seeding_goals(state: &SessionState) {
+    let defaults = state.seeding_limits.lock().await.clone();
+    let torrents = state
+        .torrents
+        .lock()
+        .await
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for torrent in torrents {
+        let Some((goal, action)) = torrent.state.lock().await.check_seeding_goal(&defaults) else {
+            continue;
+        };
+
+        let _ = state.send_alert(SessionAlert::SeedingGoalReached {
+            torrent_id: torrent.id,
+            goal,
+            action,
+        });
+
+        match action {
+            SeedingGoalAction::Pause => {
+                set_torrent_status(state, &torrent.id, TorrentStatus::Stopped).await
+            }
+            SeedingGoalAction::Remove => remove_torrent(state, &torrent.id, false).await,
+            SeedingGoalAction::RemoveWithFiles => remove_torrent(state, &torrent.id, true).await,
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::{
+    disk::layout::Layout,
+    error::Result,
+    proto::metainfo::file::FileEntry,
+    session::state::SessionState,
+    torrent::{TorrentCommand, TorrentHandle},
+};
+use std::{ops::Range, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinHandle,
+    time,
+};
+
+/// Pieces ahead of the playhead that get a deadline.
+const READAHEAD_PIECES: usize = 4;
+const DEADLINE_STEP: Duration = Duration::from_millis(500);
+const MAX_HEADERS_LEN: usize = 8 * 1024;
+/// The response is given up when a piece takes longer than this.
+const PIECE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+/// Serves torrent files at `/stream/<infohash>/<file index>`, waiting for
+/// missing pieces and giving them deadlines.
+pub async fn spawn_stream_server(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let Some(listener) = state.stream_listener.as_ref() else {
+            return;
+        };
+        loop {
+            match listener.accept().await {
+                Ok((socket, _addr)) => {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        let _ = handle_connection(&state, socket).await;
+                    });
+                }
+                Err(_err) => {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+            }
+        }
+    })
+}
+
+async fn read_request(reader: &mut BufReader<TcpStream>) -> Result<Option<HttpRequest>> {
+    let mut line = String::new();
+    let mut total = reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let mut request = HttpRequest {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        range: None,
+    };
+
+    loop {
+        line.clear();
+        let n = reader.read_line(&mut line).await?;
+        total += n;
+        if n == 0 || total > MAX_HEADERS_LEN {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(Some(request));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("range") {
+                request.range = Some(value.trim().to_owned());
+            }
+        }
+    }
+}
+
+/// Single `bytes=` range, `None` if it can't be satisfied.
+fn parse_range(header: &str, length: u64) -> Option<Range<u64>> {
+    let spec = header.strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            length.saturating_sub(suffix)..length
+        }
+        (start, "") => start.parse().ok()?..length,
+        (start, end) => {
+            let end: u64 = end.parse().ok()?;
+            start.parse().ok()?..end.saturating_add(1).min(length)
+        }
+    };
+    (range.start < range.end).then_some(range)
+}
+
+fn content_type(path: &std::path::Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("mp4" | "m4v") => "video/mp4",
+        Some("mkv") => "video/x-matroska",
+        Some("webm") => "video/webm",
+        Some("avi") => "video/x-msvideo",
+        Some("mp3") => "audio/mpeg",
+        Some("flac") => "audio/flac",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn write_status(socket: &mut TcpStream, status: &str) -> Result<()> {
+    let head = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    socket.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+async fn find_torrent(state: &SessionState, info_hash: &str) -> Option<TorrentHandle> {
+    let info_hash = info_hash.to_ascii_lowercase();
+    for (torrent_id, torrent) in state.torrents.lock().await.iter() {
+        if hex::encode(torrent_id) == info_hash
+            || torrent.state.lock().await.info_hash.matches_hex(&info_hash)
+        {
+            return Some(torrent.clone());
+        }
+    }
+    None
+}
+
+async fn handle_connection(state: &SessionState, socket: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(socket);
+    let request = read_request(&mut reader).await?;
+    let mut socket = reader.into_inner();
+
+    let Some(request) = request else {
+        return write_status(&mut socket, "400 Bad Request").await;
+    };
+    let head_only = match request.method.as_str() {
+        "GET" => false,
+        "HEAD" => true,
+        _ => return write_status(&mut socket, "405 Method Not Allowed").await,
+    };
+
+    let segments: Vec<_> = request.path.trim_matches('/').split('/').collect();
+    let (info_hash, file_index) = match segments.as_slice() {
+        ["stream", info_hash, file_index] => match file_index.parse::<usize>() {
+            Ok(file_index) => (*info_hash, file_index),
+            Err(_) => return write_status(&mut socket, "404 Not Found").await,
+        },
+        _ => return write_status(&mut socket, "404 Not Found").await,
+    };
+    let Some(torrent) = find_torrent(state, info_hash).await else {
+        return write_status(&mut socket, "404 Not Found").await;
+    };
+
+    let layout = torrent.state.lock().await.layout.clone();
+    let Some(layout) = layout else {
+        return write_status(&mut socket, "503 Service Unavailable").await;
+    };
+    let Some(file) = layout.files.get(file_index).cloned() else {
+        return write_status(&mut socket, "404 Not Found").await;
+    };
+
+    let range = match request.range.as_deref() {
+        Some(header) => match parse_range(header, file.length) {
+            Some(range) => Some(range),
+            None => {
+                let head = format!(
+                    "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    file.length
+                );
+                socket.write_all(head.as_bytes()).await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
+    let mut head = match &range {
+        Some(range) => format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+            range.start,
+            range.end - 1,
+            file.length
+        ),
+        None => "HTTP/1.1 200 OK\r\n".to_owned(),
+    };
+    let range = range.unwrap_or(0..file.length);
+    head.push_str(&format!(
+        "Content-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+        content_type(&file.path),
+        range.end - range.start
+    ));
+    socket.write_all(head.as_bytes()).await?;
+    if head_only || range.is_empty() {
+        return Ok(());
+    }
+
+    let mut deadlines = Vec::new();
+    let result = stream_range(&torrent, &layout, &file, range, &mut socket, &mut deadlines).await;
+    // pieces nobody waits for anymore go back to the normal order
+    for piece in deadlines {
+        let _ = torrent
+            .send(TorrentCommand::RemovePieceDeadline(piece))
+            .await;
+    }
+    result
+}
+
+/// Writes the range of the file as its pieces arrive. Stops when the client
+/// goes away or a piece takes too long, `deadlines` collects the pieces given
+/// a deadline.
+async fn stream_range(
+    torrent: &TorrentHandle,
+    layout: &Layout,
+    file: &FileEntry,
+    range: Range<u64>,
+    socket: &mut TcpStream,
+    deadlines: &mut Vec<usize>,
+) -> Result<()> {
+    let piece_length = layout.piece_length;
+    let last_piece = ((file.offset + range.end - 1) / piece_length) as usize;
+    let mut pieces_rx = torrent.state.lock().await.subscribe_pieces();
+    let mut next_deadline = ((file.offset + range.start) / piece_length) as usize;
+    let _ = torrent
+        .send(TorrentCommand::SetPlayhead(next_deadline))
+        .await;
+
+    let mut pos = range.start;
+    while pos < range.end {
+        let piece = ((file.offset + pos) / piece_length) as usize;
+
+        next_deadline = next_deadline.max(piece);
+        while next_deadline <= last_piece.min(piece + READAHEAD_PIECES - 1) {
+            let missing = !torrent.state.lock().await.has_piece(next_deadline);
+            if missing {
+                let deadline = DEADLINE_STEP * (next_deadline - piece + 1) as u32;
+                torrent
+                    .send(TorrentCommand::SetPieceDeadline(next_deadline, deadline))
+                    .await?;
+                deadlines.push(next_deadline);
+            }
+            next_deadline += 1;
+        }
+
+        let arrived = async {
+            while !torrent.state.lock().await.has_piece(piece) {
+                if pieces_rx.changed().await.is_err() {
+                    return false;
+                }
+            }
+            true
+        };
+        tokio::select! {
+            arrived = arrived => {
+                if !arrived {
+                    return Ok(());
+                }
+            }
+            _ = client_closed(socket) => return Ok(()),
+            _ = time::sleep(PIECE_TIMEOUT) => return Ok(()),
+        }
+
+        let piece_start = layout.piece_offset(piece);
+        let chunk_end = range.end.min(piece_start + piece_length - file.offset);
+        let Some(disk) = torrent.state.lock().await.disk.clone() else {
+            return Ok(());
+        };
+        let data = disk
+            .lock()
+            .await
+            .read(piece, file.offset + pos - piece_start, chunk_end - pos)
+            .await?;
+        socket.write_all(&data).await?;
+        pos = chunk_end;
+    }
+    socket.flush().await?;
+
+    Ok(())
+}
+
+/// Completes once the client closed its side of the connection, anything it
+/// sends meanwhile is ignored.
+async fn client_closed(socket: &mut TcpStream) {
+    let mut buf = [0u8; 512];
+    while let Ok(1..) = socket.read(&mut buf).await {}
+}
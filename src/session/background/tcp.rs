@@ -1,18 +1,176 @@
-use crate::session::state::SessionState;
-use std::sync::Arc;
-use tokio::task::JoinHandle;
+use crate::{
+    error::{Error, Result},
+    peers::{mse_accept, EncryptionPolicy, MseStream, PeerConnection, PeerIo},
+    proto::{
+        bep10::{ExtendedHandshake, ExtensionMessage},
+        constants::{HANDSHAKE_PREFIX, HANDSHAKE_SIZE},
+        Handshake, Message,
+    },
+    session::state::SessionState,
+    torrent::{TorrentCommand, TorrentHandle},
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+    time,
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const EXTENDED_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_EXTENDED_HANDSHAKE_SIZE: usize = 16 * 1024;
 
 pub async fn spawn_tcp_incoming_listener(state: Arc<SessionState>) -> JoinHandle<()> {
     tokio::spawn(async move {
-        loop {
-            match state.tcp_listener.accept().await {
-                Ok((socket, _addr)) => {
-                    // process_socket
-                }
-                Err(_err) => {
-                    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let v6 = async {
+            if let Some(listener) = state.tcp_listener_v6.as_ref() {
+                accept_connections(&state, listener).await;
+            }
+        };
+        tokio::join!(accept_connections(&state, &state.tcp_listener), v6);
+    })
+}
+
+async fn accept_connections(state: &Arc<SessionState>, listener: &TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                if !state.ip_filter.allows(&addr) {
+                    continue;
                 }
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Ok(stream) = accept_encryption(&state, socket).await {
+                        let _ = accept_peer(&state, stream, addr).await;
+                    }
+                });
+            }
+            Err(_err) => {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            }
+        }
+    }
+}
+
+/// Plaintext peers start with the BitTorrent handshake, anything else is taken
+/// for an MSE handshake.
+pub async fn accept_encryption<S: AsyncRead + AsyncWrite + Unpin>(
+    state: &SessionState,
+    mut socket: S,
+) -> Result<MseStream<S>> {
+    let policy = state.settings.encryption;
+    let mut read_ahead = vec![0u8; HANDSHAKE_PREFIX.len()];
+    time::timeout(HANDSHAKE_TIMEOUT, socket.read_exact(&mut read_ahead)).await??;
+    if read_ahead == HANDSHAKE_PREFIX {
+        return match policy {
+            EncryptionPolicy::Forced => Err(Error::MseHandshake("plaintext peer".into())),
+            _ => Ok(MseStream::plaintext(socket, read_ahead)),
+        };
+    }
+    if policy == EncryptionPolicy::Disabled {
+        return Err(Error::MseHandshake("encryption disabled".into()));
+    }
+    let skeys = state.info_hashes().await;
+    let (stream, _) = time::timeout(
+        HANDSHAKE_TIMEOUT,
+        mse_accept(socket, read_ahead, &skeys, policy),
+    )
+    .await??;
+    Ok(stream)
+}
+
+/// Runs the handshake and hands the connection over to its torrent. Peers that
+/// support extensions exchange extension handshakes first.
+pub async fn accept_peer<S: PeerIo + 'static>(
+    state: &SessionState,
+    socket: S,
+    addr: SocketAddr,
+) -> Result<()> {
+    let Some((torrent, handshake, mut stream)) = accept_handshake(state, socket).await? else {
+        return Ok(());
+    };
+    let (extended, read_ahead) = match handshake.supports_extensions() {
+        true => exchange_extended_handshake(state, &mut stream, addr).await?,
+        false => (None, Vec::new()),
+    };
+    let stream: Box<dyn PeerIo> = match read_ahead.is_empty() {
+        true => Box::new(stream),
+        false => Box::new(MseStream::plaintext(stream, read_ahead)),
+    };
+    let connection = PeerConnection {
+        addr,
+        handshake,
+        extended,
+        stream,
+    };
+    torrent
+        .send(TorrentCommand::IncomingPeer(Box::new(connection)))
+        .await
+}
+
+/// Sends our extension handshake, with our address on the other family (BEP 7),
+/// and reads the one the peer sends right after the handshake. Anything else
+/// read meanwhile is returned to be replayed.
+async fn exchange_extended_handshake<S: PeerIo>(
+    state: &SessionState,
+    stream: &mut S,
+    addr: SocketAddr,
+) -> Result<(Option<ExtendedHandshake>, Vec<u8>)> {
+    let ours = ExtendedHandshake::local(
+        state.listen_port(),
+        addr.ip(),
+        state.public_ip(addr.is_ipv4()),
+    );
+    stream
+        .write_all(&Message::Extension(ExtensionMessage::Handshake(ours)).to_bytes())
+        .await?;
+
+    let mut buf = Vec::new();
+    let _ = time::timeout(EXTENDED_HANDSHAKE_TIMEOUT, async {
+        let mut chunk = [0u8; 1024];
+        while !frame_len(&buf)
+            .is_some_and(|len| buf.len() >= len || len > MAX_EXTENDED_HANDSHAKE_SIZE)
+        {
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
             }
         }
     })
+    .await;
+
+    match frame_len(&buf) {
+        Some(len) if buf.len() >= len => match Message::from_bytes(&buf[..len]) {
+            Message::Extension(ExtensionMessage::Handshake(theirs)) => {
+                Ok((Some(theirs), buf.split_off(len)))
+            }
+            _ => Ok((None, buf)),
+        },
+        _ => Ok((None, buf)),
+    }
+}
+
+/// Length of the message at the start of `buf`, with its length prefix.
+fn frame_len(buf: &[u8]) -> Option<usize> {
+    let len: [u8; 4] = buf.get(..4)?.try_into().ok()?;
+    Some(4 + u32::from_be_bytes(len) as usize)
+}
+
+/// Reads the peer's handshake and answers with the info hash it asked for, which
+/// is either hash of a hybrid torrent. `None` for unknown torrents.
+pub async fn accept_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    state: &SessionState,
+    mut socket: S,
+) -> Result<Option<(TorrentHandle, Handshake, S)>> {
+    let mut buf = [0u8; HANDSHAKE_SIZE];
+    time::timeout(HANDSHAKE_TIMEOUT, socket.read_exact(&mut buf)).await??;
+    let handshake = Handshake::new(buf);
+    let Some(torrent) = state.find_torrent(handshake.select_info_hash()).await else {
+        return Ok(None);
+    };
+
+    let reply = Handshake::from_args(&handshake.extract_info_hash(), &state.peer_id);
+    socket.write_all(reply.as_slice()).await?;
+    Ok(Some((torrent, handshake, socket)))
 }
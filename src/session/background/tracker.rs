@@ -0,0 +1,471 @@
+use crate::{
+    error::{Error, Result},
+    proto::{
+        announce::{AnnounceRequestParams, Event, HttpAnnounceResponse},
+        bep15::{fetch_add_bep15_transaction_id, Bep15ConnectRequest, Bep15Response},
+        infohash::InfoHash,
+        metainfo::AnnounceList,
+        PeerId,
+    },
+    proxy::{Proxy, Socks5Datagram},
+    session::{state::SessionState, RedirectChan},
+    torrent::{PeerSource, TorrentHandle, TorrentID, TorrentStatus},
+};
+use rand::seq::SliceRandom;
+use reqwest::{Client, Url};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::lookup_host,
+    sync::oneshot,
+    task::{JoinHandle, JoinSet},
+    time,
+};
+
+const TRACKER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const TRACKER_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const TRACKER_MIN_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60);
+const TRACKER_RESPONSE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// What a torrent announces, copied out of its state.
+#[derive(Debug, Clone)]
+struct AnnounceSnapshot {
+    info_hash: InfoHash,
+    peer_id: PeerId,
+    port: u16,
+    uploaded: u64,
+    downloaded: u64,
+    left: u64,
+    event: Option<Event>,
+}
+
+impl AnnounceSnapshot {
+    fn params(&self) -> Result<AnnounceRequestParams<'_>> {
+        let mut builder = AnnounceRequestParams::builder()
+            .info_hash(&self.info_hash)
+            .peer_id(&self.peer_id)
+            .port(self.port)
+            .uploaded(self.uploaded)
+            .downloaded(self.downloaded)
+            .left(self.left)
+            .compact(1);
+        if let Some(event) = self.event.clone() {
+            builder = builder.event(event);
+        }
+        builder.build()
+    }
+}
+
+/// Announce state of a running torrent.
+struct TrackerSchedule {
+    /// Announce list with shuffled tiers, trackers that answered move to the
+    /// front of their tier (BEP 12).
+    tiers: AnnounceList,
+    next: Instant,
+    in_flight: bool,
+    /// A tracker accepted `started`, so `stopped` is sent when the torrent stops.
+    started: bool,
+    finished: bool,
+    /// `completed` is sent with the next announce.
+    completed: bool,
+    /// Latest stats, repeated with `stopped` once the torrent is gone.
+    snapshot: AnnounceSnapshot,
+}
+
+impl TrackerSchedule {
+    fn new(announce_list: &AnnounceList, finished: bool, snapshot: AnnounceSnapshot) -> Self {
+        let mut tiers = announce_list.clone();
+        tiers
+            .iter_mut()
+            .for_each(|tier| tier.shuffle(&mut rand::rng()));
+        Self {
+            tiers,
+            next: Instant::now(),
+            in_flight: false,
+            started: false,
+            finished,
+            completed: false,
+            snapshot,
+        }
+    }
+
+    fn event(&self) -> Option<Event> {
+        match (self.started, self.completed) {
+            (false, _) => Some(Event::Started),
+            (true, true) => Some(Event::Completed),
+            (true, false) => None,
+        }
+    }
+
+    fn on_announced(&mut self, result: AnnounceResult) {
+        self.in_flight = false;
+        if result.working.is_empty() {
+            self.next = Instant::now() + TRACKER_MIN_ANNOUNCE_INTERVAL;
+            return;
+        }
+        match result.event {
+            Some(Event::Started) => self.started = true,
+            Some(Event::Completed) => self.completed = false,
+            _ => {}
+        }
+        for tier in self.tiers.iter_mut() {
+            tier.sort_by_key(|url| !result.working.contains(url));
+        }
+        let interval = result.interval.unwrap_or(TRACKER_ANNOUNCE_INTERVAL);
+        self.next = Instant::now() + interval.max(TRACKER_MIN_ANNOUNCE_INTERVAL);
+    }
+}
+
+struct AnnounceResult {
+    torrent_id: TorrentID,
+    event: Option<Event>,
+    /// Trackers that answered.
+    working: Vec<String>,
+    interval: Option<Duration>,
+}
+
+/// Announces every running torrent to its trackers with `started`, `completed`
+/// and `stopped` events. Each address family the session listens on walks the
+/// tiers on its own until a tracker answers (BEP 7, BEP 12).
+pub async fn spawn_tracker_announcer(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut schedules = BTreeMap::<TorrentID, TrackerSchedule>::new();
+        let mut announcing = JoinSet::<AnnounceResult>::new();
+        let mut ticker = time::interval(TRACKER_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                Some(Ok(result)) = announcing.join_next() => {
+                    if result.event != Some(Event::Stopped) {
+                        if let Some(schedule) = schedules.get_mut(&result.torrent_id) {
+                            schedule.on_announced(result);
+                        }
+                    }
+                    continue;
+                }
+            }
+            let torrents = state
+                .torrents
+                .lock()
+                .await
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            let mut running = BTreeSet::new();
+            for torrent in torrents {
+                let torrent_state = torrent.state.lock().await;
+                let active = matches!(
+                    torrent_state.status(),
+                    TorrentStatus::Downloading | TorrentStatus::Seeding
+                );
+                if !active || torrent_state.announce_list.is_empty() {
+                    continue;
+                }
+                running.insert(torrent.id);
+                let finished = torrent_state.is_finished();
+                let snapshot = AnnounceSnapshot {
+                    info_hash: torrent_state.info_hash.clone(),
+                    peer_id: torrent_state.peer_id.clone(),
+                    port: state.listen_port(),
+                    uploaded: torrent_state.progress.uploaded,
+                    downloaded: torrent_state.progress.downloaded,
+                    left: torrent_state.progress.left,
+                    event: None,
+                };
+                let schedule = schedules.entry(torrent.id).or_insert_with(|| {
+                    TrackerSchedule::new(&torrent_state.announce_list, finished, snapshot.clone())
+                });
+                drop(torrent_state);
+
+                schedule.snapshot = snapshot;
+                if finished && !schedule.finished {
+                    schedule.finished = true;
+                    if schedule.started {
+                        schedule.completed = true;
+                        schedule.next = Instant::now();
+                    }
+                }
+                if schedule.in_flight || schedule.next > Instant::now() {
+                    continue;
+                }
+                schedule.in_flight = true;
+                let snapshot = AnnounceSnapshot {
+                    event: schedule.event(),
+                    ..schedule.snapshot.clone()
+                };
+                announcing.spawn(announce_torrent(
+                    state.clone(),
+                    torrent.id,
+                    Some(torrent),
+                    schedule.tiers.clone(),
+                    snapshot,
+                ));
+            }
+
+            // paused or removed torrents
+            let stopped = schedules
+                .keys()
+                .filter(|id| !running.contains(*id))
+                .copied()
+                .collect::<Vec<_>>();
+            for id in stopped {
+                let Some(schedule) = schedules.remove(&id) else {
+                    continue;
+                };
+                if schedule.started {
+                    let snapshot = AnnounceSnapshot {
+                        event: Some(Event::Stopped),
+                        ..schedule.snapshot
+                    };
+                    announcing.spawn(announce_torrent(
+                        state.clone(),
+                        id,
+                        None,
+                        schedule.tiers,
+                        snapshot,
+                    ));
+                }
+            }
+        }
+    })
+}
+
+/// Peers are added to `torrent` when it is still running.
+async fn announce_torrent(
+    state: Arc<SessionState>,
+    torrent_id: TorrentID,
+    torrent: Option<TorrentHandle>,
+    tiers: AnnounceList,
+    snapshot: AnnounceSnapshot,
+) -> AnnounceResult {
+    let mut result = AnnounceResult {
+        torrent_id,
+        event: snapshot.event.clone(),
+        working: Vec::new(),
+        interval: None,
+    };
+    // the proxy resolves trackers, so there is a single walk
+    let families = match state.settings.proxy.trackers {
+        Some(_) => vec![None],
+        None => vec![Some(false), Some(true)],
+    };
+    let mut resolved = BTreeMap::<&str, Vec<SocketAddr>>::new();
+    let mut peers = Vec::new();
+    for ipv6 in families {
+        'tiers: for url in tiers.iter().flatten() {
+            let announced = match (state.settings.proxy.trackers.as_ref(), ipv6) {
+                (Some(proxy), _) => announce_via_proxy(proxy, url, &snapshot).await,
+                (None, ipv6) => {
+                    if !resolved.contains_key(url.as_str()) {
+                        resolved.insert(url, resolve_tracker(&state, url).await);
+                    }
+                    let addr = resolved[url.as_str()]
+                        .iter()
+                        .find(|addr| Some(addr.is_ipv6()) == ipv6)
+                        .copied();
+                    let Some(addr) = addr else {
+                        continue;
+                    };
+                    announce(&state, url, addr, &snapshot).await
+                }
+            };
+            if let Ok((tracker_peers, interval)) = announced {
+                peers.extend(tracker_peers);
+                result.interval = Some(result.interval.map_or(interval, |i| i.min(interval)));
+                if !result.working.contains(url) {
+                    result.working.push(url.clone());
+                }
+                break 'tiers;
+            }
+        }
+    }
+    if let Some(torrent) = torrent.filter(|_| !peers.is_empty()) {
+        torrent
+            .state
+            .lock()
+            .await
+            .add_peers(PeerSource::Tracker, peers);
+    }
+    result
+}
+
+fn tracker_host(url: &str) -> Option<(String, u16)> {
+    let url = Url::parse(url).ok()?;
+    let host = url
+        .host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    Some((host.to_string(), url.port_or_known_default()?))
+}
+
+/// First address of each family the session can reach the tracker over.
+async fn resolve_tracker(state: &SessionState, url: &str) -> Vec<SocketAddr> {
+    let Some((host, port)) = tracker_host(url) else {
+        return Vec::new();
+    };
+    let Ok(addrs) = lookup_host((host, port)).await else {
+        return Vec::new();
+    };
+    let mut found = Vec::<SocketAddr>::new();
+    for addr in addrs {
+        let reachable = state.udp_socket_for(&addr).is_some();
+        if reachable && !found.iter().any(|a| a.is_ipv4() == addr.is_ipv4()) {
+            found.push(addr);
+        }
+    }
+    found
+}
+
+async fn announce(
+    state: &SessionState,
+    url: &str,
+    addr: SocketAddr,
+    snapshot: &AnnounceSnapshot,
+) -> Result<(Vec<SocketAddr>, Duration)> {
+    let params = snapshot.params()?;
+    if url.starts_with("udp://") {
+        return announce_udp(UdpTransport::Direct(state, addr), &params).await;
+    }
+    // pinned to `addr` so each family gets its own announce
+    let mut builder = Client::builder().timeout(TRACKER_RESPONSE_TIMEOUT);
+    if let Some((host, _)) = tracker_host(url) {
+        builder = builder.resolve(&host, addr);
+    }
+    announce_http(&builder.build()?, url, &params).await
+}
+
+/// UDP trackers need a SOCKS5 proxy, HTTP proxies only tunnel TCP.
+async fn announce_via_proxy(
+    proxy: &Proxy,
+    url: &str,
+    snapshot: &AnnounceSnapshot,
+) -> Result<(Vec<SocketAddr>, Duration)> {
+    let params = snapshot.params()?;
+    if url.starts_with("udp://") {
+        let (host, port) = tracker_host(url)
+            .ok_or_else(|| Error::InvalidBep15Response(format!("invalid tracker {url}")))?;
+        let datagram = proxy.udp_associate().await?;
+        return announce_udp(UdpTransport::Proxied(&datagram, &host, port), &params).await;
+    }
+    let builder = Client::builder().timeout(TRACKER_RESPONSE_TIMEOUT);
+    announce_http(&proxy.apply(builder)?.build()?, url, &params).await
+}
+
+/// Where BEP 15 requests go.
+enum UdpTransport<'a> {
+    /// The session socket, responses come back through `bep15_router`.
+    Direct(&'a SessionState, SocketAddr),
+    /// A SOCKS5 UDP association, the proxy resolves the host.
+    Proxied(&'a Socks5Datagram, &'a str, u16),
+}
+
+impl UdpTransport<'_> {
+    async fn request(&self, transaction_id: i32, bytes: &[u8]) -> Result<Bep15Response> {
+        match *self {
+            Self::Direct(state, addr) => udp_request(state, addr, transaction_id, bytes).await,
+            Self::Proxied(datagram, host, port) => {
+                datagram.send_to(bytes, host, port).await?;
+                time::timeout(TRACKER_RESPONSE_TIMEOUT, async {
+                    let mut buf = [0u8; 2048];
+                    loop {
+                        let (n, from) = datagram.recv_from(&mut buf).await?;
+                        if let Ok(response) = Bep15Response::from_bytes(&buf[..n], from.is_ipv6()) {
+                            if response.transaction_id() == transaction_id {
+                                return Ok(response);
+                            }
+                        }
+                    }
+                })
+                .await?
+            }
+        }
+    }
+}
+
+/// <https://www.bittorrent.org/beps/bep_0015.html>
+async fn announce_udp(
+    transport: UdpTransport<'_>,
+    params: &AnnounceRequestParams<'_>,
+) -> Result<(Vec<SocketAddr>, Duration)> {
+    let connect = Bep15ConnectRequest::new();
+    let connection_id = match transport
+        .request(connect.transaction_id, &connect.to_bytes())
+        .await?
+    {
+        Bep15Response::Connect(response) => response.connection_id,
+        response => return Err(unexpected_bep15_response(response)),
+    };
+
+    let transaction_id = fetch_add_bep15_transaction_id();
+    let bytes = params.to_bep15_bytes(connection_id as u64, transaction_id as u32, rand::random());
+    match transport.request(transaction_id, &bytes).await? {
+        Bep15Response::Announce(response) => Ok((
+            response.peers,
+            Duration::from_secs(response.interval as u64),
+        )),
+        response => Err(unexpected_bep15_response(response)),
+    }
+}
+
+async fn udp_request(
+    state: &SessionState,
+    addr: SocketAddr,
+    transaction_id: i32,
+    bytes: &[u8],
+) -> Result<Bep15Response> {
+    let socket = state
+        .udp_socket_for(&addr)
+        .ok_or_else(|| Error::InvalidBep15Response(format!("no socket for {addr}")))?;
+    let (tx, rx) = oneshot::channel();
+    state.bep15_router.lock().await.insert_redirect(
+        addr,
+        transaction_id,
+        RedirectChan::Oneshot(tx),
+    );
+
+    let response = match socket.send_to(bytes, addr).await {
+        Ok(_) => time::timeout(TRACKER_RESPONSE_TIMEOUT, rx).await.ok(),
+        Err(_) => None,
+    };
+    if let Some(Ok(response)) = response {
+        return Ok(response);
+    }
+    state
+        .bep15_router
+        .lock()
+        .await
+        .remove_redirect(&addr, &transaction_id);
+    Err(Error::InvalidBep15Response(format!(
+        "no response from {addr}"
+    )))
+}
+
+fn unexpected_bep15_response(response: Bep15Response) -> Error {
+    match response {
+        Bep15Response::Error { message, .. } => Error::TrackerFailureReason(message),
+        response => Error::InvalidBep15Response(format!("unexpected response {response:?}")),
+    }
+}
+
+async fn announce_http(
+    client: &Client,
+    url: &str,
+    params: &AnnounceRequestParams<'_>,
+) -> Result<(Vec<SocketAddr>, Duration)> {
+    let separator = match url.contains('?') {
+        true => '&',
+        false => '?',
+    };
+    let full_url = format!("{url}{separator}{}", params.to_query_string());
+    let bytes = client.get(full_url).send().await?.bytes().await?;
+    let response = HttpAnnounceResponse::from_bytes(&bytes)?.check_failure_reason()?;
+    let interval = response
+        .interval
+        .map_or(TRACKER_ANNOUNCE_INTERVAL, Duration::from_secs);
+    Ok((response.peer_addrs(), interval))
+}
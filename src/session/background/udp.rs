@@ -1,79 +1,199 @@
 use crate::{
-    proto::{bep15::Bep15Response, dht::KrpcMessage},
+    proto::{
+        bep15::Bep15Response,
+        constants::{BEP15_ANNOUNCE_RESPONSE_MIN_LEN, BEP15_CONNECT_LEN, BEP15_MIN_MSG_LEN},
+        dht::KrpcMessage,
+    },
     session::state::SessionState,
+    utp::Packet,
+};
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
-use std::{net::SocketAddr, sync::Arc};
 use tokio::{
-    sync::mpsc::{channel, Sender},
+    net::UdpSocket,
+    sync::mpsc::{channel, error::TrySendError, Receiver, Sender},
     task::JoinHandle,
 };
 
+const UDP_HANDLER_QUEUE: usize = 64;
+
+type Datagram = (SocketAddr, Vec<u8>);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UdpProtocol {
+    Dht,
+    Utp,
+    Tracker,
+    Unknown,
+}
+
+/// Datagrams seen on the shared UDP socket, per protocol.
+#[derive(Debug, Default)]
+pub struct UdpStats {
+    dht: AtomicU64,
+    utp: AtomicU64,
+    tracker: AtomicU64,
+    unknown: AtomicU64,
+    /// Dropped because the protocol's handler fell behind.
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct UdpCounters {
+    pub dht: u64,
+    pub utp: u64,
+    pub tracker: u64,
+    pub unknown: u64,
+    pub dropped: u64,
+}
+
+impl UdpStats {
+    fn record(&self, protocol: UdpProtocol) {
+        let counter = match protocol {
+            UdpProtocol::Dht => &self.dht,
+            UdpProtocol::Utp => &self.utp,
+            UdpProtocol::Tracker => &self.tracker,
+            UdpProtocol::Unknown => &self.unknown,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> UdpCounters {
+        UdpCounters {
+            dht: self.dht.load(Ordering::Relaxed),
+            utp: self.utp.load(Ordering::Relaxed),
+            tracker: self.tracker.load(Ordering::Relaxed),
+            unknown: self.unknown.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct UdpHandlers {
+    dht_tx: Sender<Datagram>,
+    utp_tx: Sender<Datagram>,
+    tracker_tx: Sender<Datagram>,
+}
+
+/// Each protocol gets its own handler so a busy one doesn't hold up the others.
 pub async fn spawn_udp_listener(state: Arc<SessionState>) -> JoinHandle<()> {
-    let udp_packet_handler_tx = spawn_udp_packet_handler(state.clone()).await;
+    let handlers = UdpHandlers {
+        dht_tx: spawn_dht_handler(state.clone()),
+        utp_tx: spawn_utp_handler(state.clone()),
+        tracker_tx: spawn_tracker_handler(state.clone()),
+    };
 
     tokio::spawn(async move {
-        let mut buf = [0u8; 2048];
+        let v6 = async {
+            if let Some(socket) = state.udp_socket_v6.as_ref() {
+                recv_datagrams(&state, socket, &handlers).await;
+            }
+        };
+        tokio::join!(recv_datagrams(&state, &state.udp_socket, &handlers), v6);
+    })
+}
+
+async fn recv_datagrams(state: &SessionState, socket: &UdpSocket, handlers: &UdpHandlers) {
+    let mut buf = [0u8; 2048];
+
+    while let Ok((n, addr)) = socket.recv_from(&mut buf).await {
+        let packet = &buf[..n];
+        let protocol = identify_udp_protocol(packet);
+        state.udp_stats.record(protocol);
+        let tx = match protocol {
+            UdpProtocol::Dht => &handlers.dht_tx,
+            UdpProtocol::Utp => &handlers.utp_tx,
+            UdpProtocol::Tracker => &handlers.tracker_tx,
+            UdpProtocol::Unknown => continue,
+        };
+        if let Err(TrySendError::Full(_)) = tx.try_send((addr, packet.to_vec())) {
+            state.udp_stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
 
-        while let Ok((n, addr)) = state.udp_socket.recv_from(&mut buf).await {
-            let _ = udp_packet_handler_tx.send((addr, buf[..n].to_vec())).await;
+fn spawn_dht_handler(state: Arc<SessionState>) -> Sender<Datagram> {
+    spawn_handler(state, |state, addr, packet| async move {
+        if let Ok(msg) = KrpcMessage::from_bytes(&packet) {
+            let _ = state
+                .dht_router
+                .lock()
+                .await
+                .do_redirect(&addr, &msg.transaction_id().unwrap_or_default(), msg)
+                .await;
         }
     })
 }
 
-#[derive(Debug, Copy, Clone)]
-enum Protocol {
-    DHT,
-    Tracker,
-    // Peer,
-    Unknown,
+fn spawn_utp_handler(state: Arc<SessionState>) -> Sender<Datagram> {
+    spawn_handler(state, |state, addr, packet| async move {
+        if let Some(utp) = state.utp_for(&addr) {
+            utp.dispatch(addr, &packet).await;
+        }
+    })
+}
+
+fn spawn_tracker_handler(state: Arc<SessionState>) -> Sender<Datagram> {
+    spawn_handler(state, |state, addr, packet| async move {
+        if let Ok(msg) = Bep15Response::from_bytes(&packet, addr.is_ipv6()) {
+            let _ = state
+                .bep15_router
+                .lock()
+                .await
+                .do_redirect(&addr, &msg.transaction_id(), msg)
+                .await;
+        }
+    })
 }
 
-async fn spawn_udp_packet_handler(state: Arc<SessionState>) -> Sender<(SocketAddr, Vec<u8>)> {
-    let (tx, mut rx) = channel::<(SocketAddr, Vec<u8>)>(4);
+/// Runs `handle` for every datagram sent to the returned channel, the task ends
+/// with the listener.
+fn spawn_handler<F, Fut>(state: Arc<SessionState>, handle: F) -> Sender<Datagram>
+where
+    F: Fn(Arc<SessionState>, SocketAddr, Vec<u8>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let (tx, mut rx): (_, Receiver<Datagram>) = channel(UDP_HANDLER_QUEUE);
 
     tokio::spawn(async move {
         while let Some((addr, packet)) = rx.recv().await {
-            match identify_udp_protocol(&packet) {
-                Protocol::DHT => {
-                    if let Ok(msg) = KrpcMessage::from_bytes(&packet) {
-                        let _ = state
-                            .dht_router
-                            .lock()
-                            .await
-                            .do_redirect(&addr, &msg.transaction_id().unwrap_or_default(), msg)
-                            .await;
-                    }
-                }
-                Protocol::Tracker => {
-                    if let Ok(msg) = Bep15Response::from_bytes(&packet) {
-                        let _ = state
-                            .bep15_router
-                            .lock()
-                            .await
-                            .do_redirect(&addr, &msg.transaction_id(), msg)
-                            .await;
-                    }
-                }
-                Protocol::Unknown => {}
-            }
+            handle(state.clone(), addr, packet).await;
         }
     });
 
     tx
 }
 
-#[inline]
-fn identify_udp_protocol(packet: &[u8]) -> Protocol {
-    if packet.len() < 4 {
-        return Protocol::Unknown;
+/// A bencoded dictionary is DHT, a header with uTP's version and a known type
+/// nibble is uTP; BEP 15 responses start with a big-endian action 0 to 3, so
+/// their first byte is 0, which no uTP header has.
+pub fn identify_udp_protocol(packet: &[u8]) -> UdpProtocol {
+    if packet.len() < BEP15_MIN_MSG_LEN {
+        return UdpProtocol::Unknown;
     }
-    // b"d" = [100]
-    if packet[0] == 100 {
-        return Protocol::DHT;
-    } else {
-        match i32::from_be_bytes(packet[..4].try_into().unwrap()) {
-            0..=3 => Protocol::Tracker,
-            _ => Protocol::Unknown,
-        }
+    if packet[0] == b'd' && packet.last() == Some(&b'e') {
+        return UdpProtocol::Dht;
+    }
+    if Packet::is_utp(packet) {
+        return UdpProtocol::Utp;
+    }
+    let min_len = match u32::from_be_bytes(packet[..4].try_into().unwrap()) {
+        // connect
+        0 => BEP15_CONNECT_LEN,
+        // announce
+        1 => BEP15_ANNOUNCE_RESPONSE_MIN_LEN,
+        // scrape and error
+        2 | 3 => BEP15_MIN_MSG_LEN,
+        _ => return UdpProtocol::Unknown,
+    };
+    match packet.len() >= min_len {
+        true => UdpProtocol::Tracker,
+        false => UdpProtocol::Unknown,
     }
 }
@@ -0,0 +1,34 @@
+use crate::{
+    session::{
+        background::{accept_encryption, accept_peer},
+        state::SessionState,
+    },
+    utp::UtpSocket,
+};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+pub async fn spawn_utp_incoming_listener(state: Arc<SessionState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let v6 = async {
+            if let Some(utp) = state.utp_v6.as_ref() {
+                accept_connections(&state, utp).await;
+            }
+        };
+        tokio::join!(accept_connections(&state, &state.utp), v6);
+    })
+}
+
+async fn accept_connections(state: &Arc<SessionState>, utp: &UtpSocket) {
+    while let Ok((stream, addr)) = utp.accept().await {
+        if !state.ip_filter.allows(&addr) {
+            continue;
+        }
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Ok(stream) = accept_encryption(&state, stream).await {
+                let _ = accept_peer(&state, stream, addr).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{error::Result, peers::IpFilter, session::SessionAlert};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        PoisonError, RwLock,
+    },
+};
+use tokio::sync::mpsc::Sender;
+
+/// The session IP filter, shared with the torrents so every peer address and
+/// connection goes through it. Banned peers are blocked on top of the filter.
+#[derive(Debug)]
+pub struct SessionIpFilter {
+    filter: RwLock<IpFilter>,
+    bans: RwLock<IpFilter>,
+    /// Bans are appended to it in the `ipfilter.dat` format.
+    ban_list: Option<PathBuf>,
+    blocked: AtomicU64,
+    alert_tx: Sender<SessionAlert>,
+}
+
+impl SessionIpFilter {
+    pub fn new(filter: IpFilter, alert_tx: Sender<SessionAlert>) -> Self {
+        Self {
+            filter: RwLock::new(filter),
+            bans: RwLock::default(),
+            ban_list: None,
+            blocked: AtomicU64::new(0),
+            alert_tx,
+        }
+    }
+
+    /// Loads the bans of earlier sessions, a missing file is created on the
+    /// first ban.
+    pub fn with_ban_list(mut self, path: PathBuf) -> Result<Self> {
+        if path.exists() {
+            self.bans = RwLock::new(IpFilter::load(&path)?);
+        }
+        self.ban_list = Some(path);
+        Ok(self)
+    }
+
+    pub fn set(&self, filter: IpFilter) {
+        *self.filter.write().unwrap_or_else(PoisonError::into_inner) = filter;
+    }
+
+    pub fn num_ranges(&self) -> usize {
+        self.filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .num_ranges()
+    }
+
+    /// Blocked addresses are counted, the session reports the count.
+    pub fn allows(&self, addr: &SocketAddr) -> bool {
+        let blocked = self
+            .filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_blocked(&addr.ip());
+        if !blocked && !self.is_banned(&addr.ip()) {
+            return true;
+        }
+        self.blocked.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.bans
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_blocked(ip)
+    }
+
+    /// Blocks `ip` for the rest of the session and in the ban list.
+    pub fn ban(&self, ip: IpAddr) {
+        {
+            let mut bans = self.bans.write().unwrap_or_else(PoisonError::into_inner);
+            if bans.is_blocked(&ip) {
+                return;
+            }
+            bans.add_range(ip, ip);
+        }
+        if let Some(path) = self.ban_list.as_ref() {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{ip} - {ip} , 000 , banned"));
+        }
+        let _ = self.alert_tx.try_send(SessionAlert::PeerBanned(ip));
+    }
+
+    #[inline]
+    pub fn blocked(&self) -> u64 {
+        self.blocked.load(Ordering::Relaxed)
+    }
+}
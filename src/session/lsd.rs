@@ -0,0 +1,133 @@
+use crate::{
+    error::Result,
+    proto::{
+        constants::{INFO_HASH_V1_SIZE, LSD_MULTICAST_V4, LSD_MULTICAST_V6},
+        lsd::LsdAnnounce,
+    },
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use tokio::net::UdpSocket;
+
+/// Local Service Discovery sockets joined to the BEP 14 multicast groups.
+#[derive(Debug)]
+pub struct Lsd {
+    socket_v4: UdpSocket,
+    /// `None` when the host has no IPv6 multicast route.
+    socket_v6: Option<UdpSocket>,
+    lsd_port: u16,
+    listen_port: u16,
+    cookie: String,
+}
+
+impl Lsd {
+    /// Must be called inside a tokio runtime.
+    pub fn bind(lsd_port: u16, listen_port: u16) -> Result<Self> {
+        let socket_v4 = multicast_socket(IpAddr::V4(LSD_MULTICAST_V4), lsd_port)?;
+        let socket_v6 = multicast_socket(IpAddr::V6(LSD_MULTICAST_V6), lsd_port).ok();
+        Ok(Self {
+            socket_v4,
+            socket_v6,
+            lsd_port,
+            listen_port,
+            cookie: hex::encode(rand::random::<[u8; 8]>()),
+        })
+    }
+
+    #[inline]
+    pub fn cookie(&self) -> &str {
+        &self.cookie
+    }
+
+    /// Sends `BT-SEARCH` announces to both groups.
+    pub async fn announce(&self, info_hashes: &[[u8; INFO_HASH_V1_SIZE]]) -> Result<()> {
+        let groups = [
+            Some((&self.socket_v4, IpAddr::V4(LSD_MULTICAST_V4))),
+            self.socket_v6
+                .as_ref()
+                .map(|socket| (socket, IpAddr::V6(LSD_MULTICAST_V6))),
+        ];
+        for (socket, group) in groups.into_iter().flatten() {
+            let group = SocketAddr::new(group, self.lsd_port);
+            let announces = LsdAnnounce::split(
+                &group.to_string(),
+                self.listen_port,
+                info_hashes,
+                Some(&self.cookie),
+            );
+            for announce in announces {
+                let res = socket.send_to(&announce.to_bytes(), group).await;
+                // a missing IPv6 route shouldn't stop the IPv4 announces
+                if res.is_err() && group.is_ipv4() {
+                    res?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Next announce from another client, with the address of its peer listener.
+    pub async fn recv(&self) -> Result<(Vec<[u8; INFO_HASH_V1_SIZE]>, SocketAddr)> {
+        let mut buf_v4 = [0u8; 2048];
+        let mut buf_v6 = [0u8; 2048];
+        loop {
+            let (n, from, buf) = tokio::select! {
+                res = self.socket_v4.recv_from(&mut buf_v4) => {
+                    let (n, from) = res?;
+                    (n, from, &buf_v4)
+                }
+                res = recv_from(self.socket_v6.as_ref(), &mut buf_v6) => {
+                    let (n, from) = res?;
+                    (n, from, &buf_v6)
+                }
+            };
+            let Ok(announce) = LsdAnnounce::from_bytes(&buf[..n]) else {
+                continue;
+            };
+            if announce.cookie.as_deref() == Some(self.cookie.as_str()) {
+                continue;
+            }
+            return Ok((
+                announce.info_hashes,
+                SocketAddr::new(from.ip(), announce.port),
+            ));
+        }
+    }
+}
+
+async fn recv_from(socket: Option<&UdpSocket>, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Shares the port with other clients on the host and loops our own datagrams back.
+fn multicast_socket(group: IpAddr, port: u16) -> io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(SocketAddr::new(group, port)),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    match group {
+        IpAddr::V4(group) => {
+            socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port).into())?;
+            socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+            socket.set_multicast_loop_v4(true)?;
+        }
+        IpAddr::V6(group) => {
+            socket.set_only_v6(true)?;
+            socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port).into())?;
+            socket.join_multicast_v6(&group, 0)?;
+            socket.set_multicast_loop_v6(true)?;
+        }
+    }
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
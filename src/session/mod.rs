@@ -1,10 +1,21 @@
 mod alert;
 mod background;
+mod ip_filter;
+mod lsd;
+mod port_mapper;
+mod queue;
 mod router;
+#[allow(clippy::module_inception)]
 mod session;
+mod settings;
 mod state;
 
 pub use alert::*;
-pub use background::SessionCommand;
+pub use background::{identify_udp_protocol, SessionCommand, UdpCounters, UdpProtocol, UdpStats};
+pub use ip_filter::*;
+pub use lsd::*;
+pub use port_mapper::*;
+pub use queue::*;
 pub use router::*;
 pub use session::*;
+pub use settings::*;
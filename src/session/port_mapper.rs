@@ -0,0 +1,425 @@
+use crate::{
+    error::{Error, Result},
+    proto::{
+        constants::{NATPMP_VERSION, PCP_VERSION},
+        natpmp::{MappingProtocol, NatPmpRequest, NatPmpResponse},
+        pcp::{PcpMapRequest, PcpMapResponse},
+        upnp::{soap_error_code, ssdp_location, ssdp_search, xml_value, IgdService},
+    },
+};
+use reqwest::{header, Client, StatusCode, Url};
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time::{self, Instant},
+};
+
+/// Doubled on every retransmission, as RFC 6886 asks.
+const NATPMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const NATPMP_MAX_TRIES: u32 = 4;
+const UPNP_SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+const UPNP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// `OnlyPermanentLeasesSupported`, such gateways want a lease duration of 0.
+const UPNP_ONLY_PERMANENT_LEASES: u16 = 725;
+const UPNP_MAPPING_DESCRIPTION: &str = "rutor";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortMappingMethod {
+    Pcp,
+    NatPmp,
+    Upnp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    pub method: PortMappingMethod,
+    pub protocol: MappingProtocol,
+    pub internal_port: u16,
+    pub external_port: u16,
+    pub external_ip: Option<IpAddr>,
+    /// Granted by the gateway, zero for a permanent UPnP mapping.
+    pub lifetime: Duration,
+}
+
+/// Maps ports on the gateway with PCP, NAT-PMP or UPnP IGD.
+#[derive(Debug)]
+pub enum PortMapper {
+    NatPmp(NatPmpClient),
+    Upnp(UpnpClient),
+}
+
+impl PortMapper {
+    /// Nothing is sent until the first mapping.
+    pub async fn natpmp(gateway: SocketAddr) -> Result<Self> {
+        Ok(Self::NatPmp(NatPmpClient::connect(gateway).await?))
+    }
+
+    /// Searches an Internet Gateway Device with SSDP at `ssdp_addr`.
+    pub async fn upnp(ssdp_addr: SocketAddr) -> Result<Self> {
+        Ok(Self::Upnp(UpnpClient::discover(ssdp_addr).await?))
+    }
+
+    pub fn method(&self) -> PortMappingMethod {
+        match self {
+            Self::NatPmp(client) if client.version == NATPMP_VERSION => PortMappingMethod::NatPmp,
+            Self::NatPmp(_) => PortMappingMethod::Pcp,
+            Self::Upnp(_) => PortMappingMethod::Upnp,
+        }
+    }
+
+    /// Maps `port` to the same external port if the gateway allows it, or
+    /// renews the mapping.
+    pub async fn map(
+        &mut self,
+        protocol: MappingProtocol,
+        port: u16,
+        lifetime: Duration,
+    ) -> Result<PortMapping> {
+        match self {
+            Self::NatPmp(client) => client.map(protocol, port, lifetime).await,
+            Self::Upnp(client) => client.map(protocol, port, lifetime).await,
+        }
+    }
+
+    pub async fn unmap(&mut self, mapping: &PortMapping) -> Result<()> {
+        match self {
+            Self::NatPmp(client) => client.unmap(mapping).await,
+            Self::Upnp(client) => client.unmap(mapping).await,
+        }
+    }
+}
+
+/// Default IPv4 gateway of the routing table, Linux only.
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        // network order bytes printed as a native integer
+        Some(Ipv4Addr::from(gateway.to_ne_bytes())).filter(|ip| !ip.is_unspecified())
+    })
+}
+
+fn unspecified_like(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+/// PCP client, which falls back to NAT-PMP for gateways that answer with the
+/// NAT-PMP version.
+#[derive(Debug)]
+pub struct NatPmpClient {
+    socket: UdpSocket,
+    version: u8,
+    /// PCP identifies the mapping of each protocol by its nonce.
+    nonces: BTreeMap<MappingProtocol, [u8; 12]>,
+}
+
+impl NatPmpClient {
+    pub async fn connect(gateway: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind((unspecified_like(gateway.ip()), 0)).await?;
+        socket.connect(gateway).await?;
+        Ok(Self {
+            socket,
+            version: PCP_VERSION,
+            nonces: BTreeMap::new(),
+        })
+    }
+
+    /// Retransmits `bytes` until the gateway sends an `accept`ed response.
+    async fn request(&self, bytes: &[u8], accept: impl Fn(&[u8]) -> bool) -> Result<Vec<u8>> {
+        let mut timeout = NATPMP_INITIAL_TIMEOUT;
+        let mut buf = [0u8; 1100];
+        for _ in 0..NATPMP_MAX_TRIES {
+            self.socket.send(bytes).await?;
+            let deadline = Instant::now() + timeout;
+            while let Ok(res) = time::timeout_at(deadline, self.socket.recv(&mut buf)).await {
+                let n = res?;
+                if accept(&buf[..n]) {
+                    return Ok(buf[..n].to_vec());
+                }
+            }
+            timeout *= 2;
+        }
+        Err(Error::PortMapping("gateway did not respond".into()))
+    }
+
+    async fn map(
+        &mut self,
+        protocol: MappingProtocol,
+        port: u16,
+        lifetime: Duration,
+    ) -> Result<PortMapping> {
+        let lifetime = lifetime.as_secs() as u32;
+        if self.version == PCP_VERSION {
+            if let Some(mapping) = self.request_pcp(protocol, port, port, lifetime).await? {
+                return Ok(mapping);
+            }
+            self.version = NATPMP_VERSION;
+        }
+        let mut mapping = self.request_natpmp(protocol, port, port, lifetime).await?;
+        let request = NatPmpRequest::ExternalAddress;
+        let response = self
+            .request(&request.to_bytes(), |bytes| {
+                bytes.len() >= 2
+                    && bytes[0] == NATPMP_VERSION
+                    && bytes[1] == request.response_opcode()
+            })
+            .await
+            .and_then(|bytes| NatPmpResponse::from_bytes(&bytes));
+        if let Ok(NatPmpResponse::ExternalAddress { addr, .. }) = response {
+            mapping.external_ip = Some(IpAddr::V4(addr));
+        }
+        Ok(mapping)
+    }
+
+    async fn unmap(&mut self, mapping: &PortMapping) -> Result<()> {
+        match mapping.method {
+            PortMappingMethod::Pcp => {
+                let port = mapping.external_port;
+                self.request_pcp(mapping.protocol, mapping.internal_port, port, 0)
+                    .await?;
+            }
+            _ => {
+                self.request_natpmp(mapping.protocol, mapping.internal_port, 0, 0)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `None` when the gateway only speaks NAT-PMP.
+    async fn request_pcp(
+        &mut self,
+        protocol: MappingProtocol,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Result<Option<PortMapping>> {
+        let client_ip = self.socket.local_addr()?.ip();
+        let nonce = *self.nonces.entry(protocol).or_insert_with(rand::random);
+        let request = PcpMapRequest {
+            lifetime,
+            client_ip,
+            nonce,
+            protocol,
+            internal_port,
+            external_port,
+            external_ip: unspecified_like(client_ip),
+        };
+        let bytes = self
+            .request(&request.to_bytes(), |bytes| {
+                bytes.len() >= 4 && (bytes[0] == NATPMP_VERSION || bytes[0] == PCP_VERSION)
+            })
+            .await?;
+        if bytes[0] == NATPMP_VERSION {
+            return Ok(None);
+        }
+        let response = PcpMapResponse::from_bytes(&bytes)?;
+        if response.nonce != nonce {
+            return Err(Error::PortMapping(
+                "PCP response for another mapping".into(),
+            ));
+        }
+        Ok(Some(PortMapping {
+            method: PortMappingMethod::Pcp,
+            protocol,
+            internal_port,
+            external_port: response.external_port,
+            external_ip: Some(response.external_ip),
+            lifetime: Duration::from_secs(response.lifetime as u64),
+        }))
+    }
+
+    async fn request_natpmp(
+        &self,
+        protocol: MappingProtocol,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Result<PortMapping> {
+        let request = NatPmpRequest::Map {
+            protocol,
+            internal_port,
+            external_port,
+            lifetime,
+        };
+        let bytes = self
+            .request(&request.to_bytes(), |bytes| {
+                bytes.len() >= 2
+                    && bytes[0] == NATPMP_VERSION
+                    && bytes[1] == request.response_opcode()
+            })
+            .await?;
+        match NatPmpResponse::from_bytes(&bytes)? {
+            NatPmpResponse::Map {
+                external_port,
+                lifetime,
+                ..
+            } => Ok(PortMapping {
+                method: PortMappingMethod::NatPmp,
+                protocol,
+                internal_port,
+                external_port,
+                external_ip: None,
+                lifetime: Duration::from_secs(lifetime as u64),
+            }),
+            _ => Err(Error::PortMapping("invalid NAT-PMP response".into())),
+        }
+    }
+}
+
+/// Client of the WAN connection service of an Internet Gateway Device.
+#[derive(Debug)]
+pub struct UpnpClient {
+    client: Client,
+    service: IgdService,
+    /// Address of this host on the gateway's network, the mapping target.
+    local_ip: IpAddr,
+}
+
+impl UpnpClient {
+    pub async fn discover(ssdp_addr: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind((unspecified_like(ssdp_addr.ip()), 0)).await?;
+        let search = ssdp_search(&ssdp_addr.to_string());
+        let location = time::timeout(UPNP_SEARCH_TIMEOUT, async {
+            socket.send_to(&search, ssdp_addr).await?;
+            let mut buf = [0u8; 2048];
+            loop {
+                let (n, _) = socket.recv_from(&mut buf).await?;
+                if let Some(location) = ssdp_location(&buf[..n]) {
+                    return Ok::<_, Error>(location);
+                }
+            }
+        })
+        .await
+        .map_err(|_| Error::PortMapping("no UPnP gateway found".into()))??;
+        let location = Url::parse(&location)
+            .map_err(|e| Error::PortMapping(format!("invalid device location: {e}")))?;
+
+        let client = Client::builder().timeout(UPNP_REQUEST_TIMEOUT).build()?;
+        let description = client
+            .get(location.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let service = IgdService::from_description(&description, &location)?;
+        let local_ip = local_ip_towards(&service.control_url).await?;
+        Ok(Self {
+            client,
+            service,
+            local_ip,
+        })
+    }
+
+    async fn soap(&self, action: &str, args: &[(&str, String)]) -> Result<(StatusCode, String)> {
+        let response = self
+            .client
+            .post(self.service.control_url.clone())
+            .header(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", self.service.soap_action(action))
+            .body(self.service.soap_body(action, args))
+            .send()
+            .await?;
+        let status = response.status();
+        Ok((status, response.text().await?))
+    }
+
+    async fn map(
+        &mut self,
+        protocol: MappingProtocol,
+        port: u16,
+        lifetime: Duration,
+    ) -> Result<PortMapping> {
+        let mut lifetime = lifetime;
+        let mut response = self.add_port_mapping(protocol, port, lifetime).await?;
+        if soap_error_code(&response.1) == Some(UPNP_ONLY_PERMANENT_LEASES) {
+            lifetime = Duration::ZERO;
+            response = self.add_port_mapping(protocol, port, lifetime).await?;
+        }
+        check_soap_response("AddPortMapping", response)?;
+
+        let external_ip = match self.soap("GetExternalIPAddress", &[]).await {
+            Ok((status, body)) if status.is_success() => {
+                xml_value(&body, "NewExternalIPAddress").and_then(|ip| ip.parse().ok())
+            }
+            _ => None,
+        };
+        Ok(PortMapping {
+            method: PortMappingMethod::Upnp,
+            protocol,
+            internal_port: port,
+            external_port: port,
+            external_ip,
+            lifetime,
+        })
+    }
+
+    async fn add_port_mapping(
+        &self,
+        protocol: MappingProtocol,
+        port: u16,
+        lifetime: Duration,
+    ) -> Result<(StatusCode, String)> {
+        let args = [
+            ("NewRemoteHost", String::new()),
+            ("NewExternalPort", port.to_string()),
+            ("NewProtocol", protocol.as_str().to_string()),
+            ("NewInternalPort", port.to_string()),
+            ("NewInternalClient", self.local_ip.to_string()),
+            ("NewEnabled", "1".to_string()),
+            (
+                "NewPortMappingDescription",
+                UPNP_MAPPING_DESCRIPTION.to_string(),
+            ),
+            ("NewLeaseDuration", lifetime.as_secs().to_string()),
+        ];
+        self.soap("AddPortMapping", &args).await
+    }
+
+    async fn unmap(&mut self, mapping: &PortMapping) -> Result<()> {
+        let args = [
+            ("NewRemoteHost", String::new()),
+            ("NewExternalPort", mapping.external_port.to_string()),
+            ("NewProtocol", mapping.protocol.as_str().to_string()),
+        ];
+        let response = self.soap("DeletePortMapping", &args).await?;
+        check_soap_response("DeletePortMapping", response)
+    }
+}
+
+fn check_soap_response(action: &str, (status, body): (StatusCode, String)) -> Result<()> {
+    if status.is_success() {
+        return Ok(());
+    }
+    Err(Error::PortMapping(match soap_error_code(&body) {
+        Some(code) => format!("{action} failed with UPnP error {code}"),
+        None => format!("{action} failed with HTTP status {status}"),
+    }))
+}
+
+/// Local address of the route to `url`, found by connecting a UDP socket.
+async fn local_ip_towards(url: &Url) -> Result<IpAddr> {
+    let host = url.host_str().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or_default();
+    let addr = lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| Error::PortMapping(format!("can't resolve {host}")))?;
+    let socket = UdpSocket::bind((unspecified_like(addr.ip()), 0)).await?;
+    socket.connect(addr).await?;
+    Ok(socket.local_addr()?.ip())
+}
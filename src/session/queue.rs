@@ -0,0 +1,296 @@
+use crate::torrent::{TorrentID, TorrentStatus};
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_ACTIVE_DOWNLOADS: usize = 3;
+const DEFAULT_MAX_ACTIVE_SEEDS: usize = 3;
+const DEFAULT_MAX_ACTIVE_TOTAL: usize = 5;
+const DEFAULT_SLOW_RATE_THRESHOLD: u64 = 2 * 1024;
+const DEFAULT_SLOW_TORRENT_INACTIVE_TIME: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct QueueSettings {
+    /// `None` means unlimited.
+    pub max_active_downloads: Option<usize>,
+    pub max_active_seeds: Option<usize>,
+    pub max_active_total: Option<usize>,
+    pub dont_count_slow_torrents: bool,
+    /// Bytes per second.
+    pub slow_download_rate_threshold: u64,
+    pub slow_upload_rate_threshold: u64,
+    /// A torrent has to stay active for this long before it can be considered slow.
+    pub slow_torrent_inactive_time: Duration,
+}
+
+impl Default for QueueSettings {
+    fn default() -> Self {
+        Self {
+            max_active_downloads: Some(DEFAULT_MAX_ACTIVE_DOWNLOADS),
+            max_active_seeds: Some(DEFAULT_MAX_ACTIVE_SEEDS),
+            max_active_total: Some(DEFAULT_MAX_ACTIVE_TOTAL),
+            dont_count_slow_torrents: true,
+            slow_download_rate_threshold: DEFAULT_SLOW_RATE_THRESHOLD,
+            slow_upload_rate_threshold: DEFAULT_SLOW_RATE_THRESHOLD,
+            slow_torrent_inactive_time: DEFAULT_SLOW_TORRENT_INACTIVE_TIME,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub torrent_id: TorrentID,
+    pub status: TorrentStatus,
+    pub is_finished: bool,
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    active_since: Option<Instant>,
+}
+
+impl QueueEntry {
+    fn new(torrent_id: TorrentID, status: TorrentStatus, is_finished: bool) -> Self {
+        let mut entry = Self {
+            torrent_id,
+            status: TorrentStatus::Waiting,
+            is_finished,
+            download_rate: 0,
+            upload_rate: 0,
+            active_since: None,
+        };
+        entry.set_status(status);
+        entry
+    }
+
+    fn set_status(&mut self, status: TorrentStatus) {
+        match status {
+            TorrentStatus::Downloading | TorrentStatus::Seeding => {
+                if !self.is_active() {
+                    self.active_since = Some(Instant::now());
+                }
+            }
+            _ => self.active_since = None,
+        }
+        self.status = status;
+    }
+
+    /// Paused and errored torrents are kept in the queue but never started by it.
+    #[inline]
+    pub fn is_managed(&self) -> bool {
+        matches!(
+            self.status,
+            TorrentStatus::Waiting | TorrentStatus::Downloading | TorrentStatus::Seeding
+        )
+    }
+
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.status,
+            TorrentStatus::Downloading | TorrentStatus::Seeding
+        )
+    }
+
+    fn is_slow(&self, settings: &QueueSettings) -> bool {
+        let Some(active_since) = self.active_since else {
+            return false;
+        };
+        active_since.elapsed() >= settings.slow_torrent_inactive_time
+            && self.download_rate < settings.slow_download_rate_threshold
+            && self.upload_rate < settings.slow_upload_rate_threshold
+    }
+}
+
+/// Torrent queue ordered by position, the first entry has the highest priority.
+#[derive(Debug, Default)]
+pub struct TorrentQueue {
+    settings: QueueSettings,
+    entries: Vec<QueueEntry>,
+}
+
+impl TorrentQueue {
+    pub fn new(settings: QueueSettings) -> Self {
+        Self {
+            settings,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn settings(&self) -> &QueueSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: QueueSettings) {
+        self.settings = settings;
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &QueueEntry> {
+        self.entries.iter()
+    }
+
+    /// Appends the torrent to the bottom of the queue and returns its position.
+    pub fn push(
+        &mut self,
+        torrent_id: TorrentID,
+        status: TorrentStatus,
+        is_finished: bool,
+    ) -> usize {
+        if let Some(position) = self.position(&torrent_id) {
+            return position;
+        }
+        self.entries
+            .push(QueueEntry::new(torrent_id, status, is_finished));
+        self.entries.len() - 1
+    }
+
+    pub fn remove(&mut self, torrent_id: &TorrentID) -> bool {
+        match self.position(torrent_id) {
+            Some(position) => {
+                self.entries.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn position(&self, torrent_id: &TorrentID) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| &e.torrent_id == torrent_id)
+    }
+
+    pub fn get(&self, torrent_id: &TorrentID) -> Option<&QueueEntry> {
+        self.entries.iter().find(|e| &e.torrent_id == torrent_id)
+    }
+
+    pub fn move_up(&mut self, torrent_id: &TorrentID) -> bool {
+        match self.position(torrent_id) {
+            Some(position) if position > 0 => {
+                self.entries.swap(position, position - 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn move_down(&mut self, torrent_id: &TorrentID) -> bool {
+        match self.position(torrent_id) {
+            Some(position) if position + 1 < self.entries.len() => {
+                self.entries.swap(position, position + 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn move_top(&mut self, torrent_id: &TorrentID) -> bool {
+        match self.position(torrent_id) {
+            Some(position) if position > 0 => {
+                let entry = self.entries.remove(position);
+                self.entries.insert(0, entry);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn move_bottom(&mut self, torrent_id: &TorrentID) -> bool {
+        match self.position(torrent_id) {
+            Some(position) if position + 1 < self.entries.len() => {
+                let entry = self.entries.remove(position);
+                self.entries.push(entry);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Syncs the entry with the actual torrent state.
+    pub fn update(
+        &mut self,
+        torrent_id: &TorrentID,
+        status: TorrentStatus,
+        is_finished: bool,
+        download_rate: u64,
+        upload_rate: u64,
+    ) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|e| &e.torrent_id == torrent_id)
+        {
+            if entry.status != status {
+                entry.set_status(status);
+            }
+            entry.is_finished = is_finished;
+            entry.download_rate = download_rate;
+            entry.upload_rate = upload_rate;
+        }
+    }
+
+    /// Distributes the active slots in queue order, downloads first, and
+    /// returns the status transitions that have to be applied to torrents.
+    pub fn recalculate(&mut self) -> Vec<(TorrentID, TorrentStatus)> {
+        let Self { settings, entries } = self;
+
+        let mut num_downloads = 0;
+        let mut num_seeds = 0;
+        let mut num_total = 0;
+        let mut transitions = Vec::new();
+
+        for is_finished in [false, true] {
+            let (num_active, max_active, active_status) = if is_finished {
+                (
+                    &mut num_seeds,
+                    settings.max_active_seeds,
+                    TorrentStatus::Seeding,
+                )
+            } else {
+                (
+                    &mut num_downloads,
+                    settings.max_active_downloads,
+                    TorrentStatus::Downloading,
+                )
+            };
+
+            for entry in entries
+                .iter_mut()
+                .filter(|e| e.is_managed() && e.is_finished == is_finished)
+            {
+                let status = if settings.dont_count_slow_torrents
+                    && entry.is_active()
+                    && entry.is_slow(settings)
+                {
+                    active_status.clone()
+                } else if has_slot(*num_active, max_active)
+                    && has_slot(num_total, settings.max_active_total)
+                {
+                    *num_active += 1;
+                    num_total += 1;
+                    active_status.clone()
+                } else {
+                    TorrentStatus::Waiting
+                };
+
+                if entry.status != status {
+                    entry.set_status(status.clone());
+                    transitions.push((entry.torrent_id, status));
+                }
+            }
+        }
+
+        transitions
+    }
+}
+
+#[inline]
+fn has_slot(num_active: usize, max_active: Option<usize>) -> bool {
+    max_active.is_none_or(|max| num_active < max)
+}
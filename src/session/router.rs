@@ -37,6 +37,12 @@ impl<K: Ord, M> DerefMut for ResponseRouter<K, M> {
     }
 }
 
+impl<K: Ord, M> Default for ResponseRouter<K, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K: Ord, M> ResponseRouter<K, M> {
     pub fn new() -> Self {
         Self(BTreeMap::new())
@@ -80,7 +86,7 @@ impl<K: Ord, M> ResponseRouter<K, M> {
     ) -> bool {
         self.0
             .entry(addr)
-            .or_insert_with(BTreeMap::new)
+            .or_default()
             .insert(transaction_id, chan)
             .is_none()
     }
@@ -1,27 +1,95 @@
-use std::sync::Arc;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::{
+    net::TcpStream,
+    sync::mpsc::{Receiver, Sender},
+};
 
 use crate::{
     error::{Error, Result},
+    peers::MseStream,
     session::{
         background::{
-            spawn_command_handler, spawn_tcp_incoming_listener, spawn_udp_listener, SessionCommand,
+            spawn_blocked_reporter, spawn_command_handler, spawn_dht_announcer, spawn_lsd_service,
+            spawn_port_mapper, spawn_queue_manager, spawn_seeding_monitor, spawn_stream_server,
+            spawn_tcp_incoming_listener, spawn_tracker_announcer, spawn_udp_listener,
+            spawn_utp_incoming_listener, SessionCommand,
         },
         state::SessionState,
-        SessionAlert,
+        SessionAlert, SessionSettings, UdpCounters,
     },
+    torrent::TorrentSource,
 };
 
 pub struct Session {
     cmd_tx: Sender<SessionCommand>,
     alert_rx: Receiver<SessionAlert>,
+    state: Arc<SessionState>,
 }
 
 impl Session {
     pub async fn start() -> Result<Self> {
-        let (cmd_tx, alert_rx) = spawn_new_session().await?;
-        Ok(Self { cmd_tx, alert_rx })
+        Self::start_with_settings(SessionSettings::default()).await
+    }
+
+    pub async fn start_with_settings(settings: SessionSettings) -> Result<Self> {
+        let (cmd_tx, alert_rx, state) = spawn_new_session(settings).await?;
+        Ok(Self {
+            cmd_tx,
+            alert_rx,
+            state,
+        })
+    }
+
+    /// Port of the peer listener, useful when the settings asked for port 0.
+    #[inline]
+    pub fn listen_port(&self) -> u16 {
+        self.state.listen_port()
+    }
+
+    /// Address of the streaming server, files are served at
+    /// `/stream/<infohash>/<file index>`.
+    #[inline]
+    pub fn stream_addr(&self) -> Option<SocketAddr> {
+        self.state.stream_addr()
+    }
+
+    /// Datagrams received on the shared UDP socket, per protocol.
+    #[inline]
+    pub fn udp_counters(&self) -> UdpCounters {
+        self.state.udp_stats.counters()
+    }
+
+    /// Peer addresses and connections turned down by the IP filter so far.
+    #[inline]
+    pub fn blocked_peers(&self) -> u64 {
+        self.state.ip_filter.blocked()
+    }
+
+    /// Public address reported by the gateway when the listen port was mapped.
+    #[inline]
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        self.state.external_ip()
+    }
+
+    /// Connects to a peer with the session's encryption policy, unless the IP
+    /// filter blocks it.
+    pub async fn connect_peer(
+        &self,
+        addr: SocketAddr,
+        info_hash: &[u8; 20],
+    ) -> Result<MseStream<TcpStream>> {
+        self.state.connect_peer(addr, info_hash).await
+    }
+
+    /// Parses a source for `SessionCommand::AddTorrent` like
+    /// `TorrentSource::from_str`, .torrent URLs are downloaded through the
+    /// trackers proxy of the settings.
+    pub async fn torrent_source(&self, s: &str) -> Result<TorrentSource> {
+        TorrentSource::from_str(s, self.state.settings.proxy.trackers.as_ref()).await
     }
 
     #[inline]
@@ -32,18 +100,35 @@ impl Session {
             .map_err(|err| Error::SendSessionCommand(err.0))
     }
 
+    /// Alerts are dropped while the queue is full, the session never waits
+    /// for them to be read.
     #[inline]
     pub async fn recv(&mut self) -> Option<SessionAlert> {
         self.alert_rx.recv().await
     }
 }
 
-pub async fn spawn_new_session() -> Result<(Sender<SessionCommand>, Receiver<SessionAlert>)> {
-    let (state, alert_rx) = SessionState::init().await?;
+pub async fn spawn_new_session(
+    settings: SessionSettings,
+) -> Result<(
+    Sender<SessionCommand>,
+    Receiver<SessionAlert>,
+    Arc<SessionState>,
+)> {
+    let (state, alert_rx) = SessionState::init(settings).await?;
     let state = Arc::new(state);
 
     let udp_listener_handle = spawn_udp_listener(state.clone()).await;
     let tcp_incoming_listener_handle = spawn_tcp_incoming_listener(state.clone()).await;
+    let utp_incoming_listener_handle = spawn_utp_incoming_listener(state.clone()).await;
+    let queue_manager_handle = spawn_queue_manager(state.clone()).await;
+    let seeding_monitor_handle = spawn_seeding_monitor(state.clone()).await;
+    let stream_server_handle = spawn_stream_server(state.clone()).await;
+    let dht_announcer_handle = spawn_dht_announcer(state.clone()).await;
+    let lsd_service_handle = spawn_lsd_service(state.clone()).await;
+    let tracker_announcer_handle = spawn_tracker_announcer(state.clone()).await;
+    let blocked_reporter_handle = spawn_blocked_reporter(state.clone()).await;
+    let (port_mapper_tx, port_mapper_handle) = spawn_port_mapper(state.clone()).await;
 
     let (cmd_tx, command_jh) = spawn_command_handler(state.clone()).await;
 
@@ -51,7 +136,18 @@ pub async fn spawn_new_session() -> Result<(Sender<SessionCommand>, Receiver<Ses
         let _ = command_jh.await;
         udp_listener_handle.abort();
         tcp_incoming_listener_handle.abort();
+        utp_incoming_listener_handle.abort();
+        queue_manager_handle.abort();
+        seeding_monitor_handle.abort();
+        stream_server_handle.abort();
+        dht_announcer_handle.abort();
+        lsd_service_handle.abort();
+        tracker_announcer_handle.abort();
+        blocked_reporter_handle.abort();
+        // waited for so the mappings are removed from the gateway
+        let _ = port_mapper_tx.send(());
+        let _ = port_mapper_handle.await;
     });
 
-    Ok((cmd_tx, alert_rx))
+    Ok((cmd_tx, alert_rx, state))
 }
@@ -0,0 +1,80 @@
+use crate::{
+    peers::EncryptionPolicy,
+    proto::constants::{BOOTSTRAP_NODES, LSD_PORT, SSDP_MULTICAST_V4, SSDP_PORT},
+    proxy::ProxySettings,
+    session::QueueSettings,
+    torrent::SeedingLimits,
+};
+use std::{net::SocketAddr, path::PathBuf};
+
+const DEFAULT_LISTEN_PORT: u16 = 6881;
+const DEFAULT_SAVE_PATH: &str = "downloads";
+
+#[derive(Debug, Clone)]
+pub struct SessionSettings {
+    /// Shared by the TCP listener and the UDP socket, `0` picks a random port.
+    pub listen_port: u16,
+    pub save_path: PathBuf,
+    pub queue: QueueSettings,
+    /// Applied to torrents without their own seeding limits.
+    pub seeding: SeedingLimits,
+    /// Embedded HTTP streaming server, disabled when `None`.
+    pub stream_addr: Option<SocketAddr>,
+    /// Looks peers up in the DHT starting from the bootstrap nodes and
+    /// announces to the closest nodes found. Private torrents are never
+    /// looked up.
+    pub dht: bool,
+    /// `host:port` of the nodes asked for peers.
+    pub dht_bootstrap_nodes: Vec<String>,
+    /// BEP 14 Local Service Discovery, finds peers of the same torrents on
+    /// the local network. Private torrents are never announced.
+    pub lsd: bool,
+    pub lsd_port: u16,
+    /// Also listens on IPv6 and announces to trackers over it (BEP 7).
+    pub ipv6: bool,
+    /// MSE/PE for incoming and outgoing peer connections.
+    pub encryption: EncryptionPolicy,
+    /// eMule `ipfilter.dat` or PeerGuardian P2P list of blocked ranges.
+    pub ip_filter: Option<PathBuf>,
+    /// Peers banned for sending bad data, kept across sessions.
+    pub ban_list: Option<PathBuf>,
+    pub proxy: ProxySettings,
+    /// Maps the listen port on the gateway with UPnP IGD so peers outside
+    /// the NAT can connect to us.
+    pub upnp: bool,
+    /// Where SSDP searches for the gateway are sent.
+    pub ssdp_addr: SocketAddr,
+    /// Maps the listen port on the gateway with PCP or NAT-PMP, tried before
+    /// UPnP.
+    pub natpmp: bool,
+    /// PCP/NAT-PMP server, the default gateway when `None`.
+    pub natpmp_gateway: Option<SocketAddr>,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            listen_port: DEFAULT_LISTEN_PORT,
+            save_path: PathBuf::from(DEFAULT_SAVE_PATH),
+            queue: QueueSettings::default(),
+            seeding: SeedingLimits::default(),
+            stream_addr: None,
+            dht: true,
+            dht_bootstrap_nodes: BOOTSTRAP_NODES
+                .iter()
+                .map(|node| node.to_string())
+                .collect(),
+            lsd: false,
+            lsd_port: LSD_PORT,
+            ipv6: true,
+            encryption: EncryptionPolicy::default(),
+            ip_filter: None,
+            ban_list: None,
+            proxy: ProxySettings::default(),
+            upnp: true,
+            ssdp_addr: SocketAddr::from((SSDP_MULTICAST_V4, SSDP_PORT)),
+            natpmp: true,
+            natpmp_gateway: None,
+        }
+    }
+}
@@ -1,60 +1,230 @@
 use crate::{
     error::{Error, Result},
-    session::{Bep15ResponseRouter, DhtResponseRouter, SessionAlert},
-    torrent::{TorrentCommand, TorrentID},
+    peers::{connect_peer_via, IpFilter, MseStream},
+    proto::{constants::INFO_HASH_V1_SIZE, PeerId},
+    session::{
+        Bep15ResponseRouter, DhtResponseRouter, SessionAlert, SessionIpFilter, SessionSettings,
+        TorrentQueue, UdpStats,
+    },
+    torrent::{SeedingLimits, TorrentCommand, TorrentHandle, TorrentID},
+    utp::UtpSocket,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, PoisonError, RwLock},
 };
-use std::collections::BTreeMap;
 use tokio::{
-    net::{TcpListener, UdpSocket},
+    net::{TcpListener, TcpStream, UdpSocket},
     sync::{
         mpsc::{channel, Receiver, Sender},
         Mutex,
     },
 };
 
-const DEFAULT_UDP_PORT: u16 = 6881;
-const DEFAULT_TCP_PORT: u16 = 6881;
+/// Alerts beyond it are dropped until the application catches up.
+const ALERT_QUEUE_SIZE: usize = 1024;
 
-type TorrentsCmd = BTreeMap<TorrentID, Sender<TorrentCommand>>;
+type Torrents = BTreeMap<TorrentID, TorrentHandle>;
 
 pub struct SessionState {
-    pub udp_socket: UdpSocket,
+    pub settings: SessionSettings,
+    pub peer_id: PeerId,
+    pub udp_socket: Arc<UdpSocket>,
+    /// uTP connections multiplexed on `udp_socket`.
+    pub utp: Arc<UtpSocket>,
+    /// IPv6 counterparts on the same port, `None` without IPv6.
+    pub udp_socket_v6: Option<Arc<UdpSocket>>,
+    pub utp_v6: Option<Arc<UtpSocket>>,
+    pub udp_stats: Arc<UdpStats>,
     pub tcp_listener: TcpListener,
+    pub tcp_listener_v6: Option<TcpListener>,
+    pub stream_listener: Option<TcpListener>,
     pub dht_router: Mutex<DhtResponseRouter>,
     pub bep15_router: Mutex<Bep15ResponseRouter>,
-    torrents_cmd: Mutex<TorrentsCmd>,
+    pub torrents: Mutex<Torrents>,
+    pub queue: Mutex<TorrentQueue>,
+    pub seeding_limits: Mutex<SeedingLimits>,
+    pub ip_filter: Arc<SessionIpFilter>,
+    /// Learned from the gateway when mapping the listen port.
+    pub external_ip: RwLock<Option<IpAddr>>,
     alert_tx: Sender<SessionAlert>,
 }
 
 impl SessionState {
-    pub async fn init() -> Result<(Self, Receiver<SessionAlert>)> {
-        let udp_socket = UdpSocket::bind(("0.0.0.0", DEFAULT_UDP_PORT)).await?;
-        let tcp_listener = TcpListener::bind(("0.0.0.0", DEFAULT_TCP_PORT)).await?;
+    pub async fn init(settings: SessionSettings) -> Result<(Self, Receiver<SessionAlert>)> {
+        let tcp_listener = TcpListener::bind(("0.0.0.0", settings.listen_port)).await?;
+        let listen_port = tcp_listener.local_addr()?.port();
+        let udp_socket = Arc::new(UdpSocket::bind(("0.0.0.0", listen_port)).await?);
+        let utp = UtpSocket::new(udp_socket.clone());
+        let (tcp_listener_v6, udp_socket_v6) = match settings.ipv6 {
+            true => (
+                bind_v6(Type::STREAM, listen_port)
+                    .and_then(|socket| {
+                        socket.listen(1024)?;
+                        TcpListener::from_std(socket.into())
+                    })
+                    .ok(),
+                bind_v6(Type::DGRAM, listen_port)
+                    .and_then(|socket| UdpSocket::from_std(socket.into()))
+                    .map(Arc::new)
+                    .ok(),
+            ),
+            false => (None, None),
+        };
+        let utp_v6 = udp_socket_v6.clone().map(UtpSocket::new);
+        let stream_listener = match settings.stream_addr {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
 
         let dht_router = Mutex::new(DhtResponseRouter::new());
         let bep15_router = Mutex::new(Bep15ResponseRouter::new());
 
-        let torrents_cmd = Mutex::new(TorrentsCmd::new());
-        let (alert_tx, alert_rx) = channel::<SessionAlert>(4);
+        let torrents = Mutex::new(Torrents::new());
+        let queue = Mutex::new(TorrentQueue::new(settings.queue.clone()));
+        let seeding_limits = Mutex::new(settings.seeding.clone());
+        let (alert_tx, alert_rx) = channel::<SessionAlert>(ALERT_QUEUE_SIZE);
+        let ip_filter = match settings.ip_filter.as_ref() {
+            Some(path) => IpFilter::load(path)?,
+            None => IpFilter::new(),
+        };
+        let ip_filter = SessionIpFilter::new(ip_filter, alert_tx.clone());
+        let ip_filter = Arc::new(match settings.ban_list.clone() {
+            Some(path) => ip_filter.with_ban_list(path)?,
+            None => ip_filter,
+        });
 
         Ok((
             Self {
+                settings,
+                peer_id: PeerId::gen_new(),
                 udp_socket,
+                utp,
+                udp_socket_v6,
+                utp_v6,
+                udp_stats: Arc::default(),
                 tcp_listener,
+                tcp_listener_v6,
+                stream_listener,
                 dht_router,
                 bep15_router,
+                torrents,
+                queue,
+                seeding_limits,
+                ip_filter,
+                external_ip: RwLock::default(),
                 alert_tx,
-                torrents_cmd,
             },
             alert_rx,
         ))
     }
 
-    pub async fn send_alert(&self, alert: SessionAlert) -> Result<()> {
+    pub fn listen_port(&self) -> u16 {
+        self.tcp_listener
+            .local_addr()
+            .map(|addr| addr.port())
+            .unwrap_or(self.settings.listen_port)
+    }
+
+    pub fn udp_socket_for(&self, addr: &SocketAddr) -> Option<&Arc<UdpSocket>> {
+        match addr {
+            SocketAddr::V4(_) => Some(&self.udp_socket),
+            SocketAddr::V6(_) => self.udp_socket_v6.as_ref(),
+        }
+    }
+
+    pub fn utp_for(&self, addr: &SocketAddr) -> Option<&Arc<UtpSocket>> {
+        match addr {
+            SocketAddr::V4(_) => Some(&self.utp),
+            SocketAddr::V6(_) => self.utp_v6.as_ref(),
+        }
+    }
+
+    /// Outgoing peer connection, refused for addresses of the IP filter.
+    pub async fn connect_peer(
+        &self,
+        addr: SocketAddr,
+        info_hash: &[u8; 20],
+    ) -> Result<MseStream<TcpStream>> {
+        if !self.ip_filter.allows(&addr) {
+            return Err(Error::PeerBlocked(addr));
+        }
+        connect_peer_via(
+            self.settings.proxy.peers.as_ref(),
+            addr,
+            info_hash,
+            self.settings.encryption,
+        )
+        .await
+    }
+
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        *self
+            .external_ip
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Our public address on a family, advertised to peers connected over the
+    /// other one (BEP 7). The gateway's answer if it has one, otherwise the
+    /// source address of the default route if it is routable.
+    pub fn public_ip(&self, ipv6: bool) -> Option<IpAddr> {
+        if let Some(ip) = self.external_ip().filter(|ip| ip.is_ipv6() == ipv6) {
+            return Some(ip);
+        }
+        if ipv6 && self.tcp_listener_v6.is_none() {
+            return None;
+        }
+        route_source_ip(ipv6).filter(is_public_ip)
+    }
+
+    pub fn stream_addr(&self) -> Option<SocketAddr> {
+        self.stream_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Never waits for the application, the alert is dropped when the queue
+    /// is full.
+    pub fn send_alert(&self, alert: SessionAlert) -> Result<()> {
         self.alert_tx
-            .send(alert)
-            .await
-            .map_err(|e| Error::SendSessionAlert(e.0))
+            .try_send(alert)
+            .map_err(|e| Error::SendSessionAlert(e.into_inner()))
+    }
+
+    #[inline]
+    pub fn alert_sender(&self) -> Sender<SessionAlert> {
+        self.alert_tx.clone()
+    }
+
+    /// Looks a torrent up by the 20 bytes of a handshake, hybrid torrents match
+    /// their v1 hash and their truncated v2 hash.
+    pub async fn find_torrent(&self, info_hash: &[u8; INFO_HASH_V1_SIZE]) -> Option<TorrentHandle> {
+        let torrents = self.torrents.lock().await;
+        if let Some(torrent) = torrents.get(info_hash) {
+            return Some(torrent.clone());
+        }
+        for torrent in torrents.values() {
+            if torrent.state.lock().await.info_hash.matches(info_hash) {
+                return Some(torrent.clone());
+            }
+        }
+        None
+    }
+
+    /// The 20 byte hashes peers may use for any torrent, the MSE `SKEY`s.
+    pub async fn info_hashes(&self) -> Vec<[u8; INFO_HASH_V1_SIZE]> {
+        let mut info_hashes = Vec::new();
+        for torrent in self.torrents.lock().await.values() {
+            let state = torrent.state.lock().await;
+            for info_hash in state.info_hash.swarm_hashes() {
+                info_hashes.push(*info_hash.inner().truncate());
+            }
+        }
+        info_hashes
     }
 
     pub async fn send_to_torrent_cmd(
@@ -62,14 +232,58 @@ impl SessionState {
         torrent_id: &TorrentID,
         command: TorrentCommand,
     ) -> Option<Result<()>> {
-        if let Some(cmd) = self.torrents_cmd.lock().await.get(torrent_id) {
-            Some(
-                cmd.send(command)
-                    .await
-                    .map_err(|e| Error::SendToTorrentCmd(e.0)),
-            )
-        } else {
-            None
+        let torrent = self.torrents.lock().await.get(torrent_id).cloned();
+        match torrent {
+            Some(torrent) => Some(torrent.send(command).await),
+            None => None,
+        }
+    }
+}
+
+/// IPv6 only, so the IPv4 sockets can share the port.
+fn bind_v6(ty: Type, port: u16) -> io::Result<Socket> {
+    let protocol = match ty {
+        Type::STREAM => Protocol::TCP,
+        _ => Protocol::UDP,
+    };
+    let socket = Socket::new(Domain::IPV6, ty, Some(protocol))?;
+    socket.set_only_v6(true)?;
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    Ok(socket)
+}
+
+/// Connecting a UDP socket picks the route without sending anything, the
+/// documentation addresses stand for any host.
+fn route_source_ip(ipv6: bool) -> Option<IpAddr> {
+    let (local, remote): (SocketAddr, SocketAddr) = match ipv6 {
+        true => (
+            (Ipv6Addr::UNSPECIFIED, 0).into(),
+            (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 9).into(),
+        ),
+        false => (
+            (Ipv4Addr::UNSPECIFIED, 0).into(),
+            (Ipv4Addr::new(192, 0, 2, 1), 9).into(),
+        ),
+    };
+    let socket = std::net::UdpSocket::bind(local).ok()?;
+    socket.connect(remote).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_unspecified() || ip.is_loopback() || ip.is_private() || ip.is_link_local())
+        }
+        IpAddr::V6(ip) => {
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_unicast_link_local()
+                || ip.is_unique_local())
         }
     }
 }
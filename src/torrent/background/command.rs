@@ -1,25 +1,169 @@
-// use crate::session::state::SessionState;
-// use std::sync::Arc;
-// use tokio::{
-//     sync::mpsc::{self, Sender},
-//     task::JoinHandle,
-// };
+use crate::{
+    peers::PeerConnection,
+    session::SessionAlert,
+    torrent::{
+        spawn_peer_wire, FilePriority, SeedingLimits, TorrentID, TorrentState, TorrentStatus,
+    },
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{
+        mpsc::{self, Sender},
+        Mutex,
+    },
+    task::JoinHandle,
+    time,
+};
 
-#[derive(Debug, Clone)]
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
 pub enum TorrentCommand {
-    // AddTorrent(int),
+    /// `None` falls back to the session defaults.
+    SetSeedingLimits(Option<SeedingLimits>),
+    /// Indexed like the files of the torrent.
+    SetFilePriorities(Vec<FilePriority>),
+    /// The piece is requested before anything else and
+    /// `SessionAlert::PieceDeadlineReady` is sent once it is downloaded.
+    SetPieceDeadline(usize, Duration),
+    /// Drops the deadline of a single piece, e.g. once nobody waits for it.
+    RemovePieceDeadline(usize),
+    ClearPieceDeadlines,
+    /// Download pieces in order starting from the playhead.
+    SetSequentialDownload(bool),
+    SetPlayhead(usize),
+    /// The piece has been downloaded and verified, sent by the download tasks
+    /// of the torrent only.
+    PieceCompleted(CompletedPiece),
+    /// A peer connected to us for this torrent.
+    IncomingPeer(Box<PeerConnection>),
+    /// The bencoded info dictionary of a torrent started from a magnet link.
+    MetadataReceived(Vec<u8>),
 }
 
-// pub async fn spawn_command_handler(
-//     state: Arc<SessionState>,
-// ) -> (Sender<SessionCommand>, JoinHandle<()>) {
-//     let (tx, mut rx) = mpsc::channel(4);
+/// Index of a verified piece, only the crate can vouch for one.
+#[derive(Debug, Clone, Copy)]
+pub struct CompletedPiece(pub(crate) usize);
+
+pub async fn spawn_command_handler(
+    torrent_id: TorrentID,
+    state: Arc<Mutex<TorrentState>>,
+    alert_tx: Sender<SessionAlert>,
+) -> (Sender<TorrentCommand>, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel(4);
+    let weak_tx = tx.downgrade();
 
-//     let jh = tokio::spawn(async move {
-//         while let Some(command) = rx.recv().await {
-//             match command {}
-//         }
-//     });
+    let jh = tokio::spawn(async move {
+        let mut ticker = time::interval(TICK_INTERVAL);
+        loop {
+            tokio::select! {
+                command = rx.recv() => {
+                    let Some(command) = command else {
+                        break;
+                    };
+                    match command {
+                        TorrentCommand::SetSeedingLimits(limits) => {
+                            state.lock().await.seeding_limits = limits;
+                        }
+                        TorrentCommand::SetFilePriorities(priorities) => {
+                            // data moves between the files without the state lock
+                            let disk = {
+                                let mut state = state.lock().await;
+                                state.set_file_priorities(priorities);
+                                let priorities = state.file_priorities().to_vec();
+                                state.disk.clone().map(|disk| (disk, priorities))
+                            };
+                            if let Some((disk, priorities)) = disk {
+                                let result = disk.lock().await.set_file_priorities(&priorities).await;
+                                if let Err(e) = result {
+                                    let mut state = state.lock().await;
+                                    state.set_status(TorrentStatus::Error(e.to_string()));
+                                }
+                            }
+                        }
+                        TorrentCommand::SetPieceDeadline(piece, deadline) => {
+                            let ready = {
+                                let mut state = state.lock().await;
+                                if !state.has_piece(piece) {
+                                    state
+                                        .picker
+                                        .set_piece_deadline(piece, Instant::now() + deadline);
+                                }
+                                state.has_piece(piece)
+                            };
+                            if ready {
+                                let alert = SessionAlert::PieceDeadlineReady {
+                                    torrent_id,
+                                    piece,
+                                    late: false,
+                                };
+                                let _ = alert_tx.try_send(alert);
+                            }
+                        }
+                        TorrentCommand::RemovePieceDeadline(piece) => {
+                            state.lock().await.picker.remove_piece_deadline(piece);
+                        }
+                        TorrentCommand::ClearPieceDeadlines => {
+                            state.lock().await.picker.clear_piece_deadlines();
+                        }
+                        TorrentCommand::SetSequentialDownload(sequential) => {
+                            state.lock().await.picker.set_sequential(sequential);
+                        }
+                        TorrentCommand::SetPlayhead(piece) => {
+                            state.lock().await.picker.set_playhead(piece);
+                        }
+                        TorrentCommand::PieceCompleted(CompletedPiece(piece)) => {
+                            let deadline = {
+                                let mut state = state.lock().await;
+                                let deadline = state.picker.piece_deadline(piece);
+                                let deadline =
+                                    state.on_piece_completed(piece).then_some(deadline).flatten();
+                                if state.is_finished() {
+                                    let symlinks = match state.disk.as_ref() {
+                                        Some(disk) => disk.lock().await.create_symlinks().await,
+                                        None => Ok(()),
+                                    };
+                                    if let Err(e) = symlinks {
+                                        state.set_status(TorrentStatus::Error(e.to_string()));
+                                    }
+                                }
+                                deadline
+                            };
+                            if let Some(deadline) = deadline {
+                                let alert = SessionAlert::PieceDeadlineReady {
+                                    torrent_id,
+                                    piece,
+                                    late: Instant::now() > deadline,
+                                };
+                                let _ = alert_tx.try_send(alert);
+                            }
+                        }
+                        TorrentCommand::IncomingPeer(connection) => {
+                            let addr = connection.addr;
+                            let stream = state.lock().await.add_connection(*connection);
+                            if let Some(stream) = stream {
+                                spawn_peer_wire(state.clone(), weak_tx.clone(), addr, stream);
+                                let alert = SessionAlert::PeerConnected { torrent_id, addr };
+                                let _ = alert_tx.try_send(alert);
+                            }
+                        }
+                        TorrentCommand::MetadataReceived(info_bytes) => {
+                            let mut state = state.lock().await;
+                            if let Err(e) = state.on_metadata(info_bytes).await {
+                                state.set_status(TorrentStatus::Error(e.to_string()));
+                            }
+                        }
+                    }
+                }
+                _ = ticker.tick() => {
+                    state.lock().await.tick();
+                }
+            }
+        }
+    });
 
-//     (tx, jh)
-// }
+    (tx, jh)
+}
@@ -1,5 +1,8 @@
 mod command;
+mod peer;
 mod tracker;
+mod webseed;
 
 pub use command::*;
-pub use tracker::*;
+pub use peer::*;
+pub use webseed::*;
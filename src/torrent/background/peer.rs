@@ -0,0 +1,261 @@
+use crate::{
+    error::{Error, Result},
+    peers::PeerIo,
+    proto::{constants::MAX_MSGAGE_SIZE, BitField, HashRequest, Hashes, Message, Piece, Request},
+    torrent::{CompletedPiece, TorrentCommand, TorrentState, TorrentStatus},
+};
+use std::{collections::BTreeSet, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::{mpsc::WeakSender, Mutex},
+    task::JoinHandle,
+    time,
+};
+
+/// Peers send a keep-alive every two minutes at least.
+const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+const BLOCK_LEN: usize = 16 * 1024;
+
+/// A piece requested from the peer, all of its blocks at once.
+struct PieceDownload {
+    index: usize,
+    data: Vec<u8>,
+    /// Offsets of the blocks not received yet.
+    missing: BTreeSet<usize>,
+}
+
+struct PeerWire {
+    state: Arc<Mutex<TorrentState>>,
+    cmd_tx: WeakSender<TorrentCommand>,
+    addr: SocketAddr,
+    stream: Box<dyn PeerIo>,
+    /// Pieces the peer has.
+    bitfield: BitField,
+    choked: bool,
+    interested: bool,
+    download: Option<PieceDownload>,
+    /// Missing piece hashes of v2 files are asked for once per connection.
+    hashes_requested: bool,
+}
+
+/// Serves a connection of the torrent: pieces the peer has are downloaded
+/// from it and their blocks go through the smart ban. The connection is
+/// forgotten once the peer closes it, goes quiet or is banned.
+pub fn spawn_peer_wire(
+    state: Arc<Mutex<TorrentState>>,
+    cmd_tx: WeakSender<TorrentCommand>,
+    addr: SocketAddr,
+    stream: Box<dyn PeerIo>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut wire = PeerWire {
+            state,
+            cmd_tx,
+            addr,
+            stream,
+            bitfield: BitField::new(0),
+            choked: true,
+            interested: false,
+            download: None,
+            hashes_requested: false,
+        };
+        let _ = wire.run().await;
+        wire.state.lock().await.remove_connection(&addr);
+    })
+}
+
+impl PeerWire {
+    async fn run(&mut self) -> Result<()> {
+        loop {
+            self.request_hashes().await?;
+            self.request_piece().await?;
+            let message =
+                time::timeout(PEER_IDLE_TIMEOUT, read_message(&mut self.stream)).await??;
+            if self.cmd_tx.upgrade().is_none() {
+                return Ok(());
+            }
+            match message {
+                Message::Choke => {
+                    self.choked = true;
+                    self.download = None;
+                }
+                Message::UnChoke => self.choked = false,
+                Message::Have(index) => {
+                    let index = index as usize;
+                    if index / 8 >= self.bitfield.len() {
+                        self.bitfield.resize(index / 8 + 1, 0);
+                    }
+                    self.bitfield.set(index);
+                }
+                Message::BitField(bitfield) => self.bitfield = bitfield,
+                Message::Piece(piece) => self.on_block(piece).await?,
+                Message::HashRequest(request) => self.on_hash_request(request).await?,
+                Message::Hashes(hashes) => self.on_hashes(hashes).await,
+                _ => {}
+            }
+            let mut state = self.state.lock().await;
+            // banned meanwhile
+            if !state.connections().contains_key(&self.addr) {
+                return Ok(());
+            }
+            state.update_peer(&self.addr, &self.bitfield, self.choked);
+        }
+    }
+
+    /// Asks a v2 peer for the piece hashes the metainfo didn't have, e.g. of a
+    /// torrent started from a magnet link (BEP 52).
+    async fn request_hashes(&mut self) -> Result<()> {
+        if self.hashes_requested {
+            return Ok(());
+        }
+        let requests = {
+            let state = self.state.lock().await;
+            let v2 = state
+                .connections()
+                .get(&self.addr)
+                .is_some_and(|peer| peer.handshake.supports_v2());
+            match state.hash_trees.as_ref() {
+                Some(hash_trees) if v2 => hash_trees.hash_requests(),
+                _ => return Ok(()),
+            }
+        };
+        self.hashes_requested = true;
+        let bytes = requests
+            .into_iter()
+            .flat_map(|request| Message::HashRequest(request).to_bytes())
+            .collect::<Vec<_>>();
+        self.stream.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    async fn on_hash_request(&mut self, request: HashRequest) -> Result<()> {
+        let hashes = self
+            .state
+            .lock()
+            .await
+            .hash_trees
+            .as_ref()
+            .and_then(|hash_trees| hash_trees.hashes(&request));
+        let reply = match hashes {
+            Some(hashes) => Message::Hashes(hashes),
+            None => Message::HashReject(request),
+        };
+        self.stream.write_all(&reply.to_bytes()).await?;
+        Ok(())
+    }
+
+    /// Hashes that don't lead to the root of their file are ignored.
+    async fn on_hashes(&mut self, hashes: Hashes) {
+        if let Some(hash_trees) = self.state.lock().await.hash_trees.as_mut() {
+            hash_trees.add_hashes(&hashes);
+        }
+    }
+
+    async fn request_piece(&mut self) -> Result<()> {
+        if self.download.is_some() {
+            return Ok(());
+        }
+        let pick = {
+            let state = self.state.lock().await;
+            let index = state.pick_peer_piece(self.addr);
+            index
+                .zip(state.layout.as_ref())
+                .map(|(index, layout)| (index, layout.piece_size(index) as usize))
+        };
+        let Some((index, length)) = pick else {
+            return Ok(());
+        };
+        if !self.interested {
+            self.stream
+                .write_all(&Message::Interested.to_bytes())
+                .await?;
+            self.interested = true;
+        }
+        if self.choked {
+            return Ok(());
+        }
+        let missing = (0..length).step_by(BLOCK_LEN).collect::<BTreeSet<_>>();
+        let requests = missing
+            .iter()
+            .flat_map(|&begin| {
+                let len = BLOCK_LEN.min(length - begin);
+                Message::Request(Request::new(index as u32, begin as u32, len as u32)).to_bytes()
+            })
+            .collect::<Vec<_>>();
+        self.stream.write_all(&requests).await?;
+        self.download = Some(PieceDownload {
+            index,
+            data: vec![0; length],
+            missing,
+        });
+        Ok(())
+    }
+
+    async fn on_block(&mut self, piece: Piece) -> Result<()> {
+        let (index, begin) = (piece.index as usize, piece.begin as usize);
+        let Some(download) = self.download.as_mut().filter(|d| d.index == index) else {
+            return Ok(());
+        };
+        let expected = download.data.len().saturating_sub(begin).min(BLOCK_LEN);
+        if !download.missing.contains(&begin) || piece.block.len() != expected {
+            return Ok(());
+        }
+        let kept = self
+            .state
+            .lock()
+            .await
+            .on_peer_block(index, begin, self.addr, &piece.block);
+        if !kept {
+            // reserved for another peer, or a bad block got the peer banned
+            self.download = None;
+            return Ok(());
+        }
+        download.data[begin..begin + expected].copy_from_slice(&piece.block);
+        download.missing.remove(&begin);
+        if !download.missing.is_empty() {
+            return Ok(());
+        }
+
+        let Some(PieceDownload { data, .. }) = self.download.take() else {
+            return Ok(());
+        };
+        let disk = {
+            let mut state = self.state.lock().await;
+            if !state.verify_peer_piece(index, &data) {
+                return Ok(());
+            }
+            state.disk.clone()
+        };
+        let Some(disk) = disk else {
+            return Ok(());
+        };
+        // written without the state lock, the disk has its own
+        let written = disk.lock().await.write(index, 0, &data).await;
+        let mut state = self.state.lock().await;
+        if let Err(e) = written {
+            state.set_status(TorrentStatus::Error(e.to_string()));
+            return Ok(());
+        }
+        state.on_downloaded(data.len() as u64);
+        drop(state);
+
+        if let Some(cmd_tx) = self.cmd_tx.upgrade() {
+            let _ = cmd_tx
+                .send(TorrentCommand::PieceCompleted(CompletedPiece(index)))
+                .await;
+        }
+        Ok(())
+    }
+}
+
+/// Reads one length-prefixed message.
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Message> {
+    let len = stream.read_u32().await? as usize;
+    if len > MAX_MSGAGE_SIZE {
+        return Err(Error::PeerWire(format!("message of {len} bytes")));
+    }
+    let mut buf = vec![0u8; 4 + len];
+    buf[..4].copy_from_slice(&(len as u32).to_be_bytes());
+    stream.read_exact(&mut buf[4..]).await?;
+    Ok(Message::from_bytes(&buf))
+}
@@ -0,0 +1,116 @@
+use crate::{
+    disk::layout::Layout,
+    proto::BitField,
+    proxy::{proxied_client, Proxy},
+    torrent::{CompletedPiece, TorrentCommand, TorrentState, TorrentStatus, WebSeed},
+};
+use reqwest::Client;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc::WeakSender, Mutex},
+    task::JoinHandle,
+    time,
+};
+
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct WebSeedJob {
+    seed_index: usize,
+    seed: WebSeed,
+    piece: usize,
+    layout: Layout,
+    single_file: bool,
+}
+
+fn next_job(state: &TorrentState) -> Option<WebSeedJob> {
+    if state.status() != TorrentStatus::Downloading || state.disk.is_none() {
+        return None;
+    }
+    let info = state.info.as_ref()?;
+    let layout = state.layout.as_ref()?;
+    let seed_index = state.web_seeds.iter().position(|s| s.is_available())?;
+    let mut verifiable = BitField::new(layout.num_pieces);
+    (0..layout.num_pieces)
+        .filter(|&index| state.has_piece_hash(index))
+        .for_each(|index| verifiable.set(index));
+    let piece = state.picker.pick(&state.bitfield, &verifiable)?;
+    Some(WebSeedJob {
+        seed_index,
+        seed: state.web_seeds[seed_index].clone(),
+        piece,
+        layout: layout.clone(),
+        single_file: info.is_single_file_mode(),
+    })
+}
+
+/// Downloads pieces from the web seeds of a torrent while it is downloading.
+/// Verified pieces are reported with `TorrentCommand::PieceCompleted`.
+pub fn spawn_web_seeder(
+    state: Arc<Mutex<TorrentState>>,
+    cmd_tx: WeakSender<TorrentCommand>,
+    proxy: Option<Proxy>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let Ok(client) = proxied_client(Client::builder(), proxy.as_ref()) else {
+            return;
+        };
+        loop {
+            let job = next_job(&*state.lock().await);
+            let Some(job) = job else {
+                if cmd_tx.upgrade().is_none() {
+                    break;
+                }
+                time::sleep(IDLE_INTERVAL).await;
+                continue;
+            };
+
+            let result = job
+                .seed
+                .fetch_piece(&client, &job.layout, job.piece, job.single_file)
+                .await;
+
+            let verified = {
+                let mut state = state.lock().await;
+                let seed = job.seed_index;
+                match result {
+                    Err(e) => {
+                        state.web_seeds[seed].on_error(&e);
+                        None
+                    }
+                    Ok(data) if !state.verify_piece(job.piece, &data) => {
+                        state.web_seeds[seed].on_hash_failure();
+                        None
+                    }
+                    Ok(data) => {
+                        state.web_seeds[seed].on_success();
+                        state.disk.clone().map(|disk| (disk, data))
+                    }
+                }
+            };
+            let Some((disk, data)) = verified else {
+                continue;
+            };
+
+            // written without the state lock, the disk has its own
+            let written = disk.lock().await.write(job.piece, 0, &data).await;
+            let mut state = state.lock().await;
+            if let Err(e) = written {
+                state.set_status(TorrentStatus::Error(e.to_string()));
+                continue;
+            }
+            state.on_downloaded(data.len() as u64);
+            drop(state);
+
+            let Some(cmd_tx) = cmd_tx.upgrade() else {
+                break;
+            };
+            if cmd_tx
+                .send(TorrentCommand::PieceCompleted(CompletedPiece(job.piece)))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    })
+}
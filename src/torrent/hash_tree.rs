@@ -0,0 +1,309 @@
+/// https://www.bittorrent.org/beps/bep_0052.html
+use crate::{
+    disk::layout::Layout,
+    proto::{
+        metainfo::{
+            merkle::{self, PieceLayers, Sha256Hash, BLOCK_SIZE, ZERO_HASH},
+            Info,
+        },
+        HashRequest, Hashes,
+    },
+};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Most hashes asked for in a single hash request.
+const MAX_HASH_REQUEST_LEN: usize = 512;
+
+/// Merkle tree of a v2 file down to its piece layer.
+#[derive(Debug, Clone)]
+pub struct FileHashTree {
+    pub file_index: usize,
+    pub pieces_root: Sha256Hash,
+    pub length: u64,
+    pub first_piece: usize,
+    pub num_pieces: usize,
+    /// Filled from the metainfo or from peers, empty for single piece files.
+    piece_layer: Vec<Option<Sha256Hash>>,
+}
+
+impl FileHashTree {
+    /// Single piece files are verified against `pieces root` directly.
+    pub fn has_piece_layer(&self) -> bool {
+        self.piece_layer.iter().all(Option::is_some)
+    }
+
+    #[inline]
+    fn width(&self) -> usize {
+        self.num_pieces.next_power_of_two()
+    }
+}
+
+/// Piece verification for v2 and hybrid torrents.
+#[derive(Debug, Clone)]
+pub struct HashTrees {
+    piece_length: u64,
+    /// Sorted by `first_piece`.
+    files: Vec<FileHashTree>,
+    /// Leaf hashes of pieces, to check single blocks of them.
+    block_hashes: BTreeMap<usize, Vec<Sha256Hash>>,
+}
+
+impl HashTrees {
+    /// `None` for v1 torrents. Piece layers that don't match their root are dropped.
+    pub fn new(info: &Info, layout: &Layout, piece_layers: &PieceLayers) -> Option<Self> {
+        if info.meta_version() != 2 || info.piece_length < BLOCK_SIZE as u64 {
+            return None;
+        }
+        let piece_length = info.piece_length;
+        let files = layout
+            .files
+            .iter()
+            .zip(info.pieces_roots())
+            .enumerate()
+            .filter_map(|(file_index, (file, pieces_root))| {
+                let pieces_root = pieces_root.filter(|_| file.length > 0)?;
+                let num_pieces = file.length.div_ceil(piece_length) as usize;
+                let piece_layer = match piece_layers.get(&pieces_root) {
+                    _ if num_pieces == 1 => Vec::new(),
+                    Some(layer)
+                        if layer.len() == num_pieces
+                            && merkle::root_from_piece_layer(layer, piece_length)
+                                == pieces_root =>
+                    {
+                        layer.iter().copied().map(Some).collect()
+                    }
+                    _ => vec![None; num_pieces],
+                };
+                Some(FileHashTree {
+                    file_index,
+                    pieces_root,
+                    length: file.length,
+                    first_piece: (file.offset / piece_length) as usize,
+                    num_pieces,
+                    piece_layer,
+                })
+            })
+            .collect();
+        Some(Self {
+            piece_length,
+            files,
+            block_hashes: BTreeMap::new(),
+        })
+    }
+
+    #[inline]
+    pub fn files(&self) -> &[FileHashTree] {
+        &self.files
+    }
+
+    /// Layer of the piece hashes, counted from the 16 KiB leaves.
+    #[inline]
+    pub fn piece_layer_height(&self) -> u32 {
+        (self.piece_length / BLOCK_SIZE as u64).trailing_zeros()
+    }
+
+    pub fn has_piece_layers(&self) -> bool {
+        self.files.iter().all(FileHashTree::has_piece_layer)
+    }
+
+    fn file_of_piece(&self, index: usize) -> Option<&FileHashTree> {
+        let i = self
+            .files
+            .partition_point(|f| f.first_piece + f.num_pieces <= index);
+        self.files.get(i).filter(|f| f.first_piece <= index)
+    }
+
+    /// `None` if the piece isn't part of a v2 file or its hash is still missing.
+    pub fn verify_piece(&self, index: usize, data: &[u8]) -> Option<bool> {
+        let file = self.file_of_piece(index)?;
+        // the pad file after the last piece of a hybrid torrent file isn't hashed
+        let offset = (index - file.first_piece) as u64 * self.piece_length;
+        let data = data.get(..(file.length - offset).min(data.len() as u64) as usize)?;
+        if file.num_pieces == 1 {
+            return Some(merkle::root_from_data(data) == file.pieces_root);
+        }
+        let hash = file.piece_layer[index - file.first_piece]?;
+        Some(merkle::piece_hash(data, self.piece_length) == hash)
+    }
+
+    /// The piece is part of a v2 file and its hash is known.
+    pub fn has_piece_hash(&self, index: usize) -> bool {
+        self.file_of_piece(index)
+            .and_then(|file| self.piece_root(file, index))
+            .is_some()
+    }
+
+    /// Hash of the piece in its file's tree, the root of its leaf hashes.
+    fn piece_root(&self, file: &FileHashTree, index: usize) -> Option<Sha256Hash> {
+        match file.num_pieces {
+            1 => Some(file.pieces_root),
+            _ => file.piece_layer[index - file.first_piece],
+        }
+    }
+
+    /// Number of leaves below a piece hash.
+    fn num_piece_leaves(&self, file: &FileHashTree) -> usize {
+        match file.num_pieces {
+            1 => file.length.div_ceil(BLOCK_SIZE as u64).next_power_of_two() as usize,
+            _ => self.piece_length as usize / BLOCK_SIZE,
+        }
+    }
+
+    /// Request for the leaf hashes of a piece whose hash we have, so its
+    /// blocks can be checked one by one.
+    pub fn block_hash_request(&self, index: usize) -> Option<HashRequest> {
+        let file = self.file_of_piece(index)?;
+        self.piece_root(file, index)?;
+        let length = self.num_piece_leaves(file);
+        if length == 1 || self.block_hashes.contains_key(&index) {
+            return None;
+        }
+        let first = (index - file.first_piece) * length;
+        Some(HashRequest::new(
+            file.pieces_root,
+            0,
+            first as u32,
+            length as u32,
+            0,
+        ))
+    }
+
+    /// Stores leaf hashes that lead to the hash of their piece.
+    fn add_block_hashes(&mut self, hashes: &Hashes) -> bool {
+        let request = &hashes.request;
+        let Some(file) = self
+            .files
+            .iter()
+            .find(|f| f.pieces_root == request.pieces_root)
+        else {
+            return false;
+        };
+        let length = self.num_piece_leaves(file);
+        let (first, base) = (request.index as usize, hashes.base_hashes());
+        if request.length as usize != length
+            || base.len() != length
+            || !first.is_multiple_of(length)
+            || first / length >= file.num_pieces
+        {
+            return false;
+        }
+        let index = file.first_piece + first / length;
+        if self.piece_root(file, index) != Some(merkle::merkle_root(base, length, ZERO_HASH)) {
+            return false;
+        }
+        self.block_hashes.insert(index, base.to_vec());
+        true
+    }
+
+    /// `None` if the block isn't part of a v2 file or the leaf hashes of its
+    /// piece are missing. Blocks past the end of the file aren't hashed.
+    pub fn verify_block(&self, index: usize, begin: usize, data: &[u8]) -> Option<bool> {
+        let file = self.file_of_piece(index)?;
+        if !begin.is_multiple_of(BLOCK_SIZE) {
+            return None;
+        }
+        let offset = (index - file.first_piece) as u64 * self.piece_length + begin as u64;
+        let len = file.length.checked_sub(offset)?.min(BLOCK_SIZE as u64) as usize;
+        let block = data.get(..len).filter(|_| len > 0)?;
+        let leaf = match self.num_piece_leaves(file) {
+            // the piece is a single block
+            1 => self.piece_root(file, index)?,
+            _ => *self.block_hashes.get(&index)?.get(begin / BLOCK_SIZE)?,
+        };
+        Some(<Sha256Hash>::from(Sha256::digest(block)) == leaf)
+    }
+
+    /// Requests for the piece hashes we don't have, with proofs up to `pieces root`.
+    pub fn hash_requests(&self) -> Vec<HashRequest> {
+        let base_layer = self.piece_layer_height();
+        let mut requests = Vec::new();
+        for file in self.files.iter().filter(|f| !f.has_piece_layer()) {
+            let width = file.width();
+            let length = width.min(MAX_HASH_REQUEST_LEN);
+            let proof_layers = (width / length).trailing_zeros();
+            for index in (0..file.num_pieces).step_by(length) {
+                let end = (index + length).min(file.num_pieces);
+                if file.piece_layer[index..end].iter().any(Option::is_none) {
+                    requests.push(HashRequest::new(
+                        file.pieces_root,
+                        base_layer,
+                        index as u32,
+                        length as u32,
+                        proof_layers,
+                    ));
+                }
+            }
+        }
+        requests
+    }
+
+    /// Index of the file, `length` and the number of uncles needed to reach the root.
+    fn check_request(&self, request: &HashRequest) -> Option<(usize, usize, usize)> {
+        let file_index = self
+            .files
+            .iter()
+            .position(|f| f.pieces_root == request.pieces_root && f.num_pieces > 1)?;
+        let file = &self.files[file_index];
+        let (index, length) = (request.index as usize, request.length as usize);
+        let valid = request.base_layer == self.piece_layer_height()
+            && length.is_power_of_two()
+            && length <= file.width()
+            && index.is_multiple_of(length)
+            && index < file.num_pieces;
+        valid.then(|| {
+            (
+                file_index,
+                length,
+                (file.width() / length).trailing_zeros() as usize,
+            )
+        })
+    }
+
+    /// Stores the hashes if they and their proof lead to `pieces root`, leaf
+    /// hashes if they lead to the hash of their piece.
+    pub fn add_hashes(&mut self, hashes: &Hashes) -> bool {
+        if hashes.request.base_layer == 0 && self.piece_layer_height() > 0 {
+            return self.add_block_hashes(hashes);
+        }
+        let Some((file_index, length, num_uncles)) = self.check_request(&hashes.request) else {
+            return false;
+        };
+        let base = hashes.base_hashes();
+        if base.len() != length || hashes.proof().len() != num_uncles {
+            return false;
+        }
+        let index = hashes.request.index as usize;
+        let subtree_root = merkle::merkle_root(base, length, ZERO_HASH);
+        let file = &mut self.files[file_index];
+        if merkle::root_from_proof(subtree_root, index / length, hashes.proof()) != file.pieces_root
+        {
+            return false;
+        }
+        let end = (index + length).min(file.num_pieces);
+        for (slot, hash) in file.piece_layer[index..end].iter_mut().zip(base) {
+            *slot = Some(*hash);
+        }
+        true
+    }
+
+    /// Answers a hash request from a peer, `None` means it should be rejected.
+    pub fn hashes(&self, request: &HashRequest) -> Option<Hashes> {
+        let (file_index, length, num_uncles) = self.check_request(request)?;
+        let file = &self.files[file_index];
+        let piece_layer = file
+            .piece_layer
+            .iter()
+            .copied()
+            .collect::<Option<Vec<_>>>()?;
+        let pad = merkle::zero_root(self.piece_length as usize / BLOCK_SIZE);
+        let layers = merkle::tree_layers(&piece_layer, file.width(), pad);
+
+        let index = request.index as usize;
+        let mut hashes = layers[0][index..index + length].to_vec();
+        let level = length.trailing_zeros() as usize;
+        let num_uncles = num_uncles.min(request.proof_layers as usize);
+        hashes.extend((0..num_uncles).map(|k| layers[level + k][((index / length) >> k) ^ 1]));
+        Some(Hashes::new(request.clone(), hashes))
+    }
+}
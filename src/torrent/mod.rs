@@ -1,10 +1,23 @@
-// mod state;
 mod background;
+mod hash_tree;
+mod peer_source;
+mod picker;
+mod priority;
+mod smart_ban;
 mod source;
+mod state;
+#[allow(clippy::module_inception)]
 mod torrent;
 pub mod tracker;
+mod webseed;
 
-// pub use state::*;
 pub use background::*;
+pub use hash_tree::*;
+pub use peer_source::*;
+pub use picker::*;
+pub use priority::*;
+pub use smart_ban::*;
 pub use source::*;
+pub use state::*;
 pub use torrent::*;
+pub use webseed::*;
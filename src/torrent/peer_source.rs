@@ -0,0 +1,20 @@
+/// Where a peer address came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PeerSource {
+    Tracker,
+    Dht,
+    Pex,
+    Lsd,
+    /// `x.pe` of a magnet link.
+    Magnet,
+    /// The peer connected to us.
+    Incoming,
+}
+
+impl PeerSource {
+    /// Private torrents (BEP 27) only talk to peers of their own trackers.
+    #[inline]
+    pub fn is_allowed_for_private(&self) -> bool {
+        matches!(self, Self::Tracker | Self::Incoming)
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{disk::layout::Layout, proto::BitField, torrent::FilePriority};
+use std::{cmp::Reverse, collections::BTreeMap, time::Instant};
+
+#[inline]
+fn has_piece(bitfield: &BitField, index: usize) -> bool {
+    index / 8 < bitfield.len() && bitfield.has(index)
+}
+
+/// Picks the next piece to request: pieces with a deadline first, then either
+/// in order from the playhead (sequential mode) or highest priority and rarest first.
+#[derive(Debug, Clone, Default)]
+pub struct PiecePicker {
+    priorities: Vec<FilePriority>,
+    availability: Vec<u32>,
+    deadlines: BTreeMap<usize, Instant>,
+    sequential: bool,
+    playhead: usize,
+}
+
+impl PiecePicker {
+    pub fn new(num_pieces: usize) -> Self {
+        Self {
+            priorities: vec![FilePriority::default(); num_pieces],
+            availability: vec![0; num_pieces],
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn num_pieces(&self) -> usize {
+        self.priorities.len()
+    }
+
+    /// A piece gets the highest priority of the files it overlaps.
+    pub fn set_file_priorities(&mut self, layout: &Layout, file_priorities: &[FilePriority]) {
+        for (index, priority) in self.priorities.iter_mut().enumerate() {
+            *priority = layout
+                .piece_slices(index)
+                .iter()
+                .map(|s| {
+                    file_priorities
+                        .get(s.file_index)
+                        .copied()
+                        .unwrap_or_default()
+                })
+                .max()
+                .unwrap_or_default();
+        }
+    }
+
+    #[inline]
+    pub fn piece_priority(&self, index: usize) -> FilePriority {
+        self.priorities.get(index).copied().unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn is_wanted(&self, index: usize) -> bool {
+        self.piece_priority(index).is_wanted()
+    }
+
+    #[inline]
+    pub fn availability(&self, index: usize) -> u32 {
+        self.availability.get(index).copied().unwrap_or(0)
+    }
+
+    pub fn inc_availability(&mut self, bitfield: &BitField) {
+        for (index, count) in self.availability.iter_mut().enumerate() {
+            if has_piece(bitfield, index) {
+                *count += 1;
+            }
+        }
+    }
+
+    pub fn dec_availability(&mut self, bitfield: &BitField) {
+        for (index, count) in self.availability.iter_mut().enumerate() {
+            if has_piece(bitfield, index) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// A peer announced a single piece with `have`.
+    pub fn inc_piece_availability(&mut self, index: usize) {
+        if let Some(count) = self.availability.get_mut(index) {
+            *count += 1;
+        }
+    }
+
+    /// Deadline pieces are downloaded even if they belong to skipped files.
+    pub fn set_piece_deadline(&mut self, index: usize, deadline: Instant) {
+        if index < self.num_pieces() {
+            self.deadlines.insert(index, deadline);
+        }
+    }
+
+    #[inline]
+    pub fn piece_deadline(&self, index: usize) -> Option<Instant> {
+        self.deadlines.get(&index).copied()
+    }
+
+    /// Returns `true` if the piece had a deadline.
+    #[inline]
+    pub fn remove_piece_deadline(&mut self, index: usize) -> bool {
+        self.deadlines.remove(&index).is_some()
+    }
+
+    #[inline]
+    pub fn clear_piece_deadlines(&mut self) {
+        self.deadlines.clear();
+    }
+
+    #[inline]
+    pub fn is_sequential(&self) -> bool {
+        self.sequential
+    }
+
+    #[inline]
+    pub fn set_sequential(&mut self, sequential: bool) {
+        self.sequential = sequential;
+    }
+
+    #[inline]
+    pub fn playhead(&self) -> usize {
+        self.playhead
+    }
+
+    #[inline]
+    pub fn set_playhead(&mut self, index: usize) {
+        self.playhead = index.min(self.num_pieces());
+    }
+
+    fn missing_deadlines<'a>(
+        &'a self,
+        have: &'a BitField,
+    ) -> impl Iterator<Item = (usize, Instant)> + 'a {
+        self.deadlines
+            .iter()
+            .filter(|(&i, _)| !has_piece(have, i))
+            .map(|(&i, &deadline)| (i, deadline))
+    }
+
+    pub fn pick(&self, have: &BitField, peer: &BitField) -> Option<usize> {
+        let deadline_piece = self
+            .missing_deadlines(have)
+            .filter(|&(i, _)| has_piece(peer, i))
+            .min_by_key(|&(i, deadline)| (deadline, i))
+            .map(|(i, _)| i);
+        if deadline_piece.is_some() {
+            return deadline_piece;
+        }
+
+        let mut candidates = (0..self.num_pieces())
+            .filter(|&i| self.is_wanted(i) && !has_piece(have, i) && has_piece(peer, i));
+        if self.sequential {
+            return candidates
+                .clone()
+                .find(|&i| i >= self.playhead)
+                .or_else(|| candidates.next());
+        }
+        candidates.max_by_key(|&i| {
+            (
+                self.priorities[i],
+                Reverse(self.availability[i]),
+                Reverse(i),
+            )
+        })
+    }
+
+    /// Hands out missing deadline pieces, earliest deadline first, each to the
+    /// fastest peer that has it. Every peer gets at most one piece.
+    ///
+    /// `peers` are bitfields with download rates, returns `(piece, peer index)`.
+    pub fn pick_time_critical(
+        &self,
+        have: &BitField,
+        peers: &[(&BitField, u64)],
+    ) -> Vec<(usize, usize)> {
+        let mut pieces: Vec<_> = self.missing_deadlines(have).collect();
+        pieces.sort_by_key(|&(i, deadline)| (deadline, i));
+
+        let mut by_rate: Vec<usize> = (0..peers.len()).collect();
+        by_rate.sort_by_key(|&p| Reverse(peers[p].1));
+
+        let mut picks = Vec::new();
+        for (piece, _) in pieces {
+            let peer = by_rate
+                .iter()
+                .position(|&p| has_piece(peers[p].0, piece))
+                .map(|pos| by_rate.remove(pos));
+            if let Some(peer) = peer {
+                picks.push((piece, peer));
+            }
+        }
+        picks
+    }
+
+    /// Wanted pieces that are not in `have` yet.
+    pub fn missing_pieces<'a>(&'a self, have: &'a BitField) -> impl Iterator<Item = usize> + 'a {
+        (0..self.num_pieces()).filter(|&i| self.is_wanted(i) && !has_piece(have, i))
+    }
+}
@@ -0,0 +1,37 @@
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FilePriority {
+    /// Not downloaded and never created on disk.
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl FilePriority {
+    #[inline]
+    pub fn is_wanted(&self) -> bool {
+        *self != Self::Skip
+    }
+}
+
+/// BEP 53 `so` ranges as file priorities, `None` if no file is selected.
+pub fn select_only_priorities(
+    select_only: &[RangeInclusive<usize>],
+    num_files: usize,
+) -> Option<Vec<FilePriority>> {
+    let priorities: Vec<_> = (0..num_files)
+        .map(
+            |index| match select_only.iter().any(|r| r.contains(&index)) {
+                true => FilePriority::Normal,
+                false => FilePriority::Skip,
+            },
+        )
+        .collect();
+    priorities
+        .iter()
+        .any(FilePriority::is_wanted)
+        .then_some(priorities)
+}
@@ -0,0 +1,143 @@
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// How long the owner of a failed piece may go without sending a block
+/// before another peer can take the piece over.
+const OWNER_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BlockRecord {
+    peer: IpAddr,
+    len: usize,
+    hash: [u8; 20],
+}
+
+type PieceBlocks = BTreeMap<usize, BlockRecord>;
+
+#[derive(Debug, Clone, Copy)]
+struct Owner {
+    peer: IpAddr,
+    last_block: Instant,
+}
+
+/// Remembers which peer sent which block, so the peer behind a piece that
+/// failed its hash check can be found and banned.
+///
+/// A failed piece is downloaded again from a single peer. Once it passes, the
+/// blocks of the failed attempt are compared with the good data and the
+/// peers that sent different bytes are the liars.
+#[derive(Debug, Clone, Default)]
+pub struct SmartBan {
+    /// Blocks of the pieces being downloaded, by offset.
+    blocks: BTreeMap<usize, PieceBlocks>,
+    /// Blocks of the first failed attempt of a piece, kept until it passes.
+    failed: BTreeMap<usize, PieceBlocks>,
+    /// Failed pieces and the one peer they are downloaded from again, `None`
+    /// until a peer sent the first block. An owner that stays silent for
+    /// `OWNER_TIMEOUT` loses the piece.
+    exclusive: BTreeMap<usize, Option<Owner>>,
+}
+
+impl SmartBan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `false` when the piece is downloaded again from another peer.
+    pub fn allows(&self, piece: usize, peer: IpAddr) -> bool {
+        match self.exclusive.get(&piece) {
+            Some(Some(owner)) => owner.peer == peer || owner.last_block.elapsed() >= OWNER_TIMEOUT,
+            _ => true,
+        }
+    }
+
+    #[inline]
+    pub fn is_exclusive(&self, piece: usize) -> bool {
+        self.exclusive.contains_key(&piece)
+    }
+
+    /// Records a received block, returns `false` if it must be dropped
+    /// because the piece is reserved for another peer.
+    pub fn on_block(&mut self, piece: usize, begin: usize, peer: IpAddr, data: &[u8]) -> bool {
+        if !self.allows(piece, peer) {
+            return false;
+        }
+        if let Some(owner) = self.exclusive.get_mut(&piece) {
+            *owner = Some(Owner {
+                peer,
+                last_block: Instant::now(),
+            });
+        }
+        let record = BlockRecord {
+            peer,
+            len: data.len(),
+            hash: Sha1::digest(data).into(),
+        };
+        self.blocks.entry(piece).or_default().insert(begin, record);
+        true
+    }
+
+    /// Returns the peers to ban right away: a piece that came from a single
+    /// peer has nobody else to blame.
+    pub fn on_piece_failed(&mut self, piece: usize) -> Vec<IpAddr> {
+        let blocks = self.blocks.remove(&piece).unwrap_or_default();
+        let peers = blocks
+            .values()
+            .map(|block| block.peer)
+            .collect::<BTreeSet<_>>();
+        self.exclusive.insert(piece, None);
+        if peers.len() == 1 {
+            return peers.into_iter().collect();
+        }
+        self.failed.entry(piece).or_insert(blocks);
+        Vec::new()
+    }
+
+    /// Returns the peers whose blocks of an earlier failed attempt differ
+    /// from the verified `data`.
+    pub fn on_piece_passed(&mut self, piece: usize, data: &[u8]) -> Vec<IpAddr> {
+        self.blocks.remove(&piece);
+        self.exclusive.remove(&piece);
+        let Some(failed) = self.failed.remove(&piece) else {
+            return Vec::new();
+        };
+        failed
+            .iter()
+            .filter(|(begin, block)| {
+                data.get(**begin..*begin + block.len)
+                    .is_none_or(|good| <[u8; 20]>::from(Sha1::digest(good)) != block.hash)
+            })
+            .map(|(_, block)| block.peer)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Frees a failed piece for the next peer that sends a block of it.
+    pub fn release(&mut self, piece: usize) {
+        if let Some(owner) = self.exclusive.get_mut(&piece) {
+            *owner = None;
+        }
+    }
+
+    /// Frees the failed pieces owned by a peer that went away.
+    pub fn release_peer(&mut self, peer: IpAddr) {
+        for owner in self.exclusive.values_mut() {
+            if owner.is_some_and(|owner| owner.peer == peer) {
+                *owner = None;
+            }
+        }
+    }
+
+    /// Drops the blocks of a banned peer from the pieces in progress.
+    pub fn forget_peer(&mut self, peer: IpAddr) {
+        for blocks in self.blocks.values_mut() {
+            blocks.retain(|_, block| block.peer != peer);
+        }
+        self.release_peer(peer);
+    }
+}
@@ -6,12 +6,14 @@ use crate::{
         metainfo::MetaInfo,
         MagnetLink,
     },
-    torrent::TorrentID,
+    proxy::{proxied_client, Proxy},
+    torrent::{TorrentID, TorrentInitStateParams, TorrentSpawnParams},
 };
-use reqwest::Url;
+use reqwest::{Client, Url};
 use std::str::FromStr;
 use tokio::fs;
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 pub enum TorrentSource {
     File(MetaInfo),
@@ -35,7 +37,9 @@ impl TorrentSource {
         .truncate()
     }
 
-    pub async fn from_str(s: &str) -> Result<Self> {
+    /// A magnet link, URL, file path or hex info hash. HTTP .torrent downloads
+    /// go through `proxy`, pass the `trackers` proxy of the session settings.
+    pub async fn from_str(s: &str, proxy: Option<&Proxy>) -> Result<Self> {
         fn parse_metainfo_bytes(bytes: &[u8]) -> Result<MetaInfo> {
             MetaInfo::from_bytes(bytes)
                 .map_err(|e| Error::ParseTorrentSource(format!("Invalid torrent metadata: {e}")))
@@ -49,8 +53,11 @@ impl TorrentSource {
                     .map(Self::Magnet)
                     .map_err(|e| Error::ParseTorrentSource(format!("Invalid magnet link: {e}"))),
                 "http" | "https" => {
-                    let response = reqwest::get(url)
+                    let response = proxied_client(Client::builder(), proxy)?
+                        .get(url)
+                        .send()
                         .await
+                        .and_then(|response| response.error_for_status())
                         .map_err(|e| Error::ParseTorrentSource(format!("Request failed: {e}")))?;
 
                     let bytes = response.bytes().await.map_err(|e| {
@@ -88,4 +95,78 @@ impl TorrentSource {
             }
         }
     }
+
+    pub(crate) fn init_state_params(&self, params: TorrentSpawnParams) -> TorrentInitStateParams {
+        let TorrentSpawnParams {
+            status,
+            port,
+            peer_id,
+            save_path,
+            ip_filter,
+            ..
+        } = params;
+        let (info_hash, info, announce_list, url_list, httpseeds, num_pieces, left) = match self {
+            Self::File(meta_info) => (
+                meta_info.info_hash().clone(),
+                Some(meta_info.info.clone()),
+                meta_info.announce_list(),
+                meta_info.url_list.clone().unwrap_or_default(),
+                meta_info.httpseeds.clone().unwrap_or_default(),
+                meta_info.info.num_pieces(),
+                meta_info.info.total_length(),
+            ),
+            Self::Magnet(magnet_link) => (
+                magnet_link.info_hash.clone(),
+                None,
+                magnet_link
+                    .trackers
+                    .iter()
+                    .map(|url| vec![url.clone()])
+                    .collect(),
+                magnet_link.web_seeds.clone(),
+                Vec::new(),
+                0,
+                magnet_link.exact_length.unwrap_or(0),
+            ),
+            Self::InfoHash(info_hash) => (
+                info_hash.clone(),
+                None,
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                0,
+                0,
+            ),
+        };
+
+        let piece_layers = match self {
+            Self::File(meta_info) => meta_info.piece_layers.clone().unwrap_or_default(),
+            _ => Default::default(),
+        };
+        let select_only = match self {
+            Self::Magnet(magnet_link) => magnet_link.select_only.clone(),
+            _ => Vec::new(),
+        };
+
+        TorrentInitStateParams {
+            status,
+            port,
+            peer_id,
+            info_hash,
+            info,
+            piece_layers,
+            save_path,
+            announce_list,
+            url_list,
+            httpseeds,
+            num_pieces,
+            bitfield: None,
+            have_pieces: 0,
+            uploaded: 0,
+            downloaded: 0,
+            left,
+            select_only,
+            ip_filter,
+        }
+    }
 }
@@ -1,9 +1,12 @@
 mod progress;
+mod seeding;
+#[allow(clippy::module_inception)]
 mod state;
 mod status;
 mod tracker;
 
 pub use progress::*;
+pub use seeding::*;
 pub use state::*;
 pub use status::*;
 pub use tracker::*;
@@ -25,6 +25,15 @@ impl TorrentProgress {
         }
     }
 
+    /// Share ratio, `None` until something has been downloaded.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.downloaded == 0 {
+            None
+        } else {
+            Some(self.uploaded as f64 / self.downloaded as f64)
+        }
+    }
+
     pub fn remaining_pieces(&self) -> usize {
         self.num_pieces.saturating_sub(self.have_pieces)
     }
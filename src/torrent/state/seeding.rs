@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SeedingGoalAction {
+    #[default]
+    Pause,
+    Remove,
+    RemoveWithFiles,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedingGoal {
+    Ratio,
+    SeedTime,
+    IdleTime,
+}
+
+/// Unset limits are never reached.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SeedingLimits {
+    pub ratio: Option<f64>,
+    pub seed_time: Option<Duration>,
+    pub idle_time: Option<Duration>,
+    pub action: SeedingGoalAction,
+}
+
+impl SeedingLimits {
+    pub fn check(
+        &self,
+        ratio: Option<f64>,
+        seed_time: Duration,
+        idle_time: Duration,
+    ) -> Option<SeedingGoal> {
+        if let (Some(target), Some(ratio)) = (self.ratio, ratio) {
+            if ratio >= target {
+                return Some(SeedingGoal::Ratio);
+            }
+        }
+        if self.seed_time.is_some_and(|limit| seed_time >= limit) {
+            return Some(SeedingGoal::SeedTime);
+        }
+        if self.idle_time.is_some_and(|limit| idle_time >= limit) {
+            return Some(SeedingGoal::IdleTime);
+        }
+        None
+    }
+}
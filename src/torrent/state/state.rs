@@ -1,17 +1,33 @@
 use std::{
-    ops::Deref,
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::Arc,
     time::{Duration, Instant, SystemTime},
 };
-use tokio::sync::Mutex;
 
 use crate::{
-    proto::{BitField, PeerId},
-    torrent::{
+    disk::{layout::Layout, Disk},
+    error::Result,
+    peers::{ConnectedPeer, PeerConnection, PeerIo},
+    proto::{
         infohash::InfoHash,
-        state::{TorrentProgress, TorrentStatus},
-        AnnounceList, TorrentTrackerState,
+        metainfo::{merkle::PieceLayers, AnnounceList, Info, MetaInfo},
+        BitField, PeerId,
+    },
+    session::SessionIpFilter,
+    torrent::{
+        select_only_priorities,
+        state::{
+            SeedingGoal, SeedingGoalAction, SeedingLimits, TorrentProgress, TorrentStatus,
+            TorrentTrackerState,
+        },
+        FilePriority, HashTrees, PeerSource, PiecePicker, SmartBan, WebSeed,
     },
+    util::RateMeter,
 };
+use tokio::sync::{watch, Mutex};
 
 #[derive(Debug, Clone)]
 pub struct TorrentInitStateParams {
@@ -19,13 +35,21 @@ pub struct TorrentInitStateParams {
     pub port: u16,
     pub peer_id: PeerId,
     pub info_hash: InfoHash,
+    pub info: Option<Info>,
+    pub piece_layers: PieceLayers,
+    pub save_path: PathBuf,
     pub announce_list: AnnounceList,
+    pub url_list: Vec<String>,
+    pub httpseeds: Vec<String>,
     pub num_pieces: usize,
     pub bitfield: Option<BitField>,
     pub have_pieces: usize,
     pub uploaded: u64,
     pub downloaded: u64,
     pub left: u64,
+    /// BEP 53 file indices from a magnet link.
+    pub select_only: Vec<RangeInclusive<usize>>,
+    pub ip_filter: Option<Arc<SessionIpFilter>>,
 }
 
 #[derive(Debug)]
@@ -34,24 +58,44 @@ pub struct TorrentState {
     pub port: u16,
     pub peer_id: PeerId,
     pub info_hash: InfoHash,
+    pub info: Option<Info>,
+    pub save_path: PathBuf,
     pub announce_list: AnnounceList,
+    pub web_seeds: Vec<WebSeed>,
     pub bitfield: BitField,
+    pub layout: Option<Layout>,
+    /// Merkle trees of v2 files, `None` for v1 torrents.
+    pub hash_trees: Option<HashTrees>,
+    file_priorities: Vec<FilePriority>,
+    /// Applied to the file priorities once the info is known.
+    select_only: Vec<RangeInclusive<usize>>,
+    pub picker: PiecePicker,
+    pub smart_ban: SmartBan,
+    /// Known peer addresses, filtered by `allows_peer_source` and the IP filter.
+    peers: BTreeMap<SocketAddr, PeerSource>,
+    /// Established peer connections, their streams are served by the peer
+    /// wire tasks.
+    connections: BTreeMap<SocketAddr, ConnectedPeer>,
+    /// Of the session the torrent was added to.
+    pub ip_filter: Option<Arc<SessionIpFilter>>,
+    /// Shared so pieces can be written and read without holding the state lock.
+    pub disk: Option<Arc<Mutex<Disk>>>,
+    /// Number of pieces we have, updated on every completed piece.
+    pieces_tx: watch::Sender<usize>,
     pub tracker: TorrentTrackerState,
     pub progress: TorrentProgress,
     pub num_seeders: u32,
     pub num_leechers: u32,
+    download_rate: RateMeter,
+    upload_rate: RateMeter,
     download_start_time: Option<Instant>,
     active_download_duration: Duration,
+    seed_start_time: Option<Instant>,
+    active_seed_duration: Duration,
+    last_upload_time: Option<Instant>,
+    /// Overrides the session defaults when set.
+    pub seeding_limits: Option<SeedingLimits>,
     pub init_time: SystemTime,
-    mu: Mutex<()>,
-}
-
-impl Deref for TorrentState {
-    type Target = Mutex<()>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.mu
-    }
 }
 
 impl TorrentState {
@@ -61,25 +105,73 @@ impl TorrentState {
             port,
             peer_id,
             info_hash,
+            info,
+            piece_layers,
+            save_path,
             announce_list,
+            url_list,
+            httpseeds,
             num_pieces,
             bitfield,
             have_pieces,
             uploaded,
             downloaded,
             left,
+            select_only,
+            ip_filter,
         } = params;
         let download_start_time = match status {
             TorrentStatus::Downloading => Some(Instant::now()),
             _ => None,
         };
+        let seed_start_time = match status {
+            TorrentStatus::Seeding => Some(Instant::now()),
+            _ => None,
+        };
+        let torrent_id = *info_hash.inner().truncate();
+        let web_seeds = url_list
+            .into_iter()
+            .map(WebSeed::new)
+            .chain(
+                httpseeds
+                    .into_iter()
+                    .map(|url| WebSeed::http_seed(url, torrent_id)),
+            )
+            .collect();
+        let layout = info.as_ref().map(Layout::from_torrent_info);
+        let hash_trees = info
+            .as_ref()
+            .zip(layout.as_ref())
+            .and_then(|(info, layout)| HashTrees::new(info, layout, &piece_layers));
+        let disk = layout
+            .clone()
+            .map(|layout| Disk::new(save_path.clone(), layout, &info_hash.inner().hex()))
+            .map(|disk| Arc::new(Mutex::new(disk)));
+        let file_priorities = layout
+            .as_ref()
+            .map(|layout| vec![FilePriority::default(); layout.files.len()])
+            .unwrap_or_default();
         Self {
             status,
             port,
             peer_id,
             info_hash,
+            info,
+            save_path,
             announce_list,
+            web_seeds,
             bitfield: bitfield.unwrap_or(BitField::new(num_pieces)),
+            layout,
+            hash_trees,
+            file_priorities,
+            select_only,
+            picker: PiecePicker::new(num_pieces),
+            smart_ban: SmartBan::new(),
+            peers: BTreeMap::new(),
+            connections: BTreeMap::new(),
+            ip_filter,
+            disk,
+            pieces_tx: watch::Sender::new(have_pieces),
             tracker: TorrentTrackerState::default(),
             progress: TorrentProgress {
                 num_pieces,
@@ -90,10 +182,15 @@ impl TorrentState {
             },
             num_seeders: 0,
             num_leechers: 0,
+            download_rate: RateMeter::new(),
+            upload_rate: RateMeter::new(),
             download_start_time,
             active_download_duration: Duration::ZERO,
+            seed_start_time,
+            active_seed_duration: Duration::ZERO,
+            last_upload_time: None,
+            seeding_limits: None,
             init_time: SystemTime::now(),
-            mu: Mutex::new(()),
         }
     }
 
@@ -116,17 +213,390 @@ impl TorrentState {
             }
             _ => {}
         }
+        match &status {
+            &TorrentStatus::Seeding if self.seed_start_time.is_none() => {
+                self.seed_start_time = Some(Instant::now());
+            }
+            s if *s != TorrentStatus::Seeding => {
+                if let Some(start_time) = self.seed_start_time.take() {
+                    self.active_seed_duration += start_time.elapsed();
+                }
+            }
+            _ => {}
+        }
 
         self.status = status;
     }
 
     pub fn on_downloaded(&mut self, num_bytes: u64) {
         self.progress.downloaded += num_bytes;
-        self.progress.left -= num_bytes;
+        self.progress.left = self.progress.left.saturating_sub(num_bytes);
+        self.download_rate.add(num_bytes);
     }
 
     pub fn on_uploaded(&mut self, num_bytes: u64) {
         self.progress.uploaded += num_bytes;
+        self.upload_rate.add(num_bytes);
+        if num_bytes > 0 {
+            self.last_upload_time = Some(Instant::now());
+        }
+    }
+
+    /// Called once per second by the torrent command handler.
+    pub fn tick(&mut self) {
+        self.download_rate.tick();
+        self.upload_rate.tick();
+        for connection in self.connections.values_mut() {
+            connection.download_rate.tick();
+        }
+    }
+
+    #[inline]
+    pub fn download_rate(&self) -> u64 {
+        self.download_rate.rate()
+    }
+
+    #[inline]
+    pub fn upload_rate(&self) -> u64 {
+        self.upload_rate.rate()
+    }
+
+    /// Metadata is known and every wanted piece has been downloaded.
+    pub fn is_finished(&self) -> bool {
+        self.progress.num_pieces > 0
+            && (self.progress.is_complete()
+                || self.picker.missing_pieces(&self.bitfield).next().is_none())
+    }
+
+    /// Unknown until the info is known for magnet links.
+    #[inline]
+    pub fn is_private(&self) -> bool {
+        self.info.as_ref().is_some_and(Info::is_private)
+    }
+
+    /// Private torrents don't use DHT, PEX or local service discovery.
+    #[inline]
+    pub fn allows_peer_source(&self, source: PeerSource) -> bool {
+        !self.is_private() || source.is_allowed_for_private()
+    }
+
+    /// Returns the number of new peers, peers from a disallowed source or with a
+    /// blocked address are dropped.
+    pub fn add_peers<I>(&mut self, source: PeerSource, peers: I) -> usize
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        if !self.allows_peer_source(source) {
+            return 0;
+        }
+        let len = self.peers.len();
+        for addr in peers {
+            if self
+                .ip_filter
+                .as_ref()
+                .is_none_or(|filter| filter.allows(&addr))
+            {
+                self.peers.entry(addr).or_insert(source);
+            }
+        }
+        self.peers.len() - len
+    }
+
+    #[inline]
+    pub fn peers(&self) -> &BTreeMap<SocketAddr, PeerSource> {
+        &self.peers
+    }
+
+    /// Takes a connection the peer opened and returns its stream for the
+    /// caller to serve, `None` if the IP filter refuses it or the peer is
+    /// already connected.
+    pub fn add_connection(&mut self, connection: PeerConnection) -> Option<Box<dyn PeerIo>> {
+        let addr = connection.addr;
+        let allowed = self
+            .ip_filter
+            .as_ref()
+            .is_none_or(|filter| filter.allows(&addr));
+        if !allowed || self.connections.contains_key(&addr) {
+            return None;
+        }
+        self.peers.entry(addr).or_insert(PeerSource::Incoming);
+        // the address it advertised on the other family (BEP 7)
+        let alternate = connection
+            .extended
+            .as_ref()
+            .and_then(|extended| extended.alternate_addr(addr));
+        if let Some(alternate) = alternate {
+            self.add_peers(PeerSource::Incoming, vec![alternate]);
+        }
+        let (peer, stream) = connection.into_parts();
+        self.connections.insert(addr, peer);
+        Some(stream)
+    }
+
+    /// Drops a connection that closed or timed out. The failed pieces its
+    /// peer was downloading again go to the next peer.
+    pub fn remove_connection(&mut self, addr: &SocketAddr) -> Option<ConnectedPeer> {
+        let connection = self.connections.remove(addr)?;
+        if !self.connections.keys().any(|other| other.ip() == addr.ip()) {
+            self.smart_ban.release_peer(addr.ip());
+        }
+        Some(connection)
+    }
+
+    #[inline]
+    pub fn connections(&self) -> &BTreeMap<SocketAddr, ConnectedPeer> {
+        &self.connections
+    }
+
+    /// Records the pieces and choke state the peer wire read from the peer.
+    pub fn update_peer(&mut self, addr: &SocketAddr, bitfield: &BitField, choked: bool) {
+        if let Some(connection) = self.connections.get_mut(addr) {
+            connection.bitfield.clone_from(bitfield);
+            connection.choked = choked;
+        }
+    }
+
+    /// Installs an info dictionary fetched from peers (BEP 9), `false` if it is
+    /// invalid or does not hash to the info hash of the torrent.
+    pub async fn on_metadata(&mut self, info_bytes: Vec<u8>) -> Result<bool> {
+        let Ok(metainfo) = MetaInfo::from_info_bytes(info_bytes) else {
+            return Ok(false);
+        };
+        let received = metainfo.info_hash();
+        let v1 = self.info_hash.v1().zip(received.v1()).map(|(a, b)| a == b);
+        let v2 = self.info_hash.v2().zip(received.v2()).map(|(a, b)| a == b);
+        if !matches!(
+            (v1, v2),
+            (Some(true), Some(true) | None) | (None, Some(true))
+        ) {
+            return Ok(false);
+        }
+        self.set_info(metainfo.info, &PieceLayers::default())
+            .await
+            .map(|_| true)
+    }
+
+    /// Installs the info of a torrent started without metadata, verified by the caller.
+    async fn set_info(&mut self, info: Info, piece_layers: &PieceLayers) -> Result<()> {
+        if self.info.is_some() {
+            return Ok(());
+        }
+        let layout = Layout::from_torrent_info(&info);
+        let num_pieces = layout.num_pieces;
+        self.hash_trees = HashTrees::new(&info, &layout, piece_layers);
+        self.disk = Some(Arc::new(Mutex::new(Disk::new(
+            self.save_path.clone(),
+            layout.clone(),
+            &self.info_hash.inner().hex(),
+        ))));
+        self.file_priorities = vec![FilePriority::default(); layout.files.len()];
+        self.bitfield = BitField::new(num_pieces);
+        self.picker = PiecePicker::new(num_pieces);
+        self.progress.num_pieces = num_pieces;
+        self.progress.have_pieces = 0;
+        self.progress.left = info.total_length();
+        self.layout = Some(layout);
+        self.info = Some(info);
+        if self.is_private() {
+            self.peers
+                .retain(|_, source| source.is_allowed_for_private());
+        }
+
+        let Some(priorities) =
+            select_only_priorities(&self.select_only, self.file_priorities.len())
+        else {
+            return Ok(());
+        };
+        self.set_file_priorities(priorities);
+        // the disk was just created, nobody else waits for it
+        match self.disk.as_ref() {
+            Some(disk) => {
+                disk.lock()
+                    .await
+                    .set_file_priorities(&self.file_priorities)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    #[inline]
+    pub fn select_only(&self) -> &[RangeInclusive<usize>] {
+        &self.select_only
+    }
+
+    #[inline]
+    pub fn file_priorities(&self) -> &[FilePriority] {
+        &self.file_priorities
+    }
+
+    /// Missing files get `Normal`, extra entries are ignored. The disk is left
+    /// to the caller, so its I/O doesn't run under the state lock.
+    pub fn set_file_priorities(&mut self, priorities: Vec<FilePriority>) {
+        let Some(layout) = self.layout.as_ref() else {
+            return;
+        };
+        let mut file_priorities = priorities;
+        file_priorities.resize(layout.files.len(), FilePriority::default());
+        self.picker.set_file_priorities(layout, &file_priorities);
+        self.file_priorities = file_priorities;
+        self.progress.left = self.wanted_left();
+    }
+
+    /// Bytes of wanted pieces that are still missing.
+    pub fn wanted_left(&self) -> u64 {
+        let Some(layout) = self.layout.as_ref() else {
+            return self.progress.left;
+        };
+        self.picker
+            .missing_pieces(&self.bitfield)
+            .map(|index| layout.piece_size(index))
+            .sum()
+    }
+
+    /// Checks the v1 piece hash and the Merkle tree, hybrid torrents must pass both
+    /// (the tree only once its piece hash is known).
+    pub fn verify_piece(&self, index: usize, data: &[u8]) -> bool {
+        let Some(info) = self.info.as_ref() else {
+            return false;
+        };
+        let v1 = info
+            .piece_hash(index)
+            .map(|_| info.verify_piece(index, data));
+        let v2 = self
+            .hash_trees
+            .as_ref()
+            .and_then(|hash_trees| hash_trees.verify_piece(index, data));
+        match (v1, v2) {
+            (Some(v1), v2) => v1 && v2.unwrap_or(true),
+            (None, v2) => v2.unwrap_or(false),
+        }
+    }
+
+    /// Pieces of v2 files can't be verified before their hash came from the
+    /// metainfo or from peers.
+    pub fn has_piece_hash(&self, index: usize) -> bool {
+        let v1 = self
+            .info
+            .as_ref()
+            .is_some_and(|info| info.piece_hash(index).is_some());
+        v1 || self
+            .hash_trees
+            .as_ref()
+            .is_some_and(|hash_trees| hash_trees.has_piece_hash(index))
+    }
+
+    /// Next piece to request from a connected peer. Failed pieces reserved for
+    /// another peer are left out, and deadline pieces go to the fastest
+    /// unchoked peers that have them.
+    pub fn pick_peer_piece(&self, peer: SocketAddr) -> Option<usize> {
+        if self.status != TorrentStatus::Downloading || self.disk.is_none() {
+            return None;
+        }
+        let connection = self.connections.get(&peer)?;
+        let candidates: Vec<_> = self
+            .connections
+            .values()
+            .filter(|other| !other.choked || other.addr == peer)
+            .map(|other| (other, self.peer_pieces(other, &[])))
+            .collect();
+        let rated: Vec<_> = candidates
+            .iter()
+            .map(|(other, pieces)| (pieces, other.download_rate.rate()))
+            .collect();
+        let critical = self.picker.pick_time_critical(&self.bitfield, &rated);
+        let assigned = critical
+            .iter()
+            .find(|&&(_, index)| candidates[index].0.addr == peer);
+        if let Some(&(piece, _)) = assigned {
+            return Some(piece);
+        }
+        let taken: Vec<_> = critical.iter().map(|&(piece, _)| piece).collect();
+        let allowed = self.peer_pieces(connection, &taken);
+        self.picker.pick(&self.bitfield, &allowed)
+    }
+
+    /// Pieces the smart ban lets us download from the peer and that can be
+    /// verified, minus `skip`.
+    fn peer_pieces(&self, peer: &ConnectedPeer, skip: &[usize]) -> BitField {
+        let num_pieces = self.progress.num_pieces;
+        let mut pieces = BitField::new(num_pieces);
+        (0..num_pieces)
+            .filter(|&index| index / 8 < peer.bitfield.len() && peer.bitfield.has(index))
+            .filter(|&index| self.smart_ban.allows(index, peer.addr.ip()) && !skip.contains(&index))
+            .filter(|&index| self.has_piece_hash(index))
+            .for_each(|index| pieces.set(index));
+        pieces
+    }
+
+    /// Records a block received from a peer, `false` if it must be dropped.
+    /// A block that fails its v2 leaf hash gets the peer banned right away.
+    pub fn on_peer_block(
+        &mut self,
+        piece: usize,
+        begin: usize,
+        peer: SocketAddr,
+        data: &[u8],
+    ) -> bool {
+        let verified = self
+            .hash_trees
+            .as_ref()
+            .and_then(|hash_trees| hash_trees.verify_block(piece, begin, data));
+        if verified == Some(false) {
+            self.ban_peer(peer.ip());
+            return false;
+        }
+        let kept = self.smart_ban.on_block(piece, begin, peer.ip(), data);
+        if let Some(connection) = self.connections.get_mut(&peer).filter(|_| kept) {
+            connection.download_rate.add(data.len() as u64);
+        }
+        kept
+    }
+
+    /// Like `verify_piece` for a piece assembled from peer blocks, the peers
+    /// found to have sent bad data are banned.
+    pub fn verify_peer_piece(&mut self, index: usize, data: &[u8]) -> bool {
+        let valid = self.verify_piece(index, data);
+        let liars = match valid {
+            true => self.smart_ban.on_piece_passed(index, data),
+            false => self.smart_ban.on_piece_failed(index),
+        };
+        for ip in liars {
+            self.ban_peer(ip);
+        }
+        valid
+    }
+
+    pub fn ban_peer(&mut self, ip: IpAddr) {
+        self.peers.retain(|addr, _| addr.ip() != ip);
+        self.connections.retain(|addr, _| addr.ip() != ip);
+        self.smart_ban.forget_peer(ip);
+        if let Some(filter) = self.ip_filter.as_ref() {
+            filter.ban(ip);
+        }
+    }
+
+    /// Returns `true` if the piece had a deadline.
+    pub fn on_piece_completed(&mut self, index: usize) -> bool {
+        if index >= self.progress.num_pieces || self.bitfield.has(index) {
+            return false;
+        }
+        self.bitfield.set(index);
+        self.progress.have_pieces += 1;
+        self.progress.left = self.wanted_left();
+        self.pieces_tx.send_replace(self.progress.have_pieces);
+        self.picker.remove_piece_deadline(index)
+    }
+
+    /// Wakes up on every completed piece.
+    #[inline]
+    pub fn subscribe_pieces(&self) -> watch::Receiver<usize> {
+        self.pieces_tx.subscribe()
+    }
+
+    #[inline]
+    pub fn has_piece(&self, index: usize) -> bool {
+        index < self.progress.num_pieces && self.bitfield.has(index)
     }
 
     pub fn active_download_time(&self) -> Duration {
@@ -136,34 +606,50 @@ impl TorrentState {
         }
     }
 
-    fn build_params(&self, event: Event) -> AnnounceRequestParams {
-        let mut params = AnnounceBuilder::new()
-            .info_hash(self.info_hash.inner().urlencode())
-            .peer_id(self.peer_id.urlencode())
-            .port(self.port)
-            .uploaded(ctx_data.state.progress.uploaded)
-            .downloaded(ctx_data.state.progress.downloaded)
-            .left(ctx_data.state.progress.left)
-            .event(event.as_str())
-            .build()
-            .unwrap();
-        if let Some(tracker_id) = self.tracker_id.as_ref() {
-            params.tracker_id = Some(tracker_id.clone());
+    pub fn active_seed_time(&self) -> Duration {
+        match self.seed_start_time {
+            Some(start) => self.active_seed_duration + start.elapsed(),
+            None => self.active_seed_duration,
         }
-        params
     }
 
-    pub async fn announce_completed(&self) {
-        if let Some(cli) = self.tracker.client.as_ref() {
-            let params = self.build_params(Event::Completed, ctx_data);
-            let _ = cli.announce(&params).await;
+    /// Time since the last uploaded byte of the current seeding session.
+    pub fn seed_idle_time(&self) -> Duration {
+        let Some(seed_start_time) = self.seed_start_time else {
+            return Duration::ZERO;
+        };
+        match self.last_upload_time {
+            Some(last_upload_time) if last_upload_time > seed_start_time => {
+                last_upload_time.elapsed()
+            }
+            _ => seed_start_time.elapsed(),
         }
     }
 
-    pub async fn announce_stopped(&self) {
-        if let Some(cli) = self.client.as_ref() {
-            let params = self.build_params(Event::Stopped, ctx_data);
-            let _ = cli.announce(&params).await;
+    /// Share ratio for the seeding goals. A torrent that downloaded nothing,
+    /// e.g. seeded from files we already had, is measured against its size.
+    pub fn seeding_ratio(&self) -> Option<f64> {
+        self.progress.ratio().or_else(|| {
+            let total = self.info.as_ref()?.total_length();
+            (total > 0).then(|| self.progress.uploaded as f64 / total as f64)
+        })
+    }
+
+    /// Only seeding torrents can reach a goal.
+    pub fn check_seeding_goal(
+        &self,
+        defaults: &SeedingLimits,
+    ) -> Option<(SeedingGoal, SeedingGoalAction)> {
+        if self.status != TorrentStatus::Seeding {
+            return None;
         }
+        let limits = self.seeding_limits.as_ref().unwrap_or(defaults);
+        limits
+            .check(
+                self.seeding_ratio(),
+                self.active_seed_time(),
+                self.seed_idle_time(),
+            )
+            .map(|goal| (goal, limits.action))
     }
 }
@@ -1,3 +1,5 @@
+use std::{convert::Infallible, str::FromStr};
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum TorrentStatus {
     #[default]
@@ -10,15 +12,17 @@ pub enum TorrentStatus {
     Error(String),
 }
 
-impl Into<String> for TorrentStatus {
-    fn into(self) -> String {
-        self.as_str().into()
+impl From<TorrentStatus> for String {
+    fn from(status: TorrentStatus) -> Self {
+        status.as_str().into()
     }
 }
 
-impl TorrentStatus {
-    pub fn from_str(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
+impl FromStr for TorrentStatus {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
             "waiting" => TorrentStatus::Waiting,
             "started" => TorrentStatus::Started,
             "downloading" => TorrentStatus::Downloading,
@@ -27,9 +31,11 @@ impl TorrentStatus {
             "completed" => TorrentStatus::Completed,
             "error" => TorrentStatus::Error("".into()),
             _ => TorrentStatus::Waiting,
-        }
+        })
     }
+}
 
+impl TorrentStatus {
     pub fn as_str(&self) -> &str {
         match self {
             TorrentStatus::Waiting => "waiting",
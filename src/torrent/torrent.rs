@@ -1,63 +1,56 @@
 use crate::{
     error::{Error, Result},
     proto::PeerId,
-    util::Ctx,
+    proxy::Proxy,
+    session::{SessionAlert, SessionIpFilter},
+    torrent::{
+        spawn_command_handler, spawn_web_seeder, TorrentCommand, TorrentSource, TorrentState,
+        TorrentStatus,
+    },
 };
-use std::{path::Path, sync::Arc, time::Duration};
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::{mpsc::Sender, Mutex};
 
 pub type TorrentID = [u8; 20];
 
-// #[derive(Debug, Clone)]
-// pub enum TorrentSource<'a> {
-//     File(&'a [u8]),
-//     Link(&'a str),
-// }
-
-// pub struct TorrentState {}
-
-// impl TorrentState {
-//     pub async fn new(source: TorrentSource) -> Self {
-//         Self {}
-//     }
-// }
-
-// pub type TorrentCtx = Ctx<Arc<TorrentState>>;
-
-// #[derive(Debug)]
-// pub struct TorrentCtxData {
-//     pub state: TorrentState,
-//     pub tracker_client_timeout: Duration,
-//     pub tracker_user_agent: &'static str, // "uTorrent/2210(25110)"
-// }
-
-// конвеер
-// трекер передает полученные пиры и свой адрес для отправки ивента
-// // клиент треке
-// pub struct Torrent {
-//     pub metainfo: MetaInfo,
-//     pub info_hash: InfoHash,
-//     cmd_tx: Sender<String>,
-//     cmd_rx: Receiver<String>,
-// }
-
-// impl Torrent {
-//     pub async fn from_bytes(bytes: &[u8]) -> Result<Self> {
-//         let mut metainfo = MetaInfo::from_bytes(bytes)?;
-//         let info_hash = metainfo.take_info_hash().expect("");
-//         let (tx, rx) = mpsc::channel(32);
-//         Ok(Self {
-//             metainfo: metainfo,
-//             info_hash: info_hash,
-//             cmd_tx: tx,
-//             cmd_rx: rx,
-//         })
-//     }
-
-//     pub async fn from_file(path: impl AsRef<Path>) -> Result<Self> {
-//         let bytes = tokio::fs::read(path).await?;
-//         Self::from_bytes(&bytes).await
-//     }
-
-//     pub async fn start_listener() {}
-// }
+#[derive(Debug, Clone)]
+pub struct TorrentSpawnParams {
+    pub status: TorrentStatus,
+    pub port: u16,
+    pub peer_id: PeerId,
+    pub save_path: PathBuf,
+    pub alert_tx: Sender<SessionAlert>,
+    pub web_seed_proxy: Option<Proxy>,
+    /// In place before the torrent takes any peer.
+    pub ip_filter: Option<Arc<SessionIpFilter>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TorrentHandle {
+    pub id: TorrentID,
+    pub state: Arc<Mutex<TorrentState>>,
+    cmd_tx: Sender<TorrentCommand>,
+}
+
+impl TorrentHandle {
+    pub async fn spawn(source: TorrentSource, params: TorrentSpawnParams) -> Self {
+        let (source, id) = source.split_torrent_id();
+        let alert_tx = params.alert_tx.clone();
+        let web_seed_proxy = params.web_seed_proxy.clone();
+        let state = TorrentState::init(source.init_state_params(params));
+        let state = Arc::new(Mutex::new(state));
+        let (cmd_tx, _) = spawn_command_handler(id, state.clone(), alert_tx).await;
+        // idles until the torrent has web seeds, e.g. from its metadata
+        spawn_web_seeder(state.clone(), cmd_tx.downgrade(), web_seed_proxy);
+
+        Self { id, state, cmd_tx }
+    }
+
+    #[inline]
+    pub async fn send(&self, command: TorrentCommand) -> Result<()> {
+        self.cmd_tx
+            .send(command)
+            .await
+            .map_err(|e| Error::SendToTorrentCmd(e.0))
+    }
+}
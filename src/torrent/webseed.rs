@@ -0,0 +1,250 @@
+/// https://www.bittorrent.org/beps/bep_0019.html
+/// https://www.bittorrent.org/beps/bep_0017.html
+use crate::{
+    disk::layout::Layout,
+    error::{Error, Result},
+    proto::{infohash::InfoHashV1, metainfo::file::FileEntry},
+    torrent::TorrentID,
+    util::parse_http_date,
+};
+use reqwest::{header, Client, StatusCode, Url};
+use std::time::{Duration, Instant, SystemTime};
+
+const MAX_HASH_FAILURES: u32 = 3;
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebSeedKind {
+    /// BEP 19 `url-list`, plain files served with range requests.
+    Url,
+    /// BEP 17 `httpseeds`, a script serving pieces by `info_hash` and `piece`.
+    Http { info_hash: TorrentID },
+}
+
+#[derive(Debug, Clone)]
+pub struct WebSeed {
+    pub url: String,
+    pub kind: WebSeedKind,
+    failures: u32,
+    hash_failures: u32,
+    retry_at: Option<Instant>,
+    banned: bool,
+}
+
+impl WebSeed {
+    pub fn new(url: String) -> Self {
+        Self::with_kind(url, WebSeedKind::Url)
+    }
+
+    pub fn http_seed(url: String, info_hash: TorrentID) -> Self {
+        Self::with_kind(url, WebSeedKind::Http { info_hash })
+    }
+
+    fn with_kind(url: String, kind: WebSeedKind) -> Self {
+        Self {
+            url,
+            kind,
+            failures: 0,
+            hash_failures: 0,
+            retry_at: None,
+            banned: false,
+        }
+    }
+
+    #[inline]
+    pub fn is_banned(&self) -> bool {
+        self.banned
+    }
+
+    #[inline]
+    pub fn retry_at(&self) -> Option<Instant> {
+        self.retry_at
+    }
+
+    #[inline]
+    pub fn hash_failures(&self) -> u32 {
+        self.hash_failures
+    }
+
+    pub fn is_available(&self) -> bool {
+        !self.banned && self.retry_at.is_none_or(|at| at <= Instant::now())
+    }
+
+    /// A url ending with `/` is a directory: the file path (which starts with the
+    /// torrent name) is appended. Otherwise it points to the file of a single-file torrent.
+    pub fn file_url(&self, file: &FileEntry, single_file: bool) -> Result<Url> {
+        let mut url = Url::parse(&self.url).map_err(|e| Error::WebSeed(e.to_string()))?;
+        if single_file && !self.url.ends_with('/') {
+            return Ok(url);
+        }
+        url.path_segments_mut()
+            .map_err(|_| Error::WebSeed(format!("Invalid web seed url: {}", self.url)))?
+            .pop_if_empty()
+            .extend(&file.torrent_path);
+        Ok(url)
+    }
+
+    fn status_error(status: StatusCode, retry_after: Option<Duration>) -> Error {
+        Error::WebSeedStatus {
+            status: status.as_u16(),
+            retry_after,
+        }
+    }
+
+    async fn fetch_range(client: &Client, url: Url, start: u64, length: u64) -> Result<Vec<u8>> {
+        let response = client
+            .get(url)
+            .header(
+                header::RANGE,
+                format!("bytes={}-{}", start, start + length - 1),
+            )
+            .send()
+            .await?;
+
+        let status = response.status();
+        match status {
+            StatusCode::PARTIAL_CONTENT => {}
+            // the whole file, fine when that is what we asked for
+            StatusCode::OK if start == 0 && response.content_length() == Some(length) => {}
+            StatusCode::OK => return Err(Error::WebSeedRangesIgnored),
+            _ => {
+                let retry_after = response
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(retry_after);
+                return Err(Self::status_error(status, retry_after));
+            }
+        }
+
+        let bytes = response.bytes().await?;
+        match bytes.get(..length as usize) {
+            Some(data) if data.len() as u64 == length => Ok(data.to_vec()),
+            _ => Err(Error::WebSeed("Short response body".into())),
+        }
+    }
+
+    /// `?info_hash=...&piece=N&ranges=0-(len-1)`, a `503` carries the seconds
+    /// to wait in its body.
+    async fn fetch_script_piece(
+        &self,
+        client: &Client,
+        info_hash: &TorrentID,
+        index: usize,
+        length: u64,
+    ) -> Result<Vec<u8>> {
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        let url = format!(
+            "{}{}info_hash={}&piece={}&ranges=0-{}",
+            self.url,
+            separator,
+            InfoHashV1::new(*info_hash).urlencode(),
+            index,
+            length - 1
+        );
+        let url = Url::parse(&url).map_err(|e| Error::WebSeed(e.to_string()))?;
+        let response = client.get(url).send().await?;
+
+        let status = response.status();
+        match status {
+            StatusCode::OK => {}
+            StatusCode::SERVICE_UNAVAILABLE => {
+                let retry_after = response
+                    .text()
+                    .await
+                    .ok()
+                    .and_then(|body| body.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                return Err(Self::status_error(status, retry_after));
+            }
+            _ => return Err(Self::status_error(status, None)),
+        }
+
+        let bytes = response.bytes().await?;
+        if bytes.len() as u64 != length {
+            return Err(Error::WebSeed("Unexpected piece length".into()));
+        }
+        Ok(bytes.to_vec())
+    }
+
+    /// Downloads a whole piece. Url seeds need one range request per file it spans,
+    /// pad files are zeros and never requested.
+    pub async fn fetch_piece(
+        &self,
+        client: &Client,
+        layout: &Layout,
+        index: usize,
+        single_file: bool,
+    ) -> Result<Vec<u8>> {
+        let length = layout.piece_size(index);
+        if let WebSeedKind::Http { info_hash } = &self.kind {
+            return self
+                .fetch_script_piece(client, info_hash, index, length)
+                .await;
+        }
+
+        let mut piece = Vec::with_capacity(length as usize);
+        for slice in layout.piece_slices(index) {
+            let file = &layout.files[slice.file_index];
+            if file.is_padding() {
+                piece.resize(piece.len() + slice.length as usize, 0);
+                continue;
+            }
+            let url = self.file_url(file, single_file)?;
+            let data = Self::fetch_range(client, url, slice.file_offset, slice.length).await?;
+            piece.extend_from_slice(&data);
+        }
+        Ok(piece)
+    }
+
+    pub fn on_success(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+
+    /// Backs off exponentially unless the server sent `Retry-After`. A server
+    /// that ignores range requests is banned.
+    pub fn on_error(&mut self, error: &Error) {
+        let retry_after = match error {
+            Error::WebSeedRangesIgnored => {
+                self.banned = true;
+                return;
+            }
+            Error::WebSeedStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        };
+        self.back_off(retry_after);
+    }
+
+    /// The seed is banned after repeated hash failures, and backs off like
+    /// on errors until then.
+    pub fn on_hash_failure(&mut self) {
+        self.hash_failures += 1;
+        if self.hash_failures >= MAX_HASH_FAILURES {
+            self.banned = true;
+        } else {
+            self.back_off(None);
+        }
+    }
+
+    fn back_off(&mut self, retry_after: Option<Duration>) {
+        self.failures += 1;
+        let backoff = retry_after.unwrap_or_else(|| {
+            MIN_BACKOFF
+                .saturating_mul(1 << (self.failures - 1).min(16))
+                .min(MAX_BACKOFF)
+        });
+        self.retry_at = Some(Instant::now() + backoff);
+    }
+}
+
+/// `Retry-After` holds either delay seconds or an HTTP-date.
+fn retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = parse_http_date(value)?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
@@ -1,7 +1,9 @@
 mod context;
+mod rate;
 mod time;
 mod urlencoding;
 
 pub use context::*;
+pub use rate::*;
 pub use time::*;
 pub use urlencoding::*;
@@ -0,0 +1,45 @@
+use std::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct RateMeter {
+    bytes: u64,
+    rate: u64,
+    last_tick: Instant,
+}
+
+impl Default for RateMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateMeter {
+    pub fn new() -> Self {
+        Self {
+            bytes: 0,
+            rate: 0,
+            last_tick: Instant::now(),
+        }
+    }
+
+    #[inline]
+    pub fn add(&mut self, num_bytes: u64) {
+        self.bytes += num_bytes;
+    }
+
+    /// Closes the current measurement window and returns bytes per second.
+    pub fn tick(&mut self) -> u64 {
+        let elapsed = self.last_tick.elapsed().as_secs_f64();
+        if elapsed > 0. {
+            self.rate = (self.bytes as f64 / elapsed) as u64;
+        }
+        self.bytes = 0;
+        self.last_tick = Instant::now();
+        self.rate
+    }
+
+    #[inline]
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+}
@@ -0,0 +1,138 @@
+/// <https://datatracker.ietf.org/doc/html/rfc6817>
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Queuing delay LEDBAT aims for, in microseconds.
+pub const TARGET_DELAY: u32 = 100_000;
+const GAIN: f64 = 1.0;
+/// Base delay is the minimum over this many one-minute buckets.
+const BASE_HISTORY: usize = 2;
+const BASE_BUCKET: Duration = Duration::from_secs(60);
+/// Recent samples smoothed into the current delay.
+const CURRENT_HISTORY: usize = 4;
+const MAX_CWND: usize = 4 * 1024 * 1024;
+
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_millis(500);
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// Delay-based congestion window, in bytes.
+#[derive(Debug, Clone)]
+pub struct Ledbat {
+    cwnd: usize,
+    /// `(bucket start, minimum delay)`, oldest first.
+    base_delays: VecDeque<(Instant, u32)>,
+    current_delays: VecDeque<u32>,
+}
+
+impl Ledbat {
+    pub fn new(initial_cwnd: usize) -> Self {
+        Self {
+            cwnd: initial_cwnd,
+            base_delays: VecDeque::new(),
+            current_delays: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    /// One-way delay of our packets as echoed back by the peer. Both clocks are
+    /// arbitrary, only the difference to the base delay matters.
+    pub fn on_delay_sample(&mut self, delay: u32, now: Instant) {
+        match self.base_delays.back_mut() {
+            Some((start, min)) if now.duration_since(*start) < BASE_BUCKET => {
+                *min = (*min).min(delay);
+            }
+            _ => {
+                self.base_delays.push_back((now, delay));
+                if self.base_delays.len() > BASE_HISTORY {
+                    self.base_delays.pop_front();
+                }
+            }
+        }
+        self.current_delays.push_back(delay);
+        if self.current_delays.len() > CURRENT_HISTORY {
+            self.current_delays.pop_front();
+        }
+    }
+
+    /// Current delay above the base delay, `None` before the first sample.
+    pub fn queuing_delay(&self) -> Option<u32> {
+        let base = self.base_delays.iter().map(|(_, min)| *min).min()?;
+        let current = self.current_delays.iter().copied().min()?;
+        Some(current.saturating_sub(base))
+    }
+
+    /// Grows the window below the target delay and shrinks it above.
+    pub fn on_ack(&mut self, bytes_acked: usize, mss: usize) {
+        let queuing_delay = self.queuing_delay().unwrap_or(0) as f64;
+        let target = TARGET_DELAY as f64;
+        let off_target = (target - queuing_delay) / target;
+        let delta = GAIN * off_target * bytes_acked as f64 * mss as f64 / self.cwnd as f64;
+        let cwnd = (self.cwnd as f64 + delta).clamp(mss as f64, MAX_CWND as f64);
+        self.cwnd = cwnd as usize;
+    }
+
+    pub fn on_loss(&mut self, mss: usize) {
+        self.cwnd = (self.cwnd / 2).max(mss);
+    }
+
+    pub fn on_timeout(&mut self, mss: usize) {
+        self.cwnd = mss;
+    }
+}
+
+/// Smoothed round-trip time and retransmission timeout, RFC 6298.
+#[derive(Debug, Clone)]
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+        }
+    }
+}
+
+impl RttEstimator {
+    #[inline]
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    #[inline]
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    pub fn on_sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        self.rto = (self.srtt.unwrap_or_default() + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Exponential backoff.
+    pub fn on_timeout(&mut self) {
+        self.rto = (self.rto * 2).min(MAX_RTO);
+    }
+}
@@ -0,0 +1,718 @@
+use crate::utp::{
+    congestion::{Ledbat, RttEstimator},
+    mtu::MtuSearch,
+    packet::{seq_le, seq_lt, Packet, PacketType, UTP_HEADER_SIZE},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+use tokio::io::ReadBuf;
+
+const RECV_BUF_SIZE: usize = 1024 * 1024;
+const SEND_BUF_SIZE: usize = 1024 * 1024;
+/// Covers the 64 packets following `ack_nr + 1`.
+const MAX_SELECTIVE_ACK_LEN: usize = 8;
+/// Header plus room for the largest selective ack.
+const PACKET_OVERHEAD: usize = UTP_HEADER_SIZE + 2 + MAX_SELECTIVE_ACK_LEN;
+const MAX_SYN_RETRIES: u32 = 3;
+const MAX_RETRIES: u32 = 6;
+/// Packets acked past a missing one before it is resent.
+const DUPLICATE_ACKS: usize = 3;
+/// Out of order packets further ahead are dropped.
+const MAX_REORDER: u16 = 1024;
+/// Time a closed connection keeps answering retransmitted FINs.
+const LINGER: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    SynSent,
+    Connected,
+    /// Both FINs exchanged.
+    Closed,
+    Reset,
+    TimedOut,
+}
+
+#[derive(Debug)]
+struct SentPacket {
+    packet: Packet,
+    sent_at: Instant,
+    transmissions: u32,
+    /// Cumulatively or selectively acked.
+    acked: bool,
+    need_resend: bool,
+    /// Padded MTU probe without payload, resent unpadded when lost.
+    probe: bool,
+}
+
+/// uTP state machine without I/O: fed with packets and the current time, it
+/// returns the packets to send.
+#[derive(Debug)]
+pub(crate) struct Connection {
+    state: ConnectionState,
+    epoch: Instant,
+    recv_id: u16,
+    send_id: u16,
+    /// Next sequence number to send.
+    seq_nr: u16,
+    /// Last sequence number received in order.
+    ack_nr: u16,
+    /// An accepted connection sends a single packet until the peer has seen its
+    /// first sequence number, see `on_packet`.
+    peer_confirmed: bool,
+    send_buf: VecDeque<u8>,
+    in_flight: VecDeque<SentPacket>,
+    recv_buf: VecDeque<u8>,
+    out_of_order: HashMap<u16, (PacketType, Vec<u8>)>,
+    peer_wnd: usize,
+    ledbat: Ledbat,
+    rtt: RttEstimator,
+    mtu: MtuSearch,
+    /// Echoed in `timestamp_diff`.
+    reply_micro: u32,
+    last_ack_nr: u16,
+    duplicate_acks: usize,
+    last_loss: Option<Instant>,
+    ack_pending: bool,
+    timeout_at: Option<Instant>,
+    retries: u32,
+    close_requested: bool,
+    read_closed: bool,
+    fin_sent: Option<u16>,
+    fin_acked: bool,
+    eof: bool,
+    closed_at: Option<Instant>,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    state_waker: Option<Waker>,
+}
+
+impl Connection {
+    fn new(state: ConnectionState, recv_id: u16, send_id: u16, now: Instant) -> Self {
+        let mtu = MtuSearch::default();
+        Self {
+            state,
+            epoch: now,
+            recv_id,
+            send_id,
+            seq_nr: 1,
+            ack_nr: 0,
+            peer_confirmed: true,
+            send_buf: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            recv_buf: VecDeque::new(),
+            out_of_order: HashMap::new(),
+            peer_wnd: RECV_BUF_SIZE,
+            ledbat: Ledbat::new(2 * (mtu.packet_size() - PACKET_OVERHEAD)),
+            rtt: RttEstimator::default(),
+            mtu,
+            reply_micro: 0,
+            last_ack_nr: 0,
+            duplicate_acks: 0,
+            last_loss: None,
+            ack_pending: false,
+            timeout_at: None,
+            retries: 0,
+            close_requested: false,
+            read_closed: false,
+            fin_sent: None,
+            fin_acked: false,
+            eof: false,
+            closed_at: None,
+            read_waker: None,
+            write_waker: None,
+            state_waker: None,
+        }
+    }
+
+    /// Queues a SYN, the peer answers on `recv_id`.
+    pub fn connect(recv_id: u16, now: Instant) -> Self {
+        let mut conn = Self::new(
+            ConnectionState::SynSent,
+            recv_id,
+            recv_id.wrapping_add(1),
+            now,
+        );
+        let syn = conn.make_packet(PacketType::Syn, now);
+        conn.in_flight.push_back(SentPacket {
+            packet: Packet {
+                connection_id: recv_id,
+                ..syn
+            },
+            sent_at: now,
+            transmissions: 0,
+            acked: false,
+            need_resend: true,
+            probe: false,
+        });
+        conn.seq_nr = 2;
+        conn
+    }
+
+    /// Answers a SYN, `seq_nr` should be random.
+    pub fn accept(syn: &Packet, seq_nr: u16, now: Instant) -> Self {
+        let mut conn = Self::new(
+            ConnectionState::Connected,
+            syn.connection_id.wrapping_add(1),
+            syn.connection_id,
+            now,
+        );
+        conn.seq_nr = seq_nr;
+        conn.ack_nr = syn.seq_nr;
+        conn.last_ack_nr = seq_nr.wrapping_sub(1);
+        conn.peer_confirmed = false;
+        conn.peer_wnd = syn.wnd_size as usize;
+        conn.reply_micro = conn.now_micros(now).wrapping_sub(syn.timestamp);
+        conn.ack_pending = true;
+        conn
+    }
+
+    #[inline]
+    pub fn recv_id(&self) -> u16 {
+        self.recv_id
+    }
+
+    #[inline]
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    #[inline]
+    fn now_micros(&self, now: Instant) -> u32 {
+        now.duration_since(self.epoch).as_micros() as u32
+    }
+
+    /// Payload of a regular data packet.
+    #[inline]
+    fn mss(&self) -> usize {
+        self.mtu.packet_size() - PACKET_OVERHEAD
+    }
+
+    #[inline]
+    fn window(&self) -> usize {
+        self.ledbat.cwnd().min(self.peer_wnd)
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.in_flight
+            .iter()
+            .filter(|sent| !sent.acked && !sent.need_resend)
+            .map(|sent| sent.packet.payload.len())
+            .sum()
+    }
+
+    fn selective_ack(&self) -> Option<Vec<u8>> {
+        if self.out_of_order.is_empty() {
+            return None;
+        }
+        let mut bitmask = [0u8; MAX_SELECTIVE_ACK_LEN];
+        for bit in 0..MAX_SELECTIVE_ACK_LEN * 8 {
+            let seq_nr = self.ack_nr.wrapping_add(2 + bit as u16);
+            if self.out_of_order.contains_key(&seq_nr) {
+                bitmask[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        let used = bitmask.iter().rposition(|byte| *byte != 0)? + 1;
+        Some(bitmask[..used.div_ceil(4) * 4].to_vec())
+    }
+
+    fn make_packet(&self, packet_type: PacketType, now: Instant) -> Packet {
+        Packet {
+            packet_type,
+            connection_id: self.send_id,
+            timestamp: self.now_micros(now),
+            timestamp_diff: self.reply_micro,
+            wnd_size: RECV_BUF_SIZE.saturating_sub(self.recv_buf.len()) as u32,
+            seq_nr: self.seq_nr,
+            ack_nr: self.ack_nr,
+            selective_ack: self.selective_ack(),
+            padding: 0,
+            payload: Vec::new(),
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waker in [
+            self.read_waker.take(),
+            self.write_waker.take(),
+            self.state_waker.take(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            waker.wake();
+        }
+    }
+
+    pub fn on_packet(&mut self, packet: Packet, now: Instant) {
+        if matches!(
+            self.state,
+            ConnectionState::Reset | ConnectionState::TimedOut
+        ) {
+            return;
+        }
+        if packet.packet_type == PacketType::Reset {
+            self.state = ConnectionState::Reset;
+            self.wake_all();
+            return;
+        }
+        if packet.timestamp != 0 {
+            self.reply_micro = self.now_micros(now).wrapping_sub(packet.timestamp);
+        }
+        self.peer_wnd = packet.wnd_size as usize;
+        match packet.packet_type {
+            // the answer to the SYN got lost
+            PacketType::Syn => {
+                self.ack_pending = true;
+                return;
+            }
+            // the acceptor's first packet carries the sequence number it starts with
+            _ if self.state == ConnectionState::SynSent => {
+                self.ack_nr = packet.seq_nr.wrapping_sub(1);
+                self.state = ConnectionState::Connected;
+                if let Some(waker) = self.state_waker.take() {
+                    waker.wake();
+                }
+            }
+            _ => {}
+        }
+        // anything but a SYN means the initiator knows our sequence numbers
+        self.peer_confirmed = true;
+
+        self.process_ack(&packet, now);
+        if matches!(packet.packet_type, PacketType::Data | PacketType::Fin) {
+            self.process_data(packet);
+        }
+    }
+
+    fn process_ack(&mut self, packet: &Packet, now: Instant) {
+        let ack_nr = packet.ack_nr;
+        // acks for packets we never sent
+        if !seq_lt(ack_nr, self.seq_nr) {
+            return;
+        }
+        let selective_acks = packet.selective_acks().collect::<Vec<_>>();
+        let mut bytes_acked = 0;
+        let mut fin_acked = false;
+        for sent in self.in_flight.iter_mut().filter(|sent| !sent.acked) {
+            let seq_nr = sent.packet.seq_nr;
+            if !seq_le(seq_nr, ack_nr) && !selective_acks.contains(&seq_nr) {
+                continue;
+            }
+            sent.acked = true;
+            bytes_acked += sent.packet.payload.len();
+            // Karn's algorithm, retransmitted packets give ambiguous samples
+            if sent.transmissions == 1 {
+                self.rtt.on_sample(now.duration_since(sent.sent_at));
+            }
+            if sent.probe {
+                sent.probe = false;
+                self.mtu.on_probe_acked();
+            }
+            fin_acked |= Some(seq_nr) == self.fin_sent;
+        }
+
+        let lost = match packet.selective_ack.is_some() {
+            true => self.detect_selective_loss(),
+            false => self.detect_duplicate_ack(packet, ack_nr, bytes_acked),
+        };
+        self.last_ack_nr = ack_nr;
+        if lost
+            && self
+                .last_loss
+                .is_none_or(|t| now.duration_since(t) > self.rtt.rto())
+        {
+            self.last_loss = Some(now);
+            self.ledbat.on_loss(self.mss());
+        }
+
+        let acked_front = self.in_flight.front().is_some_and(|sent| sent.acked);
+        while self.in_flight.front().is_some_and(|sent| sent.acked) {
+            self.in_flight.pop_front();
+        }
+        if bytes_acked > 0 {
+            if packet.timestamp_diff != 0 {
+                self.ledbat.on_delay_sample(packet.timestamp_diff, now);
+            }
+            self.ledbat.on_ack(bytes_acked, self.mss());
+        }
+        if bytes_acked > 0 || acked_front {
+            self.retries = 0;
+            self.timeout_at = self
+                .in_flight
+                .iter()
+                .any(|sent| !sent.acked)
+                .then(|| now + self.rtt.rto());
+            if let Some(waker) = self.write_waker.take() {
+                waker.wake();
+            }
+        }
+        if fin_acked {
+            self.fin_acked = true;
+            if let Some(waker) = self.state_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Resends packets the peer skipped in its selective acks.
+    fn detect_selective_loss(&mut self) -> bool {
+        let mut lost = false;
+        let mut acked_after = 0;
+        for sent in self.in_flight.iter_mut().rev() {
+            if sent.acked {
+                acked_after += 1;
+            } else if acked_after >= DUPLICATE_ACKS && !sent.need_resend {
+                sent.need_resend = true;
+                // a lost probe says nothing about congestion
+                lost |= !sent.probe;
+            }
+        }
+        lost
+    }
+
+    fn detect_duplicate_ack(&mut self, packet: &Packet, ack_nr: u16, bytes_acked: usize) -> bool {
+        let duplicate = bytes_acked == 0
+            && packet.packet_type == PacketType::State
+            && ack_nr == self.last_ack_nr;
+        if !duplicate {
+            self.duplicate_acks = 0;
+            return false;
+        }
+        self.duplicate_acks += 1;
+        if self.duplicate_acks != DUPLICATE_ACKS {
+            return false;
+        }
+        match self
+            .in_flight
+            .iter_mut()
+            .find(|sent| !sent.acked && !sent.need_resend)
+        {
+            Some(sent) => {
+                sent.need_resend = true;
+                !sent.probe
+            }
+            None => false,
+        }
+    }
+
+    fn process_data(&mut self, packet: Packet) {
+        self.ack_pending = true;
+        let seq_nr = packet.seq_nr;
+        if self.eof || seq_le(seq_nr, self.ack_nr) {
+            return;
+        }
+        if seq_nr.wrapping_sub(self.ack_nr) > MAX_REORDER {
+            return;
+        }
+        if seq_nr != self.ack_nr.wrapping_add(1) {
+            self.out_of_order
+                .insert(seq_nr, (packet.packet_type, packet.payload));
+            return;
+        }
+        if !self.fits(packet.packet_type, packet.payload.len()) {
+            return;
+        }
+        self.deliver(packet.packet_type, packet.payload);
+        loop {
+            let next = self.ack_nr.wrapping_add(1);
+            match self.out_of_order.get(&next) {
+                Some((packet_type, payload)) if self.fits(*packet_type, payload.len()) => {}
+                _ => break,
+            }
+            let (packet_type, payload) = self.out_of_order.remove(&next).unwrap();
+            self.deliver(packet_type, payload);
+        }
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Data past the receive buffer is left unacked, the peer resends it
+    /// once the window opens again.
+    fn fits(&self, packet_type: PacketType, len: usize) -> bool {
+        packet_type == PacketType::Fin
+            || self.read_closed
+            || self.recv_buf.len() + len <= RECV_BUF_SIZE
+    }
+
+    fn deliver(&mut self, packet_type: PacketType, payload: Vec<u8>) {
+        if self.eof {
+            return;
+        }
+        self.ack_nr = self.ack_nr.wrapping_add(1);
+        match packet_type {
+            PacketType::Fin => {
+                self.eof = true;
+                self.out_of_order.clear();
+            }
+            _ if !self.read_closed => self.recv_buf.extend(payload),
+            _ => {}
+        }
+    }
+
+    fn on_timeout(&mut self, now: Instant) {
+        self.timeout_at = None;
+        if self.in_flight.iter().all(|sent| sent.acked) {
+            // zero window probe
+            self.peer_wnd = self.peer_wnd.max(self.mss());
+            return;
+        }
+        self.retries += 1;
+        let max_retries = match self.state {
+            ConnectionState::SynSent => MAX_SYN_RETRIES,
+            _ => MAX_RETRIES,
+        };
+        if self.retries > max_retries {
+            self.state = ConnectionState::TimedOut;
+            self.wake_all();
+            return;
+        }
+        self.rtt.on_timeout();
+        if self.in_flight.iter().any(|sent| !sent.acked && !sent.probe) {
+            self.ledbat.on_timeout(self.mss());
+            self.last_loss = Some(now);
+        }
+        for sent in self.in_flight.iter_mut().filter(|sent| !sent.acked) {
+            sent.need_resend = true;
+        }
+    }
+
+    /// Packets to send now: retransmissions, new data within the window, a FIN
+    /// once closed and otherwise a bare ack if one is due.
+    pub fn poll_transmit(&mut self, now: Instant) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        if matches!(
+            self.state,
+            ConnectionState::Reset | ConnectionState::TimedOut
+        ) {
+            return packets;
+        }
+        if self.timeout_at.is_some_and(|timeout_at| timeout_at <= now) {
+            self.on_timeout(now);
+            if self.state == ConnectionState::TimedOut {
+                return packets;
+            }
+        }
+
+        let mut budget = self.window().saturating_sub(self.bytes_in_flight());
+        let template = self.make_packet(PacketType::State, now);
+        for sent in self.in_flight.iter_mut() {
+            if !sent.need_resend || sent.acked {
+                continue;
+            }
+            if sent.probe {
+                sent.probe = false;
+                sent.packet.padding = 0;
+                self.mtu.on_probe_lost();
+            }
+            let len = sent.packet.payload.len();
+            if !packets.is_empty() && len > budget {
+                break;
+            }
+            budget = budget.saturating_sub(len);
+            sent.packet.timestamp = template.timestamp;
+            sent.packet.timestamp_diff = template.timestamp_diff;
+            sent.packet.wnd_size = template.wnd_size;
+            if sent.packet.packet_type != PacketType::Syn {
+                sent.packet.ack_nr = template.ack_nr;
+                sent.packet.selective_ack = template.selective_ack.clone();
+            }
+            sent.sent_at = now;
+            sent.transmissions += 1;
+            sent.need_resend = false;
+            packets.push(sent.packet.clone());
+        }
+
+        if self.state == ConnectionState::Connected {
+            self.send_new_data(now, &mut packets);
+        }
+
+        // an ack riding on a probe alone may never arrive
+        if packets.iter().any(|packet| packet.padding == 0) {
+            self.ack_pending = false;
+        } else if self.ack_pending {
+            self.ack_pending = false;
+            packets.push(self.make_packet(PacketType::State, now));
+        }
+        if self.timeout_at.is_none() && self.in_flight.iter().any(|sent| !sent.acked) {
+            self.timeout_at = Some(now + self.rtt.rto());
+        }
+        if self.closed_at.is_none()
+            && self.state == ConnectionState::Connected
+            && self.fin_acked
+            && (self.eof || self.read_closed)
+        {
+            self.state = ConnectionState::Closed;
+            self.closed_at = Some(now);
+            self.wake_all();
+        }
+        packets
+    }
+
+    fn send_new_data(&mut self, now: Instant, packets: &mut Vec<Packet>) {
+        let mss = self.mss();
+        let mut sent_data = false;
+        while !self.send_buf.is_empty() {
+            if !self.peer_confirmed && !self.in_flight.is_empty() {
+                break;
+            }
+            let len = mss.min(self.send_buf.len());
+            let in_flight = self.bytes_in_flight();
+            let window = self.window();
+            if window == 0 && in_flight == 0 {
+                // wait for a window update or probe the window on timeout
+                self.timeout_at.get_or_insert(now + self.rtt.rto());
+                break;
+            }
+            if in_flight + len > window && in_flight > 0 {
+                break;
+            }
+            let mut packet = self.make_packet(PacketType::Data, now);
+            packet.payload = self.send_buf.drain(..len).collect();
+            self.push_in_flight(packet.clone(), now, false);
+            packets.push(packet);
+            sent_data = true;
+            if let Some(waker) = self.write_waker.take() {
+                waker.wake();
+            }
+        }
+
+        // probes ride along with data so an idle connection sends none
+        if let Some(size) = self
+            .mtu
+            .next_probe()
+            .filter(|_| sent_data && !self.mtu.is_probing())
+        {
+            let packet = self.make_packet(PacketType::Data, now).with_padding(size);
+            self.mtu.on_probe_sent(size);
+            self.push_in_flight(packet.clone(), now, true);
+            packets.push(packet);
+        }
+
+        if self.close_requested && self.send_buf.is_empty() && self.fin_sent.is_none() {
+            let packet = self.make_packet(PacketType::Fin, now);
+            self.fin_sent = Some(packet.seq_nr);
+            self.push_in_flight(packet.clone(), now, false);
+            packets.push(packet);
+        }
+    }
+
+    fn push_in_flight(&mut self, packet: Packet, now: Instant, probe: bool) {
+        self.seq_nr = self.seq_nr.wrapping_add(1);
+        self.in_flight.push_back(SentPacket {
+            packet,
+            sent_at: now,
+            transmissions: 1,
+            acked: false,
+            need_resend: false,
+            probe,
+        });
+    }
+
+    pub fn next_timeout(&self) -> Option<Instant> {
+        let linger = self.closed_at.map(|closed_at| closed_at + LINGER);
+        match (self.timeout_at, linger) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The driver can forget the connection.
+    pub fn is_done(&self, now: Instant) -> bool {
+        matches!(
+            self.state,
+            ConnectionState::Reset | ConnectionState::TimedOut
+        ) || self
+            .closed_at
+            .is_some_and(|closed_at| now >= closed_at + LINGER)
+    }
+
+    fn io_error(&self) -> Option<io::Error> {
+        match self.state {
+            ConnectionState::Reset => Some(io::ErrorKind::ConnectionReset.into()),
+            ConnectionState::TimedOut => Some(io::ErrorKind::TimedOut.into()),
+            _ => None,
+        }
+    }
+
+    pub fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(err) = self.io_error() {
+            return Poll::Ready(Err(err));
+        }
+        match self.state {
+            ConnectionState::SynSent => {
+                self.state_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+
+    /// Also returns whether the freed space should be advertised.
+    pub fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> (Poll<io::Result<()>>, bool) {
+        if !self.recv_buf.is_empty() {
+            let free_before = RECV_BUF_SIZE.saturating_sub(self.recv_buf.len());
+            let (front, _) = self.recv_buf.as_slices();
+            let n = front.len().min(buf.remaining());
+            buf.put_slice(&front[..n]);
+            self.recv_buf.drain(..n);
+            // the peer may be waiting for the window to open
+            let window_update = free_before < self.mss() * 2;
+            self.ack_pending |= window_update;
+            return (Poll::Ready(Ok(())), window_update);
+        }
+        if self.eof {
+            return (Poll::Ready(Ok(())), false);
+        }
+        if let Some(err) = self.io_error() {
+            return (Poll::Ready(Err(err)), false);
+        }
+        self.read_waker = Some(cx.waker().clone());
+        (Poll::Pending, false)
+    }
+
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        if let Some(err) = self.io_error() {
+            return Poll::Ready(Err(err));
+        }
+        if self.close_requested || self.state == ConnectionState::Closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let free = SEND_BUF_SIZE.saturating_sub(self.send_buf.len());
+        if free == 0 {
+            self.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = free.min(data.len());
+        self.send_buf.extend(&data[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    /// Ready once our FIN is acked.
+    pub fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close_requested = true;
+        if self.fin_acked || self.state == ConnectionState::Closed {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(err) = self.io_error() {
+            return Poll::Ready(Err(err));
+        }
+        self.state_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// The stream was dropped: send what's buffered, then a FIN.
+    pub fn close(&mut self) {
+        self.close_requested = true;
+        self.read_closed = true;
+        self.recv_buf.clear();
+    }
+}
@@ -0,0 +1,13 @@
+mod congestion;
+mod connection;
+mod mtu;
+mod packet;
+mod socket;
+mod stream;
+
+pub use congestion::*;
+pub use connection::ConnectionState;
+pub use mtu::*;
+pub use packet::*;
+pub use socket::*;
+pub use stream::UtpStream;
@@ -0,0 +1,71 @@
+/// Binary search for the largest packet the path carries, between the minimum
+/// IPv4 datagram and an Ethernet frame, minus the IP and UDP headers.
+pub const MTU_FLOOR: usize = 576 - 28;
+pub const MTU_CEILING: usize = 1500 - 28;
+/// The search stops once the range is this narrow.
+const MTU_SEARCH_DONE: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct MtuSearch {
+    floor: usize,
+    ceiling: usize,
+    /// Size of the probe in flight.
+    probe: Option<usize>,
+}
+
+impl Default for MtuSearch {
+    fn default() -> Self {
+        Self::new(MTU_FLOOR, MTU_CEILING)
+    }
+}
+
+impl MtuSearch {
+    pub fn new(floor: usize, ceiling: usize) -> Self {
+        Self {
+            floor,
+            ceiling: ceiling.max(floor),
+            probe: None,
+        }
+    }
+
+    /// Largest packet known to get through.
+    #[inline]
+    pub fn packet_size(&self) -> usize {
+        self.floor
+    }
+
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.ceiling - self.floor < MTU_SEARCH_DONE
+    }
+
+    #[inline]
+    pub fn is_probing(&self) -> bool {
+        self.probe.is_some()
+    }
+
+    /// Size of the next probe, `None` when done or a probe is already in flight.
+    pub fn next_probe(&self) -> Option<usize> {
+        match self.is_done() || self.is_probing() {
+            true => None,
+            false => Some((self.floor + self.ceiling).div_ceil(2)),
+        }
+    }
+
+    #[inline]
+    pub fn on_probe_sent(&mut self, size: usize) {
+        self.probe = Some(size);
+    }
+
+    pub fn on_probe_acked(&mut self) {
+        if let Some(size) = self.probe.take() {
+            self.floor = self.floor.max(size);
+        }
+    }
+
+    pub fn on_probe_lost(&mut self) {
+        if let Some(size) = self.probe.take() {
+            self.ceiling = (size - 1).max(self.floor);
+        }
+    }
+}
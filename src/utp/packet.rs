@@ -0,0 +1,235 @@
+/// <https://www.bittorrent.org/beps/bep_0029.html>
+use crate::error::{Error, Result};
+
+pub const UTP_VERSION: u8 = 1;
+pub const UTP_HEADER_SIZE: usize = 20;
+const EXTENSION_NONE: u8 = 0;
+const EXTENSION_SELECTIVE_ACK: u8 = 1;
+/// Filler for MTU probes, skipped by other implementations like any unknown
+/// extension.
+const EXTENSION_PADDING: u8 = 3;
+const MAX_EXTENSION_LEN: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Data,
+    Fin,
+    State,
+    Reset,
+    Syn,
+}
+
+impl PacketType {
+    #[inline]
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Data),
+            1 => Some(Self::Fin),
+            2 => Some(Self::State),
+            3 => Some(Self::Reset),
+            4 => Some(Self::Syn),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn as_u8(&self) -> u8 {
+        match self {
+            Self::Data => 0,
+            Self::Fin => 1,
+            Self::State => 2,
+            Self::Reset => 3,
+            Self::Syn => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub packet_type: PacketType,
+    pub connection_id: u16,
+    /// Microseconds, only meaningful to the sender.
+    pub timestamp: u32,
+    /// Receive time minus the timestamp of the last packet from the other side.
+    pub timestamp_diff: u32,
+    /// Bytes the sender is still willing to receive.
+    pub wnd_size: u32,
+    pub seq_nr: u16,
+    pub ack_nr: u16,
+    /// Bit `i` acknowledges `ack_nr + 2 + i`, a multiple of 4 bytes.
+    pub selective_ack: Option<Vec<u8>>,
+    /// Filler bytes carried in padding extensions, see `with_padding`.
+    pub padding: usize,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(packet_type: PacketType, connection_id: u16, seq_nr: u16, ack_nr: u16) -> Self {
+        Self {
+            packet_type,
+            connection_id,
+            timestamp: 0,
+            timestamp_diff: 0,
+            wnd_size: 0,
+            seq_nr,
+            ack_nr,
+            selective_ack: None,
+            padding: 0,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Pads the packet up to about `len` bytes on the wire.
+    pub fn with_padding(mut self, len: usize) -> Self {
+        self.padding = 0;
+        let extra = len.saturating_sub(self.len());
+        self.padding = extra.saturating_sub(2 * extra.div_ceil(MAX_EXTENSION_LEN + 2));
+        self
+    }
+
+    /// Cheap check used to demultiplex the shared UDP socket.
+    #[inline]
+    pub fn is_utp(bytes: &[u8]) -> bool {
+        bytes.len() >= UTP_HEADER_SIZE
+            && bytes[0] & 0x0f == UTP_VERSION
+            && PacketType::from_u8(bytes[0] >> 4).is_some()
+            && matches!(
+                bytes[1],
+                EXTENSION_NONE | EXTENSION_SELECTIVE_ACK | EXTENSION_PADDING
+            )
+    }
+
+    /// Unknown extensions are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < UTP_HEADER_SIZE {
+            return Err(Error::InvalidUtpPacket("packet too short".into()));
+        }
+        if bytes[0] & 0x0f != UTP_VERSION {
+            return Err(Error::InvalidUtpPacket(format!(
+                "unsupported version {}",
+                bytes[0] & 0x0f
+            )));
+        }
+        let packet_type = PacketType::from_u8(bytes[0] >> 4).ok_or_else(|| {
+            Error::InvalidUtpPacket(format!("unknown packet type {}", bytes[0] >> 4))
+        })?;
+        let u16_at = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+
+        let mut extension = bytes[1];
+        let mut offset = UTP_HEADER_SIZE;
+        let mut selective_ack = None;
+        let mut padding = 0;
+        while extension != EXTENSION_NONE {
+            let header = bytes
+                .get(offset..offset + 2)
+                .ok_or_else(|| Error::InvalidUtpPacket("truncated extension".into()))?;
+            let (next, len) = (header[0], header[1] as usize);
+            let data = bytes
+                .get(offset + 2..offset + 2 + len)
+                .ok_or_else(|| Error::InvalidUtpPacket("truncated extension".into()))?;
+            if extension == EXTENSION_SELECTIVE_ACK {
+                if len < 4 || !len.is_multiple_of(4) {
+                    return Err(Error::InvalidUtpPacket(format!(
+                        "selective ack of {len} bytes"
+                    )));
+                }
+                selective_ack = Some(data.to_vec());
+            } else if extension == EXTENSION_PADDING {
+                padding += len;
+            }
+            extension = next;
+            offset += 2 + len;
+        }
+
+        Ok(Self {
+            packet_type,
+            connection_id: u16_at(2),
+            timestamp: u32_at(4),
+            timestamp_diff: u32_at(8),
+            wnd_size: u32_at(12),
+            seq_nr: u16_at(16),
+            ack_nr: u16_at(18),
+            selective_ack,
+            padding,
+            payload: bytes[offset..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        bytes.push(self.packet_type.as_u8() << 4 | UTP_VERSION);
+        let padding_chunks = self.padding.div_ceil(MAX_EXTENSION_LEN);
+        let padding_extension = match padding_chunks {
+            0 => EXTENSION_NONE,
+            _ => EXTENSION_PADDING,
+        };
+        bytes.push(match self.selective_ack {
+            Some(_) => EXTENSION_SELECTIVE_ACK,
+            None => padding_extension,
+        });
+        bytes.extend(self.connection_id.to_be_bytes());
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.extend(self.timestamp_diff.to_be_bytes());
+        bytes.extend(self.wnd_size.to_be_bytes());
+        bytes.extend(self.seq_nr.to_be_bytes());
+        bytes.extend(self.ack_nr.to_be_bytes());
+        if let Some(selective_ack) = self.selective_ack.as_ref() {
+            bytes.push(padding_extension);
+            bytes.push(selective_ack.len() as u8);
+            bytes.extend(selective_ack);
+        }
+        let mut padding = self.padding;
+        for chunk in (0..padding_chunks).rev() {
+            let len = padding.min(MAX_EXTENSION_LEN);
+            padding -= len;
+            bytes.push(match chunk {
+                0 => EXTENSION_NONE,
+                _ => EXTENSION_PADDING,
+            });
+            bytes.push(len as u8);
+            bytes.resize(bytes.len() + len, 0);
+        }
+        bytes.extend(&self.payload);
+        bytes
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        UTP_HEADER_SIZE
+            + self.selective_ack.as_ref().map_or(0, |sack| 2 + sack.len())
+            + 2 * self.padding.div_ceil(MAX_EXTENSION_LEN)
+            + self.padding
+            + self.payload.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    /// Sequence numbers covered by the selective ack.
+    pub fn selective_acks(&self) -> impl Iterator<Item = u16> + '_ {
+        let ack_nr = self.ack_nr;
+        self.selective_ack
+            .iter()
+            .flatten()
+            .enumerate()
+            .flat_map(move |(i, byte)| {
+                (0..8)
+                    .filter(move |bit| byte & (1 << bit) != 0)
+                    .map(move |bit| ack_nr.wrapping_add(2 + (i * 8 + bit) as u16))
+            })
+    }
+}
+
+/// `a` comes before `b` in the wrapping sequence space.
+#[inline]
+pub(crate) fn seq_lt(a: u16, b: u16) -> bool {
+    a != b && b.wrapping_sub(a) < 0x8000
+}
+
+#[inline]
+pub(crate) fn seq_le(a: u16, b: u16) -> bool {
+    a == b || seq_lt(a, b)
+}
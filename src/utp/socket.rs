@@ -0,0 +1,220 @@
+use crate::{
+    error::Result,
+    utp::{
+        connection::Connection,
+        packet::{Packet, PacketType},
+        stream::{Shared, UtpStream},
+    },
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+    time::Instant,
+};
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket},
+    sync::{mpsc, Mutex as AsyncMutex},
+    time,
+};
+
+/// Accepted connections waiting for `accept`.
+const ACCEPT_BACKLOG: usize = 32;
+/// Packets queued for a connection before they are dropped.
+const CONNECTION_QUEUE: usize = 256;
+
+type Connections = HashMap<(SocketAddr, u16), mpsc::Sender<Packet>>;
+
+/// uTP connections multiplexed on a UDP socket, keyed by address and receive id.
+#[derive(Debug)]
+pub struct UtpSocket {
+    socket: Arc<UdpSocket>,
+    connections: Mutex<Connections>,
+    incoming_tx: mpsc::Sender<(UtpStream, SocketAddr)>,
+    incoming_rx: AsyncMutex<mpsc::Receiver<(UtpStream, SocketAddr)>>,
+}
+
+impl UtpSocket {
+    /// The owner of `socket` passes uTP datagrams to `dispatch`.
+    pub fn new(socket: Arc<UdpSocket>) -> Arc<Self> {
+        let (incoming_tx, incoming_rx) = mpsc::channel(ACCEPT_BACKLOG);
+        Arc::new(Self {
+            socket,
+            connections: Mutex::new(Connections::new()),
+            incoming_tx,
+            incoming_rx: AsyncMutex::new(incoming_rx),
+        })
+    }
+
+    /// Socket with its own receive loop.
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<Arc<Self>> {
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let utp = Self::new(socket.clone());
+        let weak = Arc::downgrade(&utp);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok((n, addr)) = socket.recv_from(&mut buf).await {
+                let Some(utp) = weak.upgrade() else {
+                    break;
+                };
+                utp.dispatch(addr, &buf[..n]).await;
+            }
+        });
+        Ok(utp)
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    #[inline]
+    fn connections(&self) -> MutexGuard<'_, Connections> {
+        self.connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Number of connections still driven, closed ones linger for a moment.
+    pub fn num_connections(&self) -> usize {
+        self.connections().len()
+    }
+
+    pub async fn connect(self: &Arc<Self>, addr: SocketAddr) -> Result<UtpStream> {
+        let (tx, rx) = mpsc::channel(CONNECTION_QUEUE);
+        let recv_id = {
+            let mut connections = self.connections();
+            let recv_id = loop {
+                let recv_id = rand::random::<u16>();
+                if !connections.contains_key(&(addr, recv_id)) {
+                    break recv_id;
+                }
+            };
+            connections.insert((addr, recv_id), tx);
+            recv_id
+        };
+        let shared = Arc::new(Shared::new(Connection::connect(recv_id, Instant::now())));
+        self.spawn_driver(shared.clone(), addr, recv_id, rx);
+        let stream = UtpStream::new(shared, addr);
+        stream.connected().await?;
+        Ok(stream)
+    }
+
+    pub async fn accept(&self) -> Result<(UtpStream, SocketAddr)> {
+        self.incoming_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::BrokenPipe).into())
+    }
+
+    /// Routes a datagram to its connection, `false` if it isn't uTP.
+    pub async fn dispatch(self: &Arc<Self>, addr: SocketAddr, bytes: &[u8]) -> bool {
+        let Ok(packet) = Packet::from_bytes(bytes) else {
+            return false;
+        };
+        let id = packet.connection_id;
+        let candidates = match packet.packet_type {
+            // the acceptor receives on the SYN's id plus one
+            PacketType::Syn => vec![id.wrapping_add(1)],
+            // resets carry the id of either side
+            PacketType::Reset => vec![id, id.wrapping_add(1), id.wrapping_sub(1)],
+            _ => vec![id],
+        };
+        let sender = {
+            let connections = self.connections();
+            candidates
+                .iter()
+                .find_map(|id| connections.get(&(addr, *id)).cloned())
+        };
+        if let Some(sender) = sender {
+            let _ = sender.try_send(packet);
+            return true;
+        }
+        match packet.packet_type {
+            PacketType::Syn => self.accept_syn(addr, packet),
+            PacketType::Reset => {}
+            _ => {
+                let reset = Packet::new(PacketType::Reset, id, rand::random(), packet.seq_nr);
+                let _ = self.socket.send_to(&reset.to_bytes(), addr).await;
+            }
+        }
+        true
+    }
+
+    fn accept_syn(self: &Arc<Self>, addr: SocketAddr, syn: Packet) {
+        let conn = Connection::accept(&syn, rand::random(), Instant::now());
+        let recv_id = conn.recv_id();
+        let shared = Arc::new(Shared::new(conn));
+        let stream = UtpStream::new(shared.clone(), addr);
+        // a full backlog drops the SYN, the peer retries
+        if self.incoming_tx.try_send((stream, addr)).is_err() {
+            return;
+        }
+        let (tx, rx) = mpsc::channel(CONNECTION_QUEUE);
+        self.connections().insert((addr, recv_id), tx);
+        self.spawn_driver(shared, addr, recv_id, rx);
+    }
+
+    fn spawn_driver(
+        self: &Arc<Self>,
+        shared: Arc<Shared>,
+        addr: SocketAddr,
+        recv_id: u16,
+        rx: mpsc::Receiver<Packet>,
+    ) {
+        let socket = self.socket.clone();
+        let utp = Arc::downgrade(self);
+        tokio::spawn(drive(shared, socket, addr, recv_id, rx, utp));
+    }
+}
+
+/// Feeds packets and timers to the connection and sends what it produces.
+async fn drive(
+    shared: Arc<Shared>,
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    recv_id: u16,
+    mut rx: mpsc::Receiver<Packet>,
+    utp: Weak<UtpSocket>,
+) {
+    loop {
+        let (packets, deadline, done) = {
+            let mut conn = shared.conn();
+            let now = Instant::now();
+            let packets = conn.poll_transmit(now);
+            (packets, conn.next_timeout(), conn.is_done(now))
+        };
+        for packet in packets {
+            let _ = socket.send_to(&packet.to_bytes(), addr).await;
+        }
+        if done {
+            break;
+        }
+        let sleep = async {
+            match deadline {
+                Some(deadline) => time::sleep_until(deadline.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            packet = rx.recv() => {
+                let Some(packet) = packet else {
+                    break;
+                };
+                let mut conn = shared.conn();
+                let now = Instant::now();
+                conn.on_packet(packet, now);
+                while let Ok(packet) = rx.try_recv() {
+                    conn.on_packet(packet, now);
+                }
+            }
+            _ = shared.notify.notified() => {}
+            _ = sleep => {}
+        }
+    }
+    if let Some(utp) = utp.upgrade() {
+        utp.connections().remove(&(addr, recv_id));
+    }
+}
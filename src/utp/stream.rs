@@ -0,0 +1,106 @@
+use crate::utp::connection::{Connection, ConnectionState};
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Notify,
+};
+
+/// Connection state shared by a stream and the task driving it.
+#[derive(Debug)]
+pub(crate) struct Shared {
+    conn: Mutex<Connection>,
+    /// Wakes the driver after a write, a read or a shutdown.
+    pub(crate) notify: Notify,
+}
+
+impl Shared {
+    pub fn new(conn: Connection) -> Self {
+        Self {
+            conn: Mutex::new(conn),
+            notify: Notify::new(),
+        }
+    }
+
+    #[inline]
+    pub fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Reliable ordered byte stream over uTP.
+#[derive(Debug)]
+pub struct UtpStream {
+    shared: Arc<Shared>,
+    peer_addr: SocketAddr,
+}
+
+impl UtpStream {
+    pub(crate) fn new(shared: Arc<Shared>, peer_addr: SocketAddr) -> Self {
+        Self { shared, peer_addr }
+    }
+
+    #[inline]
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    #[inline]
+    pub fn state(&self) -> ConnectionState {
+        self.shared.conn().state()
+    }
+
+    pub(crate) async fn connected(&self) -> io::Result<()> {
+        std::future::poll_fn(|cx| self.shared.conn().poll_connected(cx)).await
+    }
+}
+
+impl AsyncRead for UtpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let (res, window_update) = self.shared.conn().poll_read(cx, buf);
+        if window_update {
+            self.shared.notify.notify_one();
+        }
+        res
+    }
+}
+
+impl AsyncWrite for UtpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = self.shared.conn().poll_write(cx, buf);
+        if res.is_ready() {
+            self.shared.notify.notify_one();
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let res = self.shared.conn().poll_shutdown(cx);
+        self.shared.notify.notify_one();
+        res
+    }
+}
+
+impl Drop for UtpStream {
+    fn drop(&mut self) {
+        self.shared.conn().close();
+        self.shared.notify.notify_one();
+    }
+}
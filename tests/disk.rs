@@ -1,3 +1,18 @@
+use rutor::{
+    disk::{
+        self,
+        layout::{FileSlice, Layout},
+        Disk,
+    },
+    error::Error,
+    proto::metainfo::{
+        file::{FileAttributes, FileEntry},
+        Info, MetaInfo,
+    },
+    torrent::FilePriority,
+};
+use std::path::PathBuf;
+
 // use rutor::{disk, torrent};
 
 // #[test]
@@ -27,3 +42,189 @@
 
 //     println!("{:#?}", dl);
 // }
+
+#[tokio::test]
+async fn test_remove_torrent_files() {
+    let bytes = std::fs::read("resources/Books.torrent").unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let save_path = std::env::temp_dir().join("rutor_test_remove_torrent_files");
+    let files = metainfo.info.files();
+    assert!(files.len() > 1);
+
+    for file in files.iter() {
+        let path = save_path.join(&file.path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"data").unwrap();
+    }
+    let foreign = save_path.join("foreign.txt");
+    std::fs::write(&foreign, b"keep").unwrap();
+
+    disk::remove_torrent_files(&save_path, &files)
+        .await
+        .unwrap();
+
+    assert!(!save_path.join(&metainfo.info.name).exists());
+    assert!(foreign.exists());
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_remove_torrent_files_outside_save_path() {
+    let root = std::env::temp_dir().join("rutor_test_remove_outside");
+    let save_path = root.join("downloads");
+    std::fs::create_dir_all(&save_path).unwrap();
+    let outside = root.join("outside.txt");
+    std::fs::write(&outside, b"keep").unwrap();
+    let inside = save_path.join("inside.txt");
+    std::fs::write(&inside, b"data").unwrap();
+
+    for path in ["../outside.txt", "/etc/passwd"] {
+        let files = [
+            FileEntry {
+                path: PathBuf::from("inside.txt"),
+                ..Default::default()
+            },
+            FileEntry {
+                path: PathBuf::from(path),
+                ..Default::default()
+            },
+        ];
+        let result = disk::remove_torrent_files(&save_path, &files).await;
+        assert!(matches!(result, Err(Error::UnsafePath(_))));
+    }
+    assert!(outside.exists());
+    assert!(inside.exists());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+fn test_layout() -> Layout {
+    let files = [("a", 10), ("dir/b", 5), ("dir/c", 10)]
+        .into_iter()
+        .scan(0, |offset, (path, length)| {
+            let entry = FileEntry {
+                path: PathBuf::from(path),
+                length,
+                offset: *offset,
+                ..Default::default()
+            };
+            *offset += length;
+            Some(entry)
+        })
+        .collect();
+    Layout::new(8, files)
+}
+
+#[test]
+fn test_layout_slices() {
+    let layout = test_layout();
+    assert_eq!(layout.num_pieces, 4);
+    assert_eq!(layout.piece_size(3), 1);
+    assert_eq!(
+        layout.piece_slices(1),
+        vec![
+            FileSlice {
+                file_index: 0,
+                file_offset: 8,
+                piece_offset: 0,
+                length: 2,
+            },
+            FileSlice {
+                file_index: 1,
+                file_offset: 0,
+                piece_offset: 2,
+                length: 5,
+            },
+            FileSlice {
+                file_index: 2,
+                file_offset: 0,
+                piece_offset: 7,
+                length: 1,
+            },
+        ]
+    );
+    assert_eq!(layout.file_pieces(1), 1..2);
+    assert_eq!(layout.file_pieces(2), 1..4);
+}
+
+#[tokio::test]
+async fn test_disk_skipped_files() {
+    let save_path = std::env::temp_dir().join("rutor_test_disk_skipped_files");
+    let _ = std::fs::remove_dir_all(&save_path);
+
+    let mut disk = Disk::new(save_path.clone(), test_layout(), "test");
+    let priorities = [FilePriority::Normal, FilePriority::Skip, FilePriority::High];
+    disk.set_file_priorities(&priorities).await.unwrap();
+
+    let data: Vec<u8> = (0..25).collect();
+    for (index, piece) in data.chunks(8).enumerate() {
+        disk.write(index, 0, piece).await.unwrap();
+    }
+    let partfile = save_path.join(".test.parts");
+    assert!(!save_path.join("dir/b").exists());
+    assert!(partfile.exists());
+    assert_eq!(disk.read(1, 0, 8).await.unwrap(), data[8..16]);
+
+    disk.set_file_priorities(&[FilePriority::Low; 3])
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(save_path.join("dir/b")).unwrap(),
+        data[10..15]
+    );
+    assert_eq!(std::fs::read(save_path.join("dir/c")).unwrap(), data[15..]);
+    assert!(!partfile.exists());
+
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_disk_file_attributes() {
+    let info = b"d5:filesl\
+d4:attr1:x6:lengthi10e4:pathl4:a.shee\
+d4:attr1:p6:lengthi6e4:pathl4:.pad1:6ee\
+d6:lengthi10e4:pathl1:bee\
+d4:attr1:l6:lengthi0e4:pathl4:linke12:symlink pathl4:a.shee\
+d4:attr2:lh6:lengthi0e4:pathl4:evile12:symlink pathl2:..3:etceee\
+4:name1:t12:piece lengthi16e6:pieces0:e";
+    let info = Info::from_bytes(info).unwrap();
+    let files = info.files();
+    assert_eq!(files[0].attr, FileAttributes::parse("x"));
+    assert!(files[1].is_padding());
+    assert_eq!(files[2].offset, 16);
+    assert_eq!(files[3].symlink_path, Some(PathBuf::from("t/a.sh")));
+    assert!(files[4].attr.symlink && files[4].attr.hidden);
+    assert_eq!(files[4].symlink_path, None);
+
+    let save_path = std::env::temp_dir().join("rutor_test_disk_file_attributes");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let mut disk = Disk::new(save_path.clone(), Layout::from_torrent_info(&info), "attrs");
+    let mut data = vec![1u8; 10];
+    data.extend([0; 6]);
+    data.extend([2; 10]);
+    disk.write(0, 0, &data[..16]).await.unwrap();
+    disk.write(1, 0, &data[16..]).await.unwrap();
+
+    assert!(!save_path.join("t/.pad").exists());
+    assert_eq!(disk.read(0, 0, 16).await.unwrap(), data[..16]);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(save_path.join("t/a.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o111, 0o111);
+    }
+
+    disk.create_symlinks().await.unwrap();
+    assert_eq!(
+        std::fs::read_link(save_path.join("t/link")).unwrap(),
+        PathBuf::from("../t/a.sh")
+    );
+    assert_eq!(
+        std::fs::read(save_path.join("t/link")).unwrap(),
+        vec![1; 10]
+    );
+    assert!(std::fs::symlink_metadata(save_path.join("t/evil")).is_err());
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
@@ -0,0 +1,131 @@
+use rutor::peers::{mse_accept, mse_connect, mse_hash, DhKeyPair, EncryptionPolicy, IpFilter, Rc4};
+use rutor::proto::constants::{MSE_CRYPTO_PLAINTEXT, MSE_CRYPTO_RC4};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn test_mse_crypto() {
+    let (a, b) = (DhKeyPair::generate(), DhKeyPair::generate());
+    let secret = a.shared_secret(b.public_key()).unwrap();
+    assert_eq!(secret, b.shared_secret(a.public_key()).unwrap());
+    assert!(a.shared_secret(&[0; 96]).is_err());
+    let mut one = [0u8; 96];
+    one[95] = 1;
+    assert!(a.shared_secret(&one).is_err());
+
+    let key = mse_hash(&[b"keyA", &secret, &[7; 20]]);
+    let mut data = b"Message Stream Encryption".to_vec();
+    Rc4::new(&key).apply(&mut data);
+    assert_ne!(data, b"Message Stream Encryption");
+    Rc4::new(&key).apply(&mut data);
+    assert_eq!(data, b"Message Stream Encryption");
+
+    let rc4_or_plaintext = MSE_CRYPTO_RC4 | MSE_CRYPTO_PLAINTEXT;
+    let preferred = EncryptionPolicy::Preferred;
+    assert_eq!(
+        preferred.crypto_select(rc4_or_plaintext),
+        Some(MSE_CRYPTO_RC4)
+    );
+    assert_eq!(
+        preferred.crypto_select(MSE_CRYPTO_PLAINTEXT),
+        Some(MSE_CRYPTO_PLAINTEXT)
+    );
+    assert_eq!(
+        EncryptionPolicy::Forced.crypto_select(MSE_CRYPTO_PLAINTEXT),
+        None
+    );
+    assert_eq!(EncryptionPolicy::Disabled.crypto_provide(), None);
+}
+
+#[tokio::test]
+async fn test_mse_handshake() {
+    let skeys = [[1u8; 20], [2u8; 20]];
+    let (client, server) = tokio::io::duplex(4096);
+    let accept = tokio::spawn(async move {
+        let (mut stream, skey) = mse_accept(server, Vec::new(), &skeys, EncryptionPolicy::Forced)
+            .await
+            .unwrap();
+        let mut buf = vec![0u8; 5000];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+        stream.flush().await.unwrap();
+        (skey, stream.is_encrypted())
+    });
+
+    let mut stream = mse_connect(client, &[2; 20], EncryptionPolicy::Preferred)
+        .await
+        .unwrap();
+    assert!(stream.is_encrypted());
+    let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    stream.write_all(&data).await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echo = vec![0u8; 5000];
+    stream.read_exact(&mut echo).await.unwrap();
+    assert_eq!(echo, data);
+    assert_eq!(accept.await.unwrap(), ([2; 20], true));
+
+    // the responder only knows its own torrents
+    let (client, server) = tokio::io::duplex(4096);
+    let accept = tokio::spawn(async move {
+        mse_accept(server, Vec::new(), &[[1; 20]], EncryptionPolicy::Preferred)
+            .await
+            .map(|_| ())
+    });
+    let connect = mse_connect(client, &[3; 20], EncryptionPolicy::Preferred);
+    let (accepted, connected) = tokio::join!(accept, connect);
+    assert!(accepted.unwrap().is_err());
+    assert!(connected.is_err());
+}
+
+#[test]
+fn test_ip_filter() {
+    let filter = IpFilter::parse(
+        "# eMule\n\
+001.002.003.000 - 001.002.003.255 , 000 , Some org\n\
+001.002.004.000 - 001.002.004.127 , 100 , Adjacent\n\
+005.000.000.000 - 005.255.255.255 , 200 , Allowed level\n\
+// PeerGuardian\n\
+Some: org:10.0.0.0-10.0.0.255\n\
+Overlap:10.0.0.128-10.0.1.10\n\
+Foo, Inc:20.0.0.0-20.0.0.255\n\
+2001:db8:: - 2001:db8::ffff , 000 , v6\n\
+garbage line\n\
+Reversed:9.9.9.9-9.9.9.1\n",
+    );
+    assert_eq!(filter.num_ranges(), 5);
+    let blocked = |ip: &str| filter.is_blocked(&ip.parse().unwrap());
+    for ip in [
+        "1.2.3.0",
+        "1.2.4.127",
+        "10.0.0.0",
+        "10.0.1.10",
+        "9.9.9.5",
+        "20.0.0.7",
+    ] {
+        assert!(blocked(ip), "{ip}");
+    }
+    for ip in [
+        "1.2.2.255",
+        "1.2.4.128",
+        "5.1.1.1",
+        "10.0.1.11",
+        "9.9.9.10",
+        "20.0.1.0",
+    ] {
+        assert!(!blocked(ip), "{ip}");
+    }
+    assert!(blocked("2001:db8::42"));
+    assert!(!blocked("2001:db8::1:0"));
+    assert!(blocked("::ffff:10.0.0.1"));
+
+    let mut filter = IpFilter::new();
+    assert!(filter.is_empty());
+    filter.add_range(
+        "0.0.0.0".parse().unwrap(),
+        "255.255.255.255".parse().unwrap(),
+    );
+    filter.add_range("1.1.1.1".parse().unwrap(), "1.1.1.1".parse().unwrap());
+    filter.add_range("1.1.1.1".parse().unwrap(), "::1".parse().unwrap());
+    assert_eq!(filter.num_ranges(), 1);
+    assert!(filter.is_blocked(&"255.255.255.255".parse().unwrap()));
+    assert!(!filter.is_blocked(&"::1".parse().unwrap()));
+}
@@ -1,4 +1,10 @@
+use rutor::disk::layout::Layout;
 use rutor::proto;
+use rutor::proto::infohash::{InfoHash, InfoHashT};
+use rutor::proto::metainfo::{merkle, MetaInfo, TorrentBuilder, TorrentVersion};
+use rutor::torrent::HashTrees;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
 
 #[test]
 fn test_bitfield() {
@@ -10,6 +16,283 @@ fn test_bitfield() {
     println!("not has bitfield: {:?}", bitfield.has(2));
 }
 
+#[test]
+fn test_hash_messages() {
+    let request = proto::HashRequest::new([3; 32], 2, 4, 4, 1);
+    let hashes = proto::Hashes::new(request.clone(), vec![[1; 32], [2; 32], [5; 32]]);
+    assert_eq!(hashes.base_hashes().len(), 3);
+    for message in [
+        proto::Message::HashRequest(request.clone()),
+        proto::Message::Hashes(hashes),
+        proto::Message::HashReject(request),
+    ] {
+        let bytes = message.to_bytes();
+        assert_eq!(bytes.len(), message.len());
+        assert_eq!(proto::Message::from_bytes(&bytes), message);
+    }
+}
+
+#[test]
+fn test_hybrid_magnet() {
+    let v1 = "c9e15763f722f23e98a29decdfae341b98d53056";
+    let v2 = "1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e";
+    let magnet: proto::MagnetLink = format!("magnet:?xt=urn:btih:{v1}&xt=urn:btmh:{}", &v2[4..])
+        .parse()
+        .unwrap();
+    assert!(magnet.info_hash.is_hybrid());
+    assert_eq!(magnet.info_hash.inner().hex(), v1);
+    assert!(magnet.info_hash.matches_hex(&v2[4..]));
+}
+
+#[test]
+fn test_magnet_round_trip() {
+    let v1 = "c9e15763f722f23e98a29decdfae341b98d53056";
+    let v2 = "1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e";
+    let uri = format!(
+        "magnet:?xt=urn:btih:{v1}&xt=urn:btmh:{v2}&dn=my%20file&xl=1234\
+&tr=udp%3A%2F%2Ftracker.example%3A80&ws=http%3A%2F%2Fseed.example%2Ff\
+&x.pe=10.0.0.1%3A6881&so=0,2,4-6"
+    );
+    let magnet: proto::MagnetLink = uri.parse().unwrap();
+    assert!(magnet.info_hash.is_hybrid());
+    assert_eq!(magnet.display_name.as_deref(), Some("my file"));
+    assert_eq!(magnet.exact_length, Some(1234));
+    assert_eq!(magnet.web_seeds, ["http://seed.example/f"]);
+    assert_eq!(magnet.select_only, [0..=0, 2..=2, 4..=6]);
+    assert_eq!(magnet.to_uri(), uri);
+    assert_eq!(
+        magnet.to_uri().parse::<proto::MagnetLink>().unwrap(),
+        magnet
+    );
+
+    let bytes = std::fs::read("resources/ubuntu-25.04-desktop-amd64.iso.torrent").unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let magnet = proto::MagnetLink::from_metainfo(&metainfo);
+    assert_eq!(&magnet.info_hash, metainfo.info_hash());
+    assert_eq!(magnet.exact_length, Some(metainfo.info.total_length()));
+    assert!(!magnet.trackers.is_empty());
+    let parsed: proto::MagnetLink = magnet.to_uri().parse().unwrap();
+    assert_eq!(parsed, magnet);
+}
+
+#[test]
+fn test_lsd_announce() {
+    let announce = proto::lsd::LsdAnnounce::from_bytes(
+        b"BT-SEARCH * HTTP/1.1\r\nhost: 239.192.152.143:6771\r\nPORT: 6881\r\n\
+infohash: 0101010101010101010101010101010101010101\r\nInfohash: bad\r\ncookie: abc\r\n\r\n\r\n",
+    )
+    .unwrap();
+    assert_eq!(announce.port, 6881);
+    assert_eq!(announce.info_hashes, [[1; 20]]);
+    assert_eq!(announce.cookie.as_deref(), Some("abc"));
+    let bytes = announce.to_bytes();
+    assert_eq!(
+        proto::lsd::LsdAnnounce::from_bytes(&bytes).unwrap(),
+        announce
+    );
+    assert!(proto::lsd::LsdAnnounce::from_bytes(b"GET / HTTP/1.1\r\n\r\n").is_err());
+
+    let announces = proto::lsd::LsdAnnounce::split("h", 1, &[[2; 20]; 100], None);
+    assert!(announces.len() > 1);
+    assert!(announces.iter().all(|a| a.to_bytes().len() <= 1400));
+    assert_eq!(
+        announces.iter().map(|a| a.info_hashes.len()).sum::<usize>(),
+        100
+    );
+}
+
+#[test]
+fn test_natpmp_pcp_messages() {
+    use proto::natpmp::{MappingProtocol, NatPmpRequest, NatPmpResponse};
+    use proto::pcp::{PcpMapRequest, PcpMapResponse};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let request = NatPmpRequest::Map {
+        protocol: MappingProtocol::Tcp,
+        internal_port: 6881,
+        external_port: 6881,
+        lifetime: 7200,
+    };
+    let bytes = request.to_bytes();
+    assert_eq!(bytes[..4], [0, 2, 0, 0]);
+    assert_eq!(NatPmpRequest::from_bytes(&bytes).unwrap(), request);
+    assert_eq!(NatPmpRequest::ExternalAddress.to_bytes(), [0, 0]);
+
+    let response = NatPmpResponse::ExternalAddress {
+        epoch: 5,
+        addr: Ipv4Addr::new(203, 0, 113, 7),
+    };
+    assert_eq!(
+        NatPmpResponse::from_bytes(&response.to_bytes()).unwrap(),
+        response
+    );
+    let response = NatPmpResponse::Map {
+        protocol: MappingProtocol::Udp,
+        epoch: 5,
+        internal_port: 6881,
+        external_port: 6882,
+        lifetime: 3600,
+    };
+    let bytes = response.to_bytes();
+    assert_eq!(bytes[1], 129);
+    assert_eq!(NatPmpResponse::from_bytes(&bytes).unwrap(), response);
+    // result code 3, network failure
+    assert!(NatPmpResponse::from_bytes(&[0, 129, 0, 3, 0, 0, 0, 5]).is_err());
+
+    let request = PcpMapRequest {
+        lifetime: 7200,
+        client_ip: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+        nonce: [7; 12],
+        protocol: MappingProtocol::Udp,
+        internal_port: 6881,
+        external_port: 6881,
+        external_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    let bytes = request.to_bytes();
+    assert_eq!(bytes.len(), proto::pcp::PCP_MAP_LEN);
+    assert_eq!(bytes[..2], [2, 1]);
+    // IPv4 addresses are IPv4-mapped
+    assert_eq!(bytes[18..24], [0xff, 0xff, 192, 168, 1, 2]);
+    assert_eq!(PcpMapRequest::from_bytes(&bytes).unwrap(), request);
+
+    let response = PcpMapResponse {
+        lifetime: 3600,
+        epoch: 9,
+        nonce: [7; 12],
+        protocol: MappingProtocol::Udp,
+        internal_port: 6881,
+        external_port: 40000,
+        external_ip: IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)),
+    };
+    let mut bytes = response.to_bytes();
+    assert_eq!(PcpMapResponse::from_bytes(&bytes).unwrap(), response);
+    // NO_RESOURCES
+    bytes[3] = 8;
+    assert!(PcpMapResponse::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn test_upnp_messages() {
+    use proto::upnp::{soap_error_code, ssdp_location, ssdp_search, xml_value, IgdService};
+    use reqwest::Url;
+
+    let search = String::from_utf8(ssdp_search("239.255.255.250:1900")).unwrap();
+    assert!(search.starts_with("M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n"));
+    assert!(search.contains("ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n"));
+    assert_eq!(
+        ssdp_location(b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nLocation: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n"),
+        Some("http://192.168.1.1:5000/rootDesc.xml".to_string())
+    );
+    assert_eq!(
+        ssdp_location(b"NOTIFY * HTTP/1.1\r\nLOCATION: x\r\n\r\n"),
+        None
+    );
+
+    let description = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+<device><serviceList>
+<service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+<controlURL>/ctl/L3F</controlURL></service>
+<service><serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+<controlURL>/ctl/PPP</controlURL></service>
+<service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+<controlURL>/ctl/IPConn</controlURL></service>
+</serviceList></device></root>"#;
+    let location = Url::parse("http://192.168.1.1:5000/rootDesc.xml").unwrap();
+    let service = IgdService::from_description(description, &location).unwrap();
+    assert_eq!(
+        service.service_type,
+        "urn:schemas-upnp-org:service:WANIPConnection:1"
+    );
+    assert_eq!(
+        service.control_url.as_str(),
+        "http://192.168.1.1:5000/ctl/IPConn"
+    );
+    assert!(IgdService::from_description("<root></root>", &location).is_err());
+
+    let body = service.soap_body("DeletePortMapping", &[("NewExternalPort", "6881".into())]);
+    assert!(body.contains(
+        "<u:DeletePortMapping xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">"
+    ));
+    assert!(body.contains("<NewExternalPort>6881</NewExternalPort>"));
+    assert_eq!(
+        service.soap_action("DeletePortMapping"),
+        "\"urn:schemas-upnp-org:service:WANIPConnection:1#DeletePortMapping\""
+    );
+
+    let response = "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+        <NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>\
+        </u:GetExternalIPAddressResponse></s:Body></s:Envelope>";
+    assert_eq!(
+        xml_value(response, "NewExternalIPAddress"),
+        Some("203.0.113.7")
+    );
+    let fault = "<s:Fault><detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\">\
+        <errorCode>725</errorCode></UPnPError></detail></s:Fault>";
+    assert_eq!(soap_error_code(fault), Some(725));
+}
+
+#[test]
+fn test_ipv6_peers() {
+    use rutor::peers::{compact_peer_bytes, parse_compact_peers};
+    use std::net::SocketAddr;
+
+    let v4: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+    let v6: SocketAddr = "[2001:db8::1]:51413".parse().unwrap();
+    assert_eq!(compact_peer_bytes(&v6).len(), 18);
+    let mut peers6 = compact_peer_bytes(&v6);
+    peers6.extend(compact_peer_bytes(&v6));
+    peers6.push(0);
+    assert_eq!(parse_compact_peers(&peers6, true), [v6, v6]);
+    assert_eq!(parse_compact_peers(&compact_peer_bytes(&v4), false), [v4]);
+
+    let mut bytes = b"d8:intervali900e5:peers6:".to_vec();
+    bytes.extend(compact_peer_bytes(&v4));
+    bytes.extend(b"6:peers618:");
+    bytes.extend(compact_peer_bytes(&v6));
+    bytes.push(b'e');
+    let response = proto::announce::HttpAnnounceResponse::from_bytes(&bytes).unwrap();
+    assert_eq!(response.peers6(), [v6]);
+    assert_eq!(response.peer_addrs(), [v4, v6]);
+
+    let mut bytes = vec![0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 7, 8, 0, 0, 0, 1, 0, 0, 0, 2];
+    bytes.extend(compact_peer_bytes(&v6));
+    let response = proto::bep15::Bep15AnnounceResponse::from_bytes(&bytes, true).unwrap();
+    assert_eq!(response.transaction_id, 7);
+    assert_eq!(response.interval, 1800);
+    assert_eq!(response.peers, [v6]);
+    // the same bytes from an IPv4 tracker are three 6 byte entries
+    let response = proto::bep15::Bep15AnnounceResponse::from_bytes(&bytes, false).unwrap();
+    assert_eq!(response.peers.len(), 3);
+
+    let handshake = proto::bep10::ExtendedHandshake {
+        m: None,
+        v: None,
+        metadata_size: None,
+        reqq: None,
+        yourip: None,
+        ipv4: Some(vec![10, 0, 0, 1]),
+        ipv6: Some(compact_peer_bytes(&v6)[..16].to_vec()),
+        p: Some(6881),
+    };
+    let from_v6: SocketAddr = "[2001:db8::1]:40000".parse().unwrap();
+    let from_v4: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+    assert_eq!(handshake.alternate_addr(from_v6), Some(v4));
+    assert_eq!(
+        handshake.alternate_addr(from_v4),
+        Some("[2001:db8::1]:6881".parse().unwrap())
+    );
+
+    let message = proto::Message::Extension(proto::bep10::ExtensionMessage::Handshake(handshake));
+    assert_eq!(proto::Message::from_bytes(&message.to_bytes()), message);
+    let local = proto::bep10::ExtendedHandshake::local(6881, from_v4.ip(), Some(v6.ip()));
+    assert_eq!(local.yourip, Some(vec![10, 0, 0, 1]));
+    assert_eq!(
+        local.alternate_addr(from_v4),
+        Some(SocketAddr::new(v6.ip(), 6881))
+    );
+}
+
 #[test]
 fn test_peerid() {
     let peer_id = proto::PeerId::gen_new();
@@ -19,3 +302,377 @@ fn test_peerid() {
         String::from_utf8_lossy(peer_id.extract_fingerprint().as_slice())
     );
 }
+
+fn builder_fixture(name: &str) -> (PathBuf, Vec<(&'static str, Vec<u8>)>) {
+    let root = std::env::temp_dir().join(name).join("release");
+    let _ = std::fs::remove_dir_all(&root);
+    let files = vec![
+        (
+            "a.txt",
+            (0..40_000).map(|i| (i % 253) as u8).collect::<Vec<u8>>(),
+        ),
+        ("sub/b.bin", (0..70_000).map(|i| (i % 127) as u8).collect()),
+        ("sub/empty", Vec::new()),
+    ];
+    for (path, data) in files.iter() {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+    (root, files)
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn sha256_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    sha256(&[a.as_slice(), b.as_slice()].concat())
+}
+
+#[test]
+fn test_torrent_builder_v1() {
+    let (root, files) = builder_fixture("rutor_test_torrent_builder_v1");
+    let bytes = TorrentBuilder::new(&root)
+        .piece_length(32 * 1024)
+        .tracker("http://tracker.example/announce")
+        .tracker_tier(vec!["udp://a.example:80", "udp://b.example:80"])
+        .web_seed("http://seed.example/")
+        .comment("release")
+        .creation_date(Some(1_700_000_000))
+        .private(true)
+        .source("EXAMPLE")
+        .num_threads(3)
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info = &metainfo.info;
+
+    assert_eq!(info.name, "release");
+    assert_eq!(info.meta_version(), 1);
+    assert!(info.is_private());
+    assert_eq!(info.source.as_deref(), Some("EXAMPLE"));
+    assert_eq!(metainfo.comment.as_deref(), Some("release"));
+    assert_eq!(metainfo.creation_date, Some(1_700_000_000));
+    assert_eq!(
+        metainfo.url_list,
+        Some(vec!["http://seed.example/".to_owned()])
+    );
+    assert_eq!(metainfo.announce_list().len(), 2);
+
+    let entries = info.files();
+    let paths: Vec<_> = entries.iter().map(|f| f.path.clone()).collect();
+    assert_eq!(
+        paths,
+        files
+            .iter()
+            .map(|(path, _)| PathBuf::from("release").join(path))
+            .collect::<Vec<_>>()
+    );
+
+    let data: Vec<u8> = files.iter().flat_map(|(_, d)| d.clone()).collect();
+    assert_eq!(info.num_pieces(), data.len().div_ceil(32 * 1024));
+    for (index, piece) in data.chunks(32 * 1024).enumerate() {
+        assert!(info.verify_piece(index, piece));
+    }
+    std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_torrent_builder_hybrid() {
+    let (root, files) = builder_fixture("rutor_test_torrent_builder_hybrid");
+    let bytes = TorrentBuilder::new(&root)
+        .version(TorrentVersion::Hybrid)
+        .piece_length(16 * 1024)
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info = &metainfo.info;
+    assert_eq!(info.meta_version(), 2);
+    assert!(bytes.windows(15).any(|w| w == b"12:piece layers"));
+
+    // both swarms, the handshake may carry either hash
+    let info_bytes = metainfo.info_bytes().unwrap();
+    let InfoHash::Hybrid(v1, v2) = metainfo.info_hash() else {
+        panic!("expected a hybrid info hash");
+    };
+    assert_eq!(v1.as_slice(), sha1::Sha1::digest(info_bytes).as_slice());
+    assert_eq!(v2.as_slice(), sha256(info_bytes).as_slice());
+    assert_eq!(metainfo.info_hash().swarm_hashes().len(), 2);
+    assert!(metainfo.info_hash().matches(v1.truncate()));
+    assert!(metainfo.info_hash().matches(v2.truncate()));
+    assert!(!metainfo.info_hash().matches(&[0; 20]));
+
+    // v1 pieces cover the files padded to piece boundaries
+    let entries = info.files();
+    let pad = 16 * 1024 - 40_000 % (16 * 1024);
+    assert_eq!(
+        entries[1].path,
+        PathBuf::from("release/.pad").join(pad.to_string())
+    );
+    assert_eq!(entries[2].offset % (16 * 1024), 0);
+    let mut data = files[0].1.clone();
+    data.resize(data.len() + pad, 0);
+    data.extend(&files[1].1);
+    let hash_trees = HashTrees::new(
+        info,
+        &Layout::from_torrent_info(info),
+        metainfo.piece_layers.as_ref().unwrap(),
+    )
+    .unwrap();
+    for (index, piece) in data.chunks(16 * 1024).enumerate() {
+        assert!(info.verify_piece(index, piece));
+        assert_eq!(hash_trees.verify_piece(index, piece), Some(true));
+    }
+    std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_v2_pieces_roots_colliding_paths() {
+    // both names sanitize to `a_`, the second file is renamed on disk
+    let mut info = b"d9:file treed".to_vec();
+    for (name, root) in [(b"a\x01", [1u8; 32]), (b"a\x02", [2u8; 32])] {
+        info.extend(b"2:");
+        info.extend(name);
+        info.extend(b"d0:d6:lengthi100e11:pieces root32:");
+        info.extend(root);
+        info.extend(b"ee");
+    }
+    info.extend(b"e12:meta versioni2e4:name4:test12:piece lengthi16384ee");
+    let torrent = [b"d4:info".as_slice(), &info, b"e"].concat();
+    let metainfo = MetaInfo::from_bytes(&torrent).unwrap();
+
+    let files = metainfo.info.files();
+    assert_eq!(files[0].path, PathBuf::from("test/a_"));
+    assert_eq!(files[1].path, PathBuf::from("test/a_.1"));
+    assert_eq!(files[1].torrent_path, vec!["test", "a\u{2}"]);
+    assert_eq!(
+        metainfo.info.pieces_roots(),
+        vec![Some([1; 32]), Some([2; 32])]
+    );
+}
+
+#[test]
+fn test_torrent_builder_v2() {
+    let root = std::env::temp_dir().join("rutor_test_torrent_builder_v2");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let data: Vec<u8> = (0..40_000).map(|i| (i % 199) as u8).collect();
+    let path = root.join("video.mkv");
+    std::fs::write(&path, &data).unwrap();
+
+    let bytes = TorrentBuilder::new(&path)
+        .version(TorrentVersion::V2)
+        .piece_length(16 * 1024)
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    assert_eq!(metainfo.info.total_length(), 40_000);
+    assert_eq!(metainfo.info.files()[0].path, PathBuf::from("video.mkv"));
+
+    // three one-block pieces, the tree is padded to four leaves
+    let layer: Vec<[u8; 32]> = data.chunks(16 * 1024).map(sha256).collect();
+    let root_hash = sha256_pair(
+        &sha256_pair(&layer[0], &layer[1]),
+        &sha256_pair(&layer[2], &[0; 32]),
+    );
+    assert_eq!(merkle::root_from_piece_layer(&layer, 16 * 1024), root_hash);
+    let pieces_root = [b"11:pieces root32:".as_slice(), root_hash.as_slice()].concat();
+    assert!(bytes.windows(pieces_root.len()).any(|w| w == pieces_root));
+    assert!(bytes
+        .windows(layer.concat().len())
+        .any(|w| w == layer.concat()));
+
+    assert!(TorrentBuilder::new(&path)
+        .piece_length(1000)
+        .build()
+        .is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_metainfo_to_bytes() {
+    for path in [
+        "resources/Books.torrent",
+        "resources/Red_Hot_Chili_Peppers.torrent",
+    ] {
+        let bytes = std::fs::read(path).unwrap();
+        let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+        let info_hash = metainfo.info_hash().clone();
+
+        let reparsed = MetaInfo::from_bytes(&metainfo.to_bytes().unwrap()).unwrap();
+        assert_eq!(reparsed.info_hash(), &info_hash);
+        assert_eq!(reparsed.info_bytes(), metainfo.info_bytes());
+        assert_eq!(reparsed.announce_list(), metainfo.announce_list());
+        assert_eq!(reparsed.comment, metainfo.comment);
+
+        let mut edited = metainfo.clone();
+        edited.set_announce_list(vec![vec!["udp://tracker.example:6969".to_owned()]]);
+        edited.comment = Some("edited".to_owned());
+        edited.url_list = Some(vec!["http://seed.example/".to_owned()]);
+        let reparsed = MetaInfo::from_bytes(&edited.to_bytes().unwrap()).unwrap();
+        assert_eq!(reparsed.info_hash(), &info_hash);
+        assert_eq!(reparsed.info_bytes(), metainfo.info_bytes());
+        assert_eq!(
+            reparsed.announce_list(),
+            vec![vec!["udp://tracker.example:6969".to_owned()]]
+        );
+        assert_eq!(reparsed.comment.as_deref(), Some("edited"));
+        assert_eq!(
+            reparsed.url_list,
+            Some(vec!["http://seed.example/".to_owned()])
+        );
+    }
+
+    // Keys out of order and an unknown outer key survive the round trip.
+    let info = b"d6:lengthi5e4:name1:x12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa1:zi1ee";
+    let mut bytes = b"d8:announce3:abc4:info".to_vec();
+    bytes.extend_from_slice(info);
+    bytes.extend_from_slice(b"7:unknown3:keye");
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    assert_eq!(metainfo.info_bytes(), Some(&info[..]));
+    assert_eq!(
+        metainfo.info_hash().inner().hex(),
+        hex::encode(sha1::Sha1::digest(info))
+    );
+    assert_eq!(metainfo.to_bytes().unwrap(), bytes);
+}
+
+#[test]
+fn test_sanitize_component() {
+    use rutor::proto::metainfo::sanitize::{sanitize_component, sanitize_path, MAX_COMPONENT_LEN};
+
+    assert_eq!(sanitize_component(".."), "_");
+    assert_eq!(sanitize_component("."), "_");
+    assert_eq!(sanitize_component(""), "_");
+    assert_eq!(sanitize_component("a/../b"), "a_.._b");
+    assert_eq!(sanitize_component("x\0y\\z"), "x_y_z");
+    assert_eq!(sanitize_component("movie.mkv"), "movie.mkv");
+    assert_eq!(
+        sanitize_path(&["", "/etc", "..", "passwd"]),
+        PathBuf::from("_etc/_/passwd")
+    );
+    assert_eq!(sanitize_path::<&str>(&[]), PathBuf::from("_"));
+
+    let long = format!("{}.mkv", "é".repeat(200));
+    let truncated = sanitize_component(&long);
+    assert!(truncated.len() <= MAX_COMPONENT_LEN);
+    assert!(truncated.ends_with("é.mkv"));
+}
+
+/// Random path components, biased towards the ones a malicious torrent would use.
+fn crafted_component(rng: &mut impl rand::Rng) -> String {
+    const POOL: [&str; 12] = [
+        "..",
+        ".",
+        "",
+        "/",
+        "/etc/passwd",
+        "a/../b",
+        "C:\\Windows",
+        "x\0y",
+        "dup",
+        "dup.txt",
+        "файл",
+        "con",
+    ];
+    match rng.random_range(0..4) {
+        0 => "é".repeat(rng.random_range(100..200)) + ".txt",
+        1 => (0..rng.random_range(1..8))
+            .map(|_| rng.random_range(0x20u8..0x7f) as char)
+            .collect(),
+        _ => POOL[rng.random_range(0..POOL.len())].to_owned(),
+    }
+}
+
+fn crafted_info(rng: &mut impl rand::Rng, v2: bool) -> Vec<u8> {
+    use serde_bencode::value::Value;
+    use std::collections::HashMap;
+
+    let bytes = |s: &str| Value::Bytes(s.as_bytes().to_vec());
+    let num_files = rng.random_range(1..12);
+    let paths: Vec<Vec<String>> = (0..num_files)
+        .map(|_| {
+            (0..rng.random_range(1..4))
+                .map(|_| crafted_component(rng))
+                .collect()
+        })
+        .collect();
+
+    let mut info = HashMap::new();
+    info.insert(b"name".to_vec(), bytes(&crafted_component(rng)));
+    info.insert(b"piece length".to_vec(), Value::Int(16384));
+    if v2 {
+        let mut tree = HashMap::new();
+        for path in paths.iter().filter(|p| p.iter().all(|c| !c.is_empty())) {
+            let mut node = &mut tree;
+            for component in path {
+                let entry = node
+                    .entry(component.as_bytes().to_vec())
+                    .or_insert_with(|| Value::Dict(HashMap::new()));
+                let Value::Dict(dict) = entry else {
+                    unreachable!()
+                };
+                node = dict;
+            }
+            if node.is_empty() {
+                let file = HashMap::from([(b"length".to_vec(), Value::Int(1))]);
+                node.insert(Vec::new(), Value::Dict(file));
+            }
+        }
+        info.insert(b"file tree".to_vec(), Value::Dict(tree));
+        info.insert(b"meta version".to_vec(), Value::Int(2));
+    } else {
+        let files = paths
+            .iter()
+            .map(|path| {
+                let path = path.iter().map(|c| bytes(c)).collect();
+                Value::Dict(HashMap::from([
+                    (b"length".to_vec(), Value::Int(1)),
+                    (b"path".to_vec(), Value::List(path)),
+                ]))
+            })
+            .collect();
+        info.insert(b"files".to_vec(), Value::List(files));
+        info.insert(b"pieces".to_vec(), Value::Bytes(vec![0; 20]));
+    }
+    serde_bencode::to_bytes(&Value::Dict(info)).unwrap()
+}
+
+#[test]
+fn test_sanitize_crafted_torrents() {
+    use rand::SeedableRng;
+    use rutor::proto::metainfo::{sanitize::MAX_COMPONENT_LEN, Info};
+    use std::collections::HashSet;
+    use std::path::Component;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+    let mut parsed = 0;
+    for round in 0..400 {
+        let bytes = crafted_info(&mut rng, round % 2 == 1);
+        let Ok(info) = Info::from_bytes(&bytes) else {
+            continue;
+        };
+        let files = info.files();
+        parsed += 1;
+        let mut seen = HashSet::new();
+        for file in files.iter() {
+            for component in file.path.components() {
+                let Component::Normal(name) = component else {
+                    panic!("unsafe component in {:?}", file.path);
+                };
+                let name = name.to_str().unwrap();
+                assert!(name.len() <= MAX_COMPONENT_LEN);
+                assert!(!name.contains(['/', '\\', '\0']));
+            }
+            assert!(seen.insert(file.path.clone()), "duplicate {:?}", file.path);
+        }
+        // no file is the directory of another one
+        for file in files.iter() {
+            for dir in file.path.ancestors().skip(1) {
+                assert!(!seen.contains(dir), "{dir:?} is a file and a directory");
+            }
+        }
+    }
+    assert!(parsed > 300);
+}
@@ -0,0 +1,432 @@
+use rutor::proto::dht::{KrpcArgs, KrpcMessage, QueryArgs};
+use rutor::proto::metainfo::{MetaInfo, TorrentBuilder};
+use rutor::proxy::{Proxy, ProxySettings};
+use rutor::session::{Session, SessionCommand, SessionSettings};
+use rutor::torrent::TorrentSource;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpListener, TcpStream, UdpSocket};
+
+const USERNAME: &str = "user";
+const PASSWORD: &str = "secret";
+
+async fn read_socks_addr<R: AsyncRead + Unpin>(reader: &mut R) -> SocketAddr {
+    let ip = match reader.read_u8().await.unwrap() {
+        1 => {
+            let mut octets = [0u8; 4];
+            reader.read_exact(&mut octets).await.unwrap();
+            IpAddr::from(Ipv4Addr::from(octets))
+        }
+        4 => {
+            let mut octets = [0u8; 16];
+            reader.read_exact(&mut octets).await.unwrap();
+            IpAddr::from(Ipv6Addr::from(octets))
+        }
+        3 => {
+            let len = reader.read_u8().await.unwrap() as usize;
+            let mut host = vec![0u8; len];
+            reader.read_exact(&mut host).await.unwrap();
+            let port = reader.read_u16().await.unwrap();
+            let host = String::from_utf8(host).unwrap();
+            // names only the proxy knows
+            let host = host.strip_suffix(".proxy.test").unwrap_or(&host);
+            return lookup_host((host, port)).await.unwrap().next().unwrap();
+        }
+        atyp => panic!("unknown address type {atyp}"),
+    };
+    SocketAddr::new(ip, reader.read_u16().await.unwrap())
+}
+
+fn socks_addr_bytes(addr: SocketAddr) -> Vec<u8> {
+    let mut bytes = match addr.ip() {
+        IpAddr::V4(ip) => [&[1u8][..], &ip.octets()].concat(),
+        IpAddr::V6(ip) => [&[4u8][..], &ip.octets()].concat(),
+    };
+    bytes.extend(addr.port().to_be_bytes());
+    bytes
+}
+
+/// A SOCKS5 stand-in requiring `USERNAME`/`PASSWORD`, with CONNECT and UDP
+/// ASSOCIATE. The relay reports an unspecified address like most servers.
+async fn spawn_socks5_proxy() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve_socks5(stream));
+        }
+    });
+    addr
+}
+
+async fn serve_socks5(mut stream: TcpStream) {
+    let mut greeting = [0u8; 2];
+    stream.read_exact(&mut greeting).await.unwrap();
+    let mut methods = vec![0u8; greeting[1] as usize];
+    stream.read_exact(&mut methods).await.unwrap();
+    if !methods.contains(&2) {
+        stream.write_all(&[5, 0xff]).await.unwrap();
+        return;
+    }
+    stream.write_all(&[5, 2]).await.unwrap();
+
+    let mut fields = Vec::new();
+    stream.read_u8().await.unwrap();
+    for _ in 0..2 {
+        let len = stream.read_u8().await.unwrap() as usize;
+        let mut field = vec![0u8; len];
+        stream.read_exact(&mut field).await.unwrap();
+        fields.push(String::from_utf8(field).unwrap());
+    }
+    if fields != [USERNAME, PASSWORD] {
+        stream.write_all(&[1, 1]).await.unwrap();
+        return;
+    }
+    stream.write_all(&[1, 0]).await.unwrap();
+
+    let mut request = [0u8; 3];
+    stream.read_exact(&mut request).await.unwrap();
+    let target = read_socks_addr(&mut stream).await;
+    match request[1] {
+        1 => {
+            let mut upstream = TcpStream::connect(target).await.unwrap();
+            let bound = "0.0.0.0:0".parse().unwrap();
+            stream
+                .write_all(&[&[5, 0, 0][..], &socks_addr_bytes(bound)].concat())
+                .await
+                .unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+        }
+        3 => {
+            let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let port = relay.local_addr().unwrap().port();
+            let bound = SocketAddr::from(([0, 0, 0, 0], port));
+            stream
+                .write_all(&[&[5, 0, 0][..], &socks_addr_bytes(bound)].concat())
+                .await
+                .unwrap();
+            let relay_task = tokio::spawn(relay_udp(relay));
+            // the association ends with the control connection
+            let _ = stream.read_u8().await;
+            relay_task.abort();
+        }
+        command => panic!("unexpected command {command}"),
+    }
+}
+
+async fn relay_udp(relay: UdpSocket) {
+    let mut client = None::<SocketAddr>;
+    let mut buf = [0u8; 4096];
+    loop {
+        let (n, from) = relay.recv_from(&mut buf).await.unwrap();
+        if client.is_none_or(|client| client == from) {
+            client = Some(from);
+            let mut packet = &buf[3..n];
+            let target = read_socks_addr(&mut packet).await;
+            relay.send_to(packet, target).await.unwrap();
+        } else if let Some(client) = client {
+            let packet = [&[0, 0, 0][..], &socks_addr_bytes(from), &buf[..n]].concat();
+            relay.send_to(&packet, client).await.unwrap();
+        }
+    }
+}
+
+/// An HTTP proxy stand-in requiring Basic `USERNAME`/`PASSWORD`.
+async fn spawn_http_proxy() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let header = read_http_header(&mut stream).await;
+                // base64 of "user:secret"
+                let authorized = header
+                    .to_ascii_lowercase()
+                    .contains("proxy-authorization: basic dxnlcjpzzwnyzxq=\r\n");
+                if !authorized {
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                        .await;
+                    return;
+                }
+                let target = header.split_whitespace().nth(1).unwrap();
+                let mut upstream;
+                match target.strip_prefix("http://") {
+                    // plain HTTP is forwarded as is rather than tunnelled
+                    Some(url) => {
+                        let authority = url.split('/').next().unwrap();
+                        upstream = TcpStream::connect(authority).await.unwrap();
+                        upstream.write_all(header.as_bytes()).await.unwrap();
+                    }
+                    None => {
+                        upstream = TcpStream::connect(target).await.unwrap();
+                        stream
+                            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                            .await
+                            .unwrap();
+                    }
+                }
+                let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+            });
+        }
+    });
+    addr
+}
+
+async fn read_http_header(stream: &mut TcpStream) -> String {
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        header.push(stream.read_u8().await.unwrap());
+    }
+    String::from_utf8(header).unwrap()
+}
+
+async fn spawn_tcp_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+async fn assert_echo(mut stream: TcpStream) {
+    stream.write_all(b"through the tunnel").await.unwrap();
+    let mut buf = [0u8; 18];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"through the tunnel");
+}
+
+#[tokio::test]
+async fn test_socks5_connect() {
+    let echo = spawn_tcp_echo().await;
+    let proxy = Proxy::socks5(spawn_socks5_proxy().await.to_string()).auth(USERNAME, PASSWORD);
+    let stream = proxy.connect("127.0.0.1", echo.port()).await.unwrap();
+    assert_echo(stream).await;
+    // host names are resolved by the proxy
+    let stream = proxy.connect("localhost", echo.port()).await.unwrap();
+    assert_echo(stream).await;
+
+    let wrong = Proxy::socks5(proxy.addr.clone()).auth(USERNAME, "wrong");
+    assert!(wrong.connect("127.0.0.1", echo.port()).await.is_err());
+    let anonymous = Proxy::socks5(proxy.addr.clone());
+    assert!(anonymous.connect("127.0.0.1", echo.port()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_http_connect() {
+    let echo = spawn_tcp_echo().await;
+    let proxy = Proxy::http(spawn_http_proxy().await.to_string()).auth(USERNAME, PASSWORD);
+    let stream = proxy.connect("127.0.0.1", echo.port()).await.unwrap();
+    assert_echo(stream).await;
+
+    let wrong = Proxy::http(proxy.addr.clone()).auth(USERNAME, "wrong");
+    assert!(wrong.connect("127.0.0.1", echo.port()).await.is_err());
+    assert!(!proxy.supports_udp());
+    assert!(proxy.udp_associate().await.is_err());
+}
+
+#[tokio::test]
+async fn test_socks5_udp_associate() {
+    let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let echo_addr = echo.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..n], from).await.unwrap();
+        }
+    });
+    let proxy_addr = spawn_socks5_proxy().await;
+    let proxy = Proxy::socks5(proxy_addr.to_string()).auth(USERNAME, PASSWORD);
+    let datagram = proxy.udp_associate().await.unwrap();
+    // the unspecified relay address is replaced by the proxy's
+    assert_eq!(datagram.relay_addr().ip(), proxy_addr.ip());
+
+    datagram
+        .send_to(b"datagram", "127.0.0.1", echo_addr.port())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 64];
+    let (n, from) = tokio::time::timeout(Duration::from_secs(5), datagram.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..n], b"datagram");
+    assert_eq!(from, echo_addr);
+}
+
+#[tokio::test]
+async fn test_torrent_source_via_proxy() {
+    let torrent = std::fs::read("resources/Books.torrent").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = listener.local_addr().unwrap();
+    let body = torrent.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_http_header(&mut stream).await;
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream.write_all(&body).await.unwrap();
+        }
+    });
+
+    let url = format!("http://{server}/Books.torrent");
+    for proxy in [
+        Proxy::socks5(spawn_socks5_proxy().await.to_string()),
+        Proxy::http(spawn_http_proxy().await.to_string()),
+    ] {
+        let proxy = proxy.auth(USERNAME, PASSWORD);
+        let source = TorrentSource::from_str(&url, Some(&proxy)).await.unwrap();
+        let expected = MetaInfo::from_bytes(&torrent).unwrap();
+        assert_eq!(
+            source.torrent_id(),
+            *expected.info_hash().inner().truncate()
+        );
+
+        let wrong = proxy.clone().auth(USERNAME, "wrong");
+        assert!(TorrentSource::from_str(&url, Some(&wrong)).await.is_err());
+
+        // the session downloads through its trackers proxy
+        for (proxy, ok) in [(proxy, true), (wrong, false)] {
+            let session = Session::start_with_settings(SessionSettings {
+                listen_port: 0,
+                dht: false,
+                upnp: false,
+                natpmp: false,
+                proxy: ProxySettings {
+                    trackers: Some(proxy),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+            assert_eq!(session.torrent_source(&url).await.is_ok(), ok);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_udp_tracker_via_proxy() {
+    let root = std::env::temp_dir().join("rutor_test_udp_tracker_via_proxy");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    std::fs::write(root.join("data.bin"), "proxied".repeat(1000)).unwrap();
+    let bytes = TorrentBuilder::new(root.join("data.bin"))
+        .tracker(format!(
+            "udp://localhost:{}/announce",
+            tracker.local_addr().unwrap().port()
+        ))
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info_hash = *metainfo.info_hash().inner().truncate();
+
+    let proxy = Proxy::socks5(spawn_socks5_proxy().await.to_string()).auth(USERNAME, PASSWORD);
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        dht: false,
+        lsd: false,
+        proxy: ProxySettings {
+            trackers: Some(proxy),
+            ..Default::default()
+        },
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo,
+        ))))
+        .await
+        .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let mut buf = [0u8; 2048];
+        let (n, relay) = tracker.recv_from(&mut buf).await.unwrap();
+        assert_eq!(n, 16);
+        let mut reply = vec![0, 0, 0, 0];
+        reply.extend(&buf[12..16]);
+        reply.extend([5; 8]);
+        tracker.send_to(&reply, relay).await.unwrap();
+
+        let (n, from) = tracker.recv_from(&mut buf).await.unwrap();
+        assert_eq!(n, 98);
+        assert_eq!(from, relay);
+        assert_eq!(buf[..8], [5; 8]);
+        assert_eq!(buf[16..36], info_hash);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_dht_via_proxy() {
+    let root = std::env::temp_dir().join("rutor_test_dht_via_proxy");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let node = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    std::fs::write(root.join("data.bin"), "proxied".repeat(1000)).unwrap();
+    let bytes = TorrentBuilder::new(root.join("data.bin")).build().unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info_hash = metainfo.info_hash().inner().truncate().to_vec();
+
+    // the local resolver can't find the node, only the proxy can
+    let bootstrap = format!("localhost.proxy.test:{}", node.local_addr().unwrap().port());
+    assert!(lookup_host(bootstrap.as_str()).await.is_err());
+    let proxy = Proxy::socks5(spawn_socks5_proxy().await.to_string()).auth(USERNAME, PASSWORD);
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        dht: true,
+        dht_bootstrap_nodes: vec![bootstrap],
+        lsd: false,
+        proxy: ProxySettings {
+            peers: Some(proxy),
+            ..Default::default()
+        },
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo,
+        ))))
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 2048];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(10), node.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    match KrpcMessage::from_bytes(&buf[..n]).unwrap().into_args(None) {
+        KrpcArgs::Query(QueryArgs::GetPeers { info_hash: got, .. }) => {
+            assert_eq!(got, info_hash)
+        }
+        args => panic!("unexpected DHT message {args:?}"),
+    }
+    std::fs::remove_dir_all(&root).unwrap();
+}
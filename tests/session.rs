@@ -0,0 +1,1576 @@
+use rutor::peers::{connect_peer, mse_connect, EncryptionPolicy, IpFilter, PeerConnection};
+use rutor::proto::bep10::{ExtendedHandshake, ExtensionMessage};
+use rutor::proto::dht::{KrpcArgs, KrpcMessage, QueryArgs};
+use rutor::proto::infohash::{InfoHash, InfoHashT, InfoHashV1};
+use rutor::proto::metainfo::{MetaInfo, TorrentBuilder, TorrentVersion};
+use rutor::proto::natpmp::{MappingProtocol, NatPmpRequest, NatPmpResponse};
+use rutor::proto::pcp::{PcpMapRequest, PcpMapResponse};
+use rutor::proto::{BitField, Handshake, Message, PeerId, Piece};
+use rutor::session::{
+    identify_udp_protocol, Lsd, PortMappingMethod, QueueSettings, Session, SessionAlert,
+    SessionCommand, SessionIpFilter, SessionSettings, TorrentQueue, UdpCounters, UdpProtocol,
+};
+use rutor::torrent::{TorrentCommand, TorrentID, TorrentSource, TorrentStatus};
+use rutor::utp::{Packet, PacketType, UtpSocket};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc::Receiver;
+
+fn queue_settings(downloads: usize, seeds: usize, total: usize) -> QueueSettings {
+    QueueSettings {
+        max_active_downloads: Some(downloads),
+        max_active_seeds: Some(seeds),
+        max_active_total: Some(total),
+        dont_count_slow_torrents: false,
+        ..Default::default()
+    }
+}
+
+fn status_of(queue: &TorrentQueue, torrent_id: &TorrentID) -> TorrentStatus {
+    queue.get(torrent_id).unwrap().status.clone()
+}
+
+#[test]
+fn test_queue_active_limits() {
+    let mut queue = TorrentQueue::new(queue_settings(2, 1, 3));
+    let downloads = [[1u8; 20], [2u8; 20], [3u8; 20]];
+    let seeds = [[4u8; 20], [5u8; 20]];
+    for torrent_id in downloads {
+        queue.push(torrent_id, TorrentStatus::Waiting, false);
+    }
+    for torrent_id in seeds {
+        queue.push(torrent_id, TorrentStatus::Waiting, true);
+    }
+
+    let transitions = queue.recalculate();
+    assert_eq!(transitions.len(), 3);
+    assert_eq!(status_of(&queue, &downloads[0]), TorrentStatus::Downloading);
+    assert_eq!(status_of(&queue, &downloads[1]), TorrentStatus::Downloading);
+    assert_eq!(status_of(&queue, &downloads[2]), TorrentStatus::Waiting);
+    assert_eq!(status_of(&queue, &seeds[0]), TorrentStatus::Seeding);
+    assert_eq!(status_of(&queue, &seeds[1]), TorrentStatus::Waiting);
+
+    // A finished download frees its slot and competes for a seeding one.
+    queue.update(&downloads[0], TorrentStatus::Downloading, true, 0, 0);
+    queue.recalculate();
+    assert_eq!(status_of(&queue, &downloads[2]), TorrentStatus::Downloading);
+    assert_eq!(status_of(&queue, &seeds[0]), TorrentStatus::Waiting);
+    assert_eq!(status_of(&queue, &downloads[0]), TorrentStatus::Seeding);
+
+    assert!(queue.recalculate().is_empty());
+}
+
+#[test]
+fn test_queue_positions() {
+    let mut queue = TorrentQueue::new(queue_settings(1, 1, 1));
+    let (a, b, c) = ([1u8; 20], [2u8; 20], [3u8; 20]);
+    for torrent_id in [a, b, c] {
+        queue.push(torrent_id, TorrentStatus::Waiting, false);
+    }
+    queue.recalculate();
+    assert_eq!(status_of(&queue, &a), TorrentStatus::Downloading);
+
+    assert!(queue.move_bottom(&a));
+    assert!(!queue.move_bottom(&a));
+    assert_eq!(queue.position(&a), Some(2));
+    assert!(queue.move_up(&c));
+    assert_eq!(queue.position(&c), Some(0));
+    assert!(queue.move_down(&b));
+    assert!(queue.move_top(&a));
+    assert_eq!(
+        queue.iter().map(|e| e.torrent_id).collect::<Vec<_>>(),
+        vec![a, c, b]
+    );
+
+    assert!(queue.move_down(&a));
+    let transitions = queue.recalculate();
+    assert_eq!(
+        transitions,
+        vec![(c, TorrentStatus::Downloading), (a, TorrentStatus::Waiting)]
+    );
+
+    // Paused torrents are skipped by the queue.
+    queue.update(&c, TorrentStatus::Stopped, false, 0, 0);
+    queue.recalculate();
+    assert_eq!(status_of(&queue, &a), TorrentStatus::Downloading);
+    assert_eq!(status_of(&queue, &c), TorrentStatus::Stopped);
+}
+
+#[test]
+fn test_queue_dont_count_slow_torrents() {
+    let mut queue = TorrentQueue::new(QueueSettings {
+        dont_count_slow_torrents: true,
+        slow_torrent_inactive_time: Duration::ZERO,
+        ..queue_settings(1, 1, 2)
+    });
+    let (a, b) = ([1u8; 20], [2u8; 20]);
+    queue.push(a, TorrentStatus::Waiting, false);
+    queue.push(b, TorrentStatus::Waiting, false);
+
+    queue.recalculate();
+    assert_eq!(status_of(&queue, &b), TorrentStatus::Waiting);
+
+    // `a` has no traffic, so it stops counting against the limits.
+    queue.recalculate();
+    assert_eq!(status_of(&queue, &a), TorrentStatus::Downloading);
+    assert_eq!(status_of(&queue, &b), TorrentStatus::Downloading);
+
+    // Once `a` picks up speed again it takes the slot back.
+    queue.update(&a, TorrentStatus::Downloading, false, 1024 * 1024, 0);
+    queue.update(&b, TorrentStatus::Downloading, false, 1024 * 1024, 0);
+    queue.recalculate();
+    assert_eq!(status_of(&queue, &a), TorrentStatus::Downloading);
+    assert_eq!(status_of(&queue, &b), TorrentStatus::Waiting);
+}
+
+async fn recv_status(session: &mut Session) -> (TorrentID, TorrentStatus) {
+    loop {
+        if let SessionAlert::TorrentStatusChanged { torrent_id, status } =
+            session.recv().await.unwrap()
+        {
+            return (torrent_id, status);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_session_queue() {
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        queue: queue_settings(1, 1, 1),
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let mut torrent_ids = Vec::new();
+    for path in [
+        "resources/Books.torrent",
+        "resources/Red_Hot_Chili_Peppers.torrent",
+    ] {
+        let source = TorrentSource::from_str(path, None).await.unwrap();
+        torrent_ids.push(source.torrent_id());
+        session
+            .send(SessionCommand::AddTorrent(Box::new(source)))
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        recv_status(&mut session).await,
+        (torrent_ids[0], TorrentStatus::Downloading)
+    );
+
+    session
+        .send(SessionCommand::QueueMoveTop(torrent_ids[1]))
+        .await
+        .unwrap();
+    let mut changes = vec![
+        recv_status(&mut session).await,
+        recv_status(&mut session).await,
+    ];
+    changes.sort_by_key(|(_, status)| status.as_str().to_owned());
+    assert_eq!(
+        changes,
+        vec![
+            (torrent_ids[1], TorrentStatus::Downloading),
+            (torrent_ids[0], TorrentStatus::Waiting),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_unread_alerts() {
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        queue: queue_settings(1, 1, 1),
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let source = TorrentSource::from_str("resources/Books.torrent", None)
+        .await
+        .unwrap();
+    let torrent_id = source.torrent_id();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(source)))
+        .await
+        .unwrap();
+
+    // nobody reads the alerts, the commands still go through
+    tokio::time::timeout(Duration::from_secs(10), async {
+        for _ in 0..50 {
+            for command in [
+                SessionCommand::PauseTorrent(torrent_id),
+                SessionCommand::ResumeTorrent(torrent_id),
+            ] {
+                session.send(command).await.unwrap();
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert!(matches!(
+        session.recv().await,
+        Some(SessionAlert::TorrentAdded(id)) if id == torrent_id
+    ));
+}
+
+/// A peer with every piece of `data`.
+async fn serve_pieces(mut stream: DuplexStream, data: Vec<u8>) {
+    let num_pieces = data.len().div_ceil(16384);
+    for message in [
+        Message::BitField(BitField::full(num_pieces)),
+        Message::UnChoke,
+    ] {
+        stream.write_all(&message.to_bytes()).await.unwrap();
+    }
+    while let Ok(len) = stream.read_u32().await {
+        let mut buf = len.to_be_bytes().to_vec();
+        buf.resize(4 + len as usize, 0);
+        if stream.read_exact(&mut buf[4..]).await.is_err() {
+            return;
+        }
+        let Message::Request(request) = Message::from_bytes(&buf) else {
+            continue;
+        };
+        let start = request.index as usize * 16384 + request.begin as usize;
+        let block = data[start..start + request.length as usize].to_vec();
+        let piece = Piece::new(request.index, request.begin, block);
+        if stream
+            .write_all(&Message::Piece(piece).to_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Single file torrent of `data` in 16 KiB pieces.
+fn stream_torrent(name: &str, data: &[u8]) -> MetaInfo {
+    let source_path = std::env::temp_dir().join(name);
+    std::fs::write(&source_path, data).unwrap();
+    let bytes = TorrentBuilder::new(&source_path)
+        .piece_length(16384)
+        .build()
+        .unwrap();
+    std::fs::remove_file(&source_path).unwrap();
+    MetaInfo::from_bytes(&bytes).unwrap()
+}
+
+/// Connects a peer with every piece of `data` to the torrent.
+async fn add_serving_peer(session: &Session, torrent_id: TorrentID, data: Vec<u8>) {
+    let (stream, remote) = tokio::io::duplex(64 * 1024);
+    let connection = PeerConnection {
+        addr: "10.0.0.1:6881".parse().unwrap(),
+        handshake: Handshake::new([0; 68]),
+        extended: None,
+        stream: Box::new(stream),
+    };
+    session
+        .send(SessionCommand::SendToTorrent {
+            torrent_id,
+            command: TorrentCommand::IncomingPeer(Box::new(connection)),
+        })
+        .await
+        .unwrap();
+    tokio::spawn(serve_pieces(remote, data));
+}
+
+async fn http_get(addr: SocketAddr, path: &str, range: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nRange: {range}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_stream_server() {
+    let save_path = std::env::temp_dir().join("rutor_test_stream_server");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: save_path.clone(),
+        stream_addr: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let addr = session.stream_addr().unwrap();
+
+    let data: Vec<u8> = (0..40_000).map(|i| b'a' + (i % 26) as u8).collect();
+    let metainfo = stream_torrent("rutor_test_stream_server.txt", &data);
+    let torrent_id = TorrentSource::File(metainfo.clone()).torrent_id();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo.clone(),
+        ))))
+        .await
+        .unwrap();
+    let path = format!("/stream/{}/0", metainfo.info_hash().inner().hex());
+
+    let request = {
+        let path = path.clone();
+        tokio::spawn(async move { http_get(addr, &path, "bytes=100-199").await })
+    };
+    loop {
+        if let SessionAlert::TorrentAdded(_) = session.recv().await.unwrap() {
+            break;
+        }
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    add_serving_peer(&session, torrent_id, data.clone()).await;
+    loop {
+        if let SessionAlert::PieceDeadlineReady { piece, .. } = session.recv().await.unwrap() {
+            assert_eq!(piece, 0);
+            break;
+        }
+    }
+
+    let response = request.await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+    assert!(response.contains("Content-Range: bytes 100-199/"));
+    assert!(response.ends_with(std::str::from_utf8(&data[100..200]).unwrap()));
+
+    let response = http_get(addr, &path, &format!("bytes=100-{}", u64::MAX)).await;
+    let length = metainfo.info.files()[0].length;
+    assert!(response.contains(&format!("Content-Range: bytes 100-{}/{length}", length - 1)));
+    let response = http_get(addr, &path, "bytes=-0").await;
+    assert!(response.starts_with("HTTP/1.1 416"));
+    let response = http_get(addr, "/stream/00/0", "bytes=0-").await;
+    assert!(response.starts_with("HTTP/1.1 404"));
+
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_stream_client_gone() {
+    let save_path = std::env::temp_dir().join("rutor_test_stream_client_gone");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: save_path.clone(),
+        stream_addr: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let addr = session.stream_addr().unwrap();
+
+    let data: Vec<u8> = (0..40_000).map(|i| (i % 251) as u8).collect();
+    let metainfo = stream_torrent("rutor_test_stream_client_gone.bin", &data);
+    let torrent_id = TorrentSource::File(metainfo.clone()).torrent_id();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo.clone(),
+        ))))
+        .await
+        .unwrap();
+    loop {
+        if let SessionAlert::TorrentAdded(_) = session.recv().await.unwrap() {
+            break;
+        }
+    }
+
+    // the client leaves while the server waits for the first piece
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "GET /stream/{}/0 HTTP/1.1\r\nRange: bytes=0-99\r\n\r\n",
+        metainfo.info_hash().inner().hex()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = [0u8; 16];
+    stream.read_exact(&mut head).await.unwrap();
+    assert!(head.starts_with(b"HTTP/1.1 206"));
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // its deadline went away with it
+    add_serving_peer(&session, torrent_id, data).await;
+    loop {
+        match session.recv().await.unwrap() {
+            SessionAlert::PieceDeadlineReady { piece, .. } => panic!("deadline of {piece}"),
+            SessionAlert::TorrentStatusChanged {
+                status: TorrentStatus::Seeding,
+                ..
+            } => break,
+            _ => {}
+        }
+    }
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+/// Sends a handshake for `info_hash` and returns the info hash of the reply.
+async fn handshake(port: u16, info_hash: [u8; 20]) -> Option<[u8; 20]> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    exchange_handshake(stream, info_hash).await
+}
+
+/// Same over uTP, on the session's UDP socket.
+async fn utp_handshake(utp: &Arc<UtpSocket>, port: u16, info_hash: [u8; 20]) -> Option<[u8; 20]> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let stream = utp.connect(addr).await.unwrap();
+    exchange_handshake(stream, info_hash).await
+}
+
+async fn exchange_handshake<S>(mut stream: S, info_hash: [u8; 20]) -> Option<[u8; 20]>
+where
+    S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    let handshake = Handshake::from_args(
+        &InfoHash::V1(InfoHashV1::new(info_hash)),
+        &PeerId::gen_new(),
+    );
+    stream.write_all(handshake.as_slice()).await.unwrap();
+    let mut buf = [0u8; 68];
+    stream.read_exact(&mut buf).await.ok()?;
+    Some(*Handshake::new(buf).select_info_hash())
+}
+
+#[tokio::test]
+async fn test_hybrid_inbound_handshake() {
+    let root = std::env::temp_dir().join("rutor_test_hybrid_handshake");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let data: Vec<u8> = (0..50_000).map(|i| (i % 173) as u8).collect();
+    std::fs::write(root.join("data.bin"), &data).unwrap();
+    let bytes = TorrentBuilder::new(root.join("data.bin"))
+        .version(TorrentVersion::Hybrid)
+        .piece_length(16 * 1024)
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let (v1, v2) = (
+        *metainfo.info_hash().v1().unwrap().truncate(),
+        *metainfo.info_hash().v2().unwrap().truncate(),
+    );
+
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let port = session.listen_port();
+    assert_eq!(handshake(port, v1).await, None);
+
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo,
+        ))))
+        .await
+        .unwrap();
+    for info_hash in [v1, v2] {
+        let mut reply = None;
+        for _ in 0..50 {
+            reply = handshake(port, info_hash).await;
+            if reply.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(reply, Some(info_hash));
+    }
+    assert_eq!(handshake(port, [9; 20]).await, None);
+
+    let utp = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    assert_eq!(utp_handshake(&utp, port, v2).await, Some(v2));
+    assert_eq!(utp_handshake(&utp, port, [9; 20]).await, None);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_inbound_peer_connection() {
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: std::env::temp_dir().join("rutor_test_inbound_peer_connection"),
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let port = session.listen_port();
+    let source = TorrentSource::from_str("resources/Books.torrent", None)
+        .await
+        .unwrap();
+    let torrent_id = source.torrent_id();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(source)))
+        .await
+        .unwrap();
+    recv_alert(&mut session, |alert| {
+        matches!(alert, SessionAlert::TorrentAdded(_)).then_some(())
+    })
+    .await;
+
+    // the torrent gets the connection once the handshake is answered
+    assert_eq!(handshake(port, torrent_id).await, Some(torrent_id));
+    let addr = recv_alert(&mut session, |alert| match alert {
+        SessionAlert::PeerConnected {
+            torrent_id: id,
+            addr,
+        } if id == torrent_id => Some(addr),
+        _ => None,
+    })
+    .await;
+    assert!(addr.ip().is_loopback());
+
+    let utp = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    assert_eq!(
+        utp_handshake(&utp, port, torrent_id).await,
+        Some(torrent_id)
+    );
+    let addr = recv_alert(&mut session, |alert| match alert {
+        SessionAlert::PeerConnected { addr, .. } => Some(addr),
+        _ => None,
+    })
+    .await;
+    assert_eq!(addr, utp.local_addr().unwrap());
+}
+
+#[tokio::test]
+async fn test_extended_handshake() {
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: std::env::temp_dir().join("rutor_test_extended_handshake"),
+        dht: false,
+        lsd: false,
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let port = session.listen_port();
+    let source = TorrentSource::from_str("resources/Books.torrent", None)
+        .await
+        .unwrap();
+    let torrent_id = source.torrent_id();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(source)))
+        .await
+        .unwrap();
+    recv_alert(&mut session, |alert| {
+        matches!(alert, SessionAlert::TorrentAdded(_)).then_some(())
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let handshake = Handshake::from_args(
+        &InfoHash::V1(InfoHashV1::new(torrent_id)),
+        &PeerId::gen_new(),
+    );
+    assert!(handshake.supports_extensions());
+    stream.write_all(handshake.as_slice()).await.unwrap();
+    let mut buf = [0u8; 68];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert!(Handshake::new(buf).supports_extensions());
+
+    // the session's extension handshake follows its handshake
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.unwrap();
+    let mut message = len.to_vec();
+    message.resize(4 + u32::from_be_bytes(len) as usize, 0);
+    stream.read_exact(&mut message[4..]).await.unwrap();
+    let Message::Extension(ExtensionMessage::Handshake(extended)) = Message::from_bytes(&message)
+    else {
+        panic!("expected an extension handshake");
+    };
+    assert_eq!(extended.p, Some(port));
+    assert_eq!(extended.yourip, Some(vec![127, 0, 0, 1]));
+    assert_eq!(extended.ipv4, None);
+
+    let ours = ExtendedHandshake::local(7000, [127, 0, 0, 1].into(), None);
+    let mut bytes = Message::Extension(ExtensionMessage::Handshake(ours)).to_bytes();
+    bytes.extend(Message::Interested.to_bytes());
+    stream.write_all(&bytes).await.unwrap();
+    let addr = recv_alert(&mut session, |alert| match alert {
+        SessionAlert::PeerConnected { addr, .. } => Some(addr),
+        _ => None,
+    })
+    .await;
+    assert_eq!(addr, stream.local_addr().unwrap());
+}
+
+/// Waits for the next DHT `get_peers` query and returns its info hash.
+async fn recv_get_peers(node: &UdpSocket, wait: Duration) -> Option<Vec<u8>> {
+    let mut buf = [0u8; 2048];
+    let (n, _) = tokio::time::timeout(wait, node.recv_from(&mut buf))
+        .await
+        .ok()?
+        .unwrap();
+    match KrpcMessage::from_bytes(&buf[..n]).unwrap().into_args(None) {
+        KrpcArgs::Query(QueryArgs::GetPeers { info_hash, .. }) => Some(info_hash),
+        args => panic!("unexpected DHT message {args:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_private_torrent_dht() {
+    let root = std::env::temp_dir().join("rutor_test_private_torrent_dht");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let mut torrents = Vec::new();
+    for (name, private) in [("private.bin", true), ("public.bin", false)] {
+        std::fs::write(root.join(name), name.repeat(1000)).unwrap();
+        let bytes = TorrentBuilder::new(root.join(name))
+            .private(private)
+            .build()
+            .unwrap();
+        let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(metainfo.info.is_private(), private);
+        torrents.push(metainfo);
+    }
+
+    let node = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        dht: true,
+        dht_bootstrap_nodes: vec![node.local_addr().unwrap().to_string()],
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let public_hash = torrents[1].info_hash().inner().truncate().to_vec();
+    for metainfo in torrents {
+        session
+            .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+                metainfo,
+            ))))
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        recv_get_peers(&node, Duration::from_secs(5)).await,
+        Some(public_hash)
+    );
+    assert_eq!(recv_get_peers(&node, Duration::from_secs(2)).await, None);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+/// Bencoded KRPC reply, `fields` are encoded values under sorted keys.
+fn krpc_reply(transaction_id: i32, fields: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut reply = b"d1:rd".to_vec();
+    for (key, value) in fields {
+        reply.extend(format!("{}:{key}", key.len()).bytes());
+        reply.extend(value);
+    }
+    reply.extend(b"e1:t4:");
+    reply.extend(transaction_id.to_be_bytes());
+    reply.extend(b"1:y1:re");
+    reply
+}
+
+fn bencode_bytes(bytes: &[u8]) -> Vec<u8> {
+    [format!("{}:", bytes.len()).as_bytes(), bytes].concat()
+}
+
+fn compact_addr(addr: SocketAddr) -> Vec<u8> {
+    let SocketAddr::V4(addr) = addr else {
+        panic!("IPv4 only");
+    };
+    [&addr.ip().octets()[..], &addr.port().to_be_bytes()].concat()
+}
+
+async fn recv_krpc(node: &UdpSocket) -> (KrpcMessage, SocketAddr) {
+    let mut buf = [0u8; 2048];
+    let (n, from) = tokio::time::timeout(Duration::from_secs(5), node.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    (KrpcMessage::from_bytes(&buf[..n]).unwrap(), from)
+}
+
+#[tokio::test]
+async fn test_dht_lookup() {
+    let root = std::env::temp_dir().join("rutor_test_dht_lookup");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("data.bin"), "dht".repeat(1000)).unwrap();
+    let bytes = TorrentBuilder::new(root.join("data.bin")).build().unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info_hash = *metainfo.info_hash().inner().truncate();
+
+    let bootstrap = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let closer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        dht: true,
+        dht_bootstrap_nodes: vec![bootstrap.local_addr().unwrap().to_string()],
+        lsd: false,
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo,
+        ))))
+        .await
+        .unwrap();
+
+    // the bootstrap node only knows a closer node
+    let (query, from) = recv_krpc(&bootstrap).await;
+    let node = [&info_hash[..], &compact_addr(closer.local_addr().unwrap())].concat();
+    let reply = krpc_reply(
+        query.transaction_id().unwrap(),
+        &[
+            ("id", bencode_bytes(&[0xff; 20])),
+            ("nodes", bencode_bytes(&node)),
+        ],
+    );
+    bootstrap.send_to(&reply, from).await.unwrap();
+
+    // which has peers and takes our announce
+    let (query, from) = recv_krpc(&closer).await;
+    match query.clone().into_args(None) {
+        KrpcArgs::Query(QueryArgs::GetPeers { info_hash: got, .. }) => {
+            assert_eq!(got, info_hash)
+        }
+        args => panic!("unexpected DHT message {args:?}"),
+    }
+    let peer = compact_addr("192.0.2.7:6881".parse().unwrap());
+    let values = [b"l".as_slice(), &bencode_bytes(&peer), b"e"].concat();
+    let reply = krpc_reply(
+        query.transaction_id().unwrap(),
+        &[
+            ("id", bencode_bytes(&info_hash)),
+            ("token", bencode_bytes(b"tk")),
+            ("values", values),
+        ],
+    );
+    closer.send_to(&reply, from).await.unwrap();
+    let (announce, _) = recv_krpc(&closer).await;
+    match announce.into_args(None) {
+        KrpcArgs::Query(QueryArgs::AnnouncePeer {
+            info_hash: got,
+            port,
+            token,
+            ..
+        }) => {
+            assert_eq!(got, info_hash);
+            assert_eq!(token, b"tk");
+            assert_eq!(port, session.listen_port() as i64);
+        }
+        args => panic!("unexpected DHT message {args:?}"),
+    }
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_private_torrent_lsd() {
+    let root = std::env::temp_dir().join("rutor_test_private_torrent_lsd");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let mut torrents = Vec::new();
+    for (name, private) in [("private.bin", true), ("public.bin", false)] {
+        std::fs::write(root.join(name), name.repeat(1000)).unwrap();
+        let bytes = TorrentBuilder::new(root.join(name))
+            .private(private)
+            .build()
+            .unwrap();
+        torrents.push(MetaInfo::from_bytes(&bytes).unwrap());
+    }
+
+    let lsd = Lsd::bind(16772, 7003).unwrap();
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        lsd: true,
+        lsd_port: 16772,
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let public_hash = *torrents[1].info_hash().inner().truncate();
+    for metainfo in torrents {
+        session
+            .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+                metainfo,
+            ))))
+            .await
+            .unwrap();
+    }
+    let (info_hashes, peer) = tokio::time::timeout(Duration::from_secs(5), lsd.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info_hashes, [public_hash]);
+    assert_eq!(peer.port(), session.listen_port());
+    // the IPv6 group may deliver another copy of the same announce
+    while let Ok(res) = tokio::time::timeout(Duration::from_millis(500), lsd.recv()).await {
+        assert_eq!(res.unwrap().0, [public_hash]);
+    }
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_lsd_loopback() {
+    let a = Lsd::bind(16771, 7001).unwrap();
+    let b = Lsd::bind(16771, 7002).unwrap();
+    a.announce(&[[1; 20], [2; 20]]).await.unwrap();
+    let (info_hashes, peer) = tokio::time::timeout(Duration::from_secs(5), b.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info_hashes, [[1; 20], [2; 20]]);
+    assert_eq!(peer.port(), 7001);
+    // our own announce is looped back but skipped
+    assert!(tokio::time::timeout(Duration::from_millis(500), a.recv())
+        .await
+        .is_err());
+}
+
+#[test]
+fn test_identify_udp_protocol() {
+    assert_eq!(
+        identify_udp_protocol(b"d1:ad2:id20:aaaaaaaaaaaaaaaaaaaae1:q4:ping1:t2:aa1:y1:qe"),
+        UdpProtocol::Dht
+    );
+    for packet_type in [
+        PacketType::Data,
+        PacketType::Fin,
+        PacketType::State,
+        PacketType::Reset,
+        PacketType::Syn,
+    ] {
+        let mut packet = Packet::new(packet_type, 1, 2, 3);
+        assert_eq!(identify_udp_protocol(&packet.to_bytes()), UdpProtocol::Utp);
+        packet.selective_ack = Some(vec![1, 0, 0, 0]);
+        packet.payload = b"d1:ae".to_vec();
+        assert_eq!(identify_udp_protocol(&packet.to_bytes()), UdpProtocol::Utp);
+    }
+
+    let mut connect = vec![0, 0, 0, 0, 0, 0, 0, 42];
+    connect.extend([7; 8]);
+    assert_eq!(identify_udp_protocol(&connect), UdpProtocol::Tracker);
+    assert_eq!(identify_udp_protocol(&connect[..12]), UdpProtocol::Unknown);
+    let mut error = vec![0, 0, 0, 3, 0, 0, 0, 42];
+    error.extend(b"denied");
+    assert_eq!(identify_udp_protocol(&error), UdpProtocol::Tracker);
+    assert_eq!(
+        identify_udp_protocol(&[0, 0, 0, 9, 0, 0, 0, 42]),
+        UdpProtocol::Unknown
+    );
+    assert_eq!(identify_udp_protocol(b"d1:a"), UdpProtocol::Unknown);
+    assert_eq!(identify_udp_protocol(&[0xff; 32]), UdpProtocol::Unknown);
+}
+
+#[tokio::test]
+async fn test_udp_counters() {
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        dht: false,
+        lsd: false,
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = SocketAddr::from(([127, 0, 0, 1], session.listen_port()));
+    let utp = Packet::new(PacketType::State, 999, 1, 1).to_bytes();
+    for datagram in [&b"d1:y1:re"[..], &utp, &[0xff; 32]] {
+        socket.send_to(datagram, addr).await.unwrap();
+    }
+
+    let expected = UdpCounters {
+        dht: 1,
+        utp: 1,
+        unknown: 1,
+        ..Default::default()
+    };
+    for _ in 0..50 {
+        if session.udp_counters() == expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(session.udp_counters(), expected);
+}
+
+#[tokio::test]
+async fn test_encrypted_inbound_handshake() {
+    let root = std::env::temp_dir().join("rutor_test_encrypted_handshake");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("data.bin"), vec![5u8; 40_000]).unwrap();
+    let bytes = TorrentBuilder::new(root.join("data.bin"))
+        .piece_length(16 * 1024)
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info_hash = *metainfo.info_hash().v1().unwrap().truncate();
+
+    let mut ports = Vec::new();
+    let mut sessions = Vec::new();
+    for encryption in [EncryptionPolicy::Forced, EncryptionPolicy::Disabled] {
+        let session = Session::start_with_settings(SessionSettings {
+            listen_port: 0,
+            save_path: root.join(format!("{encryption:?}")),
+            dht: false,
+            lsd: false,
+            encryption,
+            upnp: false,
+            natpmp: false,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        session
+            .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+                metainfo.clone(),
+            ))))
+            .await
+            .unwrap();
+        ports.push(session.listen_port());
+        sessions.push(session);
+    }
+    let (forced, disabled) = (ports[0], ports[1]);
+    for _ in 0..50 {
+        if handshake(disabled, info_hash).await.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+    let mut reply = None;
+    for _ in 0..50 {
+        if let Ok(stream) = connect_peer(addr(forced), &info_hash, EncryptionPolicy::Forced).await {
+            assert!(stream.is_encrypted());
+            reply = exchange_handshake(stream, info_hash).await;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(reply, Some(info_hash));
+    assert_eq!(handshake(forced, info_hash).await, None);
+
+    // uTP peers go through the same encryption as TCP ones
+    let utp = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    let stream = utp.connect(addr(forced)).await.unwrap();
+    let stream = mse_connect(stream, &info_hash, EncryptionPolicy::Forced)
+        .await
+        .unwrap();
+    assert!(stream.is_encrypted());
+    assert_eq!(exchange_handshake(stream, info_hash).await, Some(info_hash));
+    assert_eq!(utp_handshake(&utp, forced, info_hash).await, None);
+
+    // plaintext fallback against a peer without encryption
+    let stream = connect_peer(addr(disabled), &info_hash, EncryptionPolicy::Preferred)
+        .await
+        .unwrap();
+    assert!(!stream.is_encrypted());
+    assert_eq!(exchange_handshake(stream, info_hash).await, Some(info_hash));
+    assert!(
+        connect_peer(addr(disabled), &info_hash, EncryptionPolicy::Forced)
+            .await
+            .is_err()
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+/// Answers one BEP 15 connect and announce, returns the announce and its sender.
+async fn serve_udp_tracker(tracker: &UdpSocket, peer: SocketAddr) -> (Vec<u8>, SocketAddr) {
+    let mut buf = [0u8; 2048];
+    let (n, from) = tracker.recv_from(&mut buf).await.unwrap();
+    assert_eq!(n, 16);
+    let mut reply = vec![0, 0, 0, 0];
+    reply.extend(&buf[12..16]);
+    reply.extend([5; 8]);
+    tracker.send_to(&reply, from).await.unwrap();
+
+    let (n, from) = tracker.recv_from(&mut buf).await.unwrap();
+    assert_eq!(n, 98);
+    assert_eq!(buf[..8], [5; 8]);
+    let mut reply = vec![0, 0, 0, 1];
+    reply.extend(&buf[12..16]);
+    reply.extend([0, 0, 7, 8, 0, 0, 0, 1, 0, 0, 0, 1]);
+    reply.extend(rutor::peers::compact_peer_bytes(&peer));
+    tracker.send_to(&reply, from).await.unwrap();
+    (buf[..n].to_vec(), from)
+}
+
+#[tokio::test]
+async fn test_dual_stack_announce() {
+    let root = std::env::temp_dir().join("rutor_test_dual_stack_announce");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let tracker_v4 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let tracker_v6 = UdpSocket::bind("[::1]:0").await.unwrap();
+    std::fs::write(root.join("data.bin"), "dual stack".repeat(1000)).unwrap();
+    let bytes = TorrentBuilder::new(root.join("data.bin"))
+        .tracker(format!("udp://{}", tracker_v4.local_addr().unwrap()))
+        .tracker(format!(
+            "udp://{}/announce",
+            tracker_v6.local_addr().unwrap()
+        ))
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info_hash = *metainfo.info_hash().inner().truncate();
+
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        dht: false,
+        lsd: false,
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let port = session.listen_port();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo,
+        ))))
+        .await
+        .unwrap();
+
+    let wait = Duration::from_secs(10);
+    let peer_v4 = "10.0.0.1:6881".parse().unwrap();
+    let peer_v6 = "[2001:db8::1]:6881".parse().unwrap();
+    for (tracker, peer) in [(&tracker_v4, peer_v4), (&tracker_v6, peer_v6)] {
+        let (announce, from) = tokio::time::timeout(wait, serve_udp_tracker(tracker, peer))
+            .await
+            .unwrap();
+        assert_eq!(from.is_ipv6(), peer.is_ipv6());
+        assert_eq!(announce[16..36], info_hash);
+        assert_eq!(announce[96..98], port.to_be_bytes());
+    }
+
+    // the session listens on IPv6 too
+    let stream = TcpStream::connect(("::1", port)).await.unwrap();
+    assert_eq!(exchange_handshake(stream, info_hash).await, Some(info_hash));
+    let utp = UtpSocket::bind("[::1]:0").await.unwrap();
+    let stream = utp
+        .connect(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port)))
+        .await
+        .unwrap();
+    assert_eq!(exchange_handshake(stream, info_hash).await, Some(info_hash));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_tracker_events() {
+    let root = std::env::temp_dir().join("rutor_test_tracker_events");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let data = "tracker events".repeat(2000);
+    std::fs::write(root.join("data.bin"), &data).unwrap();
+    let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let web_seed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    // the first tier refuses connections, so the second one is announced to
+    let bytes = TorrentBuilder::new(root.join("data.bin"))
+        .tracker("http://127.0.0.1:1/announce")
+        .tracker(format!("udp://{}", tracker.local_addr().unwrap()))
+        .web_seed(format!(
+            "http://{}/data.bin",
+            web_seed.local_addr().unwrap()
+        ))
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let torrent_id = *metainfo.info_hash().inner().truncate();
+
+    let session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        dht: false,
+        lsd: false,
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo,
+        ))))
+        .await
+        .unwrap();
+
+    let wait = Duration::from_secs(10);
+    let peer = "10.0.0.1:6881".parse().unwrap();
+    let event = |announce: &[u8]| u32::from_be_bytes(announce[80..84].try_into().unwrap());
+    let left = |announce: &[u8]| u64::from_be_bytes(announce[64..72].try_into().unwrap());
+    let (announce, _) = tokio::time::timeout(wait, serve_udp_tracker(&tracker, peer))
+        .await
+        .unwrap();
+    assert_eq!((event(&announce), left(&announce)), (2, data.len() as u64));
+
+    // the web seed serves the file once `started` went out
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = web_seed.accept().await.unwrap();
+            let data = data.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                let len = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..len]).to_lowercase();
+                let (start, end) = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().split_once('-'))
+                    .map(|(start, end)| (start.parse().unwrap(), end.parse().unwrap()))
+                    .unwrap_or((0, data.len() - 1));
+                let header = format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    end + 1 - start
+                );
+                let _ = socket.write_all(header.as_bytes()).await;
+                let _ = socket.write_all(&data.as_bytes()[start..=end]).await;
+            });
+        }
+    });
+    let (announce, _) = tokio::time::timeout(wait, serve_udp_tracker(&tracker, peer))
+        .await
+        .unwrap();
+    assert_eq!((event(&announce), left(&announce)), (1, 0));
+
+    session
+        .send(SessionCommand::PauseTorrent(torrent_id))
+        .await
+        .unwrap();
+    let (announce, _) = tokio::time::timeout(wait, serve_udp_tracker(&tracker, peer))
+        .await
+        .unwrap();
+    assert_eq!(event(&announce), 3);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+/// Skips other alerts until `f` returns something.
+async fn recv_alert<T>(session: &mut Session, mut f: impl FnMut(SessionAlert) -> Option<T>) -> T {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(value) = f(session.recv().await.unwrap()) {
+                return value;
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_ip_filter() {
+    let root = std::env::temp_dir().join("rutor_test_ip_filter");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let filter_path = root.join("ipfilter.dat");
+    let private = "010.000.000.000 - 010.255.255.255 , 000 , Private\n";
+    std::fs::write(
+        &filter_path,
+        format!("{private}Loopback:127.0.0.1-127.0.0.1\n"),
+    )
+    .unwrap();
+
+    let tracker = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+    std::fs::write(root.join("data.bin"), "ip filter".repeat(1000)).unwrap();
+    let bytes = TorrentBuilder::new(root.join("data.bin"))
+        .tracker(format!("udp://{}", tracker.local_addr().unwrap()))
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info_hash = *metainfo.info_hash().inner().truncate();
+
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        save_path: root.join("downloads"),
+        dht: false,
+        lsd: false,
+        ip_filter: Some(filter_path.clone()),
+        upnp: false,
+        natpmp: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let port = session.listen_port();
+    session
+        .send(SessionCommand::AddTorrent(Box::new(TorrentSource::File(
+            metainfo,
+        ))))
+        .await
+        .unwrap();
+
+    // peers from the tracker
+    let peer = "10.0.0.1:6881".parse().unwrap();
+    tokio::spawn(async move { serve_udp_tracker(&tracker, peer).await });
+    let blocked = recv_alert(&mut session, |alert| match alert {
+        SessionAlert::PeersBlocked { blocked } => Some(blocked),
+        _ => None,
+    })
+    .await;
+    assert_eq!(blocked, 1);
+
+    // incoming and outgoing connections
+    assert_eq!(handshake(port, info_hash).await, None);
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    assert!(session.connect_peer(addr, &info_hash).await.is_err());
+    assert_eq!(session.blocked_peers(), 3);
+    let blocked = recv_alert(&mut session, |alert| match alert {
+        SessionAlert::PeersBlocked { blocked } => Some(blocked),
+        _ => None,
+    })
+    .await;
+    assert_eq!(blocked, 3);
+
+    std::fs::write(&filter_path, private).unwrap();
+    session.send(SessionCommand::ReloadIpFilter).await.unwrap();
+    let num_ranges = recv_alert(&mut session, |alert| match alert {
+        SessionAlert::IpFilterReloaded { num_ranges } => Some(num_ranges),
+        _ => None,
+    })
+    .await;
+    assert_eq!(num_ranges, 1);
+    let stream = session.connect_peer(addr, &info_hash).await.unwrap();
+    assert_eq!(exchange_handshake(stream, info_hash).await, Some(info_hash));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_ban_list() {
+    let path = std::env::temp_dir().join("rutor_test_ban_list.dat");
+    let _ = std::fs::remove_file(&path);
+    let (alert_tx, mut alert_rx) = tokio::sync::mpsc::channel(4);
+    let filter = SessionIpFilter::new(IpFilter::new(), alert_tx.clone())
+        .with_ban_list(path.clone())
+        .unwrap();
+    let peer: SocketAddr = "10.0.0.2:6881".parse().unwrap();
+    assert!(filter.allows(&peer));
+    filter.ban(peer.ip());
+    filter.ban(peer.ip());
+    assert!(!filter.allows(&peer));
+    assert!(filter.allows(&"10.0.0.3:6881".parse().unwrap()));
+    assert!(matches!(
+        alert_rx.recv().await,
+        Some(SessionAlert::PeerBanned(ip)) if ip == peer.ip()
+    ));
+
+    // bans outlive the session
+    let filter = SessionIpFilter::new(IpFilter::new(), alert_tx)
+        .with_ban_list(path.clone())
+        .unwrap();
+    assert!(filter.is_banned(&peer.ip()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// NAT-PMP gateway, or PCP with `pcp`, granting 2 second leases. Mapping
+/// requests are reported as `(protocol, external port, lifetime)`.
+async fn spawn_fake_natpmp_gateway(
+    pcp: bool,
+) -> (SocketAddr, Receiver<(MappingProtocol, u16, u32)>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1100];
+        loop {
+            let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+            let reply = match (buf[0], pcp) {
+                (2, true) => {
+                    let request = PcpMapRequest::from_bytes(&buf[..n]).unwrap();
+                    let _ = tx
+                        .send((request.protocol, request.external_port, request.lifetime))
+                        .await;
+                    PcpMapResponse {
+                        lifetime: request.lifetime.min(2),
+                        epoch: 1,
+                        nonce: request.nonce,
+                        protocol: request.protocol,
+                        internal_port: request.internal_port,
+                        external_port: 40000,
+                        external_ip: "203.0.113.7".parse().unwrap(),
+                    }
+                    .to_bytes()
+                }
+                // NAT-PMP gateways answer PCP with an unsupported version
+                (2, false) => vec![0, buf[1] | 0x80, 0, 1, 0, 0, 0, 1],
+                _ => match NatPmpRequest::from_bytes(&buf[..n]).unwrap() {
+                    NatPmpRequest::ExternalAddress => NatPmpResponse::ExternalAddress {
+                        epoch: 1,
+                        addr: "203.0.113.7".parse().unwrap(),
+                    }
+                    .to_bytes(),
+                    NatPmpRequest::Map {
+                        protocol,
+                        internal_port,
+                        external_port,
+                        lifetime,
+                    } => {
+                        let _ = tx.send((protocol, external_port, lifetime)).await;
+                        NatPmpResponse::Map {
+                            protocol,
+                            epoch: 1,
+                            internal_port,
+                            external_port: internal_port,
+                            lifetime: lifetime.min(2),
+                        }
+                        .to_bytes()
+                    }
+                },
+            };
+            socket.send_to(&reply, from).await.unwrap();
+        }
+    });
+    (addr, rx)
+}
+
+#[tokio::test]
+async fn test_natpmp_port_mapping() {
+    for pcp in [false, true] {
+        let (gateway, mut requests) = spawn_fake_natpmp_gateway(pcp).await;
+        let mut session = Session::start_with_settings(SessionSettings {
+            listen_port: 0,
+            dht: false,
+            lsd: false,
+            upnp: false,
+            natpmp_gateway: Some(gateway),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let port = session.listen_port();
+        let (method, external_port) = match pcp {
+            true => (PortMappingMethod::Pcp, 40000),
+            false => (PortMappingMethod::NatPmp, port),
+        };
+
+        let mut mapped = Vec::new();
+        while mapped.len() < 2 {
+            let mapping = recv_alert(&mut session, |alert| match alert {
+                SessionAlert::PortMapped(mapping) => Some(mapping),
+                _ => None,
+            })
+            .await;
+            assert_eq!(mapping.method, method);
+            assert_eq!(mapping.internal_port, port);
+            assert_eq!(mapping.external_port, external_port);
+            assert_eq!(mapping.external_ip, Some("203.0.113.7".parse().unwrap()));
+            mapped.push(mapping.protocol);
+        }
+        mapped.sort();
+        assert_eq!(mapped, [MappingProtocol::Tcp, MappingProtocol::Udp]);
+        assert_eq!(session.external_ip(), Some("203.0.113.7".parse().unwrap()));
+
+        // the 2 second leases are renewed after a second
+        let wait = Duration::from_secs(5);
+        let mut renewals = 0;
+        while renewals < 4 {
+            let (_, _, lifetime) = tokio::time::timeout(wait, requests.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(lifetime > 0);
+            renewals += 1;
+        }
+
+        drop(session);
+        let mut removed = Vec::new();
+        while removed.len() < 2 {
+            let (protocol, _, lifetime) = tokio::time::timeout(wait, requests.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if lifetime == 0 {
+                removed.push(protocol);
+            }
+        }
+        removed.sort();
+        assert_eq!(removed, [MappingProtocol::Tcp, MappingProtocol::Udp]);
+    }
+}
+
+async fn read_http_request(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        header.push(stream.read_u8().await.ok()?);
+    }
+    let header = String::from_utf8(header).unwrap();
+    let len = header
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .map_or(0, |(_, value)| value.trim().parse().unwrap());
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await.ok()?;
+    Some((header, String::from_utf8(body).unwrap()))
+}
+
+/// UPnP IGD that only accepts permanent leases. SOAP requests are reported
+/// as `(action, body)`.
+async fn spawn_fake_igd() -> (SocketAddr, Receiver<(String, String)>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    let ssdp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let ssdp_addr = ssdp.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (n, from) = ssdp.recv_from(&mut buf).await.unwrap();
+            assert!(buf[..n].starts_with(b"M-SEARCH * HTTP/1.1\r\n"));
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                 LOCATION: http://{http_addr}/rootDesc.xml\r\n\r\n"
+            );
+            ssdp.send_to(reply.as_bytes(), from).await.unwrap();
+        }
+    });
+
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while let Some((header, body)) = read_http_request(&mut stream).await {
+                    let action = header
+                        .lines()
+                        .find_map(|line| line.strip_prefix("soapaction: "))
+                        .and_then(|value| value.trim_matches('"').split_once('#'))
+                        .map(|(_, action)| action.to_string())
+                        .unwrap_or_default();
+                    let (status, response) = match action.as_str() {
+                        "" => (
+                            "200 OK",
+                            "<root><device><serviceList><service>\
+                             <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+                             <controlURL>/ctl/IPConn</controlURL>\
+                             </service></serviceList></device></root>"
+                                .to_string(),
+                        ),
+                        "AddPortMapping"
+                            if !body.contains("<NewLeaseDuration>0</NewLeaseDuration>") =>
+                        {
+                            (
+                                "500 Internal Server Error",
+                                "<s:Envelope><s:Body><s:Fault><detail><UPnPError>\
+                                 <errorCode>725</errorCode></UPnPError></detail>\
+                                 </s:Fault></s:Body></s:Envelope>"
+                                    .to_string(),
+                            )
+                        }
+                        "GetExternalIPAddress" => (
+                            "200 OK",
+                            "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+                             <NewExternalIPAddress>203.0.113.9</NewExternalIPAddress>\
+                             </u:GetExternalIPAddressResponse></s:Body></s:Envelope>"
+                                .to_string(),
+                        ),
+                        _ => ("200 OK", "<s:Envelope></s:Envelope>".to_string()),
+                    };
+                    if !action.is_empty() {
+                        let _ = tx.send((action, body)).await;
+                    }
+                    let reply = format!(
+                        "HTTP/1.1 {status}\r\nContent-Type: text/xml\r\nContent-Length: {}\r\n\r\n{response}",
+                        response.len()
+                    );
+                    if stream.write_all(reply.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    (ssdp_addr, rx)
+}
+
+#[tokio::test]
+async fn test_upnp_port_mapping() {
+    let (ssdp_addr, mut requests) = spawn_fake_igd().await;
+    let mut session = Session::start_with_settings(SessionSettings {
+        listen_port: 0,
+        dht: false,
+        lsd: false,
+        natpmp: false,
+        ssdp_addr,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let port = session.listen_port();
+
+    let mut mapped = Vec::new();
+    while mapped.len() < 2 {
+        let mapping = recv_alert(&mut session, |alert| match alert {
+            SessionAlert::PortMapped(mapping) => Some(mapping),
+            _ => None,
+        })
+        .await;
+        assert_eq!(mapping.method, PortMappingMethod::Upnp);
+        assert_eq!(mapping.external_port, port);
+        assert_eq!(mapping.external_ip, Some("203.0.113.9".parse().unwrap()));
+        // the gateway only accepts permanent leases
+        assert_eq!(mapping.lifetime, Duration::ZERO);
+        mapped.push(mapping.protocol);
+    }
+    mapped.sort();
+    assert_eq!(mapped, [MappingProtocol::Tcp, MappingProtocol::Udp]);
+    assert_eq!(session.external_ip(), Some("203.0.113.9".parse().unwrap()));
+
+    let wait = Duration::from_secs(5);
+    let mut added = 0;
+    while added < 2 {
+        let (action, body) = tokio::time::timeout(wait, requests.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if action == "AddPortMapping" && body.contains("<NewLeaseDuration>0</NewLeaseDuration>") {
+            assert!(body.contains(&format!("<NewInternalPort>{port}</NewInternalPort>")));
+            assert!(body.contains("<NewInternalClient>127.0.0.1</NewInternalClient>"));
+            added += 1;
+        }
+    }
+
+    drop(session);
+    let mut removed = Vec::new();
+    while removed.len() < 2 {
+        let (action, body) = tokio::time::timeout(wait, requests.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if action == "DeletePortMapping" {
+            assert!(body.contains(&format!("<NewExternalPort>{port}</NewExternalPort>")));
+            removed.push(body.contains("<NewProtocol>TCP</NewProtocol>"));
+        }
+    }
+    removed.sort();
+    assert_eq!(removed, [false, true]);
+}
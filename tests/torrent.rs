@@ -1,19 +1,28 @@
-use std::collections::BTreeMap;
+use rutor::disk::layout::Layout;
+use rutor::error::Error;
+use rutor::peers::{IpFilter, PeerConnection};
+use rutor::proto;
+use rutor::proto::bep10::ExtendedHandshake;
+use rutor::proto::infohash::{InfoHash, InfoHashV1};
+use rutor::proto::metainfo::{merkle, MetaInfo, TorrentBuilder, TorrentVersion};
+use rutor::session::{SessionAlert, SessionIpFilter};
+use rutor::torrent::{
+    FilePriority, HashTrees, PeerSource, PiecePicker, SeedingGoal, SeedingGoalAction,
+    SeedingLimits, SmartBan, TorrentCommand, TorrentHandle, TorrentInitStateParams, TorrentSource,
+    TorrentSpawnParams, TorrentState, TorrentStatus, WebSeed,
+};
+use rutor::util::parse_http_date;
+use sha1::{Digest, Sha1};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::thread::spawn;
-
-use rutor::proto;
-use rutor::proto::constants::DHT_GET_PEERS_QUERY_STR;
-use rutor::proto::dht::{fetch_add_dht_transaction_id, DhtTransactionID};
-use rutor::session::RedirectChan;
-use rutor::torrent::{self, TorrentSource};
-use tokio::net::UdpSocket;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::net::TcpListener;
 
 #[tokio::test]
 async fn test_metainfo() {
-    let source = TorrentSource::from_str("resources/Red_Hot_Chili_Peppers.torrent")
+    let source = TorrentSource::from_str("resources/Red_Hot_Chili_Peppers.torrent", None)
         .await
         .unwrap();
     println!("{:#?}", source);
@@ -160,3 +169,1554 @@ async fn test_metainfo() {
 //         println!("{:#?}", res);
 //     }
 // }
+
+fn init_state(status: TorrentStatus) -> TorrentState {
+    TorrentState::init(TorrentInitStateParams {
+        status,
+        port: 6881,
+        peer_id: proto::PeerId::gen_new(),
+        info_hash: InfoHash::V1(InfoHashV1::new([7; 20])),
+        info: None,
+        piece_layers: Default::default(),
+        save_path: PathBuf::from("downloads"),
+        announce_list: Vec::new(),
+        url_list: Vec::new(),
+        httpseeds: Vec::new(),
+        num_pieces: 4,
+        bitfield: None,
+        have_pieces: 4,
+        uploaded: 0,
+        downloaded: 0,
+        left: 1000,
+        select_only: Vec::new(),
+        ip_filter: None,
+    })
+}
+
+#[test]
+fn test_seeding_limits() {
+    let limits = SeedingLimits {
+        ratio: Some(2.),
+        seed_time: Some(Duration::from_secs(3600)),
+        idle_time: Some(Duration::from_secs(600)),
+        ..Default::default()
+    };
+    assert_eq!(limits.check(None, Duration::ZERO, Duration::ZERO), None);
+    assert_eq!(
+        limits.check(Some(2.5), Duration::ZERO, Duration::ZERO),
+        Some(SeedingGoal::Ratio)
+    );
+    assert_eq!(
+        limits.check(Some(1.), Duration::from_secs(3600), Duration::ZERO),
+        Some(SeedingGoal::SeedTime)
+    );
+    assert_eq!(
+        limits.check(Some(1.), Duration::ZERO, Duration::from_secs(601)),
+        Some(SeedingGoal::IdleTime)
+    );
+    assert_eq!(
+        SeedingLimits::default().check(Some(100.), Duration::MAX, Duration::MAX),
+        None
+    );
+}
+
+#[test]
+fn test_torrent_state_seeding_goal() {
+    let defaults = SeedingLimits {
+        ratio: Some(1.),
+        action: SeedingGoalAction::Remove,
+        ..Default::default()
+    };
+
+    let mut state = init_state(TorrentStatus::Downloading);
+    state.on_downloaded(1000);
+    state.on_uploaded(1500);
+    assert_eq!(state.progress.ratio(), Some(1.5));
+    assert_eq!(state.check_seeding_goal(&defaults), None);
+
+    state.set_status(TorrentStatus::Seeding);
+    assert_eq!(
+        state.check_seeding_goal(&defaults),
+        Some((SeedingGoal::Ratio, SeedingGoalAction::Remove))
+    );
+
+    state.seeding_limits = Some(SeedingLimits {
+        ratio: Some(2.),
+        idle_time: Some(Duration::ZERO),
+        action: SeedingGoalAction::Pause,
+        ..Default::default()
+    });
+    assert_eq!(
+        state.check_seeding_goal(&defaults),
+        Some((SeedingGoal::IdleTime, SeedingGoalAction::Pause))
+    );
+
+    state.set_status(TorrentStatus::Stopped);
+    assert_eq!(state.check_seeding_goal(&defaults), None);
+}
+
+#[test]
+fn test_seeding_ratio_without_download() {
+    let bytes = std::fs::read("resources/Books.torrent").unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let total = metainfo.info.total_length();
+    let mut state = TorrentState::init(TorrentInitStateParams {
+        status: TorrentStatus::Seeding,
+        port: 6881,
+        peer_id: proto::PeerId::gen_new(),
+        info_hash: InfoHash::V1(InfoHashV1::new([7; 20])),
+        info: Some(metainfo.info.clone()),
+        piece_layers: Default::default(),
+        save_path: PathBuf::from("downloads"),
+        announce_list: Vec::new(),
+        url_list: Vec::new(),
+        httpseeds: Vec::new(),
+        num_pieces: metainfo.info.num_pieces(),
+        bitfield: None,
+        have_pieces: metainfo.info.num_pieces(),
+        uploaded: 0,
+        downloaded: 0,
+        left: 0,
+        select_only: Vec::new(),
+        ip_filter: None,
+    });
+    let defaults = SeedingLimits {
+        ratio: Some(1.),
+        ..Default::default()
+    };
+    state.on_uploaded(total / 2);
+    assert_eq!(state.progress.ratio(), None);
+    assert_eq!(state.check_seeding_goal(&defaults), None);
+
+    state.on_uploaded(total - total / 2);
+    assert_eq!(state.seeding_ratio(), Some(1.));
+    assert_eq!(
+        state.check_seeding_goal(&defaults),
+        Some((SeedingGoal::Ratio, SeedingGoalAction::Pause))
+    );
+}
+
+#[tokio::test]
+async fn test_file_priorities() {
+    let bytes = std::fs::read("resources/Books.torrent").unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let num_pieces = metainfo.info.num_pieces();
+    let mut state = TorrentState::init(TorrentInitStateParams {
+        status: TorrentStatus::Downloading,
+        port: 6881,
+        peer_id: proto::PeerId::gen_new(),
+        info_hash: InfoHash::V1(InfoHashV1::new([7; 20])),
+        info: Some(metainfo.info.clone()),
+        piece_layers: Default::default(),
+        save_path: std::env::temp_dir().join("rutor_test_file_priorities"),
+        announce_list: Vec::new(),
+        url_list: Vec::new(),
+        httpseeds: Vec::new(),
+        num_pieces,
+        bitfield: None,
+        have_pieces: 0,
+        uploaded: 0,
+        downloaded: 0,
+        left: metainfo.info.total_length(),
+        select_only: Vec::new(),
+        ip_filter: None,
+    });
+    let layout = state.layout.clone().unwrap();
+    assert!(layout.files.len() > 1);
+
+    let mut priorities = vec![FilePriority::Skip; layout.files.len()];
+    priorities[0] = FilePriority::High;
+    state.set_file_priorities(priorities);
+
+    let pieces = layout.file_pieces(0);
+    let left: u64 = pieces.clone().map(|i| layout.piece_size(i)).sum();
+    assert_eq!(state.progress.left, left);
+    assert!(!state.is_finished());
+
+    let mut peer = proto::BitField::new(num_pieces);
+    (0..num_pieces).for_each(|i| peer.set(i));
+    state.picker.inc_availability(&peer);
+    let picked = state.picker.pick(&state.bitfield, &peer).unwrap();
+    assert!(pieces.contains(&picked));
+
+    for index in pieces {
+        state.on_piece_completed(index);
+    }
+    assert_eq!(state.progress.left, 0);
+    assert!(state.is_finished());
+    assert_eq!(state.picker.pick(&state.bitfield, &peer), None);
+}
+
+#[tokio::test]
+async fn test_file_priorities_command() {
+    let metainfo = web_seed_metainfo(&web_seed_files(), &[], &[]);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: std::env::temp_dir().join("rutor_test_file_priorities_command"),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: None,
+        },
+    )
+    .await;
+
+    // the state stays available while the disk is busy
+    let disk = torrent.state.lock().await.disk.clone().unwrap();
+    let busy = disk.lock().await;
+    torrent
+        .send(TorrentCommand::SetFilePriorities(vec![FilePriority::Skip]))
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let priorities = torrent.state.lock().await.file_priorities().to_vec();
+            if priorities == [FilePriority::Skip, FilePriority::Normal] {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    drop(busy);
+}
+
+#[tokio::test]
+async fn test_magnet_select_only() {
+    let bytes = std::fs::read("resources/Books.torrent").unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let magnet: proto::MagnetLink = format!(
+        "magnet:?xt=urn:btih:{}&so=0,2-3",
+        metainfo.info_hash().inner().hex()
+    )
+    .parse()
+    .unwrap();
+    let mut state = TorrentState::init(TorrentInitStateParams {
+        status: TorrentStatus::Downloading,
+        port: 6881,
+        peer_id: proto::PeerId::gen_new(),
+        info_hash: magnet.info_hash,
+        info: None,
+        piece_layers: Default::default(),
+        save_path: std::env::temp_dir().join("rutor_test_magnet_select_only"),
+        announce_list: Vec::new(),
+        url_list: Vec::new(),
+        httpseeds: Vec::new(),
+        num_pieces: 0,
+        bitfield: None,
+        have_pieces: 0,
+        uploaded: 0,
+        downloaded: 0,
+        left: 0,
+        select_only: magnet.select_only,
+        ip_filter: None,
+    });
+    assert_eq!(state.select_only(), [0..=0, 2..=3]);
+    assert!(state.file_priorities().is_empty());
+
+    let other = std::fs::read("resources/ubuntu-25.04-desktop-amd64.iso.torrent").unwrap();
+    let other = MetaInfo::from_bytes(&other).unwrap();
+    assert!(!state
+        .on_metadata(other.info_bytes().unwrap().to_vec())
+        .await
+        .unwrap());
+    assert!(state.info.is_none());
+    assert!(state
+        .on_metadata(metainfo.info_bytes().unwrap().to_vec())
+        .await
+        .unwrap());
+    let layout = state.layout.clone().unwrap();
+    assert!(layout.files.len() > 4);
+    let priorities = state.file_priorities();
+    assert_eq!(priorities.len(), layout.files.len());
+    for (index, priority) in priorities.iter().enumerate() {
+        assert_eq!(priority.is_wanted(), [0, 2, 3].contains(&index));
+    }
+    let left: u64 = (0..layout.num_pieces)
+        .filter(|&i| state.picker.piece_priority(i).is_wanted())
+        .map(|i| layout.piece_size(i))
+        .sum();
+    assert_eq!(state.progress.left, left);
+    assert!(left < metainfo.info.total_length());
+}
+
+#[test]
+fn test_private_torrent_peer_sources() {
+    let path = std::env::temp_dir().join("rutor_test_private_peer_sources.bin");
+    std::fs::write(&path, vec![5u8; 40_000]).unwrap();
+    let bytes = TorrentBuilder::new(&path).private(true).build().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+
+    let mut state = init_state(TorrentStatus::Downloading);
+    let peers = ["10.0.0.1:6881", "10.0.0.2:6881"].map(|addr| addr.parse::<SocketAddr>().unwrap());
+    assert_eq!(state.add_peers(PeerSource::Dht, [peers[0]]), 1);
+    assert!(!state.is_private());
+
+    state.info = Some(metainfo.info);
+    assert!(state.is_private());
+    for source in [
+        PeerSource::Dht,
+        PeerSource::Pex,
+        PeerSource::Lsd,
+        PeerSource::Magnet,
+    ] {
+        assert!(!state.allows_peer_source(source));
+        assert_eq!(state.add_peers(source, [peers[1]]), 0);
+    }
+    assert_eq!(state.add_peers(PeerSource::Tracker, peers), 1);
+    assert_eq!(state.peers().get(&peers[1]), Some(&PeerSource::Tracker));
+}
+
+#[test]
+fn test_connection_alternate_addr() {
+    let mut state = init_state(TorrentStatus::Downloading);
+    let addr: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+    let v6 = "2001:db8::7".parse().unwrap();
+    let connection = PeerConnection {
+        addr,
+        handshake: proto::Handshake::new([0; 68]),
+        extended: Some(ExtendedHandshake::local(6881, addr.ip(), Some(v6))),
+        stream: Box::new(tokio::io::duplex(64).0),
+    };
+    assert!(state.add_connection(connection).is_some());
+    assert_eq!(state.peers().get(&addr), Some(&PeerSource::Incoming));
+    assert_eq!(
+        state.peers().get(&SocketAddr::new(v6, 6881)),
+        Some(&PeerSource::Incoming)
+    );
+}
+
+#[tokio::test]
+async fn test_peer_disconnect() {
+    let metainfo = web_seed_metainfo(&web_seed_files(), &[], &[]);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Stopped,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: std::env::temp_dir().join("rutor_test_peer_disconnect"),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: None,
+        },
+    )
+    .await;
+
+    let addr: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+    let (stream, mut remote) = tokio::io::duplex(64);
+    let connection = PeerConnection {
+        addr,
+        handshake: proto::Handshake::new([0; 68]),
+        extended: None,
+        stream: Box::new(stream),
+    };
+    torrent
+        .send(TorrentCommand::IncomingPeer(Box::new(connection)))
+        .await
+        .unwrap();
+    remote.write_all(&[0; 4]).await.unwrap();
+    let connected = |torrent: &TorrentHandle| {
+        let state = torrent.state.clone();
+        async move { state.lock().await.connections().contains_key(&addr) }
+    };
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !connected(&torrent).await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    drop(remote);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while connected(&torrent).await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+/// A peer with every piece of `data`, `corrupt` flips a byte of every block
+/// it sends.
+async fn serve_pieces(mut stream: DuplexStream, data: Vec<u8>, corrupt: bool) {
+    let num_pieces = data.len().div_ceil(16384);
+    for message in [
+        proto::Message::BitField(proto::BitField::full(num_pieces)),
+        proto::Message::UnChoke,
+    ] {
+        stream.write_all(&message.to_bytes()).await.unwrap();
+    }
+    while let Ok(len) = stream.read_u32().await {
+        let mut buf = len.to_be_bytes().to_vec();
+        buf.resize(4 + len as usize, 0);
+        if stream.read_exact(&mut buf[4..]).await.is_err() {
+            return;
+        }
+        let proto::Message::Request(request) = proto::Message::from_bytes(&buf) else {
+            continue;
+        };
+        let start = request.index as usize * 16384 + request.begin as usize;
+        let mut block = data[start..start + request.length as usize].to_vec();
+        if corrupt {
+            block[0] ^= 0xff;
+        }
+        let piece = proto::Piece::new(request.index, request.begin, block);
+        if stream
+            .write_all(&proto::Message::Piece(piece).to_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn connect_peer(torrent: &TorrentHandle, addr: SocketAddr) -> DuplexStream {
+    let (stream, remote) = tokio::io::duplex(64 * 1024);
+    let connection = PeerConnection {
+        addr,
+        handshake: proto::Handshake::new([0; 68]),
+        extended: None,
+        stream: Box::new(stream),
+    };
+    torrent
+        .send(TorrentCommand::IncomingPeer(Box::new(connection)))
+        .await
+        .unwrap();
+    remote
+}
+
+#[tokio::test]
+async fn test_peer_smart_ban() {
+    let files = web_seed_files();
+    let data: Vec<u8> = files.iter().flat_map(|(_, d)| d.clone()).collect();
+    let metainfo = web_seed_metainfo(&files, &[], &[]);
+    let save_path = std::env::temp_dir().join("rutor_test_peer_smart_ban");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let ip_filter = Arc::new(SessionIpFilter::new(IpFilter::new(), alert_tx.clone()));
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: save_path.clone(),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: Some(ip_filter.clone()),
+        },
+    )
+    .await;
+
+    // the only sender of a bad piece is banned and disconnected
+    let liar: SocketAddr = "10.0.0.2:6881".parse().unwrap();
+    let remote = connect_peer(&torrent, liar).await;
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        serve_pieces(remote, data.clone(), true),
+    )
+    .await
+    .unwrap();
+    assert!(ip_filter.is_banned(&liar.ip()));
+    assert!(!torrent.state.lock().await.connections().contains_key(&liar));
+
+    let honest: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+    let remote = connect_peer(&torrent, honest).await;
+    tokio::spawn(serve_pieces(remote, data.clone(), false));
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !torrent.state.lock().await.is_finished() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(std::fs::read(save_path.join("test/a")).unwrap(), files[0].1);
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_piece_deadline_alert() {
+    let files = web_seed_files();
+    let data: Vec<u8> = files.iter().flat_map(|(_, d)| d.clone()).collect();
+    let metainfo = web_seed_metainfo(&files, &[], &[]);
+    let save_path = std::env::temp_dir().join("rutor_test_piece_deadline_alert");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let (alert_tx, mut alert_rx) = tokio::sync::mpsc::channel(16);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: save_path.clone(),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: None,
+        },
+    )
+    .await;
+
+    for command in [
+        TorrentCommand::SetPieceDeadline(2, Duration::from_secs(60)),
+        TorrentCommand::SetPieceDeadline(1, Duration::from_secs(30)),
+    ] {
+        torrent.send(command).await.unwrap();
+    }
+    let remote = connect_peer(&torrent, "10.0.0.1:6881".parse().unwrap()).await;
+    tokio::spawn(serve_pieces(remote, data, false));
+
+    let mut ready = Vec::new();
+    while ready.len() < 2 {
+        if let SessionAlert::PieceDeadlineReady { piece, late, .. } = alert_rx.recv().await.unwrap()
+        {
+            ready.push((piece, late));
+        }
+    }
+    assert_eq!(ready, vec![(1, false), (2, false)]);
+
+    // pieces we already have are ready right away
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !torrent.state.lock().await.is_finished() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    torrent
+        .send(TorrentCommand::SetPieceDeadline(0, Duration::ZERO))
+        .await
+        .unwrap();
+    loop {
+        if let SessionAlert::PieceDeadlineReady { piece, late, .. } = alert_rx.recv().await.unwrap()
+        {
+            assert_eq!((piece, late), (0, false));
+            break;
+        }
+    }
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_time_critical_peers() {
+    let metainfo = web_seed_metainfo(&web_seed_files(), &[], &[]);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: std::env::temp_dir().join("rutor_test_time_critical_peers"),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: None,
+        },
+    )
+    .await;
+
+    let mut state = torrent.state.lock().await;
+    let fast: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+    let slow: SocketAddr = "10.0.0.2:6881".parse().unwrap();
+    let mut remotes = Vec::new();
+    for addr in [fast, slow] {
+        let (stream, remote) = tokio::io::duplex(64);
+        remotes.push(remote);
+        let connection = PeerConnection {
+            addr,
+            handshake: proto::Handshake::new([0; 68]),
+            extended: None,
+            stream: Box::new(stream),
+        };
+        assert!(state.add_connection(connection).is_some());
+        state.update_peer(&addr, &proto::BitField::full(3), false);
+    }
+    assert!(state.on_peer_block(0, 0, fast, &[0; 16384]));
+    state.tick();
+
+    state
+        .picker
+        .set_piece_deadline(2, Instant::now() + Duration::from_secs(2));
+    state
+        .picker
+        .set_piece_deadline(1, Instant::now() + Duration::from_secs(1));
+    assert_eq!(state.pick_peer_piece(fast), Some(1));
+    assert_eq!(state.pick_peer_piece(slow), Some(2));
+
+    // a peer choking us gets nothing time critical
+    state.update_peer(&fast, &proto::BitField::full(3), true);
+    assert_eq!(state.pick_peer_piece(slow), Some(1));
+}
+
+/// A v2 peer with every piece of `data`, hash requests are answered from
+/// `hash_trees`.
+async fn serve_v2_pieces(mut stream: DuplexStream, data: Vec<u8>, hash_trees: HashTrees) {
+    let num_pieces = data.len().div_ceil(16384);
+    for message in [
+        proto::Message::BitField(proto::BitField::full(num_pieces)),
+        proto::Message::UnChoke,
+    ] {
+        stream.write_all(&message.to_bytes()).await.unwrap();
+    }
+    while let Ok(len) = stream.read_u32().await {
+        let mut buf = len.to_be_bytes().to_vec();
+        buf.resize(4 + len as usize, 0);
+        if stream.read_exact(&mut buf[4..]).await.is_err() {
+            return;
+        }
+        let reply = match proto::Message::from_bytes(&buf) {
+            proto::Message::Request(request) => {
+                let start = request.index as usize * 16384 + request.begin as usize;
+                let block = data[start..start + request.length as usize].to_vec();
+                proto::Message::Piece(proto::Piece::new(request.index, request.begin, block))
+            }
+            proto::Message::HashRequest(request) => match hash_trees.hashes(&request) {
+                Some(hashes) => proto::Message::Hashes(hashes),
+                None => proto::Message::HashReject(request),
+            },
+            _ => continue,
+        };
+        if stream.write_all(&reply.to_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_v2_hashes_from_peer() {
+    let path = std::env::temp_dir().join("rutor_test_v2_hashes_from_peer.bin");
+    let data: Vec<u8> = (0..40_000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let bytes = TorrentBuilder::new(&path)
+        .version(TorrentVersion::V2)
+        .piece_length(16384)
+        .build()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let layout = Layout::from_torrent_info(&metainfo.info);
+    let piece_layers = metainfo.piece_layers.take().unwrap();
+    let hash_trees = HashTrees::new(&metainfo.info, &layout, &piece_layers).unwrap();
+
+    let save_path = std::env::temp_dir().join("rutor_test_v2_hashes_from_peer");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo.clone()),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: save_path.clone(),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: None,
+        },
+    )
+    .await;
+    {
+        let state = torrent.state.lock().await;
+        assert!(!state.hash_trees.as_ref().unwrap().has_piece_layers());
+        assert!(!state.has_piece_hash(0));
+    }
+
+    let (stream, remote) = tokio::io::duplex(64 * 1024);
+    let mut handshake = proto::Handshake::new([0; 68]);
+    handshake[27] |= 0x10;
+    assert!(handshake.supports_v2());
+    let connection = PeerConnection {
+        addr: "10.0.0.1:6881".parse().unwrap(),
+        handshake,
+        extended: None,
+        stream: Box::new(stream),
+    };
+    torrent
+        .send(TorrentCommand::IncomingPeer(Box::new(connection)))
+        .await
+        .unwrap();
+    tokio::spawn(serve_v2_pieces(remote, data.clone(), hash_trees));
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !torrent.state.lock().await.is_finished() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(torrent
+        .state
+        .lock()
+        .await
+        .hash_trees
+        .as_ref()
+        .unwrap()
+        .has_piece_layers());
+    let file_path = save_path.join(&metainfo.info.files()[0].path);
+    assert_eq!(std::fs::read(file_path).unwrap(), data);
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[test]
+fn test_smart_ban() {
+    let path = std::env::temp_dir().join("rutor_test_smart_ban.bin");
+    let data: Vec<u8> = (0..32 * 1024).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let bytes = TorrentBuilder::new(&path)
+        .piece_length(16 * 1024)
+        .build()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+
+    let mut state = init_state(TorrentStatus::Downloading);
+    state.info = Some(metainfo.info);
+    let [honest, liar, other] = ["10.0.0.1:6881", "10.0.0.2:6881", "10.0.0.3:6881"]
+        .map(|addr| addr.parse::<SocketAddr>().unwrap());
+    state.add_peers(PeerSource::Tracker, [honest, liar, other]);
+
+    // four blocks of the first piece, the liar poisons one of them
+    let piece = &data[..16 * 1024];
+    let mut received = piece.to_vec();
+    for (i, peer) in [honest, liar, honest, other].into_iter().enumerate() {
+        let begin = i * 4096;
+        let mut block = piece[begin..begin + 4096].to_vec();
+        if peer == liar {
+            block[7] ^= 0xff;
+        }
+        assert!(state.on_peer_block(0, begin, peer, &block));
+        received[begin..begin + 4096].copy_from_slice(&block);
+    }
+    assert!(!state.verify_peer_piece(0, &received));
+    assert_eq!(state.peers().len(), 3);
+
+    // downloaded again from whoever sends the first block
+    assert!(state.smart_ban.is_exclusive(0));
+    assert!(state.on_peer_block(0, 0, other, &piece[..4096]));
+    assert!(!state.on_peer_block(0, 4096, honest, &piece[4096..8192]));
+    for begin in (4096..piece.len()).step_by(4096) {
+        assert!(state.on_peer_block(0, begin, other, &piece[begin..begin + 4096]));
+    }
+    assert!(state.verify_peer_piece(0, piece));
+    assert!(!state.smart_ban.is_exclusive(0));
+    assert!(!state.peers().contains_key(&liar));
+    assert_eq!(state.peers().len(), 2);
+
+    // a bad piece from a single peer bans it right away
+    let mut bad = data[16 * 1024..].to_vec();
+    bad[0] ^= 1;
+    assert!(state.on_peer_block(1, 0, honest, &bad));
+    assert!(!state.verify_peer_piece(1, &bad));
+    assert_eq!(state.peers().keys().collect::<Vec<_>>(), [&other]);
+
+    // a banned peer gives up its claim on a piece
+    let mut smart_ban = SmartBan::new();
+    assert!(smart_ban.on_block(2, 0, liar.ip(), b"bad!"));
+    assert!(smart_ban.on_block(2, 4, honest.ip(), b"good"));
+    assert!(smart_ban.on_piece_failed(2).is_empty());
+    assert!(smart_ban.on_block(2, 0, liar.ip(), b"bad!"));
+    assert!(!smart_ban.allows(2, honest.ip()));
+    smart_ban.forget_peer(liar.ip());
+    assert!(smart_ban.allows(2, honest.ip()));
+    assert_eq!(smart_ban.on_piece_passed(2, b"goodgood"), [liar.ip()]);
+}
+
+#[test]
+fn test_smart_ban_release() {
+    let [owner, other] =
+        ["10.0.0.1:6881", "10.0.0.2:6881"].map(|addr| addr.parse::<SocketAddr>().unwrap());
+    let mut smart_ban = SmartBan::new();
+    assert!(smart_ban.on_block(0, 0, owner.ip(), b"bad!"));
+    assert!(smart_ban.on_block(0, 4, other.ip(), b"good"));
+    assert!(smart_ban.on_piece_failed(0).is_empty());
+    assert!(smart_ban.on_block(0, 0, owner.ip(), b"good"));
+    assert!(!smart_ban.allows(0, other.ip()));
+    smart_ban.release(0);
+    assert!(smart_ban.is_exclusive(0));
+    assert!(smart_ban.on_block(0, 4, other.ip(), b"good"));
+    assert!(!smart_ban.allows(0, owner.ip()));
+
+    // the owner's connection closes
+    let mut state = init_state(TorrentStatus::Downloading);
+    state.smart_ban = smart_ban;
+    for addr in [other, SocketAddr::new(other.ip(), 6882)] {
+        let connection = PeerConnection {
+            addr,
+            handshake: proto::Handshake::new([0; 68]),
+            extended: None,
+            stream: Box::new(tokio::io::duplex(64).0),
+        };
+        assert!(state.add_connection(connection).is_some());
+    }
+    assert!(state.remove_connection(&other).is_some());
+    assert!(!state.smart_ban.allows(0, owner.ip()));
+    assert!(state
+        .remove_connection(&SocketAddr::new(other.ip(), 6882))
+        .is_some());
+    assert!(state.smart_ban.allows(0, owner.ip()));
+    assert!(state.remove_connection(&other).is_none());
+}
+
+#[test]
+fn test_piece_picker_streaming() {
+    let mut have = proto::BitField::new(8);
+    let mut peer = proto::BitField::new(8);
+    (0..8).for_each(|i| peer.set(i));
+    let mut slow_peer = proto::BitField::new(8);
+    slow_peer.set(6);
+
+    let mut picker = PiecePicker::new(8);
+    picker.inc_availability(&peer);
+    picker.inc_availability(&slow_peer);
+    assert_eq!(picker.pick(&have, &peer), Some(0));
+
+    picker.set_sequential(true);
+    picker.set_playhead(5);
+    assert_eq!(picker.pick(&have, &peer), Some(5));
+    have.set(5);
+    have.set(6);
+    have.set(7);
+    assert_eq!(picker.pick(&have, &peer), Some(0));
+
+    let now = std::time::Instant::now();
+    picker.set_piece_deadline(3, now + Duration::from_secs(2));
+    picker.set_piece_deadline(2, now + Duration::from_secs(1));
+    assert_eq!(picker.pick(&have, &peer), Some(2));
+    assert_eq!(picker.pick(&have, &slow_peer), None);
+
+    let peers = [(&slow_peer, 10), (&peer, 100), (&peer, 50)];
+    assert_eq!(
+        picker.pick_time_critical(&have, &peers),
+        vec![(2, 1), (3, 2)]
+    );
+
+    have.set(2);
+    assert!(picker.remove_piece_deadline(2));
+    assert_eq!(picker.pick(&have, &peer), Some(3));
+}
+
+fn bencode_str(s: &[u8]) -> Vec<u8> {
+    [format!("{}:", s.len()).as_bytes(), s].concat()
+}
+
+/// Multi-file torrent with the given files and web seed urls.
+fn web_seed_torrent(
+    files: &[(&str, &[u8])],
+    piece_length: usize,
+    url_list: &[String],
+    httpseeds: &[String],
+) -> Vec<u8> {
+    let data: Vec<u8> = files.iter().flat_map(|(_, d)| d.to_vec()).collect();
+    let pieces: Vec<u8> = data
+        .chunks(piece_length)
+        .flat_map(|piece| Sha1::digest(piece).to_vec())
+        .collect();
+
+    let mut info = b"d5:filesl".to_vec();
+    for (path, d) in files {
+        if path.starts_with(".pad/") {
+            info.extend(b"d4:attr1:p");
+        } else {
+            info.extend(b"d");
+        }
+        info.extend(format!("6:lengthi{}e4:pathl", d.len()).as_bytes());
+        for component in path.split('/') {
+            info.extend(bencode_str(component.as_bytes()));
+        }
+        info.extend(b"ee");
+    }
+    info.extend(format!("e4:name4:test12:piece lengthi{piece_length}e6:pieces").as_bytes());
+    info.extend(bencode_str(&pieces));
+    info.extend(b"e");
+
+    let mut torrent = b"d4:info".to_vec();
+    torrent.extend(info);
+    match url_list {
+        [] => {}
+        [url] => {
+            torrent.extend(b"8:url-list");
+            torrent.extend(bencode_str(url.as_bytes()));
+        }
+        urls => {
+            torrent.extend(b"8:url-list");
+            torrent.extend(b"l");
+            urls.iter()
+                .for_each(|url| torrent.extend(bencode_str(url.as_bytes())));
+            torrent.extend(b"e");
+        }
+    }
+    if !httpseeds.is_empty() {
+        torrent.extend(b"9:httpseedsl");
+        httpseeds
+            .iter()
+            .for_each(|url| torrent.extend(bencode_str(url.as_bytes())));
+        torrent.extend(b"e");
+    }
+    torrent.extend(b"e");
+    torrent
+}
+
+#[derive(Clone, Copy)]
+enum SeedMode {
+    Good,
+    Corrupt,
+    Busy,
+    BusyUntil,
+    ScriptBusy,
+    NoRanges,
+}
+
+/// Minimal web seed stand-in serving `files` under `/seed/test/` and
+/// 16 KiB pieces through the BEP 17 script at `/script`.
+async fn spawn_web_seed(files: Vec<(String, Vec<u8>)>, mode: SeedMode) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let files = Arc::new(files);
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let files = files.clone();
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(socket);
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                let path = line.split_whitespace().nth(1).unwrap().to_owned();
+                let mut range = None;
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.trim_end().strip_prefix("range: bytes=") {
+                        let (start, end) = value.split_once('-').unwrap();
+                        range = Some((
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        ));
+                    }
+                }
+                let mut socket = reader.into_inner();
+
+                let file = files
+                    .iter()
+                    .find(|(name, _)| format!("/seed/test/{name}") == path);
+                if let Some(query) = path.strip_prefix("/script?") {
+                    let response = match mode {
+                        SeedMode::ScriptBusy => {
+                            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 2\r\n\r\n30"
+                                .to_vec()
+                        }
+                        _ => {
+                            let param = |key: &str| {
+                                query
+                                    .split('&')
+                                    .find_map(|kv| kv.strip_prefix(&format!("{key}=")))
+                                    .unwrap()
+                                    .to_owned()
+                            };
+                            assert!(!param("info_hash").is_empty());
+                            let piece = param("piece").parse::<usize>().unwrap();
+                            let ranges = param("ranges");
+                            let (start, end) = ranges.split_once('-').unwrap();
+                            let data: Vec<u8> = files.iter().flat_map(|(_, d)| d.clone()).collect();
+                            let offset = piece * 16384;
+                            let body = &data[offset + start.parse::<usize>().unwrap()
+                                ..=offset + end.parse::<usize>().unwrap()];
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                                body.len()
+                            )
+                            .into_bytes();
+                            response.extend(body);
+                            response
+                        }
+                    };
+                    let _ = socket.write_all(&response).await;
+                    return;
+                }
+                let response = match (mode, file, range) {
+                    (SeedMode::Busy | SeedMode::ScriptBusy, _, _) => {
+                        b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 120\r\nContent-Length: 0\r\n\r\n".to_vec()
+                    }
+                    (SeedMode::BusyUntil, _, _) => {
+                        b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: Fri, 01 Jan 2100 00:00:00 GMT\r\nContent-Length: 0\r\n\r\n".to_vec()
+                    }
+                    (SeedMode::NoRanges, Some((_, data)), _) => {
+                        let mut response =
+                            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", data.len())
+                                .into_bytes();
+                        response.extend(data);
+                        response
+                    }
+                    (_, Some((_, data)), Some((start, end))) => {
+                        let mut body = data[start..=end].to_vec();
+                        if let SeedMode::Corrupt = mode {
+                            body.iter_mut().for_each(|b| *b = !*b);
+                        }
+                        let mut response = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes();
+                        response.extend(body);
+                        response
+                    }
+                    _ => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                };
+                let _ = socket.write_all(&response).await;
+            });
+        }
+    });
+    addr
+}
+
+fn web_seed_files() -> Vec<(String, Vec<u8>)> {
+    vec![
+        (
+            "a".to_owned(),
+            (0..10_000).map(|i| (i % 251) as u8).collect(),
+        ),
+        (
+            "dir/b".to_owned(),
+            (0..30_000).map(|i| (i % 241) as u8).collect(),
+        ),
+    ]
+}
+
+fn web_seed_metainfo(
+    files: &[(String, Vec<u8>)],
+    url_list: &[String],
+    httpseeds: &[String],
+) -> MetaInfo {
+    let files: Vec<_> = files
+        .iter()
+        .map(|(path, data)| (path.as_str(), data.as_slice()))
+        .collect();
+    MetaInfo::from_bytes(&web_seed_torrent(&files, 16384, url_list, httpseeds)).unwrap()
+}
+
+#[tokio::test]
+async fn test_web_seed_fetch_piece() {
+    let files = web_seed_files();
+    let addr = spawn_web_seed(files.clone(), SeedMode::Good).await;
+    let url = format!("http://{addr}/seed/");
+    let metainfo = web_seed_metainfo(&files, std::slice::from_ref(&url), &[]);
+    assert_eq!(metainfo.url_list, Some(vec![url.clone()]));
+
+    let layout = Layout::from_torrent_info(&metainfo.info);
+    let seed = WebSeed::new(url);
+    assert_eq!(
+        seed.file_url(&layout.files[1], false).unwrap().as_str(),
+        format!("http://{addr}/seed/test/dir/b")
+    );
+
+    let client = reqwest::Client::new();
+    let data: Vec<u8> = files.iter().flat_map(|(_, d)| d.clone()).collect();
+    for index in 0..layout.num_pieces {
+        let piece = seed
+            .fetch_piece(&client, &layout, index, false)
+            .await
+            .unwrap();
+        assert!(metainfo.info.verify_piece(index, &piece));
+        let start = layout.piece_offset(index) as usize;
+        assert_eq!(piece, data[start..start + piece.len()]);
+    }
+}
+
+#[test]
+fn test_web_seed_file_url() {
+    // the second file is renamed on disk but served under its torrent path
+    let files = vec![
+        ("dir/a".to_owned(), vec![1; 100]),
+        ("dir/a".to_owned(), vec![2; 100]),
+        ("b\\c".to_owned(), vec![3; 100]),
+    ];
+    let metainfo = web_seed_metainfo(&files, &[], &[]);
+    let layout = Layout::from_torrent_info(&metainfo.info);
+    assert_eq!(layout.files[1].path, PathBuf::from("test/dir/a.1"));
+    assert_eq!(layout.files[2].path, PathBuf::from("test/b_c"));
+
+    let seed = WebSeed::new("http://localhost/seed/".into());
+    let urls: Vec<_> = layout
+        .files
+        .iter()
+        .map(|f| seed.file_url(f, false).unwrap().to_string())
+        .collect();
+    assert_eq!(
+        urls,
+        vec![
+            "http://localhost/seed/test/dir/a",
+            "http://localhost/seed/test/dir/a",
+            "http://localhost/seed/test/b%5Cc",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_web_seed_padding() {
+    let files = web_seed_files();
+    let addr = spawn_web_seed(files.clone(), SeedMode::Good).await;
+    let padded = vec![
+        files[0].clone(),
+        (".pad/6384".to_owned(), vec![0; 6384]),
+        files[1].clone(),
+    ];
+    let url = format!("http://{addr}/seed/");
+    let metainfo = web_seed_metainfo(&padded, std::slice::from_ref(&url), &[]);
+
+    let layout = Layout::from_torrent_info(&metainfo.info);
+    assert!(layout.files[1].is_padding());
+    let seed = WebSeed::new(url);
+    let client = reqwest::Client::new();
+    let data: Vec<u8> = padded.iter().flat_map(|(_, d)| d.clone()).collect();
+    for index in 0..layout.num_pieces {
+        let piece = seed
+            .fetch_piece(&client, &layout, index, false)
+            .await
+            .unwrap();
+        assert!(metainfo.info.verify_piece(index, &piece));
+        let start = layout.piece_offset(index) as usize;
+        assert_eq!(piece, data[start..start + piece.len()]);
+    }
+}
+
+#[tokio::test]
+async fn test_web_seed_retry_after() {
+    let files = web_seed_files();
+    let addr = spawn_web_seed(files.clone(), SeedMode::Busy).await;
+    let metainfo = web_seed_metainfo(&files, &[], &[]);
+    let layout = Layout::from_torrent_info(&metainfo.info);
+
+    let mut seed = WebSeed::new(format!("http://{addr}/seed/"));
+    let client = reqwest::Client::new();
+    let error = seed
+        .fetch_piece(&client, &layout, 0, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        Error::WebSeedStatus {
+            status: 503,
+            retry_after: Some(retry_after),
+        } if retry_after == Duration::from_secs(120)
+    ));
+
+    seed.on_error(&error);
+    assert!(!seed.is_available());
+    assert!(seed.retry_at().unwrap() > Instant::now() + Duration::from_secs(100));
+
+    let addr = spawn_web_seed(files.clone(), SeedMode::BusyUntil).await;
+    let seed = WebSeed::new(format!("http://{addr}/seed/"));
+    let error = seed
+        .fetch_piece(&client, &layout, 0, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        Error::WebSeedStatus {
+            status: 503,
+            retry_after: Some(retry_after),
+        } if retry_after > Duration::from_secs(70 * 365 * 86_400)
+    ));
+
+    assert_eq!(
+        parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+        Some(UNIX_EPOCH + Duration::from_secs(784_111_777))
+    );
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+}
+
+#[tokio::test]
+async fn test_web_seed_ranges_ignored() {
+    let files = web_seed_files();
+    let addr = spawn_web_seed(files.clone(), SeedMode::NoRanges).await;
+    let metainfo = web_seed_metainfo(&files, &[], &[]);
+    let layout = Layout::from_torrent_info(&metainfo.info);
+
+    // the first file is served whole, the part of the second is not
+    let mut seed = WebSeed::new(format!("http://{addr}/seed/"));
+    let client = reqwest::Client::new();
+    let error = seed
+        .fetch_piece(&client, &layout, 0, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::WebSeedRangesIgnored));
+    seed.on_error(&error);
+    assert!(seed.is_banned());
+}
+
+#[test]
+fn test_web_seed_hash_failure() {
+    let mut seed = WebSeed::new("http://localhost/seed/".into());
+    seed.on_hash_failure();
+    assert!(!seed.is_banned());
+    assert!(!seed.is_available());
+    assert!(seed.retry_at().unwrap() > Instant::now());
+    seed.on_hash_failure();
+    seed.on_hash_failure();
+    assert!(seed.is_banned());
+}
+
+#[tokio::test]
+async fn test_web_seed_added_later() {
+    let files = web_seed_files();
+    let addr = spawn_web_seed(files.clone(), SeedMode::Good).await;
+    let metainfo = web_seed_metainfo(&files, &[], &[]);
+    let save_path = std::env::temp_dir().join("rutor_test_web_seed_added_later");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let mut filter = IpFilter::new();
+    let blocked: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+    filter.add_range(blocked.ip(), blocked.ip());
+    let ip_filter = Arc::new(SessionIpFilter::new(filter, alert_tx.clone()));
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: save_path.clone(),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: Some(ip_filter),
+        },
+    )
+    .await;
+    {
+        let mut state = torrent.state.lock().await;
+        assert_eq!(state.add_peers(PeerSource::Tracker, [blocked]), 0);
+        assert!(state.web_seeds.is_empty());
+        state
+            .web_seeds
+            .push(WebSeed::new(format!("http://{addr}/seed/")));
+    }
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !torrent.state.lock().await.is_finished() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_web_seed_download() {
+    let files = web_seed_files();
+    let corrupt = spawn_web_seed(files.clone(), SeedMode::Corrupt).await;
+    let good = spawn_web_seed(files.clone(), SeedMode::Good).await;
+    let url_list = [
+        format!("http://{corrupt}/seed/"),
+        format!("http://{good}/seed"),
+    ];
+    let metainfo = web_seed_metainfo(&files, &url_list, &[]);
+
+    let save_path = std::env::temp_dir().join("rutor_test_web_seed_download");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: save_path.clone(),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: None,
+        },
+    )
+    .await;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !torrent.state.lock().await.is_finished() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // the corrupt seed backs off after its first failure, the good one
+    // finishes the download meanwhile
+    let state = torrent.state.lock().await;
+    assert_eq!(state.web_seeds[0].hash_failures(), 1);
+    assert!(!state.web_seeds[0].is_available());
+    assert!(!state.web_seeds[1].is_banned());
+    assert_eq!(state.progress.left, 0);
+    for (path, data) in files.iter() {
+        assert_eq!(
+            &std::fs::read(save_path.join("test").join(path)).unwrap(),
+            data
+        );
+    }
+    drop(state);
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_spawn_ip_filter() {
+    let mut filter = IpFilter::new();
+    filter.add_range(
+        "10.0.0.0".parse().unwrap(),
+        "10.255.255.255".parse().unwrap(),
+    );
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let ip_filter = Arc::new(SessionIpFilter::new(filter, alert_tx.clone()));
+    let metainfo = web_seed_metainfo(&web_seed_files(), &[], &[]);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Stopped,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: std::env::temp_dir().join("rutor_test_spawn_ip_filter"),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: Some(ip_filter.clone()),
+        },
+    )
+    .await;
+
+    let peers = ["10.0.0.1:6881", "192.0.2.1:6881"].map(|addr| addr.parse().unwrap());
+    let mut state = torrent.state.lock().await;
+    assert_eq!(state.add_peers(PeerSource::Tracker, peers), 1);
+    assert!(!state.peers().contains_key(&peers[0]));
+    assert_eq!(ip_filter.blocked(), 1);
+}
+
+#[tokio::test]
+async fn test_http_seed() {
+    let files = web_seed_files();
+    let good = spawn_web_seed(files.clone(), SeedMode::Good).await;
+    let busy = spawn_web_seed(files.clone(), SeedMode::ScriptBusy).await;
+    let httpseeds = [
+        format!("http://{busy}/script"),
+        format!("http://{good}/script"),
+    ];
+    let metainfo = web_seed_metainfo(&files, &[], &httpseeds);
+    assert_eq!(metainfo.httpseeds, Some(httpseeds.to_vec()));
+    assert_eq!(metainfo.url_list, None);
+
+    let layout = Layout::from_torrent_info(&metainfo.info);
+    let torrent_id = *metainfo.info_hash().inner().truncate();
+    let client = reqwest::Client::new();
+    let mut seed = WebSeed::http_seed(httpseeds[0].clone(), torrent_id);
+    let error = seed
+        .fetch_piece(&client, &layout, 0, false)
+        .await
+        .unwrap_err();
+    seed.on_error(&error);
+    assert!(seed.retry_at().unwrap() > Instant::now() + Duration::from_secs(20));
+
+    let save_path = std::env::temp_dir().join("rutor_test_http_seed");
+    let _ = std::fs::remove_dir_all(&save_path);
+    let (alert_tx, _alert_rx) = tokio::sync::mpsc::channel(4);
+    let torrent = TorrentHandle::spawn(
+        TorrentSource::File(metainfo),
+        TorrentSpawnParams {
+            status: TorrentStatus::Downloading,
+            port: 6881,
+            peer_id: proto::PeerId::gen_new(),
+            save_path: save_path.clone(),
+            alert_tx,
+            web_seed_proxy: None,
+            ip_filter: None,
+        },
+    )
+    .await;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !torrent.state.lock().await.is_finished() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let state = torrent.state.lock().await;
+    assert!(!state.web_seeds[0].is_available());
+    for (path, data) in files.iter() {
+        assert_eq!(
+            &std::fs::read(save_path.join("test").join(path)).unwrap(),
+            data
+        );
+    }
+    drop(state);
+    std::fs::remove_dir_all(&save_path).unwrap();
+}
+
+#[test]
+fn test_v2_hash_trees() {
+    let root = std::env::temp_dir()
+        .join("rutor_test_v2_hash_trees")
+        .join("release");
+    let _ = std::fs::remove_dir_all(&root);
+    let files: Vec<(&str, Vec<u8>)> = vec![
+        ("a.bin", (0..40_000).map(|i| (i % 251) as u8).collect()),
+        ("b.bin", (0..5_000).map(|i| (i % 13) as u8).collect()),
+        ("c.bin", (0..120_000).map(|i| (i % 241) as u8).collect()),
+    ];
+    std::fs::create_dir_all(&root).unwrap();
+    for (path, data) in files.iter() {
+        std::fs::write(root.join(path), data).unwrap();
+    }
+    let bytes = TorrentBuilder::new(&root)
+        .version(TorrentVersion::V2)
+        .piece_length(16 * 1024)
+        .build()
+        .unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let info = &metainfo.info;
+
+    // every file starts at a piece boundary
+    assert_eq!(info.num_pieces(), 3 + 1 + 8);
+    let layout = Layout::from_torrent_info(info);
+    assert_eq!(layout.num_pieces, 12);
+    let offsets: Vec<_> = layout.files.iter().map(|f| f.offset).collect();
+    assert_eq!(offsets, vec![0, 3 * 16384, 4 * 16384]);
+    assert_eq!(layout.piece_size(2), 40_000 - 2 * 16384);
+    assert_eq!(layout.piece_size(3), 5_000);
+
+    let piece_layers = metainfo.piece_layers.clone().unwrap();
+    assert_eq!(piece_layers.len(), 2);
+    let pieces: Vec<Vec<u8>> = files
+        .iter()
+        .flat_map(|(_, data)| data.chunks(16 * 1024).map(|c| c.to_vec()))
+        .collect();
+
+    let full = HashTrees::new(info, &layout, &piece_layers).unwrap();
+    assert!(full.has_piece_layers());
+    assert!(full.hash_requests().is_empty());
+    for (index, piece) in pieces.iter().enumerate() {
+        assert_eq!(full.verify_piece(index, piece), Some(true));
+    }
+    assert_eq!(full.verify_piece(0, &pieces[1]), Some(false));
+    assert_eq!(full.verify_piece(3, &pieces[4]), Some(false));
+
+    // without piece layers only the single piece file can be checked
+    let mut trees = HashTrees::new(info, &layout, &Default::default()).unwrap();
+    assert!(!trees.has_piece_layers());
+    assert_eq!(trees.verify_piece(0, &pieces[0]), None);
+    assert_eq!(trees.verify_piece(3, &pieces[3]), Some(true));
+
+    let requests = trees.hash_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!((requests[0].length, requests[0].proof_layers), (4, 0));
+    assert_eq!((requests[1].length, requests[1].proof_layers), (8, 0));
+
+    // two hashes of `c.bin` with the uncles up to its root
+    let c_root = trees.files()[2].pieces_root;
+    let request = proto::HashRequest::new(c_root, 0, 2, 2, 2);
+    let hashes = full.hashes(&request).unwrap();
+    assert_eq!(hashes.hashes.len(), 4);
+
+    let mut tampered = hashes.clone();
+    tampered.hashes[3][0] ^= 1;
+    assert!(!trees.add_hashes(&tampered));
+    assert!(trees.add_hashes(&hashes));
+    assert_eq!(trees.verify_piece(6, &pieces[6]), Some(true));
+    assert_eq!(trees.verify_piece(7, &pieces[6]), Some(false));
+    assert_eq!(trees.verify_piece(4, &pieces[4]), None);
+
+    for request in trees.hash_requests() {
+        let hashes = full.hashes(&request).unwrap();
+        assert!(trees.add_hashes(&hashes));
+    }
+    assert!(trees.has_piece_layers());
+    assert!(full
+        .hashes(&proto::HashRequest::new(c_root, 1, 0, 2, 0))
+        .is_none());
+
+    std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_v2_block_verification() {
+    let root = std::env::temp_dir()
+        .join("rutor_test_v2_block_verification")
+        .join("release");
+    let _ = std::fs::remove_dir_all(&root);
+    let files: Vec<(&str, Vec<u8>)> = vec![
+        ("a.bin", (0..150_000).map(|i| (i % 251) as u8).collect()),
+        ("b.bin", (0..40_000).map(|i| (i % 13) as u8).collect()),
+    ];
+    std::fs::create_dir_all(&root).unwrap();
+    for (path, data) in files.iter() {
+        std::fs::write(root.join(path), data).unwrap();
+    }
+    let bytes = TorrentBuilder::new(&root)
+        .version(TorrentVersion::V2)
+        .piece_length(64 * 1024)
+        .build()
+        .unwrap();
+    std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    let metainfo = MetaInfo::from_bytes(&bytes).unwrap();
+    let layout = Layout::from_torrent_info(&metainfo.info);
+    let piece_layers = metainfo.piece_layers.clone().unwrap();
+    let mut trees = HashTrees::new(&metainfo.info, &layout, &piece_layers).unwrap();
+    let (a, b) = (&files[0].1, &files[1].1);
+    let block = 16 * 1024;
+
+    // leaf hashes are needed first
+    assert_eq!(trees.verify_block(0, block, &a[block..2 * block]), None);
+    let request = trees.block_hash_request(0).unwrap();
+    assert_eq!(
+        (request.base_layer, request.index, request.length),
+        (0, 0, 4)
+    );
+    let leaves = merkle::block_hashes(&a[..4 * block]);
+    let mut tampered = proto::Hashes::new(request.clone(), leaves.clone());
+    tampered.hashes[2][0] ^= 1;
+    assert!(!trees.add_hashes(&tampered));
+    assert!(trees.add_hashes(&proto::Hashes::new(request, leaves)));
+    assert!(trees.block_hash_request(0).is_none());
+    assert_eq!(
+        trees.verify_block(0, block, &a[block..2 * block]),
+        Some(true)
+    );
+    assert_eq!(trees.verify_block(0, block, &a[..block]), Some(false));
+    assert_eq!(trees.verify_block(0, 100, &a[100..100 + block]), None);
+
+    // the last piece of a file has zero leaves past its end
+    let request = trees.block_hash_request(2).unwrap();
+    assert_eq!((request.index, request.length), (8, 4));
+    let mut leaves = merkle::block_hashes(&a[8 * block..]);
+    leaves.resize(4, merkle::ZERO_HASH);
+    assert!(trees.add_hashes(&proto::Hashes::new(request, leaves)));
+    assert_eq!(trees.verify_block(2, block, &a[9 * block..]), Some(true));
+    assert_eq!(trees.verify_block(2, 2 * block, &[0; 16]), None);
+
+    // a file within one piece is checked against its root
+    let request = trees.block_hash_request(3).unwrap();
+    assert_eq!((request.index, request.length), (0, 4));
+    let mut leaves = merkle::block_hashes(b);
+    leaves.resize(4, merkle::ZERO_HASH);
+    assert!(trees.add_hashes(&proto::Hashes::new(request, leaves)));
+    assert_eq!(
+        trees.verify_block(3, 2 * block, &b[2 * block..]),
+        Some(true)
+    );
+
+    // a bad block is dropped and its sender banned, the piece is kept
+    let mut state = init_state(TorrentStatus::Downloading);
+    state.info = Some(metainfo.info.clone());
+    state.hash_trees = Some(trees);
+    let [honest, liar] =
+        ["10.0.0.1:6881", "10.0.0.2:6881"].map(|addr| addr.parse::<SocketAddr>().unwrap());
+    state.add_peers(PeerSource::Tracker, [honest, liar]);
+    assert!(state.on_peer_block(0, 0, honest, &a[..block]));
+    let mut bad = a[block..2 * block].to_vec();
+    bad[0] ^= 1;
+    assert!(!state.on_peer_block(0, block, liar, &bad));
+    assert_eq!(state.peers().keys().collect::<Vec<_>>(), [&honest]);
+    assert!(state.on_peer_block(0, block, honest, &a[block..2 * block]));
+}
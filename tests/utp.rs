@@ -0,0 +1,277 @@
+use rutor::utp::{
+    Ledbat, MtuSearch, Packet, PacketType, RttEstimator, UtpSocket, UtpStream, TARGET_DELAY,
+    UTP_HEADER_SIZE,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UdpSocket;
+
+#[test]
+fn test_utp_packet() {
+    let mut packet = Packet::new(PacketType::Data, 7, 65535, 10);
+    packet.timestamp = 123;
+    packet.timestamp_diff = 456;
+    packet.wnd_size = 789;
+    packet.selective_ack = Some(vec![0b101, 0, 0, 0b1000_0000]);
+    packet.payload = b"hello".to_vec();
+    let bytes = packet.to_bytes();
+    assert_eq!(bytes.len(), packet.len());
+    assert_eq!(bytes[0], 0x01);
+    assert!(Packet::is_utp(&bytes));
+    assert_eq!(Packet::from_bytes(&bytes).unwrap(), packet);
+    assert_eq!(packet.selective_acks().collect::<Vec<_>>(), [12, 14, 43]);
+
+    // MTU probes are padded with extensions
+    let probe = packet.with_padding(1200);
+    let bytes = probe.to_bytes();
+    assert!(bytes.len() <= 1200 && bytes.len() >= 1195);
+    assert_eq!(bytes.len(), probe.len());
+    assert!(Packet::is_utp(&bytes));
+    assert_eq!(Packet::from_bytes(&bytes).unwrap(), probe);
+    let probe = Packet::new(PacketType::Data, 7, 1, 0).with_padding(600);
+    assert_eq!(Packet::from_bytes(&probe.to_bytes()).unwrap(), probe);
+
+    // unknown extensions are skipped
+    let mut bytes = Packet::new(PacketType::State, 1, 2, 3).to_bytes();
+    bytes[1] = 9;
+    bytes.extend([0, 2, 0xaa, 0xbb, b'x']);
+    let parsed = Packet::from_bytes(&bytes).unwrap();
+    assert_eq!(parsed.selective_ack, None);
+    assert_eq!(parsed.payload, b"x");
+
+    assert!(Packet::from_bytes(&bytes[..UTP_HEADER_SIZE - 1]).is_err());
+    let mut bad_version = Packet::new(PacketType::Syn, 1, 1, 0).to_bytes();
+    bad_version[0] = 0x42;
+    assert!(Packet::from_bytes(&bad_version).is_err());
+    assert!(!Packet::is_utp(b"d1:ad2:id20:"));
+    assert!(!Packet::is_utp(&[0; 20]));
+}
+
+#[test]
+fn test_utp_congestion() {
+    let now = Instant::now();
+    let mss = 1000;
+    let mut ledbat = Ledbat::new(2 * mss);
+    ledbat.on_delay_sample(50_000, now);
+    ledbat.on_ack(mss, mss);
+    let grown = ledbat.cwnd();
+    assert!(grown > 2 * mss);
+
+    ledbat.on_delay_sample(50_000 + 3 * TARGET_DELAY, now);
+    for _ in 0..4 {
+        ledbat.on_delay_sample(50_000 + 3 * TARGET_DELAY, now);
+    }
+    assert_eq!(ledbat.queuing_delay(), Some(3 * TARGET_DELAY));
+    ledbat.on_ack(mss, mss);
+    assert!(ledbat.cwnd() < grown);
+    ledbat.on_loss(mss);
+    ledbat.on_timeout(mss);
+    assert_eq!(ledbat.cwnd(), mss);
+
+    let mut rtt = RttEstimator::default();
+    assert_eq!(rtt.rto(), Duration::from_secs(1));
+    rtt.on_sample(Duration::from_millis(10));
+    assert_eq!(rtt.rto(), Duration::from_millis(500));
+    rtt.on_timeout();
+    assert_eq!(rtt.rto(), Duration::from_secs(1));
+
+    let mut mtu = MtuSearch::new(500, 1500);
+    while let Some(size) = mtu.next_probe() {
+        mtu.on_probe_sent(size);
+        match size <= 1200 {
+            true => mtu.on_probe_acked(),
+            false => mtu.on_probe_lost(),
+        }
+    }
+    assert!(mtu.packet_size() <= 1200 && mtu.packet_size() > 1180);
+}
+
+fn test_data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+/// Sends `data` while reading the other side, then shuts down and expects EOF.
+async fn exchange(stream: UtpStream, send: Vec<u8>, expected: usize) -> Vec<u8> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let write = async move {
+        writer.write_all(&send).await.unwrap();
+        writer.shutdown().await.unwrap();
+    };
+    let read = async move {
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received.len(), expected);
+        received
+    };
+    tokio::join!(write, read).1
+}
+
+async fn transfer(server: Arc<UtpSocket>, client: Arc<UtpSocket>, addr: SocketAddr, len: usize) {
+    let (a, b) = (test_data(len, 1), test_data(len / 2, 7));
+    let (a2, b2) = (a.clone(), b.clone());
+    let server_task = tokio::spawn(async move {
+        let (stream, _) = server.accept().await.unwrap();
+        exchange(stream, b2, len).await
+    });
+    let stream = client.connect(addr).await.unwrap();
+    let received = exchange(stream, a2, len / 2).await;
+    let server_received = tokio::time::timeout(Duration::from_secs(30), server_task)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(received == b, "client got corrupted data");
+    assert!(server_received == a, "server got corrupted data");
+}
+
+#[tokio::test]
+async fn test_utp_loopback() {
+    let server = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        transfer(server.clone(), client.clone(), addr, 1 << 20),
+    )
+    .await
+    .unwrap();
+
+    // stray packets for unknown connections get a reset
+    let probe = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let data = Packet::new(PacketType::Data, 999, 5, 1).to_bytes();
+    probe.send_to(&data, addr).await.unwrap();
+    let mut buf = [0u8; 64];
+    let (n, _) = probe.recv_from(&mut buf).await.unwrap();
+    let reset = Packet::from_bytes(&buf[..n]).unwrap();
+    assert_eq!(reset.packet_type, PacketType::Reset);
+    assert_eq!(reset.connection_id, 999);
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(server.num_connections(), 0);
+    assert_eq!(client.num_connections(), 0);
+}
+
+/// Forwards datagrams between a single client and `server`, `filter` decides
+/// per datagram: `Some(false)` drops it, `Some(true)` holds it back until the
+/// next one went through.
+async fn spawn_proxy<F>(server: SocketAddr, mut filter: F) -> SocketAddr
+where
+    F: FnMut(usize, &[u8]) -> Option<bool> + Send + 'static,
+{
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut client = None;
+        let mut held: Option<(Vec<u8>, SocketAddr)> = None;
+        let mut buf = [0u8; 4096];
+        for count in 0.. {
+            let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+            let to = match from == server {
+                true => match client {
+                    Some(client) => client,
+                    None => continue,
+                },
+                false => {
+                    client = Some(from);
+                    server
+                }
+            };
+            match filter(count, &buf[..n]) {
+                Some(false) => {}
+                Some(true) if held.is_none() => held = Some((buf[..n].to_vec(), to)),
+                _ => {
+                    let _ = socket.send_to(&buf[..n], to).await;
+                    if let Some((bytes, to)) = held.take() {
+                        let _ = socket.send_to(&bytes, to).await;
+                    }
+                }
+            }
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_utp_loss_and_reordering() {
+    let server = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    let proxy = spawn_proxy(server.local_addr().unwrap(), |count, _| {
+        match count % 17 {
+            // the SYN and its answer go through
+            _ if count < 2 => None,
+            5 => Some(false),
+            11 => Some(true),
+            _ => None,
+        }
+    })
+    .await;
+    tokio::time::timeout(
+        Duration::from_secs(60),
+        transfer(server, client, proxy, 200_000),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_utp_mtu_black_hole() {
+    let server = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    // datagrams above 1000 bytes never arrive
+    let proxy = spawn_proxy(server.local_addr().unwrap(), |_, bytes| {
+        (bytes.len() > 1000).then_some(false)
+    })
+    .await;
+    tokio::time::timeout(
+        Duration::from_secs(60),
+        transfer(server, client, proxy, 100_000),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_utp_receive_buffer_limit() {
+    let server = UtpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let accept = {
+        let server = server.clone();
+        tokio::spawn(async move { server.accept().await.unwrap() })
+    };
+
+    // a peer that ignores the advertised window
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut syn = Packet::new(PacketType::Syn, 100, 1, 0);
+    syn.wnd_size = 1 << 20;
+    peer.send_to(&syn.to_bytes(), addr).await.unwrap();
+    let mut buf = [0u8; 2048];
+    let (n, _) = peer.recv_from(&mut buf).await.unwrap();
+    let reply = Packet::from_bytes(&buf[..n]).unwrap();
+    assert_eq!(reply.packet_type, PacketType::State);
+    // the stream is never read
+    let _stream = accept.await.unwrap();
+
+    let num_packets = 1100u16;
+    for seq_nr in 2..2 + num_packets {
+        let mut packet = Packet::new(PacketType::Data, 101, seq_nr, reply.seq_nr.wrapping_sub(1));
+        packet.wnd_size = 1 << 20;
+        packet.payload = vec![0; 1000];
+        peer.send_to(&packet.to_bytes(), addr).await.unwrap();
+        if seq_nr % 64 == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    let mut acked = 0;
+    while let Ok(Ok((n, _))) =
+        tokio::time::timeout(Duration::from_millis(500), peer.recv_from(&mut buf)).await
+    {
+        let packet = Packet::from_bytes(&buf[..n]).unwrap();
+        acked = acked.max(packet.ack_nr);
+    }
+    // 1 MiB is buffered, the rest is dropped unacked
+    assert_eq!(acked as usize, 1 + (1 << 20) / 1000);
+}